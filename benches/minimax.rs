@@ -2,19 +2,19 @@ extern crate easybench;
 extern crate minimax;
 extern crate nokamute;
 
-use minimax::{Game, IterativeOptions, IterativeSearch, ParallelOptions, ParallelSearch, Strategy};
+use minimax::{IterativeOptions, IterativeSearch, ParallelOptions, ParallelSearch, Strategy};
 use nokamute::{loc_to_hex, Board, Bug, Rules, Turn};
 
 fn empty_board_depth(depth: u8) {
-    let mut board = Board::default();
+    let board = Board::default();
     let options = IterativeOptions::new().with_table_byte_size(16000).with_null_window_search(true);
     let mut strategy = IterativeSearch::new(nokamute::BasicEvaluator::default(), options);
     strategy.set_max_depth(depth);
-    let m = strategy.choose_move(&mut board);
+    let m = strategy.choose_move(&board);
     assert!(m.is_some());
 }
 
-fn full_board_depth(depth: u8) {
+fn full_board() -> Board {
     let mut board = Board::default();
     // From some game I found online, subbed out some expansion pieces.
     board.apply(Turn::Place(loc_to_hex((4, 0)), Bug::Queen));
@@ -36,10 +36,15 @@ fn full_board_depth(depth: u8) {
     board.apply(Turn::Pass);
     board.apply(Turn::Place(loc_to_hex((5, 5)), Bug::Ant));
     board.apply(Turn::Pass);
+    board
+}
+
+fn full_board_depth(depth: u8) {
+    let board = full_board();
     let options = IterativeOptions::new().with_table_byte_size(16000).with_null_window_search(true);
     let mut strategy = IterativeSearch::new(nokamute::BasicEvaluator::default(), options);
     strategy.set_max_depth(depth);
-    let m = strategy.choose_move(&mut board);
+    let m = strategy.choose_move(&board);
     assert!(m.is_some());
 }
 
@@ -53,14 +58,75 @@ fn playout(mut depth: usize) -> Board {
     board
 }
 
+// Expand every child of the full board position the way a search does, evaluating
+// it and generating its moves, which both need the cut vertexes.
+fn expand_children(board: &mut Board) {
+    let eval = nokamute::BasicEvaluator::default();
+    let mut moves = Vec::new();
+    Rules::generate_moves(board, &mut moves);
+    let mut replies = Vec::new();
+    for &m in moves.iter() {
+        board.apply(m);
+        minimax::Evaluator::evaluate(&eval, board);
+        replies.clear();
+        Rules::generate_moves(board, &mut replies);
+        board.undo(m);
+    }
+}
+
+// Like playout, but the same game every time.
+fn fixed_playout(depth: usize) -> Board {
+    let mut board = Board::default();
+    let mut moves = Vec::new();
+    let mut seed = 12345u64;
+    for _ in 0..depth {
+        moves.clear();
+        Rules::generate_moves(&board, &mut moves);
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        board.apply(moves[(seed >> 33) as usize % moves.len()]);
+        if Rules::get_winner(&board).is_some() {
+            break;
+        }
+    }
+    board
+}
+
+// Generate the moves of some early and midgame positions, where placements
+// make up most of the moves.
+fn generate_moves(boards: &[Board]) {
+    let mut moves = Vec::new();
+    for board in boards.iter() {
+        moves.clear();
+        Rules::generate_moves(board, &mut moves);
+    }
+}
+
+// Generate the moves of the full board, with movements of every kind of bug,
+// into one reused buffer the way a search does.
+fn generate_movements(board: &Board, moves: &mut Vec<Turn>) {
+    moves.clear();
+    Rules::generate_moves(board, moves);
+}
+
+// MCTS rollouts a second from a midgame position, where rollouts start from
+// boards with a long history behind them.
+fn mcts_rollouts() -> f64 {
+    let board = fixed_playout(30);
+    let mut search = nokamute::MctsSearch::new(minimax::MCTSOptions::default(), 200, 0.5);
+    search.set_max_rollouts(2000);
+    let start = std::time::Instant::now();
+    search.choose_move(&board);
+    search.stats().rollouts() as f64 / start.elapsed().as_secs_f64()
+}
+
 // Find one random position and run the iterative strategies at a deeper level to compare timings.
 fn deep_iterations() {
     let board = playout(20);
     let opts = IterativeOptions::new().verbose().with_table_byte_size(32 << 20);
     let eval = nokamute::BasicEvaluator::default();
     let strategies: [Box<dyn minimax::Strategy<nokamute::Rules>>; 2] = [
-        Box::new(IterativeSearch::new(eval.clone(), opts)),
-        Box::new(ParallelSearch::new(eval.clone(), opts, ParallelOptions::new())),
+        Box::new(IterativeSearch::new(eval, opts)),
+        Box::new(ParallelSearch::new(eval, opts, ParallelOptions::new())),
     ];
     for mut strategy in strategies {
         strategy.choose_move(&board);
@@ -68,7 +134,7 @@ fn deep_iterations() {
 }
 
 fn main() {
-    let mut filter = std::env::args().skip(1).next().unwrap_or("".to_string());
+    let mut filter = std::env::args().nth(1).unwrap_or("".to_string());
     if filter == "--bench" {
         filter = "".to_string();
     }
@@ -82,6 +148,29 @@ fn main() {
         println!("random playout: {}", easybench::bench(|| playout(200)));
     }
 
+    if "expand children".contains(&filter) {
+        let mut board = full_board();
+        println!("expand children: {}", easybench::bench(|| expand_children(&mut board)));
+    }
+
+    if "generate moves".contains(&filter) {
+        let boards = [8, 12, 16, 20, 24].map(fixed_playout);
+        println!("generate moves: {}", easybench::bench(|| generate_moves(&boards)));
+    }
+
+    if "generate movements".contains(&filter) {
+        let board = full_board();
+        let mut moves = Vec::new();
+        println!(
+            "generate movements: {}",
+            easybench::bench(|| generate_movements(&board, &mut moves))
+        );
+    }
+
+    if "mcts rollouts".contains(&filter) {
+        println!("mcts rollouts:  {:.0}/s", mcts_rollouts());
+    }
+
     if "deep iterations".contains(&filter) {
         println!("deep iterations:");
         deep_iterations();
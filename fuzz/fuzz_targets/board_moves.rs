@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nokamute::{Board, GameRecord, Rules, Turn};
+
+const GAME_TYPES: [&str; 8] =
+    ["Base", "Base+M", "Base+L", "Base+P", "Base+ML", "Base+MP", "Base+LP", "Base+MLP"];
+
+// Plays out a game chosen by the input: the first byte picks the game type,
+// and each byte after either plays one of the legal moves or takes one back.
+// Every position is checked to survive being written out and read back in.
+fuzz_target!(|data: &[u8]| {
+    let Some((&game_type, data)) = data.split_first() else {
+        return;
+    };
+    let mut board = Board::from_game_type(GAME_TYPES[game_type as usize % 8]).unwrap();
+    let mut history: Vec<Turn> = Vec::new();
+    let mut moves = Vec::new();
+    for &byte in data {
+        if byte == 0xff {
+            if let Some(turn) = history.pop() {
+                board.undo(turn);
+            }
+        } else {
+            if Rules::get_winner(&board).is_some() {
+                break;
+            }
+            moves.clear();
+            Rules::generate_moves(&board, &mut moves);
+            assert!(!moves.is_empty(), "no moves, not even a pass");
+            let turn = moves[byte as usize % moves.len()];
+            board.apply(turn);
+            history.push(turn);
+        }
+
+        let position = board.to_position_string();
+        let reread = Board::from_position_string(&position).unwrap();
+        assert_eq!(position, reread.to_position_string());
+        let record = GameRecord::from_board(&board, "", "");
+        let replayed = record.to_board().unwrap();
+        assert_eq!(board.game_string(), replayed.game_string());
+    }
+});
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nokamute::{boardspace_to_game_string, Board, GameRecord, PlayerConfig, UhpServer};
+
+// Arbitrary text to every game and position parser. Whatever parses must
+// also replay.
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    if let Ok(game_string) = boardspace_to_game_string(&text) {
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set Instant True");
+        server.command(&format!("newgame {}", game_string));
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        assert!(!output.contains("err") && !output.contains("invalidmove"), "{}", output);
+    }
+    if let Ok(records) = GameRecord::parse_all(&text) {
+        for record in records {
+            let _ = record.to_board();
+        }
+    }
+    let _ = Board::from_position_string(&text);
+});
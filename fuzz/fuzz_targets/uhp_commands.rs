@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nokamute::{PlayerConfig, UhpServer};
+
+// Arbitrary lines to the UHP server, as a web backend would pass them on.
+// Searches are instant so each input runs quickly, and the options that only
+// change resource use are left alone.
+fuzz_target!(|data: &[u8]| {
+    let text = String::from_utf8_lossy(data);
+    let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+    server.command("options set Instant True");
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("perft") {
+            continue;
+        }
+        if line.starts_with("options") && line.contains("set") && !line.contains("StrictMoves") {
+            continue;
+        }
+        if server.command(line) {
+            break;
+        }
+    }
+});
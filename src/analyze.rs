@@ -0,0 +1,145 @@
+use crate::notation::Result;
+use crate::player::{exit, parse_timeout, PlayerConfig};
+use crate::{BasicEvaluator, Board, Color, Rules};
+use minimax::{Evaluation, IterativeSearch, Strategy, Winner, BEST_EVAL};
+use std::time::Duration;
+
+// Post-mortem of a finished game: search the position before every move and
+// compare the engine's preferred move and value to what was played.
+
+// One move of an analyzed game. Values are from white's perspective.
+pub(crate) struct MoveAnalysis {
+    pub(crate) played: String,
+    // Value of the position before the move, with best play.
+    pub(crate) value: Evaluation,
+    pub(crate) best: String,
+    // Value after the played move.
+    pub(crate) value_after: Evaluation,
+    // How much worse the played move is than the best move, for the mover.
+    pub(crate) loss: i32,
+}
+
+impl MoveAnalysis {
+    pub(crate) fn is_blunder(&self, threshold: i32) -> bool {
+        self.loss >= threshold && self.played != self.best
+    }
+}
+
+// Value for the player to move, with wins and losses as BEST_EVAL and -BEST_EVAL.
+fn search_value(
+    search: &mut IterativeSearch<BasicEvaluator>, board: &Board,
+) -> (Evaluation, Option<String>) {
+    match Rules::get_winner(board) {
+        Some(Winner::PlayerToMove) => (BEST_EVAL, None),
+        Some(Winner::PlayerJustMoved) => (-BEST_EVAL, None),
+        Some(Winner::Draw) => (0, None),
+        None => {
+            let best = search.choose_move(&board.for_search()).map(|m| board.to_move_string(m));
+            (search.root_value(), best)
+        }
+    }
+}
+
+fn for_white(board: &Board, value: Evaluation) -> Evaluation {
+    if board.to_move() == Color::White {
+        value
+    } else {
+        -value
+    }
+}
+
+pub(crate) fn analyze_game(
+    config: &PlayerConfig, game_string: &str, depth: Option<u8>, timeout: Option<Duration>,
+) -> Result<Vec<MoveAnalysis>> {
+    let game = Board::from_game_string(game_string)?;
+    let turns = game.turn_history.clone();
+    let mut board = Board::from_game_type(&game.game_type())?;
+    let mut search = IterativeSearch::new(config.eval, config.opts);
+    match (depth, timeout) {
+        (Some(depth), _) => search.set_max_depth(depth),
+        (None, Some(timeout)) => search.set_timeout(timeout),
+        (None, None) => search.set_max_depth(4),
+    }
+
+    let mut analysis = Vec::new();
+    let (mut value, mut best) = search_value(&mut search, &board);
+    for turn in turns {
+        let played = board.to_move_string(turn);
+        let before = for_white(&board, value);
+        board.apply(turn);
+        let (value_after, best_after) = search_value(&mut search, &board);
+        // The mover's value before, and the opponent's value after.
+        let loss = value as i32 + value_after as i32;
+        analysis.push(MoveAnalysis {
+            played,
+            value: before,
+            best: best.unwrap_or_default(),
+            value_after: for_white(&board, value_after),
+            loss,
+        });
+        value = value_after;
+        best = best_after;
+    }
+    Ok(analysis)
+}
+
+fn fmt_value(value: Evaluation) -> String {
+    match value {
+        BEST_EVAL => "+win".to_owned(),
+        v if v == -BEST_EVAL => "-win".to_owned(),
+        v => format!("{:+}", v),
+    }
+}
+
+pub fn analyze_main(
+    config: PlayerConfig, game_string: &str, depth: Option<u8>, timeout: Option<String>,
+    threshold: i32,
+) {
+    let timeout = timeout.map(|input| parse_timeout(&input));
+    let analysis = analyze_game(&config, game_string, depth, timeout)
+        .unwrap_or_else(|err| exit(format!("Could not analyze game: {}", err)));
+    let mut blunders = [0; 2];
+    for (i, m) in analysis.iter().enumerate() {
+        let number =
+            if i % 2 == 0 { format!("{}.", i / 2 + 1) } else { format!("{}...", i / 2 + 1) };
+        let mut line = format!(
+            "{:>6} {:<14} {:>6} -> {:>6}  best {}",
+            number,
+            m.played,
+            fmt_value(m.value),
+            fmt_value(m.value_after),
+            m.best
+        );
+        if m.is_blunder(threshold) {
+            blunders[i % 2] += 1;
+            let lost =
+                if m.loss >= BEST_EVAL as i32 { "the game".to_owned() } else { m.loss.to_string() };
+            line = format!("{:<60} ?? blunder, lost {}", line, lost);
+        }
+        println!("{}", line);
+    }
+    println!("Blunders: white {}, black {}", blunders[0], blunders[1]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_game() {
+        // White surrounds its own queen on the last move.
+        let game = r"Base;BlackWins;Black[5];wS1;bG1 wS1\;wQ wS1/;bQ bG1-;wS2 wQ-;bQ wQ\;wA1 \wQ;bG1 /wA1;wA2 \wS2";
+        let config = PlayerConfig::new();
+        let analysis = analyze_game(&config, game, Some(2), None).unwrap();
+        assert_eq!(9, analysis.len());
+        let last = analysis.last().unwrap();
+        assert_eq!(r"wA2 \wS2", last.played);
+        assert_eq!(-BEST_EVAL, last.value_after);
+        assert_ne!(last.played, last.best);
+        assert!(last.value > -BEST_EVAL);
+        assert!(last.is_blunder(100));
+        // The opening placements are all about even.
+        assert!(!analysis[0].is_blunder(100));
+        assert!(!analysis[1].is_blunder(100));
+    }
+}
@@ -1,26 +1,91 @@
-extern crate minimax;
-
-use crate::bug::Bug;
+use crate::bug::{Bug, NUM_BUGS};
 use crate::hex_grid::*;
-use std::borrow::Borrow;
-use std::cmp::{max, min};
-use std::collections::hash_map::DefaultHasher;
-use std::default::Default;
-use std::hash::Hasher;
-
-lazy_static! {
-    static ref ZOBRIST_TABLE: Box<[u64; GRID_SIZE * 2]> = {
-        let mut table = Box::new([0u64; GRID_SIZE * 2]);
-        let mut hasher = DefaultHasher::new();
-        for i in 0..table.len() {
-            hasher.write_usize(i);
-            table[i] = hasher.finish();
-        }
-        table
-    };
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::default::Default;
+
+// Zobrist keys: one per hex and color for tiles, one per color and bug for
+// the count of that bug in hand, and one for black to move. The table is
+// sized from the grid and the bugs, so growing either grows it.
+const HAND_KEYS: usize = GRID_SIZE * 2;
+const TO_MOVE_KEY: usize = HAND_KEYS + 2 * NUM_BUGS;
+// Keeps transposition table entries from pruned nodes apart from full width ones.
+const WIDENED_KEY: usize = TO_MOVE_KEY + 1;
+const ZOBRIST_KEYS: usize = WIDENED_KEY + 1;
+
+// Builds can pick other keys with NOKAMUTE_ZOBRIST_SEED=<u64>, to check that
+// nothing depends on particular hash values. Saved hashes, like tablebase
+// files, only match builds with the same seed.
+const ZOBRIST_SEED: u64 = match option_env!("NOKAMUTE_ZOBRIST_SEED") {
+    Some(seed) => parse_seed(seed),
+    None => 0,
+};
+
+const fn parse_seed(seed: &str) -> u64 {
+    let digits = seed.as_bytes();
+    assert!(!digits.is_empty(), "NOKAMUTE_ZOBRIST_SEED is empty");
+    let mut value = 0u64;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(digits[i].is_ascii_digit(), "NOKAMUTE_ZOBRIST_SEED must be a u64");
+        value = match value.checked_mul(10) {
+            Some(value) => value,
+            None => panic!("NOKAMUTE_ZOBRIST_SEED must be a u64"),
+        };
+        value = match value.checked_add((digits[i] - b'0') as u64) {
+            Some(value) => value,
+            None => panic!("NOKAMUTE_ZOBRIST_SEED must be a u64"),
+        };
+        i += 1;
+    }
+    value
+}
+
+// Pseudorandom keys from splitmix64 starting at the seed, computed at
+// compile time.
+const fn zobrist_table(seed: u64) -> [u64; ZOBRIST_KEYS] {
+    let mut table = [0u64; ZOBRIST_KEYS];
+    let mut state = seed;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static ZOBRIST_TABLE: [u64; ZOBRIST_KEYS] = zobrist_table(ZOBRIST_SEED);
+
+// Piece sets other than the standard ones keep exactly one queen, as the
+// rules only track one per player, at most three of each other bug, as tiles
+// only have room for numbers up to 3, and at most as many tiles as Base+MLP,
+// so the hive fits on the grid.
+pub(super) fn valid_starting(starting: &[u8; 8]) -> bool {
+    starting[Bug::Queen as usize] == 1
+        && starting.iter().all(|&count| count <= 3)
+        && starting.iter().map(|&count| count as usize).sum::<usize>() <= 14
+}
+
+#[cfg(feature = "engine")]
+pub use minimax::Winner;
+
+// Same as minimax::Winner, for builds without the search engine.
+#[cfg(not(feature = "engine"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Winner {
+    PlayerJustMoved,
+    Draw,
+    PlayerToMove,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Color {
     Black = 1,
     White = 0,
@@ -57,12 +122,12 @@ impl Node {
 
     pub(crate) fn color(self) -> Color {
         // Color enum is densely packed in 1 bit.
-        unsafe { std::mem::transmute::<u8, Color>(self.0 >> 7) }
+        unsafe { core::mem::transmute::<u8, Color>(self.0 >> 7) }
     }
 
     pub(crate) fn bug(self) -> Bug {
         // Bug enum is densely packed in 3 bits.
-        unsafe { std::mem::transmute::<u8, Bug>((self.0 >> 4) & 7) }
+        unsafe { core::mem::transmute::<u8, Bug>((self.0 >> 4) & 7) }
     }
 
     pub(crate) fn bug_num(self) -> u8 {
@@ -109,6 +174,47 @@ impl UnderNode {
     }
 }
 
+// House rules that change which moves are legal, so variant communities can
+// play them without forking the engine. The default is the rules as published,
+// with the tournament opening.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Variant {
+    // The tournament rule against placing the queen on a player's first
+    // turn, which cuts down on draws.
+    pub tournament_opening: bool,
+    // Queens can't move until every tile in their player's hand is placed.
+    // Pillbugs may still throw them.
+    pub queen_moves_last: bool,
+    // The highest a stack may grow, counting the tile climbing on. With 1,
+    // nothing climbs. None leaves stacks limited only by the tiles.
+    pub max_stack_height: Option<u8>,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant { tournament_opening: true, queen_moves_last: false, max_stack_height: None }
+    }
+}
+
+// Late move pruning for alpha-beta searches, for Hive's branching factor: an
+// ant alone can have dozens of destinations. The minimax crate's search
+// doesn't tell move generation how much depth is left or what the bounds are,
+// so late moves can't be searched shallower as in late move reductions, or
+// skipped by their static value as in futility pruning. Instead, nodes deeper
+// than the first plies of a search only get every queen attack and defense
+// that move ordering ranks first, and then the other moves ending closest to
+// the enemy queen, up to the limit. Pruned nodes hash apart from full width
+// ones in the transposition table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Widening {
+    // Plies from the root searched at full width.
+    pub(crate) full_plies: u8,
+    // How many moves deeper nodes search.
+    pub(crate) moves: usize,
+}
+
 #[derive(Clone)]
 pub struct Board {
     // Indexed by Hex.
@@ -118,22 +224,53 @@ pub struct Board {
     underworld: [UnderNode; 8],
     underworld_size: usize,
     pub(crate) remaining: [[u8; 8]; 2],
-    pub(crate) queens: [Hex; 2],
+    // Where each queen is, on top or covered, or None until she's placed.
+    // Set by the queen's own tile going on and off the board, so it doesn't
+    // depend on whose turn it is or how the position was set up.
+    pub(crate) queens: [Option<Hex>; 2],
     pub(crate) occupied_hexes: [Vec<Hex>; 2],
+    // The same hexes as occupied_hexes, for whole-board bit operations.
+    occupied_sets: [HexSet; 2],
+    // Tiles that would split the hive if lifted. Kept up to date by apply
+    // and undo, so move generation and evaluation share one computation.
+    // None when tiles were changed by other means.
+    cut_vertexes: Option<HexSet>,
+    cut_vertex_history: Vec<Option<HexSet>>,
 
     pub(crate) turn_num: u16,
-    zobrist_table: &'static [u64; GRID_SIZE * 2],
+    zobrist_table: &'static [u64; ZOBRIST_KEYS],
     zobrist_hash: u64,
     zobrist_history: Vec<u64>,
+    // Like zobrist_hash, but keyed on hexes relative to the white queen, so
+    // that the same hive shifted across the grid hashes the same. Transposition
+    // tables can't use it, as they store moves with absolute hexes.
+    position_hash: u64,
+    // Also includes the last moved piece.
+    position_history: Vec<u64>,
+    // How many times a position must occur to draw the game.
+    draw_repetitions: u8,
+    pub(crate) variant: Variant,
+    // Index in position_history of the position a search started from. A
+    // position repeating one from there on is a draw, as whoever steered
+    // into the cycle can go around it again.
+    search_root: Option<usize>,
+    // Prunes searches from search_root past their first plies.
+    widening: Option<Widening>,
     // Board history.
     pub(super) turn_history: Vec<Turn>,
 
-    pub(super) game_type_bits: u8,
+    // Each player's hand at the start of the game.
+    pub(super) starting: [u8; 8],
+
+    // Test positions put down tile by tile, ignoring the hands and the
+    // number of queens, so only the tiles themselves can be validated.
+    #[cfg(test)]
+    fixture: bool,
 }
 
 impl Board {
     pub fn to_move(&self) -> Color {
-        if self.turn_num % 2 == 0 {
+        if self.turn_num.is_multiple_of(2) {
             Color::White
         } else {
             Color::Black
@@ -152,6 +289,44 @@ impl Board {
         hash.rotate_left(((height as u32) << 3) | bug as u32)
     }
 
+    // Counts in hand are keyed like heights, by realigning one key per bug.
+    // An empty hand hashes to nothing, so bugs outside the game don't count.
+    fn hand_zobrist(&self, color: Color, bug: Bug, count: u8) -> u64 {
+        if count == 0 {
+            return 0;
+        }
+        let hash = self.zobrist_table[HAND_KEYS + color as usize * NUM_BUGS + bug as usize];
+        hash.rotate_left((count as u32) << 3)
+    }
+
+    fn hand_hash(&self) -> u64 {
+        let mut hash = 0;
+        for color in [Color::White, Color::Black] {
+            for bug in Bug::iter_all() {
+                hash ^= self.hand_zobrist(color, bug, self.remaining[color as usize][bug as usize]);
+            }
+        }
+        hash
+    }
+
+    fn to_move_zobrist(&self) -> u64 {
+        self.zobrist_table[TO_MOVE_KEY]
+    }
+
+    fn take_from_hand(&mut self, color: Color, bug: Bug) {
+        let count = self.remaining[color as usize][bug as usize];
+        self.zobrist_hash ^=
+            self.hand_zobrist(color, bug, count) ^ self.hand_zobrist(color, bug, count - 1);
+        self.remaining[color as usize][bug as usize] = count - 1;
+    }
+
+    fn return_to_hand(&mut self, color: Color, bug: Bug) {
+        let count = self.remaining[color as usize][bug as usize];
+        self.zobrist_hash ^=
+            self.hand_zobrist(color, bug, count) ^ self.hand_zobrist(color, bug, count + 1);
+        self.remaining[color as usize][bug as usize] = count + 1;
+    }
+
     pub fn get_underworld(&self) -> &[UnderNode] {
         &self.underworld[0..self.underworld_size]
     }
@@ -165,6 +340,12 @@ impl Board {
         self.underworld_size += 1;
     }
 
+    // Whether another tile can't go on top of a stack.
+    #[cfg(feature = "engine")]
+    pub(crate) fn underworld_full(&self) -> bool {
+        self.underworld_size >= self.underworld.len()
+    }
+
     fn remove_underworld(&mut self, hex: Hex) -> Node {
         for i in (0..self.underworld_size).rev() {
             if self.underworld[i].hex == hex {
@@ -179,15 +360,18 @@ impl Board {
 
     fn occupied_add(&mut self, color: Color, hex: Hex) {
         self.occupied_hexes[color as usize].push(hex);
+        self.occupied_sets[color as usize].set(hex);
     }
 
     fn occupied_remove(&mut self, color: Color, hex: Hex) {
+        self.occupied_sets[color as usize].unset(hex);
         let vec = &mut self.occupied_hexes[color as usize];
         let i = vec.iter().position(|&x| x == hex).unwrap();
         vec.swap_remove(i);
     }
 
     fn insert(&mut self, hex: Hex, bug: Bug, bug_num: u8, color: Color) {
+        self.cut_vertexes = None;
         let prev = self.node(hex);
         if prev.occupied() {
             if prev.color() != color {
@@ -205,12 +389,14 @@ impl Board {
         self.zobrist_hash ^= self.zobrist(hex, bug, color, self.height(hex));
 
         if bug == Bug::Queen {
-            self.queens[color as usize] = hex;
+            self.queens[color as usize] = Some(hex);
         }
+        self.update_position_hash(hex, bug, color, self.height(hex));
     }
 
     // Asserts that there is something there.
     fn remove(&mut self, hex: Hex) -> (Bug, u8, Color) {
+        self.cut_vertexes = None;
         let height = self.height(hex);
         let prev = self.node(hex);
 
@@ -229,11 +415,116 @@ impl Board {
 
         self.zobrist_hash ^= self.zobrist(hex, bug, color, height);
         if bug == Bug::Queen {
-            self.queens[color as usize] = START_HEX;
+            self.queens[color as usize] = None;
         }
+        self.update_position_hash(hex, bug, color, height);
         (bug, prev.bug_num(), color)
     }
 
+    // Puts a tile from the hand on the board without taking a turn, to set up
+    // a position.
+    pub(super) fn set_up_tile(&mut self, hex: Hex, bug: Bug, bug_num: u8, color: Color) {
+        self.insert(hex, bug, bug_num, color);
+        self.take_from_hand(color, bug);
+    }
+
+    pub(super) fn set_up_turn(&mut self, turn_num: u16) {
+        if (self.turn_num ^ turn_num) & 1 == 1 {
+            self.zobrist_hash ^= self.to_move_zobrist();
+        }
+        self.turn_num = turn_num;
+    }
+
+    // A test position with only the given tiles, all of them put down. Each
+    // player has the same bugs.
+    #[cfg(test)]
+    pub(crate) fn from_tiles(tiles: &[(Loc, Bug, Color)], turn_num: u16) -> Board {
+        let mut starting = [0; 8];
+        for &(_, bug, color) in tiles {
+            if color == Color::White {
+                starting[bug as usize] += 1;
+            }
+        }
+        let mut board = Board::new(starting);
+        let mut numbers = [[0; 8]; 2];
+        for &(loc, bug, color) in tiles {
+            numbers[color as usize][bug as usize] += 1;
+            board.set_up_tile(loc_to_hex(loc), bug, numbers[color as usize][bug as usize], color);
+        }
+        board.set_up_turn(turn_num);
+        board
+    }
+
+    // Offset from the white queen, or from the start before it is placed.
+    fn relative(&self, hex: Hex) -> Hex {
+        hex.wrapping_sub(self.queens[Color::White as usize].unwrap_or(START_HEX)) & GRID_MASK
+    }
+
+    fn update_position_hash(&mut self, hex: Hex, bug: Bug, color: Color, height: u8) {
+        if bug == Bug::Queen && color == Color::White {
+            // Everything is relative to the white queen, so start over.
+            self.rehash_position();
+        } else {
+            self.position_hash ^= self.zobrist(self.relative(hex), bug, color, height);
+        }
+    }
+
+    fn rehash_position(&mut self) {
+        self.position_hash = self.tiles_position_hash();
+    }
+
+    fn tiles_position_hash(&self) -> u64 {
+        let mut hash = 0;
+        for &hex in self.occupied_hexes.iter().flatten() {
+            let node = self.node(hex);
+            hash ^= self.zobrist(self.relative(hex), node.bug(), node.color(), self.height(hex));
+        }
+        for under in self.get_underworld() {
+            let node = under.node;
+            hash ^= self.zobrist(self.relative(under.hex), node.bug(), node.color(), under.height);
+        }
+        hash
+    }
+
+    // The position, including the last moved piece, independent of where the
+    // hive sits on the grid. Repetitions are only checked between positions
+    // with the same player to move, so that is left out.
+    fn position_key(&self) -> u64 {
+        match self.turn_history.last() {
+            Some(Turn::Move(_, end)) => self.position_hash ^ self.relative(*end) as u64,
+            _ => self.position_hash,
+        }
+    }
+
+    // Hash of the position that is the same for all 12 rotations and
+    // reflections of the hive, as well as for translations. Computed from
+    // scratch, so it is too slow for search and meant for comparing openings.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut tiles = Vec::new();
+        for &hex in self.occupied_hexes.iter().flatten() {
+            tiles.push((hex, self.node(hex), self.height(hex)));
+        }
+        for under in self.get_underworld() {
+            tiles.push((under.hex, under.node, under.height));
+        }
+        let to_move = if self.to_move() == Color::Black { self.to_move_zobrist() } else { 0 };
+        let hand = self.hand_hash();
+        (0..12)
+            .map(|symmetry| {
+                let transform = |hex| transform_offset(self.relative(hex), symmetry);
+                let mut hash = to_move ^ hand;
+                for &(hex, node, height) in tiles.iter() {
+                    hash ^= self.zobrist(transform(hex), node.bug(), node.color(), height);
+                }
+                if let Some(Turn::Move(_, end)) = self.turn_history.last() {
+                    hash ^= transform(*end) as u64;
+                }
+                hash
+            })
+            .min()
+            .unwrap()
+    }
+
     fn underworld_height(&self, hex: Hex, node: Node) -> u8 {
         let height = node.clipped_height();
         if height > 2 {
@@ -248,7 +539,7 @@ impl Board {
         }
     }
 
-    fn height(&self, hex: Hex) -> u8 {
+    pub(crate) fn height(&self, hex: Hex) -> u8 {
         self.underworld_height(hex, self.node(hex))
     }
 
@@ -260,12 +551,22 @@ impl Board {
         &self.remaining[self.turn_num as usize & 1]
     }
 
+    #[cfg(feature = "engine")]
     pub(crate) fn get_opponent_remaining(&self) -> &[u8; 8] {
         &self.remaining[!self.turn_num as usize & 1]
     }
 
-    fn mut_remaining(&mut self) -> &mut [u8; 8] {
-        &mut self.remaining[self.turn_num as usize & 1]
+    // The number of the next tile of this bug the player to move places.
+    pub(crate) fn next_bug_num(&self, bug: Bug) -> u8 {
+        self.starting[bug as usize] - self.get_remaining()[bug as usize] + 1
+    }
+
+    // Whether tiles of this bug are numbered in their names: bugs with
+    // several tiles, and the base game's numbered bugs even when there's
+    // only one of them.
+    pub(crate) fn numbered(&self, bug: Bug) -> bool {
+        self.starting[bug as usize] > 1
+            || matches!(bug, Bug::Ant | Bug::Grasshopper | Bug::Beetle | Bug::Spider)
     }
 
     pub(crate) fn get_available_bugs(&self) -> [(Bug, u8); 8] {
@@ -282,38 +583,238 @@ impl Board {
         ]
     }
 
+    // Whether the player to move is on their fourth turn or later without
+    // the queen out, and so must place her now. Passes count as turns, so
+    // that's turn 6 for white and 7 for black however the turns went.
     pub(crate) fn queen_required(&self) -> bool {
         self.turn_num > 5 && self.get_remaining()[Bug::Queen as usize] > 0
     }
 
+    // Whether the tile is a queen the variant keeps in place until her
+    // player's hand is empty.
+    pub(crate) fn queen_held(&self, node: Node) -> bool {
+        self.variant.queen_moves_last
+            && node.bug() == Bug::Queen
+            && self.remaining[node.color() as usize].iter().any(|&num| num > 0)
+    }
+
+    // How many of each queen's neighbors are filled. A queen under a beetle
+    // still counts the hexes around her stack, and one still in hand has none.
     pub(crate) fn queens_surrounded(&self) -> [usize; 2] {
+        let occupied = self.occupied_sets[0].union(&self.occupied_sets[1]);
         let mut out = [0; 2];
         for (i, entry) in out.iter_mut().enumerate() {
-            *entry = adjacent(self.queens[i]).iter().filter(|adj| self.occupied(**adj)).count();
+            *entry = self.queens[i].map_or(0, |queen| {
+                adjacent(queen).iter().filter(|adj| occupied.get(**adj)).count()
+            });
         }
         out
     }
 
-    pub(super) fn new(remaining: [u8; 8]) -> Self {
-        let mut game_type_bits = 0u8;
-        for (i, &remain) in remaining.iter().enumerate() {
-            if remain > 0 {
-                game_type_bits |= 1 << i;
+    // Whether the game can only be drawn from here, whatever either player
+    // does: the game has too few tiles to surround a queen, or neither player
+    // can place or move anything, so the position never changes again. It
+    // doesn't look ahead, so tiles still shuffling around walled-in queens
+    // aren't caught, but it gives up at the first tile that can go anywhere.
+    pub fn dead_drawn(&self) -> bool {
+        // Six tiles around one queen, besides herself.
+        if self.starting.iter().map(|&num| 2 * num as usize).sum::<usize>() < 7 {
+            return true;
+        }
+        if self.turn_num < 2 {
+            return false;
+        }
+        let occupied = self.occupied_sets[0].union(&self.occupied_sets[1]);
+        let mut turns = Vec::new();
+        for color in [Color::White, Color::Black] {
+            let remaining = &self.remaining[color as usize];
+            if remaining.iter().any(|&num| num > 0) {
+                let friends = &self.occupied_sets[color as usize];
+                let enemies = &self.occupied_sets[color.other()];
+                let hexes =
+                    friends.adjacent().difference(&enemies.adjacent()).difference(&occupied);
+                if hexes.iter().next().is_some() {
+                    return false;
+                }
+            }
+            if remaining[Bug::Queen as usize] > 0 {
+                continue;
+            }
+            // Evaluation asks at every leaf, so first look for a free tile
+            // that can slide a step, which is all its moves take.
+            let cuts = self.find_cut_vertexes();
+            let mut buf = [0; 6];
+            let slider = self.occupied_hexes[color as usize].iter().any(|&hex| {
+                let node = self.node(hex);
+                matches!(node.bug(), Bug::Queen | Bug::Ant | Bug::Pillbug)
+                    && !node.is_stacked()
+                    && !cuts.get(hex)
+                    && !self.queen_held(node)
+                    && self.slidable_adjacent(&mut buf, hex, hex).next().is_some()
+            });
+            if slider {
+                return false;
+            }
+            // A stunned tile only sits out one turn, so it counts as free.
+            self.generate_color_movements(color, None, &mut turns);
+            if !turns.is_empty() {
+                return false;
             }
         }
-        Board {
+        true
+    }
+
+    pub(super) fn new(remaining: [u8; 8]) -> Self {
+        let mut board = Board {
             nodes: [Node::empty(); GRID_SIZE],
             underworld: [UnderNode::empty(); 8],
             underworld_size: 0,
             remaining: [remaining; 2],
-            queens: [START_HEX; 2],
+            queens: [None; 2],
             occupied_hexes: [Vec::new(), Vec::new()],
+            occupied_sets: [HexSet::new(); 2],
+            cut_vertexes: None,
+            cut_vertex_history: Vec::new(),
             turn_num: 0,
-            zobrist_table: ZOBRIST_TABLE.borrow(),
+            zobrist_table: &ZOBRIST_TABLE,
             zobrist_hash: 0,
             zobrist_history: Vec::new(),
+            position_hash: 0,
+            position_history: Vec::new(),
+            draw_repetitions: 3,
+            variant: Variant::default(),
+            search_root: None,
+            widening: None,
             turn_history: Vec::new(),
-            game_type_bits,
+            starting: remaining,
+            #[cfg(test)]
+            fixture: false,
+        };
+        board.zobrist_hash = board.hand_hash();
+        board
+    }
+
+    // Tournament rules draw on the third occurrence of a position. Searches
+    // may prefer to call it a draw on the second, to see draws sooner.
+    pub fn set_draw_repetitions(&mut self, count: u8) {
+        self.draw_repetitions = count.max(1);
+    }
+
+    // On by default, as in UHP. Without it, the queen may go down first.
+    pub fn set_tournament_opening(&mut self, on: bool) {
+        self.variant.tournament_opening = on;
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    // Changes the house rules. Set them before the game starts, as moves
+    // already played aren't checked again.
+    pub fn set_variant(&mut self, mut variant: Variant) {
+        variant.max_stack_height = variant.max_stack_height.map(|max| max.max(1));
+        self.variant = variant;
+    }
+
+    // A copy to search from, which scores lines that come back to a position
+    // already on the search path as draws. Repeats of positions from before
+    // the search still need draw_repetitions occurrences.
+    //
+    // These draws depend on the path, so the search keys the path into its
+    // hashes (see path_key), and a transposition reached without a repeat
+    // can't reuse scores that counted on one.
+    #[cfg(feature = "engine")]
+    pub(crate) fn for_search(&self) -> Board {
+        let mut board = self.clone();
+        board.search_root = Some(self.position_history.len().saturating_sub(1));
+        board
+    }
+
+    // Like for_search, with late move pruning.
+    #[cfg(feature = "engine")]
+    pub(crate) fn for_search_with(&self, widening: Option<Widening>) -> Board {
+        let mut board = self.for_search();
+        board.widening = widening;
+        board
+    }
+
+    // The late move pruning to apply here, once a search is past the plies it
+    // searches at full width.
+    #[cfg(feature = "engine")]
+    fn widened(&self) -> Option<Widening> {
+        let (widening, root) = (self.widening?, self.search_root?);
+        let ply = self.position_history.len().saturating_sub(root + 1);
+        (ply >= widening.full_plies as usize).then_some(widening)
+    }
+
+    // The positions on the search path that a later position could still
+    // repeat: those from the search root on, back to the last placement.
+    // Movement phase transpositions reached by different lines stop sharing
+    // table entries, as their draws by repetition can differ.
+    #[cfg(feature = "engine")]
+    fn path_key(&self) -> u64 {
+        let Some(root) = self.search_root else {
+            return 0;
+        };
+        let mut key = 0;
+        let mut i = self.position_history.len().saturating_sub(1);
+        while i > root && !matches!(self.turn_history[i], Turn::Place(..)) {
+            i -= 1;
+            // Mixed, so the position hashes can't cancel out the board's.
+            key ^= self.position_history[i].wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(29);
+        }
+        key
+    }
+
+    // A copy for playing forward from, as in MCTS rollouts, with only the
+    // history that rules and evaluation still look at: back to the last
+    // placement, as nothing before it can repeat, and at least the last four
+    // turns. Undo works as far back as that history, and game strings only
+    // cover it.
+    pub fn snapshot(&self) -> Board {
+        let mut board = Board::new([0; 8]);
+        board.restore(self);
+        board
+    }
+
+    // Makes this board a snapshot of another, reusing its allocations, so
+    // that a board kept for the purpose can be reset without allocating.
+    pub fn restore(&mut self, from: &Board) {
+        let len = from.turn_history.len();
+        let placed = from.turn_history.iter().rposition(|turn| matches!(turn, Turn::Place(..)));
+        let start = placed.unwrap_or(0).min(len.saturating_sub(4));
+        let keep = len - start;
+        fn tail<T: Clone>(to: &mut Vec<T>, from: &[T], keep: usize) {
+            to.clear();
+            to.extend_from_slice(&from[from.len().saturating_sub(keep)..]);
+        }
+
+        self.nodes = from.nodes;
+        self.underworld = from.underworld;
+        self.underworld_size = from.underworld_size;
+        self.remaining = from.remaining;
+        self.queens = from.queens;
+        for color in 0..2 {
+            self.occupied_hexes[color].clone_from(&from.occupied_hexes[color]);
+        }
+        self.occupied_sets = from.occupied_sets;
+        self.cut_vertexes = from.cut_vertexes;
+        tail(&mut self.cut_vertex_history, &from.cut_vertex_history, keep);
+        self.turn_num = from.turn_num;
+        self.zobrist_table = from.zobrist_table;
+        self.zobrist_hash = from.zobrist_hash;
+        tail(&mut self.zobrist_history, &from.zobrist_history, keep);
+        self.position_hash = from.position_hash;
+        tail(&mut self.position_history, &from.position_history, keep);
+        self.draw_repetitions = from.draw_repetitions;
+        self.variant = from.variant;
+        self.search_root = from.search_root.map(|root| root.saturating_sub(start));
+        self.widening = from.widening;
+        tail(&mut self.turn_history, &from.turn_history, keep);
+        self.starting = from.starting;
+        #[cfg(test)]
+        {
+            self.fixture = from.fixture;
         }
     }
 
@@ -332,7 +833,135 @@ impl Default for Board {
     }
 }
 
+// Serialized form of a Board. Only the tiles are stored, rather than the whole
+// grid, and the hashes are rebuilt from them. The histories are kept so that
+// the restored board can still undo moves and detect repetitions.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BoardState {
+    // Which bugs are in the game, for states saved before starting.
+    game_type_bits: u8,
+    #[serde(default)]
+    starting: Option<[u8; 8]>,
+    remaining: [[u8; 8]; 2],
+    // (hex, color, bug, bug number) of every tile, with stacks from the bottom up.
+    tiles: Vec<(Hex, Color, Bug, u8)>,
+    turn_num: u16,
+    turn_history: Vec<Turn>,
+    zobrist_history: Vec<u64>,
+    position_history: Vec<u64>,
+    draw_repetitions: u8,
+    #[serde(default = "tournament_opening_default")]
+    tournament_opening: bool,
+    // The rest of the variant, for states saved with one.
+    #[serde(default)]
+    variant: Option<Variant>,
+}
+
+#[cfg(feature = "serde")]
+fn tournament_opening_default() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Board {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tiles = Vec::new();
+        let mut push = |hex: Hex, node: Node| {
+            tiles.push((hex, node.color(), node.bug(), node.bug_num()));
+        };
+        for under in self.get_underworld() {
+            push(under.hex, under.node);
+        }
+        for &hex in self.occupied_hexes.iter().flatten() {
+            push(hex, self.node(hex));
+        }
+        let mut game_type_bits = 0;
+        for (bug, &count) in self.starting.iter().enumerate() {
+            if count > 0 {
+                game_type_bits |= 1 << bug;
+            }
+        }
+        BoardState {
+            game_type_bits,
+            starting: Some(self.starting),
+            remaining: self.remaining,
+            tiles,
+            turn_num: self.turn_num,
+            turn_history: self.turn_history.clone(),
+            zobrist_history: self.zobrist_history.clone(),
+            position_history: self.position_history.clone(),
+            draw_repetitions: self.draw_repetitions,
+            tournament_opening: self.variant.tournament_opening,
+            variant: Some(self.variant),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Board {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let state = BoardState::deserialize(deserializer)?;
+        let mut board = Board::new([0; 8]);
+        board.starting = state.starting.unwrap_or_else(|| {
+            let mut starting = *Bug::initial_quantity();
+            for (bug, count) in starting.iter_mut().enumerate() {
+                if state.game_type_bits & 1 << bug == 0 {
+                    *count = 0;
+                }
+            }
+            starting
+        });
+        if !valid_starting(&board.starting) {
+            return Err(D::Error::custom("invalid piece set"));
+        }
+        board.remaining = state.remaining;
+        board.zobrist_hash = board.hand_hash();
+        for (hex, color, bug, bug_num) in state.tiles {
+            if hex as usize >= GRID_SIZE || !(1..=3).contains(&bug_num) {
+                return Err(D::Error::custom("invalid tile"));
+            }
+            if board.occupied(hex) && board.underworld_size == board.underworld.len() {
+                return Err(D::Error::custom("too many stacked tiles"));
+            }
+            board.insert(hex, bug, bug_num, color);
+        }
+
+        board.turn_num = state.turn_num;
+        if board.turn_num & 1 == 1 {
+            board.zobrist_hash ^= board.to_move_zobrist();
+        }
+        if let Some(Turn::Move(_, end)) = state.turn_history.last() {
+            board.zobrist_hash ^= *end as u64;
+        }
+        board.turn_history = state.turn_history;
+        board.set_draw_repetitions(state.draw_repetitions);
+        board.variant = state.variant.unwrap_or(Variant {
+            tournament_opening: state.tournament_opening,
+            ..Variant::default()
+        });
+
+        // The rebuilt hashes must agree with the end of the histories.
+        let len = board.turn_history.len();
+        if state.zobrist_history.len() != len
+            || state.position_history.len() != len
+            || state.zobrist_history.last().is_some_and(|&hash| hash != board.zobrist_hash)
+            || state.position_history.last().is_some_and(|&key| key != board.position_key())
+        {
+            return Err(D::Error::custom("history does not match the tiles"));
+        }
+        board.zobrist_history = state.zobrist_history;
+        board.position_history = state.position_history;
+        // Hands that don't add up with the tiles would overflow on placing.
+        board.validate().map_err(D::Error::custom)?;
+        Ok(board)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Turn {
     Place(Hex, Bug),
     Move(Hex, Hex),
@@ -342,35 +971,53 @@ pub enum Turn {
 
 impl Board {
     pub fn apply(&mut self, turn: Turn) {
+        let cuts = self.cut_vertexes;
+        self.cut_vertex_history.push(cuts);
         match turn {
             Turn::Place(hex, bug) => {
-                let bug_num =
-                    Bug::initial_quantity()[bug as usize] - self.get_remaining()[bug as usize] + 1;
+                let bug_num = self.next_bug_num(bug);
                 self.insert(hex, bug, bug_num, self.to_move());
-                self.mut_remaining()[bug as usize] -= 1;
+                self.take_from_hand(self.to_move(), bug);
+                self.cut_vertexes = cuts.and_then(|cuts| self.add_leaf_cut_vertexes(cuts, hex));
             }
             Turn::Move(start, end) => {
+                let vacated = self.height(start) == 1;
+                let filled = !self.occupied(end);
                 let (bug, bug_num, color) = self.remove(start);
                 self.insert(end, bug, bug_num, color);
                 // Encode a marker of last moved location for pillbug throwability.
                 self.zobrist_hash ^= end as u64;
+                self.cut_vertexes = match (vacated, filled) {
+                    // Moving along the top of the hive doesn't change its shape.
+                    (false, false) => cuts,
+                    (false, true) => cuts.and_then(|cuts| self.add_leaf_cut_vertexes(cuts, end)),
+                    (true, _) => None,
+                };
             }
-            _ => {}
+            Turn::Pass => self.cut_vertexes = cuts,
         };
+        if self.cut_vertexes.is_none() {
+            self.cut_vertexes = Some(self.compute_cut_vertexes());
+        }
         self.turn_num += 1;
         // Encode positions differently based on who is to move.
-        self.zobrist_hash ^= 0xa6c11b626b105b7c;
+        self.zobrist_hash ^= self.to_move_zobrist();
         // Undo last-moved zobrist bits.
         if let Some(Turn::Move(_, end)) = self.turn_history.last() {
             self.zobrist_hash ^= *end as u64;
         }
         self.zobrist_history.push(self.zobrist_hash);
         self.turn_history.push(turn);
+        self.position_history.push(self.position_key());
+
+        #[cfg(debug_assertions)]
+        self.assert_valid();
     }
 
     pub fn undo(&mut self, turn: Turn) {
         self.turn_num -= 1;
         self.zobrist_history.pop();
+        self.position_history.pop();
         self.turn_history.pop();
         if let Some(Turn::Move(_, end)) = self.turn_history.last() {
             self.zobrist_hash ^= *end as u64;
@@ -378,7 +1025,7 @@ impl Board {
         match turn {
             Turn::Place(hex, bug) => {
                 self.remove(hex);
-                self.mut_remaining()[bug as usize] += 1;
+                self.return_to_hand(self.to_move(), bug);
             }
             Turn::Move(start, end) => {
                 let (bug, bug_num, color) = self.remove(end);
@@ -387,37 +1034,161 @@ impl Board {
             }
             Turn::Pass => {}
         }
+        // Boards deserialized mid-game have no history to restore.
+        self.cut_vertexes = self.cut_vertex_history.pop().flatten();
         // Encode positions differently based on who is to move.
-        self.zobrist_hash ^= 0xa6c11b626b105b7c;
+        self.zobrist_hash ^= self.to_move_zobrist();
+        #[cfg(debug_assertions)]
+        self.assert_valid();
+    }
+
+    #[cfg(debug_assertions)]
+    fn assert_valid(&self) {
+        #[cfg(test)]
+        let result = if self.fixture { self.validate_tiles() } else { self.validate() };
+        #[cfg(not(test))]
+        let result = self.validate();
+        if let Err(err) = result {
+            panic!("{} after {:?}", err, self.turn_history);
+        }
     }
-}
 
-impl Board {
-    fn generate_placements(&self, turns: &mut Vec<Turn>) {
-        let mut enemy_adjacent = HexSet::new();
-        for &enemy in self.occupied_hexes[self.to_move().other()].iter() {
-            for adj in adjacent(enemy) {
-                enemy_adjacent.set(adj);
+    // Checks the redundant parts of the board against each other: the
+    // occupied lists against the grid, the underworld against the stacks,
+    // the hashes and cut vertexes against recomputing them from scratch, the
+    // queens against where they are, and the hands against the tiles out.
+    // Debug builds check after every apply and undo, and deserialized boards
+    // are checked in every build.
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        self.validate_tiles()?;
+        let mut tile_counts = [[0u8; 8]; 2];
+        let mut queens = [None; 2];
+        for (hex, node, _) in self.all_tiles() {
+            tile_counts[node.color() as usize][node.bug() as usize] += 1;
+            if node.bug() == Bug::Queen {
+                queens[node.color() as usize] = Some(hex);
+            }
+        }
+        for color in 0..2 {
+            if self.queens[color] != queens[color] {
+                return Err(format!("queen of color {} is not at {:?}", color, self.queens[color]));
+            }
+            for bug in Bug::iter_all() {
+                let initial = self.starting[bug as usize];
+                let out = tile_counts[color][bug as usize] + self.remaining[color][bug as usize];
+                if out != initial {
+                    let name = bug.name();
+                    return Err(format!("color {} has {} of {} {}s", color, out, initial, name));
+                }
             }
         }
+        Ok(())
+    }
 
-        let mut visited = HexSet::new();
-        for &friend in self.occupied_hexes[self.to_move() as usize].iter() {
-            for hex in adjacent(friend) {
-                if visited.get(hex) {
-                    continue;
+    // Everything validate checks but the queens and hands, which test
+    // positions don't keep.
+    fn validate_tiles(&self) -> Result<(), String> {
+        for (color, hexes) in self.occupied_hexes.iter().enumerate() {
+            for (i, &hex) in hexes.iter().enumerate() {
+                let node = self.node(hex);
+                if !node.occupied() || node.color() as usize != color {
+                    return Err(format!("occupied hex {} has no tile of color {}", hex, color));
                 }
-                visited.set(hex);
-                if self.occupied(hex) || enemy_adjacent.get(hex) {
+                if hexes[..i].contains(&hex) || !self.occupied_sets[color].get(hex) {
+                    return Err(format!("hex {} is listed wrong", hex));
+                }
+            }
+            if self.occupied_sets[color].iter().count() != hexes.len() {
+                return Err(format!("occupied set of color {} has extra hexes", color));
+            }
+        }
+        let tiles = self.occupied_hexes.iter().map(Vec::len).sum::<usize>();
+        if self.nodes.iter().filter(|node| node.occupied()).count() != tiles {
+            return Err("tiles are missing from the occupied lists".into());
+        }
+        for &hex in self.occupied_hexes.iter().flatten() {
+            if adjacent(hex).iter().any(|&adj| !adjacent(adj).contains(&hex)) {
+                return Err(format!("hex {} is not adjacent to its neighbors", hex));
+            }
+        }
+
+        // Each stack's covered tiles are numbered from 1 up, under its top.
+        let underworld = self.get_underworld();
+        for (i, under) in underworld.iter().enumerate() {
+            let below = underworld[..i].iter().filter(|other| other.hex == under.hex).count();
+            if under.height as usize != below + 1
+                || under.node.clipped_height() != min(under.height, 3)
+            {
+                return Err(format!("tile under hex {} has height {}", under.hex, under.height));
+            }
+            if !self.occupied(under.hex) || self.height(under.hex) <= under.height {
+                return Err(format!("tile under hex {} is not covered", under.hex));
+            }
+        }
+        for &hex in self.occupied_hexes.iter().flatten() {
+            let covered = underworld.iter().filter(|under| under.hex == hex).count();
+            if self.height(hex) as usize != covered + 1 {
+                return Err(format!("stack at hex {} has the wrong height", hex));
+            }
+        }
+
+        let mut zobrist_hash = self.hand_hash();
+        for (hex, node, height) in self.all_tiles() {
+            zobrist_hash ^= self.zobrist(hex, node.bug(), node.color(), height);
+        }
+        if self.turn_num & 1 == 1 {
+            zobrist_hash ^= self.to_move_zobrist();
+        }
+        if let Some(Turn::Move(_, end)) = self.turn_history.last() {
+            zobrist_hash ^= *end as u64;
+        }
+        if zobrist_hash != self.zobrist_hash {
+            return Err("zobrist hash does not match the tiles".into());
+        }
+        if self.tiles_position_hash() != self.position_hash {
+            return Err("position hash does not match the tiles".into());
+        }
+
+        // Only a connected hive has its cut vertexes computed from scratch.
+        if let Some(cuts) = self.cut_vertexes.filter(|_| self.is_connected()) {
+            if cuts != self.compute_cut_vertexes() {
+                return Err("cut vertexes are out of date".into());
+            }
+        }
+        Ok(())
+    }
+
+    // Every tile, covered or not, with its hex and height.
+    fn all_tiles(&self) -> impl Iterator<Item = (Hex, Node, u8)> + '_ {
+        let tiles = self
+            .occupied_hexes
+            .iter()
+            .flatten()
+            .map(|&hex| (hex, self.node(hex), self.height(hex)));
+        let covered =
+            self.get_underworld().iter().map(|under| (under.hex, under.node, under.height));
+        tiles.chain(covered)
+    }
+}
+
+impl Board {
+    fn generate_placements(&self, turns: &mut Vec<Turn>) {
+        let friends = &self.occupied_sets[self.to_move() as usize];
+        let enemies = &self.occupied_sets[self.to_move().other()];
+        // Empty hexes next to a friend and not next to an enemy.
+        let hexes = friends
+            .adjacent()
+            .difference(&enemies.adjacent())
+            .difference(friends)
+            .difference(enemies);
+        for hex in hexes.iter() {
+            for (bug, num_left) in self.get_available_bugs().iter() {
+                if self.queen_required() && *bug != Bug::Queen {
                     continue;
                 }
-                for (bug, num_left) in self.get_available_bugs().iter() {
-                    if self.queen_required() && *bug != Bug::Queen {
-                        continue;
-                    }
-                    if *num_left > 0 {
-                        turns.push(Turn::Place(hex, *bug));
-                    }
+                if *num_left > 0 {
+                    turns.push(Turn::Place(hex, *bug));
                 }
             }
         }
@@ -427,12 +1198,32 @@ impl Board {
     // Algorithm explanation: https://web.archive.org/web/20180830110222/https://www.eecs.wsu.edu/~holder/courses/CptS223/spr08/slides/graphapps.pdf
     // Example code: https://cp-algorithms.com/graph/cutpoints.html
     //
-    // TODO: cache movability for each tile, and somehow iteratively update it
-    // Need to persist the DFS tree from an arbitrary root.
-    // DFS iteration order is important.
-    // Perhaps you can find the first-explored neighbor and restart the DFS search at that point?
-    // This would be very fast for repeated insertion/removal of leaf nodes (Placement heavy parts of search).
+    // Boards maintain this incrementally through apply and undo; see
+    // add_leaf_cut_vertexes. Tiles lifted off the ground still recompute
+    // from scratch, as that can create new cut vertexes anywhere.
     pub(crate) fn find_cut_vertexes(&self) -> HexSet {
+        self.cut_vertexes.unwrap_or_else(|| self.compute_cut_vertexes())
+    }
+
+    // A new tile on the ground touching just one other tile makes that tile a
+    // cut vertex, unless it was alone, and leaves the rest of the hive as it
+    // was. Returns None if the new tile touches more, which can join up
+    // parts of the hive and free tiles anywhere.
+    fn add_leaf_cut_vertexes(&self, mut cuts: HexSet, hex: Hex) -> Option<HexSet> {
+        let mut neighbors = adjacent(hex).into_iter().filter(|&adj| self.occupied(adj));
+        match (neighbors.next(), neighbors.next()) {
+            (None, _) => {}
+            (Some(adj), None) => {
+                if adjacent(adj).iter().any(|&n| n != hex && self.occupied(n)) {
+                    cuts.set(adj);
+                }
+            }
+            (Some(_), Some(_)) => return None,
+        }
+        Some(cuts)
+    }
+
+    fn compute_cut_vertexes(&self) -> HexSet {
         struct State<'a> {
             board: &'a Board,
             visited: HexSet,
@@ -481,8 +1272,11 @@ impl Board {
             }
         }
 
-        let start = self.queens[0]; // Some occupied node.
-        dfs(&mut state, start, 0, true);
+        // Some occupied node: the white queen, or any tile before she's placed.
+        let start = self.queens[0].or_else(|| self.occupied_hexes.iter().flatten().next().copied());
+        if let Some(start) = start {
+            dfs(&mut state, start, 0, true);
+        }
         state.immovable
     }
 
@@ -545,6 +1339,9 @@ impl Board {
                 // Piles on both sides are too high and we cannot pass through.
                 continue;
             }
+            if self.variant.max_stack_height.is_some_and(|max| heights[i] >= max) {
+                continue;
+            }
             out[n] = neighbors[i];
             n += 1;
         }
@@ -661,7 +1458,7 @@ impl Board {
         }
     }
 
-    fn generate_throws(&self, immovable: &HexSet, hex: Hex, turns: &mut Vec<Turn>) -> bool {
+    fn generate_throws(&self, immovable: &HexSet, hex: Hex, turns: &mut Vec<Turn>) {
         let mut starts = [0; 6];
         let mut num_starts = 0;
         let mut ends = [0; 6];
@@ -674,11 +1471,9 @@ impl Board {
                     ends[num_ends] = adj;
                     num_ends += 1;
                 }
-                1 => {
-                    if !immovable.get(adj) {
-                        starts[num_starts] = adj;
-                        num_starts += 1;
-                    }
+                1 if !immovable.get(adj) => {
+                    starts[num_starts] = adj;
+                    num_starts += 1;
                 }
                 _ => {}
             }
@@ -688,7 +1483,6 @@ impl Board {
                 turns.push(Turn::Move(start, end));
             }
         }
-        num_starts > 0 && num_ends > 0
     }
 
     fn generate_mosquito(&self, hex: Hex, turns: &mut Vec<Turn>) {
@@ -739,24 +1533,55 @@ impl Board {
         }
     }
 
+    // Moves of the tile at hex by itself, for either color, assuming it is
+    // free to move. Leaves out throws by pillbugs.
+    pub(crate) fn generate_piece_movements(&self, hex: Hex, turns: &mut Vec<Turn>) {
+        let node = self.node(hex);
+        if node.is_stacked() {
+            self.generate_stack_walking(hex, turns);
+            return;
+        }
+        match node.bug() {
+            Bug::Queen => self.generate_walk1(hex, turns),
+            Bug::Grasshopper => self.generate_jumps(hex, turns),
+            Bug::Spider => self.generate_walk3(hex, turns),
+            Bug::Ant => self.generate_walk_all(hex, turns),
+            Bug::Beetle => {
+                self.generate_walk1(hex, turns);
+                self.generate_stack_walking(hex, turns);
+            }
+            Bug::Mosquito => self.generate_mosquito(hex, turns),
+            Bug::Ladybug => self.generate_ladybug(hex, turns),
+            Bug::Pillbug => self.generate_walk1(hex, turns),
+        }
+    }
+
     pub(crate) fn generate_movements(&self, turns: &mut Vec<Turn>) {
-        let mut immovable = self.find_cut_vertexes();
         let stunned = match self.turn_history.last() {
-            Some(Turn::Move(_, dest)) => Some(dest),
+            Some(Turn::Move(_, dest)) => Some(*dest),
             _ => None,
         };
+        self.generate_color_movements(self.to_move(), stunned, turns);
+    }
+
+    // Movements of one color's tiles, and the tiles its pillbugs can throw,
+    // whichever player is to move. Leaves the stunned tile, the one moved on
+    // the last turn, where it is.
+    fn generate_color_movements(&self, color: Color, stunned: Option<Hex>, turns: &mut Vec<Turn>) {
+        let mut immovable = self.find_cut_vertexes();
         if let Some(moved) = stunned {
             // Can't move pieces that were moved on the opponent's turn.
-            immovable.set(*moved);
+            immovable.set(moved);
         }
 
-        let mut dedup = false;
-        for &hex in self.occupied_hexes[self.to_move() as usize].iter() {
+        let first = turns.len();
+        let mut throws = Vec::new();
+        for &hex in self.occupied_hexes[color as usize].iter() {
             let node = self.node(hex);
             if node.is_stacked() {
-                self.generate_stack_walking(hex, turns);
                 // Don't let mosquito on stack use pillbug ability.
                 // Although the rules don't seem to specify either way.
+                self.generate_piece_movements(hex, turns);
                 continue;
             }
             // Check for throw ability before movability, as pinned pillbugs can still throw.
@@ -767,50 +1592,106 @@ impl Board {
                         n.occupied() && n.bug() == Bug::Pillbug
                     }));
             // However pillbugs just thrown cannot throw.
-            if pillbug_powers && stunned != Some(&hex) {
-                dedup |= self.generate_throws(&immovable, hex, turns);
+            if pillbug_powers && stunned != Some(hex) {
+                self.generate_throws(&immovable, hex, &mut throws);
             }
-            if immovable.get(hex) {
+            if immovable.get(hex) || self.queen_held(node) {
                 continue;
             }
-            match node.bug() {
-                Bug::Queen => self.generate_walk1(hex, turns),
-                Bug::Grasshopper => self.generate_jumps(hex, turns),
-                Bug::Spider => self.generate_walk3(hex, turns),
-                Bug::Ant => self.generate_walk_all(hex, turns),
-                Bug::Beetle => {
-                    self.generate_walk1(hex, turns);
-                    self.generate_stack_walking(hex, turns);
+            self.generate_piece_movements(hex, turns);
+        }
+
+        if !throws.is_empty() {
+            self.add_throws(first, &mut throws, turns);
+        }
+    }
+
+    // Appends the throws not already among the turns from first on. A pillbug
+    // and a mosquito can throw the same tile to the same hex, and a tile next
+    // to a pillbug can often walk where it could be thrown. Only the few
+    // thrown tiles' moves are compared, found with a bitset of their hexes.
+    fn add_throws(&self, first: usize, throws: &mut Vec<Turn>, turns: &mut Vec<Turn>) {
+        throws.sort_unstable();
+        throws.dedup();
+        let mut thrown = HexSet::new();
+        for &turn in throws.iter() {
+            if let Turn::Move(start, _) = turn {
+                thrown.set(start);
+            }
+        }
+        for &turn in turns[first..].iter() {
+            if let Turn::Move(start, _) = turn {
+                if thrown.get(start) {
+                    if let Ok(i) = throws.binary_search(&turn) {
+                        throws.remove(i);
+                    }
                 }
-                Bug::Mosquito => self.generate_mosquito(hex, turns),
-                Bug::Ladybug => self.generate_ladybug(hex, turns),
-                Bug::Pillbug => self.generate_walk1(hex, turns),
             }
         }
+        turns.append(throws);
+    }
+}
 
-        if dedup {
-            // Mosquitos and pillbugs can create duplicate turns, so sort and dedup.
-            // TODO: Maybe only enable this for perft?
-            // Dups get resolved quickly with the transposition table and this dedup is very slow.
-            turns.sort_unstable();
-            turns.dedup();
+// The enemy tiles that could fill a hex next to a queen on their next turn,
+// for overlays showing where she is in danger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Threat {
+    pub queen: Color,
+    // Next to the queen, and may already be occupied, for climbing bugs.
+    pub hex: Hex,
+    // Where the tiles that could get there are. Besides the enemy's own
+    // tiles, that includes any tile an enemy pillbug could throw there.
+    pub attackers: Vec<Hex>,
+}
+
+impl Board {
+    // Each hex around each placed queen, and which tiles the enemy could put
+    // there in one move. The player not to move is assumed to move next from
+    // this position, so their tiles aren't stunned.
+    pub fn threat_map(&self) -> Vec<Threat> {
+        let mut threats = Vec::new();
+        let mut turns = Vec::new();
+        for queen in [Color::White, Color::Black] {
+            let Some(queen_hex) = self.queens[queen as usize] else {
+                continue;
+            };
+            let enemy = if queen == Color::White { Color::Black } else { Color::White };
+            turns.clear();
+            // Tiles only move once their own queen is out.
+            if self.queens[enemy as usize].is_some() {
+                let stunned = match self.turn_history.last() {
+                    Some(Turn::Move(_, dest)) if enemy == self.to_move() => Some(*dest),
+                    _ => None,
+                };
+                self.generate_color_movements(enemy, stunned, &mut turns);
+            }
+            for hex in adjacent(queen_hex) {
+                let mut attackers = turns
+                    .iter()
+                    .filter_map(|&turn| match turn {
+                        // A pillbug can throw the queen herself around.
+                        Turn::Move(start, end) if end == hex && start != queen_hex => Some(start),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                attackers.sort_unstable();
+                attackers.dedup();
+                threats.push(Threat { queen, hex, attackers });
+            }
         }
+        threats
     }
 }
 
 pub struct Rules;
 
-impl minimax::Game for Rules {
-    type S = Board;
-    type M = Turn;
-
-    fn generate_moves(board: &Board, turns: &mut Vec<Turn>) {
+// The rules are usable without the search engine; minimax::Game delegates here.
+impl Rules {
+    pub fn generate_moves(board: &Board, turns: &mut Vec<Turn>) {
         if board.turn_num < 2 {
             // Special case for the first 2 turns:
             for (bug, num_left) in board.get_available_bugs().iter() {
-                if *bug == Bug::Queen {
-                    // To reduce draws, implement tournament rule where
-                    // you can't place your queen first.
+                if *bug == Bug::Queen && board.variant.tournament_opening {
                     continue;
                 }
                 if *num_left > 0 {
@@ -842,35 +1723,135 @@ impl minimax::Game for Rules {
         }
     }
 
-    fn get_winner(board: &Board) -> Option<minimax::Winner> {
-        let queens_surrounded = board.queens_surrounded();
-        let n = board.zobrist_history.len();
-        if n > 10 {
-            // Check for position repeat stalemate.
-            // More than 32 turns ago, we're not going to bother looking.
-            // Check every 4 turns as both players need to move and move back to repeat.
-            // Last turn is at zobrist_history[n-1], so offset by 1.
-            let start = if n < 35 { (n - 1) % 4 } else { n - 33 };
-            let recent_past = &board.zobrist_history[start..n];
-            let position_repeat_count =
-                recent_past.iter().step_by(4).filter(|&&hash| hash == board.zobrist_hash).count();
-            if position_repeat_count >= 2 {
-                // Draw by stalemate.
-                return Some(minimax::Winner::Draw);
+    pub fn get_winner(board: &Board) -> Option<Winner> {
+        // Only a placed queen can be lost, whether or not she is covered.
+        let surrounded = board.queens_surrounded();
+        let lost = [0, 1].map(|color| board.queens[color].is_some() && surrounded[color] == 6);
+        // Passes are only allowed without another move, so after one each
+        // neither side can ever move again.
+        if board.turn_history.ends_with(&[Turn::Pass, Turn::Pass]) {
+            return Some(Winner::Draw);
+        }
+        if let Some(&current) = board.position_history.last() {
+            // Check for position repeat stalemate, among positions with the
+            // same player to move. Placements can't be undone, so nothing
+            // before the last one can repeat.
+            let mut count = 0;
+            let last = board.position_history.len() - 1;
+            for i in (0..=last).rev().step_by(2) {
+                if board.position_history[i] == current {
+                    count += 1;
+                    let on_search_path =
+                        i != last && board.search_root.is_some_and(|root| i >= root);
+                    if count >= board.draw_repetitions || on_search_path {
+                        // Draw by stalemate.
+                        return Some(Winner::Draw);
+                    }
+                }
+                if let Turn::Place(..) = board.turn_history[i] {
+                    break;
+                }
             }
         }
 
-        if queens_surrounded == [6, 6] {
+        if lost == [true, true] {
             // Draw by simultaneous queen surrounding.
-            Some(minimax::Winner::Draw)
-        } else if queens_surrounded[board.to_move() as usize] == 6 {
-            Some(minimax::Winner::PlayerJustMoved)
-        } else if queens_surrounded[board.to_move().other()] == 6 {
-            Some(minimax::Winner::PlayerToMove)
+            Some(Winner::Draw)
+        } else if lost[board.to_move() as usize] {
+            Some(Winner::PlayerJustMoved)
+        } else if lost[board.to_move().other()] {
+            Some(Winner::PlayerToMove)
         } else {
             None
         }
     }
+}
+
+#[cfg(feature = "engine")]
+impl Rules {
+    // Put the moves most likely to matter first, so alpha-beta cuts off
+    // sooner: taking a liberty next to the enemy queen, climbing onto it, and
+    // throwing enemy bugs away from our own queen. The search then moves
+    // countermoves, history hits, and the table move in front of these.
+    // Returns how many moves were put ahead of the rest.
+    pub(crate) fn order_moves(board: &Board, turns: &mut [Turn]) -> usize {
+        let color = board.to_move() as usize;
+        let enemy_queen = board.queens[1 - color];
+        let our_queen = board.queens[color];
+        let rank = |turn: &Turn| -> u8 {
+            let (start, end) = match *turn {
+                Turn::Place(hex, _) => (None, hex),
+                Turn::Move(start, end) => (Some(start), end),
+                Turn::Pass => return 3,
+            };
+            if let Some(queen) = enemy_queen {
+                let ring = adjacent(queen);
+                if ring.contains(&end)
+                    && !board.occupied(end)
+                    && !start.is_some_and(|start| ring.contains(&start))
+                {
+                    return 0;
+                }
+                if end == queen {
+                    return 1;
+                }
+            }
+            if let (Some(queen), Some(start)) = (our_queen, start) {
+                let ring = adjacent(queen);
+                if ring.contains(&start)
+                    && !ring.contains(&end)
+                    && board.node(start).color() as usize != color
+                {
+                    return 2;
+                }
+            }
+            3
+        };
+        // A few passes of swaps are cheaper than sorting, with so few ranks.
+        let mut front = 0;
+        for r in 0..3 {
+            let start = front;
+            for i in start..turns.len() {
+                if rank(&turns[i]) == r {
+                    turns.swap(front, i);
+                    front += 1;
+                }
+            }
+        }
+        front
+    }
+
+    // Before pruning, the moves after the forcing ones are ranked by how
+    // close they end to the enemy queen, so that the ones kept press on her
+    // rather than being whichever tiles happened to be generated first.
+    fn rank_quiet_moves(board: &Board, turns: &mut [Turn]) {
+        let Some(queen) = board.queens[1 - board.to_move() as usize] else {
+            return;
+        };
+        turns.sort_unstable_by_key(|&turn| match turn {
+            Turn::Place(hex, _) | Turn::Move(_, hex) => hex_distance(hex, queen),
+            Turn::Pass => u32::MAX,
+        });
+    }
+}
+
+#[cfg(feature = "engine")]
+impl minimax::Game for Rules {
+    type S = Board;
+    type M = Turn;
+
+    fn generate_moves(board: &Board, turns: &mut Vec<Turn>) {
+        Rules::generate_moves(board, turns);
+        let forcing = Rules::order_moves(board, turns);
+        if let Some(widening) = board.widened() {
+            Rules::rank_quiet_moves(board, &mut turns[forcing..]);
+            turns.truncate(widening.moves.max(forcing));
+        }
+    }
+
+    fn get_winner(board: &Board) -> Option<Winner> {
+        Rules::get_winner(board)
+    }
 
     fn apply(board: &mut Board, turn: Turn) -> Option<Board> {
         board.apply(turn);
@@ -882,14 +1863,21 @@ impl minimax::Game for Rules {
     }
 
     fn zobrist_hash(board: &Board) -> u64 {
-        board.zobrist_hash
+        let hash = board.zobrist_hash ^ board.path_key();
+        // Pruned nodes searched fewer moves, so their results can't stand in
+        // for a full width search of the same position, or the other way.
+        if board.widened().is_some() {
+            hash ^ board.zobrist_table[WIDENED_KEY]
+        } else {
+            hash
+        }
     }
 
     fn null_move(_: &Board) -> Option<Turn> {
         Some(Turn::Pass)
     }
 
-    fn notation(board: &Board, turn: Turn) -> Option<String> {
+    fn notation(board: &Board, turn: Turn) -> Option<alloc::string::String> {
         Some(board.to_move_string(turn))
     }
 
@@ -909,6 +1897,41 @@ impl minimax::Game for Rules {
     }
 }
 
+// Split an offset between two hexes into signed steps east and southwest.
+pub(crate) fn split_offset(offset: Hex) -> (i32, i32) {
+    let row = ROW_SIZE as i32;
+    let offset = offset as i32;
+    let x = (offset + row / 2).rem_euclid(row) - row / 2;
+    let y = ((offset - x).rem_euclid(GRID_SIZE as i32) / row + row / 2) % row - row / 2;
+    (x, y)
+}
+
+// How many steps apart two hexes are.
+#[cfg(feature = "engine")]
+fn hex_distance(a: Hex, b: Hex) -> u32 {
+    let (x, y) = split_offset(b.wrapping_sub(a));
+    // Steps southeast go along both axes at once.
+    if (x >= 0) == (y >= 0) {
+        x.unsigned_abs().max(y.unsigned_abs())
+    } else {
+        x.unsigned_abs() + y.unsigned_abs()
+    }
+}
+
+// Apply one of the 12 hex symmetries to an offset from the origin: rotate
+// clockwise by 60 degrees (symmetry % 6) times, then reflect if symmetry >= 6.
+fn transform_offset(offset: Hex, symmetry: u8) -> Hex {
+    let row = ROW_SIZE as i32;
+    let (mut x, mut y) = split_offset(offset);
+    for _ in 0..symmetry % 6 {
+        (x, y) = (x - y, x);
+    }
+    if symmetry >= 6 {
+        (x, y) = (y, x);
+    }
+    (x + y * row).rem_euclid(GRID_SIZE as i32) as Hex
+}
+
 // Coordinates for populating test positions.
 pub(crate) type Loc = (i8, i8);
 pub fn loc_to_hex(loc: Loc) -> Hex {
@@ -916,7 +1939,6 @@ pub fn loc_to_hex(loc: Loc) -> Hex {
     START_HEX.wrapping_add(ROW_SIZE.wrapping_mul(loc.1 as Hex)).wrapping_add(loc.0 as Hex)
 }
 
-#[cfg(test)]
 pub(crate) fn hex_to_loc(hex: Hex) -> Loc {
     let mut x = (hex.wrapping_sub(START_HEX - ROW_SIZE / 2) / ROW_SIZE) as i8;
     if x > 7 {
@@ -946,6 +1968,7 @@ mod tests {
 
     impl Board {
         fn insert_loc(&mut self, loc: Loc, bug: Bug, color: Color) {
+            self.fixture = true;
             self.insert(loc_to_hex(loc), bug, 0, color);
         }
 
@@ -954,6 +1977,7 @@ mod tests {
         }
 
         fn fill_board(&mut self, locs: &[Loc], bug: Bug) {
+            self.fixture = true;
             for &loc in locs {
                 self.insert(loc_to_hex(loc), bug, 0, Color::Black);
             }
@@ -1025,6 +2049,336 @@ mod tests {
             }
         }
         assert_eq!(&[(-1, 0), (2, 1)], &cut_locs[..]);
+
+        // A hive away from the origin, without a white queen to start from.
+        let mut board = Board::default();
+        board.fill_board(&[(3, 3), (4, 3), (5, 3)], Bug::Ant);
+        assert!(board.compute_cut_vertexes().get(loc_to_hex((4, 3))));
+        assert_eq!(1, board.compute_cut_vertexes().iter().count());
+    }
+
+    #[test]
+    fn test_cut_vertex_history() {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        // Random games through every bug, checking the maintained cut
+        // vertexes against a fresh computation on the way down and back up.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1533);
+        for _ in 0..20 {
+            let mut board = Board::new_expansions();
+            let mut played = Vec::new();
+            let mut turns = Vec::new();
+            while played.len() < 80 && Rules::get_winner(&board).is_none() {
+                turns.clear();
+                Rules::generate_moves(&board, &mut turns);
+                let turn = *turns.choose(&mut rng).unwrap();
+                board.apply(turn);
+                played.push(turn);
+                assert_eq!(board.compute_cut_vertexes(), board.find_cut_vertexes());
+            }
+            while let Some(turn) = played.pop() {
+                board.undo(turn);
+                assert_eq!(board.compute_cut_vertexes(), board.find_cut_vertexes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+
+        // Snapshots along random games play on the same as the full board.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1590);
+        let mut scratch = Board::default();
+        let mut turns = Vec::new();
+        let mut snapshot_turns = Vec::new();
+        for _ in 0..10 {
+            let mut board = Board::new_expansions();
+            while board.turn_num < 80 && Rules::get_winner(&board).is_none() {
+                scratch.restore(&board);
+                assert_eq!(board.zobrist_hash, scratch.zobrist_hash);
+                assert_eq!(board.position_history.last(), scratch.position_history.last());
+                let mut snapshot = board.snapshot();
+                assert!(snapshot.turn_history.len() <= board.turn_history.len());
+                let mut full = board.clone();
+                let mut played = Vec::new();
+                for _ in 0..6 {
+                    assert_eq!(Rules::get_winner(&full), Rules::get_winner(&snapshot));
+                    assert_eq!(full.zobrist_hash, snapshot.zobrist_hash);
+                    if Rules::get_winner(&full).is_some() {
+                        break;
+                    }
+                    turns.clear();
+                    Rules::generate_moves(&full, &mut turns);
+                    snapshot_turns.clear();
+                    Rules::generate_moves(&snapshot, &mut snapshot_turns);
+                    assert_eq!(turns, snapshot_turns);
+                    let turn = *turns.choose(&mut rng).unwrap();
+                    full.apply(turn);
+                    snapshot.apply(turn);
+                    played.push(turn);
+                }
+                while let Some(turn) = played.pop() {
+                    snapshot.undo(turn);
+                }
+                assert_eq!(board.zobrist_hash, snapshot.zobrist_hash);
+
+                turns.clear();
+                Rules::generate_moves(&board, &mut turns);
+                board.apply(*turns.choose(&mut rng).unwrap());
+            }
+        }
+
+        // Shuffling the queens back and forth draws by repetition either way.
+        let mut board =
+            Board::from_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-")
+                .unwrap();
+        let mut cycle = Vec::new();
+        for _ in 0..2 {
+            turns.clear();
+            Rules::generate_moves(&board, &mut turns);
+            let queen = board.queens[board.to_move() as usize].unwrap();
+            let turn = *turns
+                .iter()
+                .find(|turn| matches!(turn, Turn::Move(start, _) if *start == queen))
+                .unwrap();
+            board.apply(turn);
+            cycle.push(turn);
+        }
+        cycle.extend(cycle.clone().iter().map(|turn| match turn {
+            Turn::Move(start, end) => Turn::Move(*end, *start),
+            turn => *turn,
+        }));
+        let mut plies = 2;
+        while Rules::get_winner(&board).is_none() {
+            board.apply(cycle[plies % 4]);
+            plies += 1;
+            assert_eq!(Rules::get_winner(&board), Rules::get_winner(&board.snapshot()));
+        }
+        assert_eq!(Some(Winner::Draw), Rules::get_winner(&board.snapshot()));
+        assert!(board.snapshot().turn_history.len() < board.turn_history.len());
+    }
+
+    #[test]
+    fn test_queen_tracking() {
+        let mut board = Board::from_game_string("Base+P;InProgress;White[1]").unwrap();
+        assert_eq!([None, None], board.queens);
+        let moves = ["wQ", "bQ wQ-", "wB1 -wQ", "bB1 bQ-", "wB1 wQ", "bB1 bQ", "wB1 bQ"];
+        let mut played = Vec::new();
+        for move_string in moves {
+            let turn = board.from_move_string(move_string).unwrap();
+            board.apply(turn);
+            played.push(turn);
+        }
+        // Both queens under beetles, one of them under two.
+        let white = board.find_bug(Color::White, Bug::Queen, 1).unwrap();
+        let black = board.find_bug(Color::Black, Bug::Queen, 1).unwrap();
+        assert_eq!([Some(white), Some(black)], board.queens);
+        assert_eq!(3, board.height(black));
+
+        // Set up from the position alone, on top of the stacks.
+        let set_up = Board::from_position_string(&board.to_position_string()).unwrap();
+        assert_eq!(board.queens, set_up.queens);
+        assert_eq!(Ok(()), set_up.validate());
+
+        // Taken back down to before the queens were placed.
+        while let Some(turn) = played.pop() {
+            board.undo(turn);
+            assert_eq!(Ok(()), board.validate());
+        }
+        assert_eq!([None, None], board.queens);
+    }
+
+    #[test]
+    fn test_queen_required() {
+        let placements = |board: &Board| {
+            let mut turns = Vec::new();
+            Rules::generate_moves(board, &mut turns);
+            let mut bugs = turns
+                .iter()
+                .filter_map(|turn| match turn {
+                    Turn::Place(_, bug) => Some(*bug),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            bugs.dedup();
+            bugs
+        };
+        let game = "Base;InProgress;White[4];wS1;bS1 wS1-;wA1 -wS1;bA1 bS1-;wG1 -wA1;bG1 bA1-";
+        let mut board = Board::from_game_string(game).unwrap();
+        // White's fourth turn.
+        assert_eq!(6, board.turn_num);
+        assert_eq!(vec![Bug::Queen], placements(&board));
+        let invalid = board.from_move_string("wB1 /wG1").unwrap();
+        let err = board.apply_untrusted(invalid).unwrap_err();
+        assert!(format!("{:?}", err).contains("fourth turn"), "{:?}", err);
+        // Black's fourth turn.
+        board.apply(board.from_move_string("wQ /wG1").unwrap());
+        assert_eq!(vec![Bug::Queen], placements(&board));
+        board.apply(board.from_move_string("bQ bG1-").unwrap());
+        assert!(placements(&board).len() > 1);
+
+        // A pass counts as a turn: after passing on their second turn, white
+        // has to place the queen as their third tile.
+        let mut board = Board::from_game_string("Base;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+        board.apply(Turn::Pass);
+        board.apply(board.from_move_string("bA1 bS1-").unwrap());
+        assert!(placements(&board).len() > 1);
+        board.apply(board.from_move_string("wA1 -wS1").unwrap());
+        board.apply(board.from_move_string("bG1 bA1-").unwrap());
+        assert_eq!(vec![Bug::Queen], placements(&board));
+
+        // Set up positions count turns the same way.
+        let board = Board::from_position_string("Base White[4] wS1@0,0 bS1@1,0 wA1@-1,0").unwrap();
+        assert_eq!(vec![Bug::Queen], placements(&board));
+        let board = Board::from_position_string("Base White[3] wS1@0,0 bS1@1,0 wA1@-1,0").unwrap();
+        assert!(placements(&board).len() > 1);
+    }
+
+    #[test]
+    fn test_variant() {
+        let moves_from = |board: &Board, start: Hex| {
+            let mut turns = Vec::new();
+            Rules::generate_moves(board, &mut turns);
+            turns
+                .into_iter()
+                .filter_map(|turn| match turn {
+                    Turn::Move(from, to) if from == start => Some(to),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Queens stay put while their player has tiles in hand.
+        let mut board =
+            Board::from_game_string("Base;InProgress;White[3];wS1;bS1 wS1-;wQ -wS1;bQ bS1-")
+                .unwrap();
+        let queen = board.queens[0].unwrap();
+        let queen_moves = moves_from(&board, queen);
+        assert!(!queen_moves.is_empty());
+        board.set_variant(Variant { queen_moves_last: true, ..Variant::default() });
+        assert!(moves_from(&board, queen).is_empty());
+        let err = board.apply_untrusted(Turn::Move(queen, queen_moves[0])).unwrap_err();
+        assert!(err.to_string().contains("until all of a player's tiles"), "{}", err);
+        board.remaining[0] = [0; 8];
+        assert_eq!(queen_moves, moves_from(&board, queen));
+
+        // Beetles can't climb stacks at the height limit, but can come down.
+        let game = "Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-";
+        let mut board = Board::from_game_string(game).unwrap();
+        let beetle = board.from_move_string("wB1 wS1").unwrap();
+        let climbs = |board: &Board, start: Hex| {
+            moves_from(board, start).into_iter().filter(|&to| board.occupied(to)).count()
+        };
+        let Turn::Move(start, onto) = beetle else { panic!() };
+        assert_eq!(2, climbs(&board, start));
+        board.set_variant(Variant { max_stack_height: Some(1), ..Variant::default() });
+        assert_eq!(0, climbs(&board, start));
+        assert!(!moves_from(&board, start).is_empty());
+        board.set_variant(Variant::default());
+        board.apply(beetle);
+        board.apply(board.from_move_string("bA1 bQ\\").unwrap());
+        board.set_variant(Variant { max_stack_height: Some(1), ..Variant::default() });
+        assert!(moves_from(&board, onto).iter().all(|&to| !board.occupied(to)));
+        assert!(!moves_from(&board, onto).is_empty());
+    }
+
+    #[test]
+    fn test_threat_map() {
+        let game = "Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-";
+        let board = Board::from_game_string(game).unwrap();
+        let threats = board.threat_map();
+        assert_eq!(12, threats.len());
+        let ant = board.occupied_hexes[Color::Black as usize]
+            .iter()
+            .copied()
+            .find(|&hex| board.node(hex).bug() == Bug::Ant)
+            .unwrap();
+        for threat in threats.iter().filter(|threat| threat.queen == Color::White) {
+            // The ant can get to any open hex around the white queen.
+            assert_eq!(!board.occupied(threat.hex), threat.attackers.contains(&ant));
+        }
+        // Only white tiles threaten the black queen.
+        assert!(threats.iter().filter(|threat| threat.queen == Color::Black).all(|threat| threat
+            .attackers
+            .iter()
+            .all(|&hex| board.node(hex).color() == Color::White)));
+
+        // Nothing moves before its own queen is out.
+        let board =
+            Board::from_game_string("Base;InProgress;White[3];wS1;bS1 wS1-;wQ -wS1;bA1 bS1-")
+                .unwrap();
+        let threats = board.threat_map();
+        assert_eq!(6, threats.len());
+        assert!(threats.iter().all(|threat| threat.attackers.is_empty()));
+    }
+
+    #[test]
+    fn test_dead_drawn() {
+        // A queen and two ants each can't fill six hexes around a queen.
+        assert!(Board::new([1, 0, 0, 2, 0, 0, 0, 0]).dead_drawn());
+        assert!(!Board::new([1, 0, 0, 3, 0, 0, 0, 0]).dead_drawn());
+        assert!(!Board::from_game_type("Base").unwrap().dead_drawn());
+
+        use Bug::{Mosquito, Queen};
+        use Color::{Black, White};
+
+        // Every tile but the ends pins the line, and the end mosquitoes only
+        // touch mosquitoes, so there's no move to copy.
+        let mut tiles = vec![
+            ((-3, 0), Mosquito, White),
+            ((-2, 0), Mosquito, White),
+            ((-1, 0), Mosquito, White),
+            ((0, 0), Queen, White),
+            ((1, 0), Queen, Black),
+            ((2, 0), Mosquito, Black),
+            ((3, 0), Mosquito, Black),
+            ((4, 0), Mosquito, Black),
+        ];
+        let board = Board::from_tiles(&tiles, 8);
+        assert!(board.dead_drawn());
+        let mut moves = Vec::new();
+        Rules::generate_moves(&board, &mut moves);
+        assert_eq!(vec![Turn::Pass], moves);
+
+        // A mosquito next to a queen can walk like her.
+        tiles[7].0 = (1, 1);
+        let board = Board::from_tiles(&tiles, 8);
+        assert!(!board.dead_drawn());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut board = Board::new_core_set();
+        for turn in [
+            Turn::Place(loc_to_hex((0, 0)), Bug::Queen),
+            Turn::Place(loc_to_hex((1, 0)), Bug::Queen),
+            Turn::Place(loc_to_hex((-1, 0)), Bug::Beetle),
+            Turn::Place(loc_to_hex((2, 0)), Bug::Beetle),
+            Turn::Move(loc_to_hex((-1, 0)), loc_to_hex((0, 0))),
+        ] {
+            board.apply(turn);
+            assert_eq!(Ok(()), board.validate());
+        }
+
+        // Each kind of damage is caught.
+        let mut broken = board.clone();
+        broken.zobrist_hash ^= 1;
+        assert!(broken.validate().unwrap_err().starts_with("zobrist hash"));
+        let mut broken = board.clone();
+        broken.queens[Color::Black as usize] = Some(loc_to_hex((2, 0)));
+        assert!(broken.validate().unwrap_err().starts_with("queen of color 1"));
+        let mut broken = board.clone();
+        broken.return_to_hand(Color::White, Bug::Ant);
+        assert_eq!(Err("color 0 has 4 of 3 ants".into()), broken.validate());
+        let mut broken = board.clone();
+        broken.underworld[0].height = 2;
+        assert!(broken.validate().unwrap_err().starts_with("tile under hex"));
+        let mut broken = board.clone();
+        broken.cut_vertexes = Some(HexSet::new());
+        assert_eq!(Err("cut vertexes are out of date".into()), broken.validate());
     }
 
     #[test]
@@ -1119,6 +2473,55 @@ mod tests {
         board.assert_movements(&turns, (0, 0), &[(-1, -1), (0, -1), (0, 1), (1, 1)]);
     }
 
+    #[test]
+    fn test_beetle_gates() {
+        // Stack heights:
+        //   0   2
+        //  0 (1) 1
+        //   0   2
+        // Climbing onto the tile on the right squeezes between two stacks
+        // taller than it.
+        let mut board = Board::default();
+        board.fill_board(&[(0, 0), (1, 0), (0, -1), (0, -1), (1, 1), (1, 1)], Bug::Beetle);
+        let mut turns = Vec::new();
+        board.generate_stack_walking(START_HEX, &mut turns);
+        board.assert_movements(&turns, (0, 0), &[(0, -1), (1, 1)]);
+
+        // Onto a stack as tall as the gate is fine.
+        board.fill_board(&[(1, 0)], Bug::Beetle);
+        turns.clear();
+        board.generate_stack_walking(START_HEX, &mut turns);
+        board.assert_movements(&turns, (0, 0), &[(0, -1), (1, 0), (1, 1)]);
+
+        //   0   2
+        //  0 (2) 0
+        //   0   2
+        // Climbing down between the stacks is just as blocked.
+        let mut board = Board::default();
+        board.fill_board(&[(0, 0), (0, 0), (0, -1), (0, -1), (1, 1), (1, 1)], Bug::Beetle);
+        turns.clear();
+        board.generate_stack_walking(START_HEX, &mut turns);
+        board.assert_movements(&turns, (0, 0), &[(-1, -1), (0, -1), (1, 1), (0, 1), (-1, 0)]);
+
+        // From higher up, the beetle passes over the gate.
+        board.fill_board(&[(0, 0)], Bug::Beetle);
+        turns.clear();
+        board.generate_stack_walking(START_HEX, &mut turns);
+        board.assert_movements(
+            &turns,
+            (0, 0),
+            &[(-1, -1), (0, -1), (1, 0), (1, 1), (0, 1), (-1, 0)],
+        );
+
+        // A mosquito on the stack moves as a beetle, gates included.
+        let mut board = Board::default();
+        board.fill_board(&[(0, 0), (0, -1), (0, -1), (1, 1), (1, 1)], Bug::Beetle);
+        board.fill_board(&[(0, 0)], Bug::Mosquito);
+        turns.clear();
+        board.generate_piece_movements(START_HEX, &mut turns);
+        board.assert_movements(&turns, (0, 0), &[(-1, -1), (0, -1), (1, 1), (0, 1), (-1, 0)]);
+    }
+
     #[test]
     fn test_generate_walk3() {
         let mut board = Board::default();
@@ -1192,7 +2595,7 @@ mod tests {
         board.insert_loc((1, 0), Bug::Grasshopper, Color::Black);
         turns.clear();
         // Dedup happens in generate_movements.
-        board.turn_num += 1;
+        board.set_up_turn(board.turn_num + 1);
         board.generate_movements(&mut turns);
         board.assert_movements(
             &turns,
@@ -1269,8 +2672,6 @@ mod tests {
 
     #[test]
     fn test_winner() {
-        use minimax::Game;
-
         // Draw by threefold repetition.
         let mut board = Board::default();
         let x1 = loc_to_hex((-1, -1));
@@ -1301,12 +2702,830 @@ mod tests {
         assert_eq!(None, Rules::get_winner(&board));
         // Recreate position for the third time.
         board.apply(Turn::Move(y2, y1));
-        assert_eq!(Some(minimax::Winner::Draw), Rules::get_winner(&board));
+        assert_eq!(Some(Winner::Draw), Rules::get_winner(&board));
         // Undo reverts zobrist and history.
         board.undo(Turn::Move(y2, y1));
         assert_eq!(None, Rules::get_winner(&board));
         // Redo re-reverts draw state.
         board.apply(Turn::Move(y2, y1));
-        assert_eq!(Some(minimax::Winner::Draw), Rules::get_winner(&board));
+        assert_eq!(Some(Winner::Draw), Rules::get_winner(&board));
+        // Or on the second time, if configured.
+        for _ in 0..4 {
+            board.undo(*board.turn_history.last().unwrap());
+        }
+        assert_eq!(None, Rules::get_winner(&board));
+        board.set_draw_repetitions(2);
+        assert_eq!(Some(Winner::Draw), Rules::get_winner(&board));
+        // Or when a search comes back to a position on its own path.
+        #[cfg(feature = "engine")]
+        {
+            board.set_draw_repetitions(3);
+            for _ in 0..4 {
+                board.undo(*board.turn_history.last().unwrap());
+            }
+            let mut search = board.for_search();
+            for turn in [Turn::Move(x1, x2), Turn::Move(y1, y2), Turn::Move(x2, x1)] {
+                search.apply(turn);
+                assert_eq!(None, Rules::get_winner(&search));
+            }
+            search.apply(Turn::Move(y2, y1));
+            assert_eq!(Some(Winner::Draw), Rules::get_winner(&search));
+            // But not when it repeats one from before the search.
+            board.apply(Turn::Move(x1, x2));
+            board.apply(Turn::Move(y1, y2));
+            let mut search = board.for_search();
+            search.apply(Turn::Move(x2, x1));
+            search.apply(Turn::Move(y2, y1));
+            assert_eq!(None, Rules::get_winner(&search));
+
+            // Two lines to the same position, only one of which can come
+            // back to a position on its path, don't share table entries.
+            let (a, b, c) = (loc_to_hex((-2, 0)), loc_to_hex((-2, -1)), loc_to_hex((-1, -2)));
+            let line = |via| {
+                let mut search = board.for_search();
+                for turn in [Turn::Move(x2, via), Turn::Move(y2, y1), Turn::Move(via, c)] {
+                    search.apply(turn);
+                }
+                search
+            };
+            let (mut first, mut second) = (line(a), line(b));
+            assert_eq!(first.zobrist_hash, second.zobrist_hash);
+            let key = |board| <Rules as minimax::Game>::zobrist_hash(board);
+            assert_ne!(key(&first), key(&second));
+            for turn in [Turn::Move(y1, y2), Turn::Move(c, a)] {
+                first.apply(turn);
+                second.apply(turn);
+            }
+            assert_eq!(Some(Winner::Draw), Rules::get_winner(&first));
+            assert_eq!(None, Rules::get_winner(&second));
+        }
+
+        // The queens walk side by side, repeating the position shifted by
+        // one step every two moves, which still counts as a repetition.
+        let mut board = Board::default();
+        board.insert_loc((0, 0), Bug::Queen, Color::White);
+        board.insert_loc((1, 0), Bug::Queen, Color::Black);
+        let mut hashes = Vec::new();
+        for ply in 0..11 {
+            let (x, step) = (ply % 2, ply / 2);
+            board.apply(Turn::Move(loc_to_hex((x, -step)), loc_to_hex((x, -step - 1))));
+            hashes.push(board.zobrist_hash);
+            // The same position with black to move after plies 0, 2, and 4.
+            let expected = if ply < 4 { None } else { Some(Winner::Draw) };
+            assert_eq!(expected, Rules::get_winner(&board));
+        }
+        // None of the positions repeat exactly.
+        hashes.sort_unstable();
+        hashes.dedup();
+        assert_eq!(11, hashes.len());
+
+        // Neither side able to move is a draw.
+        let mut board = Board::default();
+        board.apply(Turn::Place(loc_to_hex((0, 0)), Bug::Queen));
+        board.apply(Turn::Pass);
+        assert_eq!(None, Rules::get_winner(&board));
+        board.apply(Turn::Pass);
+        assert_eq!(Some(Winner::Draw), Rules::get_winner(&board));
+    }
+
+    #[test]
+    fn test_winner_queen_stacks() {
+        // A ring of tiles around the start hex isn't a win for anyone while
+        // both queens are in hand.
+        let mut board = Board::default();
+        board.insert_loc((0, 0), Bug::Ant, Color::White);
+        for adj in adjacent(loc_to_hex((0, 0))) {
+            board.insert(adj, Bug::Spider, 0, Color::Black);
+        }
+        assert_eq!(None, Rules::get_winner(&board));
+
+        // A covered queen is lost when the hexes around her stack fill up.
+        let mut board = Board::default();
+        board.insert_loc((0, 0), Bug::Queen, Color::White);
+        board.insert_loc((0, 0), Bug::Beetle, Color::Black);
+        let ring = adjacent(loc_to_hex((0, 0)));
+        for &adj in &ring[..5] {
+            board.insert(adj, Bug::Ant, 0, Color::Black);
+        }
+        assert_eq!([5, 0], board.queens_surrounded());
+        assert_eq!(None, Rules::get_winner(&board));
+        board.insert(ring[5], Bug::Ant, 0, Color::Black);
+        assert_eq!(Some(Winner::PlayerJustMoved), Rules::get_winner(&board));
+        // The beetle stepping off doesn't help.
+        board.remove_loc((0, 0));
+        assert_eq!(Some(Winner::PlayerJustMoved), Rules::get_winner(&board));
+        // Nor does covering her again, with black to move.
+        board.insert_loc((0, 0), Bug::Beetle, Color::White);
+        board.set_up_turn(1);
+        assert_eq!(Some(Winner::PlayerToMove), Rules::get_winner(&board));
+    }
+
+    #[test]
+    fn test_position_hash_translation() {
+        let tiles = [
+            ((0, 0), Bug::Queen, Color::White),
+            ((1, 0), Bug::Ant, Color::Black),
+            ((0, 1), Bug::Beetle, Color::White),
+            ((0, 1), Bug::Beetle, Color::Black),
+        ];
+        let mut board = Board::default();
+        let mut shifted = Board::default();
+        for &(loc, bug, color) in tiles.iter() {
+            board.insert_loc(loc, bug, color);
+            shifted.insert_loc((loc.0 + 2, loc.1 - 3), bug, color);
+        }
+        assert_ne!(board.zobrist_hash, shifted.zobrist_hash);
+        assert_eq!(board.position_hash, shifted.position_hash);
+
+        // Moving the white queen rehashes everything relative to it.
+        let hash = board.position_hash;
+        board.apply(Turn::Move(loc_to_hex((0, 0)), loc_to_hex((1, 1))));
+        assert_ne!(hash, board.position_hash);
+        board.undo(Turn::Move(loc_to_hex((0, 0)), loc_to_hex((1, 1))));
+        assert_eq!(hash, board.position_hash);
+        board.remove_loc((0, 1));
+        shifted.remove_loc((2, -2));
+        assert_eq!(board.position_hash, shifted.position_hash);
+    }
+
+    #[test]
+    fn test_zobrist_table() {
+        assert_eq!(0, parse_seed("0"));
+        assert_eq!(u64::MAX, parse_seed("18446744073709551615"));
+        let mut keys = ZOBRIST_TABLE.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(ZOBRIST_KEYS, keys.len());
+        let other = zobrist_table(ZOBRIST_SEED ^ 1);
+        assert!(ZOBRIST_TABLE.iter().zip(other.iter()).all(|(a, b)| a != b));
+    }
+
+    #[test]
+    fn test_zobrist_hand_and_turn() {
+        // The same tiles, but a mosquito still in hand.
+        let base = Board::from_game_string("Base;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+        let with_mosquito =
+            Board::from_game_string("Base+M;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+        assert_ne!(base.zobrist_hash, with_mosquito.zobrist_hash);
+
+        // The same tiles and hands, but the other player to move.
+        let white = Board::from_position_string("Base White[3] wS1@0,0 bS1@1,0").unwrap();
+        let black = Board::from_position_string("Base Black[3] wS1@0,0 bS1@1,0").unwrap();
+        assert_ne!(white.zobrist_hash, black.zobrist_hash);
+
+        // Placing from the hand and undoing it keeps the hash in step.
+        let mut board = Board::from_game_string("Base;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+        let hash = board.zobrist_hash;
+        board.apply(Turn::Place(loc_to_hex((-1, 0)), Bug::Ant));
+        assert_ne!(hash, board.zobrist_hash);
+        assert_eq!(Ok(()), board.validate());
+        board.undo(Turn::Place(loc_to_hex((-1, 0)), Bug::Ant));
+        assert_eq!(hash, board.zobrist_hash);
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let tiles = [
+            ((0, 0), Bug::Queen, Color::White),
+            ((1, 0), Bug::Queen, Color::Black),
+            ((-1, -1), Bug::Ant, Color::White),
+            ((2, 1), Bug::Beetle, Color::Black),
+            ((2, 1), Bug::Beetle, Color::White),
+            ((1, 2), Bug::Spider, Color::Black),
+        ];
+        let mut board = Board::default();
+        for &(loc, bug, color) in tiles.iter() {
+            board.insert_loc(loc, bug, color);
+        }
+        let hash = board.canonical_hash();
+        for symmetry in 0..12 {
+            let mut other = Board::default();
+            for &(loc, bug, color) in tiles.iter() {
+                let hex = transform_offset(loc_to_hex(loc).wrapping_sub(START_HEX), symmetry);
+                let loc = hex_to_loc(hex.wrapping_add(START_HEX) & GRID_MASK);
+                // Shift it too.
+                other.insert_loc((loc.0 - 1, loc.1 + 2), bug, color);
+            }
+            if symmetry > 0 {
+                assert_ne!(board.zobrist_hash, other.zobrist_hash);
+            }
+            assert_eq!(hash, other.canonical_hash(), "symmetry {}", symmetry);
+        }
+
+        // Different positions still differ.
+        board.remove_loc((1, 2));
+        board.insert_loc((1, -1), Bug::Spider, Color::Black);
+        assert_ne!(hash, board.canonical_hash());
+
+        // Mirrored first placements are the same opening.
+        let mut board1 = Board::default();
+        let mut board2 = Board::default();
+        board1.apply(Turn::Place(START_HEX, Bug::Spider));
+        board2.apply(Turn::Place(START_HEX, Bug::Spider));
+        board1.apply(Turn::Place(Direction::E.apply(START_HEX), Bug::Ant));
+        board2.apply(Turn::Place(Direction::NW.apply(START_HEX), Bug::Ant));
+        assert_eq!(board1.canonical_hash(), board2.canonical_hash());
+        board2.undo(Turn::Place(Direction::NW.apply(START_HEX), Bug::Ant));
+        assert_ne!(board1.canonical_hash(), board2.canonical_hash());
+    }
+
+    // Randomized position generators that stress specific movement rules,
+    // checked against simple reference implementations of those rules.
+    mod generated {
+        use super::*;
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::{Rng, SeedableRng};
+
+        const POSITIONS_PER_RULE: usize = 300;
+
+        // Grow a connected hive outward from a white queen at the origin,
+        // then drop beetles on top of random tiles to create stacks.
+        fn random_hive(
+            rng: &mut StdRng, bugs: &[Bug], num_tiles: usize, num_beetles: usize,
+        ) -> Board {
+            let mut board = Board { fixture: true, ..Board::default() };
+            board.insert(START_HEX, Bug::Queen, 1, Color::White);
+            let mut hexes = vec![START_HEX];
+            while hexes.len() < num_tiles {
+                let base = *hexes.choose(rng).unwrap();
+                let hex = adjacent(base)[rng.gen_range(0..6)];
+                let loc = hex_to_loc(hex);
+                // Stay well away from the grid wrapping around.
+                if board.occupied(hex) || loc.0.abs() > 5 || loc.1.abs() > 5 {
+                    continue;
+                }
+                let color = if rng.gen() { Color::White } else { Color::Black };
+                board.insert(hex, *bugs.choose(rng).unwrap(), 1, color);
+                hexes.push(hex);
+            }
+            for _ in 0..num_beetles {
+                let color = if rng.gen() { Color::White } else { Color::Black };
+                board.insert(*hexes.choose(rng).unwrap(), Bug::Beetle, 1, color);
+            }
+            board.set_up_turn(rng.gen_range(0..2));
+            board
+        }
+
+        fn all_occupied(board: &Board) -> Vec<Hex> {
+            let mut hexes = board.occupied_hexes[0].clone();
+            hexes.extend(&board.occupied_hexes[1]);
+            hexes.sort_unstable();
+            hexes
+        }
+
+        // Whether the hive stays in one piece if the top tile at this hex is lifted.
+        fn connected_without(board: &Board, lifted: Hex) -> bool {
+            let ignore = if board.height(lifted) > 1 { None } else { Some(lifted) };
+            connected_except(board, ignore)
+        }
+
+        fn connected_except(board: &Board, ignore: Option<Hex>) -> bool {
+            let occupied = |hex: Hex| board.occupied(hex) && Some(hex) != ignore;
+            let tiles = all_occupied(board).into_iter().filter(|&hex| occupied(hex));
+            let tiles = tiles.collect::<Vec<_>>();
+            if tiles.is_empty() {
+                return true;
+            }
+            let mut seen = vec![tiles[0]];
+            let mut queue = vec![tiles[0]];
+            while let Some(hex) = queue.pop() {
+                for adj in adjacent(hex) {
+                    if occupied(adj) && !seen.contains(&adj) {
+                        seen.push(adj);
+                        queue.push(adj);
+                    }
+                }
+            }
+            seen.len() == tiles.len()
+        }
+
+        // The two hexes adjacent to both a and b.
+        fn gate(a: Hex, b: Hex) -> (Hex, Hex) {
+            let mut common = adjacent(a).into_iter().filter(|hex| adjacent(b).contains(hex));
+            (common.next().unwrap(), common.next().unwrap())
+        }
+
+        // Ground level freedom to move: keep contact with the hive without
+        // squeezing between two tiles.
+        fn can_slide(board: &Board, origin: Hex, from: Hex, to: Hex) -> bool {
+            let (g1, g2) = gate(from, to);
+            let o1 = board.occupied(g1) && g1 != origin;
+            let o2 = board.occupied(g2) && g2 != origin;
+            !board.occupied(to) && o1 != o2
+        }
+
+        // Climbing freedom to move: both sides of the gate must be taller
+        // than the higher of the source and destination to block.
+        fn can_climb(board: &Board, from: Hex, from_height: u8, to: Hex) -> bool {
+            let barrier = max(from_height, board.height(to));
+            let (g1, g2) = gate(from, to);
+            !(board.height(g1) > barrier && board.height(g2) > barrier)
+        }
+
+        fn sorted_dests(turns: &[Turn], start: Hex) -> Vec<Hex> {
+            let mut ends = turns
+                .iter()
+                .filter_map(|&turn| match turn {
+                    Turn::Move(s, e) if s == start => Some(e),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            ends.sort_unstable();
+            ends.dedup();
+            ends
+        }
+
+        // Rule-independent invariants that hold for every generated movement.
+        fn check_movement_invariants(board: &mut Board, turns: &[Turn]) {
+            let hexes = all_occupied(board);
+            let hash = board.zobrist_hash;
+            let heights = hexes.iter().map(|&hex| board.height(hex)).collect::<Vec<_>>();
+            let mut unique = turns.to_vec();
+            unique.sort_unstable();
+            unique.dedup();
+            assert_eq!(unique.len(), turns.len(), "duplicate moves in {:?}", turns);
+            for &turn in turns {
+                let (start, end) = match turn {
+                    Turn::Move(start, end) => (start, end),
+                    _ => panic!("unexpected turn {:?}", turn),
+                };
+                assert_ne!(start, end);
+                assert!(board.occupied(start));
+                assert!(connected_without(board, start), "{:?} breaks the hive", turn);
+                board.apply(turn);
+                assert!(connected_without(board, end), "{:?} leaves a split hive", turn);
+                board.undo(turn);
+                assert_eq!(hash, board.zobrist_hash, "{:?} undo changed hash", turn);
+                assert_eq!(hexes, all_occupied(board), "{:?} undo changed tiles", turn);
+                let new_heights = hexes.iter().map(|&hex| board.height(hex)).collect::<Vec<_>>();
+                assert_eq!(heights, new_heights, "{:?} undo changed stacks", turn);
+            }
+        }
+
+        // Top tiles of the given bug for the player to move.
+        fn movers(board: &Board, bug: Bug) -> Vec<Hex> {
+            board.occupied_hexes[board.to_move() as usize]
+                .iter()
+                .copied()
+                .filter(|&hex| board.node(hex).bug() == bug)
+                .collect()
+        }
+
+        fn generated_turns(board: &mut Board) -> Vec<Turn> {
+            let mut turns = Vec::new();
+            board.generate_movements(&mut turns);
+            check_movement_invariants(board, &turns);
+            turns
+        }
+
+        // Lay a full set of tiles along a widening spiral arm, as far out as
+        // the grid allows without wrapping, checking the hive at every step.
+        #[test]
+        fn test_generated_spiral() {
+            // Clockwise from east.
+            const DIRS: [Loc; 6] = [(1, 0), (1, 1), (0, 1), (-1, 0), (-1, -1), (0, -1)];
+            let bugs = [
+                Bug::Ant,
+                Bug::Grasshopper,
+                Bug::Spider,
+                Bug::Beetle,
+                Bug::Ladybug,
+                Bug::Mosquito,
+                Bug::Pillbug,
+            ];
+            let limit = ROW_SIZE as i8 / 2 - 2;
+            let mut board = Board { fixture: true, ..Board::default() };
+            board.insert(START_HEX, Bug::Queen, 1, Color::White);
+            let mut loc: Loc = (0, 0);
+            let mut tiles = 1;
+            'spiral: for arm in 0.. {
+                let (dx, dy) = DIRS[arm % 6];
+                for _ in 0..=arm {
+                    loc = (loc.0 + dx, loc.1 + dy);
+                    if tiles == 28 || loc.0.abs() > limit || loc.1.abs() > limit {
+                        break 'spiral;
+                    }
+                    let color = if tiles % 2 == 0 { Color::White } else { Color::Black };
+                    board.insert(loc_to_hex(loc), bugs[tiles % bugs.len()], 1, color);
+                    tiles += 1;
+                    assert_eq!(tiles, all_occupied(&board).len(), "tile at {:?} overlaps", loc);
+
+                    let cuts = board.find_cut_vertexes();
+                    for hex in all_occupied(&board) {
+                        assert_eq!(!connected_without(&board, hex), cuts.get(hex), "{:?}", loc);
+                    }
+                    for turn_num in 0..2 {
+                        board.set_up_turn(turn_num);
+                        generated_turns(&mut board);
+                    }
+                }
+            }
+            assert!(tiles >= 20, "only {} tiles", tiles);
+        }
+
+        #[test]
+        fn test_generated_beetle_gates() {
+            let mut rng = StdRng::seed_from_u64(1508);
+            for _ in 0..POSITIONS_PER_RULE {
+                let mut board = random_hive(&mut rng, &[Bug::Beetle, Bug::Ant], 9, 5);
+                let turns = generated_turns(&mut board);
+                for hex in movers(&board, Bug::Beetle) {
+                    let height = board.height(hex);
+                    let mut expected = Vec::new();
+                    if height > 1 || connected_without(&board, hex) {
+                        for adj in adjacent(hex) {
+                            let ok = if max(height - 1, board.height(adj)) == 0 {
+                                can_slide(&board, hex, hex, adj)
+                            } else {
+                                can_climb(&board, hex, height - 1, adj)
+                            };
+                            if ok {
+                                expected.push(adj);
+                            }
+                        }
+                    }
+                    expected.sort_unstable();
+                    assert_eq!(
+                        expected,
+                        sorted_dests(&turns, hex),
+                        "beetle at {:?}",
+                        hex_to_loc(hex)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_generated_grasshopper_lines() {
+            let mut rng = StdRng::seed_from_u64(1509);
+            for _ in 0..POSITIONS_PER_RULE {
+                let mut board = random_hive(&mut rng, &[Bug::Grasshopper, Bug::Spider], 12, 3);
+                let turns = generated_turns(&mut board);
+                for hex in movers(&board, Bug::Grasshopper) {
+                    let mut expected = Vec::new();
+                    if !board.node(hex).is_stacked() && connected_without(&board, hex) {
+                        for dir in Direction::all() {
+                            let mut jump = dir.apply(hex);
+                            if !board.occupied(jump) {
+                                continue;
+                            }
+                            while board.occupied(jump) {
+                                jump = dir.apply(jump);
+                            }
+                            expected.push(jump);
+                        }
+                    }
+                    expected.sort_unstable();
+                    assert_eq!(
+                        expected,
+                        sorted_dests(&turns, hex),
+                        "grasshopper at {:?}",
+                        hex_to_loc(hex)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_generated_spider_paths() {
+            fn walk(board: &Board, origin: Hex, path: &mut Vec<Hex>, out: &mut Vec<Hex>) {
+                let hex = *path.last().unwrap();
+                if path.len() == 4 {
+                    out.push(hex);
+                    return;
+                }
+                for adj in adjacent(hex) {
+                    if adj != origin && !path.contains(&adj) && can_slide(board, origin, hex, adj) {
+                        path.push(adj);
+                        walk(board, origin, path, out);
+                        path.pop();
+                    }
+                }
+            }
+
+            let mut rng = StdRng::seed_from_u64(1510);
+            for _ in 0..POSITIONS_PER_RULE {
+                let mut board = random_hive(&mut rng, &[Bug::Spider, Bug::Ant], 10, 0);
+                let turns = generated_turns(&mut board);
+                for hex in movers(&board, Bug::Spider) {
+                    let mut expected = Vec::new();
+                    if connected_without(&board, hex) {
+                        walk(&board, hex, &mut vec![hex], &mut expected);
+                    }
+                    expected.sort_unstable();
+                    expected.dedup();
+                    assert_eq!(
+                        expected,
+                        sorted_dests(&turns, hex),
+                        "spider at {:?}",
+                        hex_to_loc(hex)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn test_generated_pillbug_throws() {
+            let mut rng = StdRng::seed_from_u64(1511);
+            for _ in 0..POSITIONS_PER_RULE {
+                let mut board = random_hive(&mut rng, &[Bug::Pillbug, Bug::Ant, Bug::Spider], 9, 4);
+                let turns = generated_turns(&mut board);
+                let mut expected = Vec::new();
+                for pillbug in movers(&board, Bug::Pillbug) {
+                    if board.node(pillbug).is_stacked() {
+                        continue;
+                    }
+                    let neighbors = adjacent(pillbug);
+                    let open = |hex: Hex| {
+                        let (g1, g2) = gate(pillbug, hex);
+                        board.height(g1) < 2 || board.height(g2) < 2
+                    };
+                    let starts = neighbors.iter().copied().filter(|&hex| {
+                        board.height(hex) == 1 && open(hex) && connected_without(&board, hex)
+                    });
+                    let ends =
+                        neighbors.iter().copied().filter(|&hex| !board.occupied(hex) && open(hex));
+                    for start in starts {
+                        for end in ends.clone() {
+                            expected.push(Turn::Move(start, end));
+                        }
+                    }
+                }
+                for turn in expected.iter() {
+                    assert!(turns.contains(turn), "missing throw {:?}", turn);
+                }
+                // Enemy tiles can only move by being thrown.
+                for turn in turns.iter() {
+                    if let Turn::Move(start, _) = *turn {
+                        if board.node(start).color() != board.to_move() {
+                            assert!(expected.contains(turn), "unexpected throw {:?}", turn);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The bugs whose moves overlap most: mosquitoes copying several
+        // neighbors, pillbugs and mosquitoes throwing the same tiles, tiles
+        // walking where they could be thrown, and spiders and ladybugs
+        // reaching a hex by more than one path. generated_turns checks that
+        // each move comes out once.
+        #[test]
+        fn test_generated_no_duplicates() {
+            let mut rng = StdRng::seed_from_u64(1607);
+            let bugs = [Bug::Mosquito, Bug::Pillbug, Bug::Spider, Bug::Ladybug, Bug::Ant];
+            for _ in 0..POSITIONS_PER_RULE {
+                let mut board = random_hive(&mut rng, &bugs, 10, 2);
+                let turns = generated_turns(&mut board);
+                // Dropping repeated throws keeps every tile's own moves.
+                let mut expected = Vec::new();
+                for &hex in board.occupied_hexes[board.to_move() as usize].iter() {
+                    if connected_without(&board, hex) && !board.queen_held(board.node(hex)) {
+                        board.generate_piece_movements(hex, &mut expected);
+                    }
+                }
+                for turn in expected.iter() {
+                    assert!(turns.contains(turn), "missing {:?}", turn);
+                }
+            }
+        }
+
+        // Property tests over random legal games, rather than random hives.
+        #[cfg(not(target_arch = "wasm32"))]
+        mod properties {
+            use super::*;
+
+            // Everything apply and undo should put back: the hash, tiles,
+            // stacks, and hands.
+            type Snapshot = (u64, Vec<u8>, Vec<(Hex, u8, u8)>, [[u8; 8]; 2]);
+
+            fn snapshot(board: &Board) -> Snapshot {
+                let nodes = board.nodes.iter().map(|node| node.0).collect();
+                let underworld = board.get_underworld().iter();
+                let mut underworld = underworld
+                    .map(|under| (under.hex, under.node.0, under.height))
+                    .collect::<Vec<_>>();
+                // Only the order within each stack matters.
+                underworld.sort_by_key(|under| under.0);
+                (board.zobrist_hash, nodes, underworld, board.remaining)
+            }
+
+            // The same board shifted across the grid.
+            fn translate(hex: Hex, offset: Hex) -> Hex {
+                GRID_MASK & hex.wrapping_add(offset)
+            }
+
+            fn translate_turn(turn: Turn, offset: Hex) -> Turn {
+                match turn {
+                    Turn::Place(hex, bug) => Turn::Place(translate(hex, offset), bug),
+                    Turn::Move(start, end) => {
+                        Turn::Move(translate(start, offset), translate(end, offset))
+                    }
+                    Turn::Pass => Turn::Pass,
+                }
+            }
+
+            fn sorted_moves(board: &Board) -> Vec<Turn> {
+                let mut turns = Vec::new();
+                Rules::generate_moves(board, &mut turns);
+                turns.sort_unstable_by_key(|&turn| format!("{:?}", turn));
+                turns
+            }
+
+            // Invariants of every position in random legal games, each move
+            // picked from the generated moves by the next choice.
+            fn check_random_game(game_type: &str, choices: &[usize], offset: Hex) {
+                let mut board = Board::from_game_type(game_type).unwrap();
+                let mut shifted = board.clone();
+                for &choice in choices {
+                    if Rules::get_winner(&board).is_some() {
+                        break;
+                    }
+                    let turns = sorted_moves(&board);
+                    // The first two tiles always go in the same places, and the
+                    // rest follow them.
+                    if board.turn_num >= 2 {
+                        let expected = turns.iter().map(|&turn| translate_turn(turn, offset));
+                        let mut expected = expected.collect::<Vec<_>>();
+                        expected.sort_unstable_by_key(|&turn| format!("{:?}", turn));
+                        assert_eq!(expected, sorted_moves(&shifted), "{}", board.game_string());
+                    }
+
+                    let before = snapshot(&board);
+                    for &turn in &turns {
+                        board.apply(turn);
+                        board.undo(turn);
+                        assert!(
+                            before == snapshot(&board),
+                            "{:?} in {}",
+                            turn,
+                            board.game_string()
+                        );
+                    }
+
+                    let turn = turns[choice % turns.len()];
+                    board.apply(turn);
+                    shifted.apply(translate_turn(turn, offset));
+                    assert!(connected_except(&board, None), "{}", board.game_string());
+                    for color in 0..2 {
+                        for (bug, &initial) in board.starting.iter().enumerate() {
+                            let placed = all_occupied(&board)
+                                .into_iter()
+                                .map(|hex| board.node(hex))
+                                .chain(board.get_underworld().iter().map(|under| under.node()))
+                                .filter(|node| {
+                                    node.color() as usize == color && node.bug() as usize == bug
+                                })
+                                .count();
+                            let remaining = board.remaining[color][bug];
+                            assert!(remaining <= initial, "{}", board.game_string());
+                            if remaining > 0 || placed > 0 {
+                                assert_eq!(initial as usize, placed + remaining as usize);
+                            }
+                        }
+                    }
+                }
+            }
+
+            proptest::proptest! {
+                #![proptest_config(proptest::test_runner::Config::with_cases(48))]
+
+                #[test]
+                fn test_random_game_invariants(
+                    game_type in proptest::sample::select(vec!["Base", "Base+M", "Base+L", "Base+P", "Base+MLP"]),
+                    choices in proptest::collection::vec(proptest::num::usize::ANY, 0..60),
+                    // Within a few hexes of the start, well clear of wrapping.
+                    (x, y) in (-3i8..=3, -3i8..=3),
+                ) {
+                    let offset = loc_to_hex((x, y)).wrapping_sub(START_HEX);
+                    check_random_game(game_type, &choices, offset);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn test_order_moves() {
+        // The white pillbug can throw the black ant off the white queen.
+        let board = Board::from_game_string(
+            r"Base+P;InProgress;White[6];wP;bG1 wP-;wQ -wP;bQ bG1-;wA1 wQ\;bA1 bQ-;wA2 /wQ;bA1 \wP;wA3 /wA2;bB1 bQ-",
+        )
+        .unwrap();
+        let mut moves = Vec::new();
+        <Rules as minimax::Game>::generate_moves(&board, &mut moves);
+        // Ants to the black queen's liberties come first.
+        let queen = adjacent(board.queens[Color::Black as usize].unwrap());
+        let attacks =
+            moves.iter().take_while(|m| matches!(m, Turn::Move(_, end) if queen.contains(end)));
+        assert_eq!(8, attacks.count());
+        // Then the pillbug throwing the black ant away from the white queen.
+        let throws: Vec<_> = moves[8..10].iter().map(|&m| board.to_move_string(m)).collect();
+        assert!(throws.iter().all(|m| m.starts_with("bA1")));
+        assert!(moves[10..].iter().all(|&m| !board.to_move_string(m).starts_with("bA1")));
+    }
+
+    #[cfg(feature = "engine")]
+    #[test]
+    fn test_widening() {
+        let board = Board::from_game_string(
+            r"Base+P;InProgress;White[6];wP;bG1 wP-;wQ -wP;bQ bG1-;wA1 wQ\;bA1 bQ-;wA2 /wQ;bA1 \wP;wA3 /wA2;bB1 bQ-",
+        )
+        .unwrap();
+        let mut search = board.for_search_with(Some(Widening { full_plies: 1, moves: 5 }));
+        let mut moves = Vec::new();
+        <Rules as minimax::Game>::generate_moves(&board, &mut moves);
+        let mut root_moves = Vec::new();
+        <Rules as minimax::Game>::generate_moves(&search, &mut root_moves);
+        assert_eq!(moves, root_moves);
+
+        // A ply down, black only gets the first moves, and every move put
+        // ahead of the rest.
+        let quiet = *moves.last().unwrap();
+        search.apply(quiet);
+        let mut reply = board.clone();
+        reply.apply(quiet);
+        let mut pruned = Vec::new();
+        <Rules as minimax::Game>::generate_moves(&search, &mut pruned);
+        let mut full = Vec::new();
+        Rules::generate_moves(&reply, &mut full);
+        let forcing = Rules::order_moves(&reply, &mut full);
+        Rules::rank_quiet_moves(&reply, &mut full[forcing..]);
+        let kept = forcing.max(5);
+        assert_eq!(&full[..kept], &pruned[..]);
+        assert!(full.len() > kept);
+        // Ranked by how close they end to the enemy queen.
+        let queen = reply.queens[0].unwrap();
+        let end = |turn: Turn| match turn {
+            Turn::Place(hex, _) | Turn::Move(_, hex) => hex,
+            Turn::Pass => unreachable!(),
+        };
+        let distances = full[forcing..].iter().map(|&turn| hex_distance(end(turn), queen));
+        assert!(distances.collect::<Vec<_>>().is_sorted());
+        assert_eq!(2, hex_distance(queen, Direction::NE.apply(Direction::E.apply(queen))));
+        assert_eq!(2, hex_distance(queen, Direction::SE.apply(Direction::E.apply(queen))));
+
+        // Pruned and full width results are kept apart in the table.
+        assert_eq!(reply.zobrist_hash, search.zobrist_hash);
+        assert_ne!(
+            <Rules as minimax::Game>::zobrist_hash(&reply),
+            <Rules as minimax::Game>::zobrist_hash(&search)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        // A white beetle on top of the white queen, and a move to undo.
+        let game = "Base;InProgress;Black[4];wB1;bB1 wB1-;wQ -wB1;bQ bB1-;wB2 -wQ;bB2 bQ-;wB2 wQ";
+        let mut board = Board::from_game_string(game).unwrap();
+        let json = serde_json::to_string(&board).unwrap();
+        let mut restored: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(game, restored.game_string());
+        assert_eq!(board.zobrist_hash, restored.zobrist_hash);
+        assert_eq!(board.position_key(), restored.position_key());
+        assert_eq!(1, restored.get_underworld().len());
+        let mut moves = board.valid_moves().split(';').map(str::to_owned).collect::<Vec<_>>();
+        let mut restored_moves =
+            restored.valid_moves().split(';').map(str::to_owned).collect::<Vec<_>>();
+        moves.sort();
+        restored_moves.sort();
+        assert_eq!(moves, restored_moves);
+
+        // The history comes along, so both boards undo back to the start.
+        while let Some(turn) = board.last_move() {
+            assert_eq!(Some(turn), restored.last_move());
+            board.undo(turn);
+            restored.undo(turn);
+            assert_eq!(board.zobrist_hash, restored.zobrist_hash);
+            assert_eq!(board.game_string(), restored.game_string());
+        }
+
+        // Tiles that don't match the history are rejected.
+        let tampered = json.replacen("\"White\",\"Queen\"", "\"Black\",\"Queen\"", 1);
+        assert_ne!(json, tampered);
+        assert!(serde_json::from_str::<Board>(&tampered).is_err());
+        assert!(serde_json::from_str::<Board>(&json.replace(",1]", ",7]")).is_err());
+        // So are hands that don't add up with the tiles and the piece set.
+        let tampered = json.replacen("\"remaining\":[[0,3,2,3", "\"remaining\":[[0,3,2,9", 1);
+        assert_ne!(json, tampered);
+        assert!(serde_json::from_str::<Board>(&tampered).is_err());
+
+        // Custom piece sets come along, and states saved without them get
+        // the standard set for their game type.
+        let board = Board::from_game_string("Base+P:A1;InProgress;White[2];wA1;bP wA1-").unwrap();
+        let json = serde_json::to_string(&board).unwrap();
+        let restored: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(board.game_string(), restored.game_string());
+        let tampered = json.replace("\"starting\":[1,3,2,1", "\"starting\":[2,3,2,1");
+        assert_ne!(json, tampered);
+        assert!(serde_json::from_str::<Board>(&tampered).is_err());
+        let board = Board::from_game_string("Base+P;InProgress;White[2];wA1;bP wA1-").unwrap();
+        let json = serde_json::to_string(&board).unwrap();
+        let old = json.replace(",\"starting\":[1,3,2,3,2,0,0,1]", "");
+        assert_ne!(json, old);
+        let restored: Board = serde_json::from_str(&old).unwrap();
+        assert_eq!("Base+P", restored.game_type());
     }
 }
@@ -0,0 +1,211 @@
+use crate::notation::{Result, UhpError};
+use crate::{loc_to_hex, Board, Hex, Rules, Turn};
+
+// Import of game archives from boardspace.net, to use their large corpus of
+// human games for the opening book and tuning. Their records are SGF-like:
+//
+//   (;
+//   GM[27]VE[Hive]SU[hive-lmp]
+//   P0[id "alice"]
+//   ; P0[1 dropb wS1 N 13 .]
+//   ; P1[2 dropb bG1 O 13 wS1-]
+//   ; P0[3 done]
+//   ; P0[4 move W wQ N 14 \wS1]
+//   ...
+//
+// Placements are "dropb piece column row reference" and moves are
+// "move color piece column row reference", with "pdropb" and "pmove" for the
+// same done by a pillbug. Only the column and row are used. Columns are
+// letters and rows numbers on an axial grid, whose orientation relative to
+// ours is found by trying both mirror images. Other actions, like "done" and
+// "pick", only drive the boardspace.net interface and are skipped. UHP has no
+// resignation, so resigned games come out in progress.
+
+// Converts one boardspace.net game record to a UHP GameString.
+pub fn boardspace_to_game_string(record: &str) -> Result<String> {
+    let properties = sgf_properties(record);
+    let game_type = properties
+        .iter()
+        .find(|(name, _)| name == "SU")
+        .map(|(_, variant)| game_type(variant))
+        .unwrap_or_else(|| Ok("Base".to_owned()))?;
+    let actions = properties
+        .iter()
+        .filter(|(name, _)| name == "P0" || name == "P1")
+        .filter_map(|(_, value)| parse_action(value).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    replay(&game_type, &actions, 1)
+        .or_else(|_| replay(&game_type, &actions, -1))
+        .map(|board| board.game_string())
+}
+
+// A placement, move, or pass, with the boardspace.net move number for errors.
+struct Action {
+    number: String,
+    piece: String,
+    // Column and row, or None for a pass.
+    to: Option<(i32, i32)>,
+}
+
+// Name and value of each property, in order.
+fn sgf_properties(record: &str) -> Vec<(String, String)> {
+    let mut properties = Vec::new();
+    let mut name = String::new();
+    let mut chars = record.chars();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if c == '[' {
+            let mut value = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    ']' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            properties.push((std::mem::take(&mut name), value));
+        } else {
+            name.clear();
+        }
+    }
+    properties
+}
+
+fn game_type(variant: &str) -> Result<String> {
+    let err = || UhpError::InvalidGameType(variant.to_owned());
+    let variant = variant.to_ascii_lowercase();
+    let expansions = match variant.strip_prefix("hive") {
+        Some("") => "",
+        Some("-ultimate") => "mlp",
+        Some(expansions) => expansions.strip_prefix('-').ok_or_else(err)?,
+        None => return Err(err()),
+    };
+    if !expansions.chars().all(|c| "mlp".contains(c)) {
+        return Err(err());
+    }
+    let mut game_type = "Base".to_owned();
+    if !expansions.is_empty() {
+        game_type.push('+');
+    }
+    for letter in ['m', 'l', 'p'] {
+        if expansions.contains(letter) {
+            game_type.push(letter.to_ascii_uppercase());
+        }
+    }
+    Ok(game_type)
+}
+
+fn parse_action(value: &str) -> Result<Option<Action>> {
+    let tokens = value.split_whitespace().collect::<Vec<_>>();
+    if tokens.len() < 2 || tokens[0].parse::<u32>().is_err() {
+        // Player info, like P0[id "alice"].
+        return Ok(None);
+    }
+    let err = || UhpError::InvalidMove(value.to_owned());
+    let (piece, column, row) = match tokens[1].to_ascii_lowercase().as_str() {
+        "dropb" | "pdropb" if tokens.len() >= 5 => (tokens[2], tokens[3], tokens[4]),
+        "move" | "pmove" if tokens.len() >= 6 => (tokens[3], tokens[4], tokens[5]),
+        "pass" => {
+            let action = Action { number: tokens[0].to_owned(), piece: String::new(), to: None };
+            return Ok(Some(action));
+        }
+        "dropb" | "pdropb" | "move" | "pmove" => return Err(err()),
+        _ => return Ok(None),
+    };
+    let column = match column.as_bytes() {
+        &[letter] if letter.is_ascii_alphabetic() => letter.to_ascii_uppercase() as i32,
+        _ => return Err(err()),
+    };
+    let row = row.parse::<i32>().map_err(|_| err())?;
+    // Numbers on bugs that only come one to a side are optional.
+    let mut piece = piece.to_owned();
+    if piece.len() == 3 && b"QMLP".contains(&piece.as_bytes()[1]) && piece.ends_with('1') {
+        piece.pop();
+    }
+    Ok(Some(Action { number: tokens[0].to_owned(), piece, to: Some((column, row)) }))
+}
+
+// Plays out the actions, with rows running the direction of row_sign.
+fn replay(game_type: &str, actions: &[Action], row_sign: i32) -> Result<Board> {
+    let mut board = Board::from_game_type(game_type)?;
+    let mut origin = None;
+    let mut moves = Vec::new();
+    for action in actions {
+        let err = || UhpError::InvalidMove(format!("move {} {}", action.number, action.piece));
+        let turn = match action.to {
+            None => Turn::Pass,
+            Some((column, row)) => {
+                let (column0, row0) = *origin.get_or_insert((column, row));
+                let hex: Hex =
+                    loc_to_hex(((column - column0) as i8, ((row - row0) * row_sign) as i8));
+                let (color, bug, bug_num, _) =
+                    board.parse_piece_name(&action.piece).ok_or_else(err)?;
+                match board.find_bug(color, bug, bug_num) {
+                    Some(start) => Turn::Move(start, hex),
+                    None if color == board.to_move() => Turn::Place(hex, bug),
+                    None => return Err(err()),
+                }
+            }
+        };
+        moves.clear();
+        Rules::generate_moves(&board, &mut moves);
+        if !moves.contains(&turn) {
+            return Err(err());
+        }
+        if let Turn::Place(_, bug) = turn {
+            // Placements must also be the piece named.
+            let placed = board.next_bug_num(bug);
+            if board.parse_piece_name(&action.piece).map(|piece| piece.2) != Some(placed) {
+                return Err(err());
+            }
+        }
+        board.apply(turn);
+    }
+    Ok(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: &str = r#"(;
+GM[27]VE[Hive]SU[hive-lmp]
+P0[id "alice"]
+P1[id "bob"]
+; P0[0 Start P0]
+; P0[1 dropb wS1 N 13 .]
+; P0[2 done]
+; P1[3 dropb bG1 O 13 wS1-]
+; P1[4 done]
+; P0[5 dropb wQ M 13 -wS1]
+; P1[6 dropb bQ1 P 13 bG1-]
+; P0[7 pick W M 13 wQ]
+; P0[8 move W wQ N 14 /wS1]
+; P0[9 done]
+; P1[10 Resign]
+)"#;
+
+    #[test]
+    fn test_boardspace() {
+        let game_string = boardspace_to_game_string(RECORD).unwrap();
+        assert!(
+            game_string
+                .starts_with("Base+MLP;InProgress;Black[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-;wQ "),
+            "{}",
+            game_string
+        );
+        // The same game with the rows numbered the other way.
+        let mirrored = boardspace_to_game_string(&RECORD.replace("N 14", "N 12")).unwrap();
+        assert_eq!(game_string.split(';').count(), mirrored.split(';').count());
+        assert!(Board::from_game_string(&mirrored).is_ok());
+
+        assert_eq!("Base", game_type("hive").unwrap());
+        assert_eq!("Base+ML", game_type("Hive-LM").unwrap());
+        assert!(game_type("hive-x").is_err());
+        assert!(game_type("chess").is_err());
+        // A move that doesn't fit either way.
+        assert!(boardspace_to_game_string(&RECORD.replace("N 14", "R 14")).is_err());
+        assert!(boardspace_to_game_string(&RECORD.replace("dropb bG1 O", "dropb bG2 O")).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Bug {
     Queen = 0,
     Grasshopper = 1,
@@ -10,6 +11,9 @@ pub enum Bug {
     Pillbug = 7,
 }
 
+// Kinds of bugs, for tables indexed by Bug.
+pub(crate) const NUM_BUGS: usize = 8;
+
 impl Bug {
     pub fn codepoint(&self) -> char {
         match *self {
@@ -71,6 +75,7 @@ impl Bug {
     }
 
     // Whether this bug can only move (itself) by crawling.
+    #[cfg(feature = "engine")]
     pub(crate) fn crawler(&self) -> bool {
         matches!(*self, Bug::Ant | Bug::Queen | Bug::Spider | Bug::Pillbug)
     }
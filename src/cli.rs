@@ -1,8 +1,9 @@
 extern crate termcolor;
 
-use crate::player::{Player, PlayerConfig};
-use crate::{Board, Bug, Color, Hex, Rules, Turn, ROW_SIZE, START_HEX};
-use minimax::{Game, Strategy};
+use crate::notation::{Result, UhpError};
+use crate::player::{exit, line_value, Decision, Player, PlayerConfig};
+use crate::{BasicEvaluator, Board, Bug, Color, Hex, MctsSearch, Rules, Turn, ROW_SIZE, START_HEX};
+use minimax::Strategy;
 use std::io::{self, BufRead, Write};
 use std::time::Duration;
 use termcolor::WriteColor;
@@ -10,12 +11,12 @@ use termcolor::WriteColor;
 impl Board {
     // return the Hex to the upper left and lower right of all occupied nodes.
     // Given wrapping, the second may be less than the first.
-    fn bounding_box(&self) -> (Hex, Hex, Hex, Hex) {
+    pub(crate) fn bounding_box(&self) -> (Hex, Hex, Hex, Hex) {
         let empty_rows = (0..ROW_SIZE)
-            .map(|r| ((0..ROW_SIZE).all(|c| !self.occupied(r * ROW_SIZE + c))))
+            .map(|r| (0..ROW_SIZE).all(|c| !self.occupied(r * ROW_SIZE + c)))
             .collect::<Vec<bool>>();
         let empty_cols = (0..ROW_SIZE)
-            .map(|c| ((0..ROW_SIZE).all(|r| !self.occupied(r * ROW_SIZE + c))))
+            .map(|c| (0..ROW_SIZE).all(|r| !self.occupied(r * ROW_SIZE + c)))
             .collect::<Vec<bool>>();
         if empty_rows.iter().all(|&r| r) {
             // Center around start hex
@@ -261,50 +262,212 @@ fn input_placement(board: &Board, moves: &[Turn]) -> Option<Turn> {
 
 pub(crate) struct CliPlayer {
     board: Board,
+    // An engine following the game, to suggest moves.
+    hinter: Option<Box<dyn Player>>,
 }
 
+// How long the hint engine thinks.
+const HINT_TIME: Duration = Duration::from_secs(2);
+
 impl CliPlayer {
-    pub(crate) fn new() -> Self {
-        Self { board: Board::default() }
+    pub(crate) fn with_hints(mut hinter: Box<dyn Player>) -> Self {
+        hinter.set_timeout(HINT_TIME);
+        Self { board: Board::default(), hinter: Some(hinter) }
+    }
+
+    // Asks until the player picks a legal move, either step by step or as a
+    // UHP MoveString. With requests, they can also resign, offer a draw
+    // before picking, or take back their last move.
+    fn read_decision(&mut self, requests: bool) -> Decision {
+        let mut moves = Vec::new();
+        Rules::generate_moves(&self.board, &mut moves);
+        if moves[0] == Turn::Pass {
+            return Decision::Play(Turn::Pass);
+        }
+        let mut offer_draw = false;
+        loop {
+            let line = read_line("Your move (? lists moves, help lists commands): ");
+            let input = line.trim();
+            let turn = if input == "?" {
+                for (piece, move_strings) in legal_move_strings(&self.board, &moves) {
+                    println!("{}: {}", piece, move_strings.join(", "));
+                }
+                None
+            } else if input == "help" {
+                self.print_help(requests);
+                None
+            } else if input.starts_with("move") {
+                input_movement(&self.board, &moves)
+            } else if input.starts_with("place") {
+                input_placement(&self.board, &moves)
+            } else if input.starts_with("hint") {
+                self.print_hint();
+                None
+            } else if requests && input.starts_with("takeback") {
+                if self.board.turn_num >= 2 {
+                    return Decision::Takeback;
+                }
+                println!("You have no move to take back.");
+                None
+            } else if requests && input.starts_with("draw") {
+                println!("Your next move comes with a draw offer.");
+                offer_draw = true;
+                None
+            } else if requests && input.starts_with("resign") {
+                return Decision::Resign;
+            } else {
+                self.parse_turn(input, &moves)
+            };
+            match turn {
+                Some(turn) if offer_draw => return Decision::OfferDraw(turn),
+                Some(turn) => return Decision::Play(turn),
+                None => {}
+            }
+        }
+    }
+
+    fn print_help(&self, requests: bool) {
+        println!("wA1 -bQ   play the move, in UHP notation; the start of one lists matches");
+        println!("?         list the legal moves");
+        println!("move      pick a tile on the board, then where it goes");
+        println!("place     pick where a new tile goes, then the bug");
+        if self.hinter.is_some() {
+            println!("hint      suggest a move");
+        }
+        if requests {
+            println!("takeback  take back your last move and the reply to it");
+            println!("draw      offer a draw with your next move");
+            println!("resign    give up the game");
+        }
+    }
+
+    // The engine's move, and the value of the line it expects for the player.
+    fn print_hint(&mut self) {
+        let Some(hinter) = self.hinter.as_mut() else {
+            println!("There is no engine to suggest moves.");
+            return;
+        };
+        match hinter.generate_move() {
+            Ok(turn) => {
+                let pv = hinter.principal_variation();
+                let line = if pv.first() == Some(&turn) { pv } else { vec![turn] };
+                let value = line_value(&self.board, &BasicEvaluator::default(), &line);
+                println!("Hint: {} ({:+})", self.board.to_move_string(turn), value);
+            }
+            Err(err) => println!("No hint: {}", err),
+        }
+    }
+
+    // A legal move typed as a MoveString. Otherwise, prints the moves it
+    // could be the start of, or why it isn't legal.
+    fn parse_turn(&self, input: &str, moves: &[Turn]) -> Option<Turn> {
+        let turn = self
+            .board
+            .from_move_string(input)
+            .and_then(|turn| self.board.check_move(turn, moves).map(|_| turn));
+        let err = match turn {
+            Ok(turn) => return Some(turn),
+            Err(err) => err,
+        };
+        let completions = completions(&self.board, moves, input);
+        if !completions.is_empty() {
+            println!("Did you mean: {}", completions.join(", "));
+            return None;
+        }
+        match err {
+            UhpError::InvalidMove(reason) => println!("{}.", reason),
+            err => println!("{}", err),
+        }
+        None
     }
 }
 
+// Every legal move as a MoveString, grouped by the piece moved or placed.
+fn legal_move_strings(board: &Board, moves: &[Turn]) -> Vec<(String, Vec<String>)> {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for &turn in moves {
+        let move_string = board.to_move_string(turn);
+        let piece = move_string.split(' ').next().unwrap_or_default().to_owned();
+        match groups.iter_mut().find(|(name, _)| *name == piece) {
+            Some((_, group)) => group.push(move_string),
+            None => groups.push((piece, vec![move_string])),
+        }
+    }
+    groups.sort();
+    for (_, group) in groups.iter_mut() {
+        group.sort();
+    }
+    groups
+}
+
+// The legal MoveStrings that start with the input, ignoring case and a
+// trailing tab.
+fn completions(board: &Board, moves: &[Turn], input: &str) -> Vec<String> {
+    let input = input.trim_end_matches('\t').to_ascii_lowercase();
+    if input.is_empty() {
+        return Vec::new();
+    }
+    legal_move_strings(board, moves)
+        .into_iter()
+        .flat_map(|(_, group)| group)
+        .filter(|move_string| move_string.to_ascii_lowercase().starts_with(&input))
+        .collect()
+}
+
 impl Player for CliPlayer {
     fn name(&self) -> String {
         "human".to_owned()
     }
 
-    fn new_game(&mut self, game_type: &str) {
-        self.board = Board::from_game_type(game_type).unwrap();
+    fn new_game(&mut self, game_type: &str) -> Result<()> {
+        self.board = Board::from_game_type(game_type)?;
+        if let Some(hinter) = self.hinter.as_mut() {
+            hinter.new_game(game_type)?;
+        }
+        Ok(())
     }
 
-    fn play_move(&mut self, turn: Turn) {
-        self.board.apply(turn)
+    fn play_move(&mut self, turn: Turn) -> Result<()> {
+        self.board.apply(turn);
+        if let Some(hinter) = self.hinter.as_mut() {
+            hinter.play_move(turn)?;
+        }
+        Ok(())
     }
 
     fn undo_move(&mut self, turn: Turn) {
         self.board.undo(turn);
+        if let Some(hinter) = self.hinter.as_mut() {
+            hinter.undo_move(turn);
+        }
     }
 
-    fn generate_move(&mut self) -> Turn {
-        let mut moves = Vec::new();
-        Rules::generate_moves(&self.board, &mut moves);
-        if moves[0] == Turn::Pass {
-            return Turn::Pass;
-        }
-        loop {
-            let line = read_line("move or place: ");
-            if line.starts_with("move") {
-                if let Some(m) = input_movement(&self.board, &moves) {
-                    break m;
-                }
-            } else if line.starts_with("place") {
-                if let Some(m) = input_placement(&self.board, &moves) {
-                    break m;
-                }
-            }
+    fn generate_move(&mut self) -> Result<Turn> {
+        match self.read_decision(false) {
+            Decision::Play(turn) | Decision::OfferDraw(turn) => Ok(turn),
+            // Only asked for with requests.
+            Decision::Resign | Decision::Takeback => unreachable!(),
         }
     }
+
+    fn decide(&mut self) -> Result<Decision> {
+        Ok(self.read_decision(true))
+    }
+
+    fn accepts_draw(&mut self) -> bool {
+        read_line("Your opponent offers a draw. Accept? [y/n] ").trim().starts_with('y')
+    }
+}
+
+// Writes an SVG image of a game's position to a file, or stdout.
+pub fn render_main(game_string: &str, output: Option<String>) {
+    let board = Board::from_game_string(game_string)
+        .unwrap_or_else(|err| exit(format!("Could not parse game string: {}", err)));
+    match output {
+        Some(path) => std::fs::write(&path, board.to_svg())
+            .unwrap_or_else(|err| exit(format!("Could not write {}: {}", path, err))),
+        None => print!("{}", board.to_svg()),
+    }
 }
 
 pub fn terminal_game_interface(config: PlayerConfig) {
@@ -348,15 +511,15 @@ pub fn terminal_game_interface(config: PlayerConfig) {
             } else {
                 player.set_timeout(Duration::from_secs(5));
             }
-            let m = player.generate_move();
-            player.play_move(m);
+            let m = player.generate_move().unwrap();
+            player.play_move(m).unwrap();
             prev_pv_board = board.clone();
             prev_pv = player.principal_variation();
             history.push(m);
             board.apply(m);
         } else if line.starts_with("mcts") {
-            let opts = minimax::MCTSOptions::default().with_max_rollout_depth(200);
-            let mut mcts = minimax::MonteCarloTreeSearch::<Rules>::new(opts);
+            let opts = minimax::MCTSOptions::default();
+            let mut mcts = MctsSearch::new(opts, 200, 0.5).verbose();
             for arg in line.split(' ').skip(1) {
                 if let Ok(num) = arg.parse::<u32>() {
                     mcts.set_max_rollouts(num);
@@ -365,26 +528,34 @@ pub fn terminal_game_interface(config: PlayerConfig) {
             if let Some(m) = mcts.choose_move(&board) {
                 history.push(m);
                 board.apply(m);
-                player.play_move(m);
+                player.play_move(m).unwrap();
             }
         } else if line.starts_with("move") {
             if let Some(m) = input_movement(&board, &moves) {
                 history.push(m);
                 board.apply(m);
-                player.play_move(m);
+                player.play_move(m).unwrap();
             }
         } else if line.starts_with("place") {
             if let Some(m) = input_placement(&board, &moves) {
                 history.push(m);
                 board.apply(m);
-                player.play_move(m);
+                player.play_move(m).unwrap();
             }
         } else if line.starts_with("pass") {
             history.push(Turn::Pass);
             board.apply(Turn::Pass);
-            player.play_move(Turn::Pass);
-        } else if line.starts_with("undo") {
-            if let Some(m) = history.pop() {
+            player.play_move(Turn::Pass).unwrap();
+        } else if line.starts_with("hint") {
+            let m = player.generate_move().unwrap();
+            let value =
+                line_value(&board, &BasicEvaluator::default(), &player.principal_variation());
+            println!("Hint: {} ({:+})", board.to_move_string(m), value);
+        } else if line.starts_with("undo") || line.starts_with("takeback") {
+            // Takeback undoes the last move of the player to move, and the
+            // reply to it.
+            let count = if line.starts_with("undo") { 1 } else { 2 };
+            for m in history.split_off(history.len().saturating_sub(count)).into_iter().rev() {
                 board.undo(m);
                 player.undo_move(m);
             }
@@ -408,11 +579,56 @@ pub fn terminal_game_interface(config: PlayerConfig) {
                 println!("Invalid game string");
                 continue;
             };
-            player.new_game(&game_string);
+            player.new_game(&game_string).unwrap();
         } else if line.starts_with('q') || line.starts_with("exit") {
             break;
         } else {
-            println!("commands: ai, pv, move, place, pass, undo, quit");
+            println!("commands: ai, hint, pv, move, place, pass, undo, takeback, quit");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_input() {
+        let board =
+            Board::from_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-")
+                .unwrap();
+        let player = CliPlayer { board, hinter: None };
+        let mut moves = Vec::new();
+        Rules::generate_moves(&player.board, &mut moves);
+
+        let groups = legal_move_strings(&player.board, &moves);
+        let pieces = groups.iter().map(|(piece, _)| piece.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["wA1", "wB1", "wG1", "wQ", "wS2"], pieces);
+        assert_eq!(moves.len(), groups.iter().map(|(_, group)| group.len()).sum::<usize>());
+        assert!(groups[3].1.iter().all(|move_string| move_string.starts_with("wQ ")));
+
+        let queen_moves = completions(&player.board, &moves, "wq\t");
+        assert_eq!(groups[3].1, queen_moves);
+        assert!(completions(&player.board, &moves, "bQ").is_empty());
+        assert_eq!(
+            Some(moves[0]),
+            player.parse_turn(&player.board.to_move_string(moves[0]), &moves)
+        );
+        assert_eq!(None, player.parse_turn("wS1 bQ-", &moves));
+
+        // The reasons a typed move isn't legal.
+        let reason = |board: &Board, move_string: &str| {
+            let mut moves = Vec::new();
+            Rules::generate_moves(board, &mut moves);
+            match board.check_move(board.from_move_string(move_string).unwrap(), &moves) {
+                Err(UhpError::InvalidMove(reason)) => reason,
+                other => panic!("{} is {:?}", move_string, other),
+            }
+        };
+        assert!(reason(&player.board, "wQ bQ-").contains("can't reach"));
+        assert!(reason(&player.board, "wS1 bQ-").contains("can't move"));
+        assert!(reason(&player.board, "wA1 bQ-").contains("touch its own color"));
+        let board = Board::from_game_string("Base;InProgress;White[2];wS1;bG1 wS1-").unwrap();
+        assert!(reason(&board, "wS1 bG1-").contains("queen must be placed"));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::Board;
+use std::time::Duration;
+
+// Total thinking time for a game, plus an increment added after each move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub total: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    // Parse e.g. "10m+5s" or "90s".
+    pub fn parse(input: &str) -> Option<Self> {
+        let (total, increment) = match input.split_once('+') {
+            Some((total, increment)) => (total, parse_duration(increment)?),
+            None => (input, Duration::ZERO),
+        };
+        Some(TimeControl { total: parse_duration(total)?, increment })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write_duration(f, self.total)?;
+        if !self.increment.is_zero() {
+            write!(f, "+")?;
+            write_duration(f, self.increment)?;
+        }
+        Ok(())
+    }
+}
+
+// In the units parse_duration accepts.
+fn write_duration(f: &mut std::fmt::Formatter, duration: Duration) -> std::fmt::Result {
+    let secs = duration.as_secs();
+    if secs > 0 && secs.is_multiple_of(60) {
+        write!(f, "{}m", secs / 60)
+    } else {
+        write!(f, "{}s", secs)
+    }
+}
+
+// Parse a duration with units, e.g. "5s" or "2m".
+pub(crate) fn parse_duration(input: &str) -> Option<Duration> {
+    if let Some(secs) = input.strip_suffix('s') {
+        secs.parse::<u64>().ok().map(Duration::from_secs)
+    } else if let Some(mins) = input.strip_suffix('m') {
+        mins.parse::<u64>().ok().map(|m| Duration::from_secs(m * 60))
+    } else {
+        None
+    }
+}
+
+// Kept back from every budget to cover communication and bookkeeping.
+const RESERVE: Duration = Duration::from_millis(50);
+
+// How many more moves we expect to make this game.
+fn expected_moves_left(board: &Board) -> u32 {
+    let moves_made = board.turn_num as u32 / 2;
+    40u32.saturating_sub(moves_made).max(15)
+}
+
+// How long to search for the next move with the given time left on our clock.
+pub fn move_budget(remaining: Duration, increment: Duration, board: &Board) -> Duration {
+    let usable = remaining.saturating_sub(RESERVE);
+    let mut budget = usable / expected_moves_left(board) + increment * 3 / 4;
+    if board.turn_num < 4 {
+        // Opening placements don't deserve much thought.
+        budget /= 4;
+    }
+    // Never bet more than half of what's left on a single move.
+    budget.min(usable / 2).max(Duration::from_millis(10))
+}
+
+#[test]
+fn test_move_budget() {
+    assert_eq!(
+        Some(TimeControl { total: Duration::from_secs(600), increment: Duration::from_secs(5) }),
+        TimeControl::parse("10m+5s")
+    );
+    assert_eq!(Some(Duration::from_secs(90)), TimeControl::parse("90s").map(|tc| tc.total));
+    assert_eq!(None, TimeControl::parse("10"));
+    assert_eq!(None, TimeControl::parse("10m+"));
+    for input in ["10m+5s", "90s", "1m+1m"] {
+        assert_eq!(input, TimeControl::parse(input).unwrap().to_string());
+    }
+
+    let board = Board::from_game_string(
+        "Base;InProgress;White[5];wG1;bG1 wG1-;wQ -wG1;bQ bG1-;wA1 \\wQ;bA1 bQ/;wA2 /wG1;bA2 bQ\\",
+    )
+    .unwrap();
+    let opening = Board::from_game_type("Base").unwrap();
+    let minute = Duration::from_secs(60);
+    let budget = move_budget(minute, Duration::ZERO, &board);
+    assert!(budget > Duration::from_secs(1) && budget < Duration::from_secs(3));
+    // Spend less in the opening, more with an increment, and less when low on time.
+    assert!(move_budget(minute, Duration::ZERO, &opening) < budget);
+    assert!(move_budget(minute, Duration::from_secs(2), &board) > budget);
+    assert!(move_budget(minute / 10, Duration::ZERO, &board) < budget);
+    // Don't flag with the increment alone.
+    let low = Duration::from_millis(500);
+    assert!(move_budget(low, Duration::from_secs(10), &board) < low);
+}
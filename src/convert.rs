@@ -0,0 +1,222 @@
+use crate::notation::{Result, UhpError};
+use crate::player::exit;
+use crate::{boardspace_to_game_string, Board, GameRecord};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Batch conversion of game archives between the notations nokamute reads:
+// UHP GameStrings one per line, boardspace.net records one per file, and our
+// own game records separated by blank lines. Every game is replayed to check
+// it, and malformed games are reported and skipped rather than ending the
+// whole batch.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GameFormat {
+    Uhp,
+    BoardSpace,
+    Record,
+}
+
+impl GameFormat {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "uhp" => Some(GameFormat::Uhp),
+            "boardspace" | "sgf" => Some(GameFormat::BoardSpace),
+            "record" => Some(GameFormat::Record),
+            _ => None,
+        }
+    }
+
+    // Guess the format of a file from its first line.
+    fn detect(text: &str) -> Self {
+        match text.trim_start() {
+            text if text.starts_with('(') => GameFormat::BoardSpace,
+            text if text.starts_with('[') => GameFormat::Record,
+            _ => GameFormat::Uhp,
+        }
+    }
+}
+
+// Each game in a file, with the line it starts on.
+fn read_games(text: &str, format: GameFormat) -> Vec<(usize, Result<GameRecord>)> {
+    let start = text.lines().position(|line| !line.trim().is_empty()).unwrap_or(0) + 1;
+    match format {
+        GameFormat::Uhp => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .map(|(i, line)| {
+                let game = Board::from_game_string(line.trim())
+                    .map(|board| GameRecord::from_board(&board, "", ""));
+                (i + 1, game)
+            })
+            .collect(),
+        GameFormat::BoardSpace => {
+            let game = boardspace_to_game_string(text)
+                .and_then(|game_string| Board::from_game_string(&game_string))
+                .map(|board| GameRecord::from_board(&board, "", ""));
+            vec![(start, game)]
+        }
+        GameFormat::Record => {
+            let mut games = Vec::new();
+            let mut current = String::new();
+            let mut current_start = 0;
+            for (i, line) in text.lines().chain(std::iter::once("")).enumerate() {
+                if line.trim().is_empty() {
+                    if !current.is_empty() {
+                        games.push((current_start, GameRecord::parse(&current)));
+                        current.clear();
+                    }
+                } else {
+                    if current.is_empty() {
+                        current_start = i + 1;
+                    }
+                    current.push_str(line);
+                    current.push('\n');
+                }
+            }
+            games
+        }
+    }
+}
+
+// Replay the game to check it, and write it out. Records come out with a
+// trailing blank line to separate them.
+fn write_game(record: &GameRecord, format: GameFormat) -> Result<String> {
+    let board = record.to_board()?;
+    match format {
+        GameFormat::Uhp => Ok(format!("{}\n", board.game_string())),
+        GameFormat::Record => Ok(format!("{}\n", record)),
+        GameFormat::BoardSpace => Err(UhpError::InvalidGameType("boardspace".to_owned())),
+    }
+}
+
+// Every file under the path, in a stable order.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        // Skip hidden files, like editor swap files.
+        if entry.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        collect_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+// Convert every game in the files and directories given, reporting each game
+// that fails with its file and line. Returns the number converted and failed.
+pub(crate) fn convert_files(
+    paths: &[String], from: Option<GameFormat>, to: GameFormat, out: &mut impl Write,
+) -> std::io::Result<(usize, usize)> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+    let (mut converted, mut failed) = (0, 0);
+    for file in files {
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("{}: {}", file.display(), err);
+                failed += 1;
+                continue;
+            }
+        };
+        let format = from.unwrap_or_else(|| GameFormat::detect(&text));
+        for (line, game) in read_games(&text, format) {
+            match game.and_then(|record| write_game(&record, to)) {
+                Ok(game) => {
+                    out.write_all(game.as_bytes())?;
+                    converted += 1;
+                }
+                Err(err) => {
+                    eprintln!("{}:{}: {:?}", file.display(), line, err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    Ok((converted, failed))
+}
+
+pub fn convert_main(paths: &[String], from: Option<String>, to: &str, output: Option<String>) {
+    let from = from.map(|name| {
+        GameFormat::from_name(&name).unwrap_or_else(|| {
+            exit(format!("Unknown format {}: use uhp, boardspace, or record", name))
+        })
+    });
+    let to = match GameFormat::from_name(to) {
+        Some(GameFormat::BoardSpace) | None => {
+            exit(format!("Can't write {}: use uhp or record", to))
+        }
+        Some(to) => to,
+    };
+    let result = match &output {
+        Some(path) => {
+            fs::File::create(path).and_then(|mut file| convert_files(paths, from, to, &mut file))
+        }
+        None => convert_files(paths, from, to, &mut std::io::stdout().lock()),
+    };
+    let (converted, failed) =
+        result.unwrap_or_else(|err| exit(format!("Could not convert: {}", err)));
+    eprintln!("Converted {} games, {} failed", converted, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let dir = std::env::temp_dir().join(format!("nokamute-convert-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let games = "Base;InProgress;White[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-\n\
+                     Base;InProgress;White[2];wS1;bG1 wS2-\n\
+                     \n\
+                     Base+M;InProgress;Black[1];wM\n";
+        fs::write(dir.join("games.txt"), games).unwrap();
+        fs::write(dir.join("sub").join("empty.txt"), "").unwrap();
+
+        // To records and back, skipping the illegal second game.
+        let mut records = Vec::new();
+        let dirs = [dir.to_str().unwrap().to_owned()];
+        let (converted, failed) =
+            convert_files(&dirs, None, GameFormat::Record, &mut records).unwrap();
+        assert_eq!((2, 1), (converted, failed));
+        let records = String::from_utf8(records).unwrap();
+        assert!(records.starts_with("[GameType \"Base\"]\n"), "{}", records);
+        assert!(records.contains("\n\n[GameType \"Base+M\"]\n"), "{}", records);
+
+        fs::remove_file(dir.join("games.txt")).unwrap();
+        fs::write(dir.join("sub").join("records.txt"), &records).unwrap();
+        let mut game_strings = Vec::new();
+        assert_eq!((2, 0), convert_files(&dirs, None, GameFormat::Uhp, &mut game_strings).unwrap());
+        let expected =
+            games.lines().filter(|line| line.starts_with("Base") && !line.contains("wS2"));
+        assert_eq!(
+            expected.map(|line| format!("{}\n", line)).collect::<String>(),
+            String::from_utf8(game_strings).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Malformed records are reported by the line they start on.
+        let text = "[GameType \"Base\"]\n1. wS1\n\n[GameType \"Base\"]\n1. wS1\n1... wQ\n";
+        let games = read_games(text, GameFormat::Record);
+        assert_eq!(vec![1, 4], games.iter().map(|(line, _)| *line).collect::<Vec<_>>());
+        assert!(write_game(games[0].1.as_ref().unwrap(), GameFormat::Uhp).is_ok());
+        assert!(write_game(games[1].1.as_ref().unwrap(), GameFormat::Uhp).is_err());
+        assert_eq!(GameFormat::BoardSpace, GameFormat::detect("\n(;\nGM[27]"));
+    }
+}
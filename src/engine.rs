@@ -0,0 +1,438 @@
+use crate::notation::{GameResult, Result, UhpError};
+use crate::{hex_to_loc, loc_to_hex, BasicEvaluator, Board, Bug, Color, Rules, Turn};
+use minimax::{Evaluator, Game, IterativeOptions, ParallelOptions, ParallelSearch, Strategy};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+// Typed interface to the rules and search for programs embedding nokamute,
+// without going through UHP strings.
+
+// A tile, e.g. the second white ant.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Piece {
+    pub color: Color,
+    pub bug: Bug,
+    // Starts at 1, in the order of placement.
+    pub number: u8,
+}
+
+// Hex coordinates: x increases to the east and y to the southwest, with the
+// first tile placed at (0, 0).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub x: i8,
+    pub y: i8,
+}
+
+impl Position {
+    fn from_hex(hex: crate::Hex) -> Self {
+        let (x, y) = hex_to_loc(hex);
+        Position { x, y }
+    }
+
+    fn hex(self) -> crate::Hex {
+        loc_to_hex((self.x, self.y))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Action {
+    Place { piece: Piece, to: Position },
+    Move { piece: Piece, from: Position, to: Position },
+    Pass,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SearchLimit {
+    Depth(u8),
+    Time(Duration),
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResult {
+    pub best: Action,
+    // For the player to move; minimax::BEST_EVAL is a forced win.
+    pub value: i16,
+    // Expected line of play, starting with the best move.
+    pub principal_variation: Vec<Action>,
+}
+
+impl SearchLimit {
+    // Whether a search with this limit went at least as far as one with the
+    // other. Depths and times aren't comparable.
+    fn covers(self, other: SearchLimit) -> bool {
+        match (self, other) {
+            (SearchLimit::Depth(a), SearchLimit::Depth(b)) => a >= b,
+            (SearchLimit::Time(a), SearchLimit::Time(b)) => a >= b,
+            _ => false,
+        }
+    }
+}
+
+// A finished search from the root, kept so it isn't repeated.
+struct CachedSearch {
+    limit: SearchLimit,
+    value: i16,
+    pv: Vec<Turn>,
+}
+
+pub struct Engine {
+    board: Board,
+    eval: BasicEvaluator,
+    opts: IterativeOptions,
+    parallel_opts: ParallelOptions,
+    search: Option<ParallelSearch<BasicEvaluator>>,
+    // Results of past searches by zobrist hash of the position. Unlike the
+    // search's own transposition table, this can be saved to disk and loaded
+    // into a later session.
+    cache: HashMap<u64, CachedSearch>,
+}
+
+impl Engine {
+    // New game of the given UHP GameTypeString, e.g. "Base+MLP".
+    pub fn new(game_type: &str) -> Result<Self> {
+        Self::from_game_string(game_type)
+    }
+
+    // Resume a game from a UHP GameString.
+    pub fn from_game_string(game_string: &str) -> Result<Self> {
+        Ok(Engine {
+            board: Board::from_game_string(game_string)?,
+            eval: BasicEvaluator::default(),
+            opts: IterativeOptions::new().with_countermoves().with_table_byte_size(100 << 20),
+            parallel_opts: ParallelOptions::new(),
+            search: None,
+            cache: HashMap::new(),
+        })
+    }
+
+    // Search threads; defaults to one per core.
+    pub fn with_threads(mut self, num_threads: usize) -> Self {
+        self.parallel_opts = self.parallel_opts.with_num_threads(num_threads);
+        self.search = None;
+        self
+    }
+
+    pub fn with_table_size_mb(mut self, size: usize) -> Self {
+        self.opts = self.opts.with_table_byte_size(size << 20);
+        self.search = None;
+        self
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn game_string(&self) -> String {
+        self.board.game_string()
+    }
+
+    pub fn to_move(&self) -> Color {
+        self.board.to_move()
+    }
+
+    // None while the game is in progress.
+    pub fn result(&self) -> Option<GameResult> {
+        self.board.game_result()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Action> {
+        let mut turns = Vec::new();
+        Rules::generate_moves(&self.board, &mut turns);
+        turns.into_iter().map(|turn| self.action(turn)).collect()
+    }
+
+    pub fn apply(&mut self, action: Action) -> Result<()> {
+        let turn = self.turn(action)?;
+        self.board.apply_untrusted(turn)
+    }
+
+    // Take back the last move, returning it.
+    pub fn undo(&mut self) -> Option<Action> {
+        let turn = self.board.last_move()?;
+        self.board.undo(turn);
+        Some(self.action(turn))
+    }
+
+    // Static evaluation for the player to move.
+    pub fn evaluate(&self) -> i16 {
+        self.eval.evaluate(&self.board)
+    }
+
+    // None if the game is over. Positions searched before with at least the
+    // same limit are answered from the cache.
+    pub fn best_move(&mut self, limit: SearchLimit) -> Option<SearchResult> {
+        if Rules::get_winner(&self.board).is_some() {
+            return None;
+        }
+        let hash = Rules::zobrist_hash(&self.board);
+        if let Some(cached) = self.cache.get(&hash).filter(|cached| cached.limit.covers(limit)) {
+            let principal_variation = self.pv_actions(&cached.pv);
+            // A hash collision with another position is very unlikely, but a
+            // bad cache file would be caught here too.
+            if let Some(&best) = principal_variation.first() {
+                return Some(SearchResult { best, value: cached.value, principal_variation });
+            }
+        }
+
+        let (eval, opts, parallel_opts) = (self.eval, self.opts, self.parallel_opts);
+        let search =
+            self.search.get_or_insert_with(|| ParallelSearch::new(eval, opts, parallel_opts));
+        match limit {
+            SearchLimit::Depth(depth) => search.set_max_depth(depth),
+            SearchLimit::Time(time) => search.set_timeout(time),
+        }
+        let best = search.choose_move(&self.board.for_search())?;
+        let value = search.root_value();
+        let mut pv = search.principal_variation();
+        if pv.first() != Some(&best) {
+            pv = vec![best];
+        }
+        let principal_variation = self.pv_actions(&pv);
+        self.cache.insert(hash, CachedSearch { limit, value, pv });
+        Some(SearchResult { best: self.action(best), value, principal_variation })
+    }
+
+    // Write the search cache to a file, to be loaded into another session.
+    // Hexes are already u16 with larger-grid.
+    #[cfg_attr(feature = "larger-grid", allow(clippy::useless_conversion))]
+    pub fn save_cache(&self, path: &Path) -> Result<()> {
+        let mut out = Vec::new();
+        out.extend_from_slice(CACHE_MAGIC);
+        out.extend_from_slice(&(crate::GRID_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&(self.cache.len() as u64).to_le_bytes());
+        for (hash, cached) in self.cache.iter() {
+            out.extend_from_slice(&hash.to_le_bytes());
+            let (kind, amount) = match cached.limit {
+                SearchLimit::Depth(depth) => (0u8, depth as u64),
+                SearchLimit::Time(time) => (1u8, time.as_millis() as u64),
+            };
+            out.push(kind);
+            out.extend_from_slice(&amount.to_le_bytes());
+            out.extend_from_slice(&cached.value.to_le_bytes());
+            out.extend_from_slice(&(cached.pv.len() as u16).to_le_bytes());
+            for &turn in cached.pv.iter() {
+                let (kind, a, b) = match turn {
+                    Turn::Place(hex, bug) => (0u8, u16::from(hex), bug as u16),
+                    Turn::Move(start, end) => (1u8, u16::from(start), u16::from(end)),
+                    Turn::Pass => (2u8, 0, 0),
+                };
+                out.push(kind);
+                out.extend_from_slice(&a.to_le_bytes());
+                out.extend_from_slice(&b.to_le_bytes());
+            }
+        }
+        std::fs::File::create(path)?.write_all(&out)?;
+        Ok(())
+    }
+
+    // Merge a file written by save_cache into the search cache. Where both
+    // have searched a position, the longer search is kept.
+    pub fn load_cache(&mut self, path: &Path) -> Result<()> {
+        let mut data = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut data)?;
+        let mut reader = CacheReader(&data);
+        if reader.take(CACHE_MAGIC.len())? != CACHE_MAGIC
+            || reader.u32()? != crate::GRID_SIZE as u32
+        {
+            return Err(invalid_cache());
+        }
+        for _ in 0..reader.u64()? {
+            let hash = reader.u64()?;
+            let limit = match (reader.u8()?, reader.u64()?) {
+                (0, depth) => SearchLimit::Depth(u8::try_from(depth).map_err(|_| invalid_cache())?),
+                (1, millis) => SearchLimit::Time(Duration::from_millis(millis)),
+                _ => return Err(invalid_cache()),
+            };
+            let value = reader.u16()? as i16;
+            let mut pv = Vec::new();
+            for _ in 0..reader.u16()? {
+                let (kind, a, b) = (reader.u8()?, reader.u16()?, reader.u16()?);
+                let hex = |x: u16| crate::Hex::try_from(x).map_err(|_| invalid_cache());
+                pv.push(match kind {
+                    0 => Turn::Place(
+                        hex(a)?,
+                        Bug::iter_all().nth(b as usize).ok_or_else(invalid_cache)?,
+                    ),
+                    1 => Turn::Move(hex(a)?, hex(b)?),
+                    2 => Turn::Pass,
+                    _ => return Err(invalid_cache()),
+                });
+            }
+            let cached = CachedSearch { limit, value, pv };
+            match self.cache.get(&hash) {
+                Some(prev) if !cached.limit.covers(prev.limit) => {}
+                _ => {
+                    self.cache.insert(hash, cached);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // UHP MoveString for the action, e.g. "wA1 -bQ".
+    pub fn move_string(&self, action: Action) -> Result<String> {
+        Ok(self.board.to_move_string(self.turn(action)?))
+    }
+
+    pub fn parse_move(&self, move_string: &str) -> Result<Action> {
+        Ok(self.action(self.board.from_move_string(move_string)?))
+    }
+
+    fn action(&self, turn: Turn) -> Action {
+        action(&self.board, turn)
+    }
+
+    // Actions for a line of play, up to the first move that isn't legal.
+    fn pv_actions(&self, pv: &[Turn]) -> Vec<Action> {
+        let mut board = self.board.clone();
+        let mut actions = Vec::new();
+        let mut turns = Vec::new();
+        for &turn in pv {
+            turns.clear();
+            Rules::generate_moves(&board, &mut turns);
+            if !turns.contains(&turn) {
+                break;
+            }
+            actions.push(action(&board, turn));
+            board.apply(turn);
+        }
+        actions
+    }
+
+    // Checks that the action is legal and names the right piece.
+    fn turn(&self, action: Action) -> Result<Turn> {
+        let turn = match action {
+            Action::Place { piece, to } => Turn::Place(to.hex(), piece.bug),
+            Action::Move { from, to, .. } => Turn::Move(from.hex(), to.hex()),
+            Action::Pass => Turn::Pass,
+        };
+        let mut turns = Vec::new();
+        Rules::generate_moves(&self.board, &mut turns);
+        if !turns.contains(&turn) {
+            let reason = self.board.illegal_reason(turn, &turns);
+            return Err(UhpError::InvalidMove(format!("{:?}: {}", action, reason)));
+        }
+        if self.action(turn) != action {
+            return Err(UhpError::InvalidMove(format!("{:?}", action)));
+        }
+        Ok(turn)
+    }
+}
+
+const CACHE_MAGIC: &[u8] = b"nokamute cache 1\n";
+
+fn invalid_cache() -> UhpError {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid cache file").into()
+}
+
+struct CacheReader<'a>(&'a [u8]);
+
+impl<'a> CacheReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid_cache());
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn action(board: &Board, turn: Turn) -> Action {
+    match turn {
+        Turn::Place(hex, bug) => {
+            let number = board.next_bug_num(bug);
+            let piece = Piece { color: board.to_move(), bug, number };
+            Action::Place { piece, to: Position::from_hex(hex) }
+        }
+        Turn::Move(from, to) => {
+            let node = board.node(from);
+            let piece = Piece { color: node.color(), bug: node.bug(), number: node.bug_num() };
+            Action::Move { piece, from: Position::from_hex(from), to: Position::from_hex(to) }
+        }
+        Turn::Pass => Action::Pass,
+    }
+}
+
+#[test]
+fn test_engine() {
+    let mut engine = Engine::new("Base").unwrap().with_threads(1).with_table_size_mb(1);
+    assert_eq!(4, engine.legal_moves().len());
+    let ant = Piece { color: Color::White, bug: Bug::Ant, number: 1 };
+    let origin = Position { x: 0, y: 0 };
+    engine.apply(Action::Place { piece: ant, to: origin }).unwrap();
+    assert_eq!("Base;InProgress;Black[1];wA1", engine.game_string());
+
+    // Wrong piece number, occupied destination, wrong color.
+    let beetle = Piece { color: Color::Black, bug: Bug::Beetle, number: 1 };
+    let east = Position { x: 1, y: 0 };
+    assert!(engine
+        .apply(Action::Place { piece: Piece { number: 2, ..beetle }, to: east })
+        .is_err());
+    assert!(engine.apply(Action::Place { piece: beetle, to: origin }).is_err());
+    assert!(engine.apply(Action::Place { piece: ant, to: east }).is_err());
+    let action = Action::Place { piece: beetle, to: east };
+    assert_eq!("bB1 wA1-", engine.move_string(action).unwrap());
+    assert_eq!(action, engine.parse_move("bB1 wA1-").unwrap());
+    engine.apply(action).unwrap();
+
+    let result = engine.best_move(SearchLimit::Depth(2)).unwrap();
+    assert!(engine.legal_moves().contains(&result.best));
+    assert_eq!(Some(&result.best), result.principal_variation.first());
+    engine.apply(result.best).unwrap();
+    assert_eq!(Some(result.best), engine.undo());
+    assert_eq!(Some(action), engine.undo());
+    assert_eq!(None, engine.result());
+}
+
+#[test]
+fn test_engine_cache() {
+    let game = "Base;InProgress;White[2];wA1;bB1 wA1-";
+    let mut engine = Engine::from_game_string(game).unwrap().with_threads(1).with_table_size_mb(1);
+    let result = engine.best_move(SearchLimit::Depth(2)).unwrap();
+    let path = std::env::temp_dir().join(format!("nokamute-cache-{}", std::process::id()));
+    engine.save_cache(&path).unwrap();
+
+    // A new session answers from the loaded cache, unless asked to go deeper.
+    let mut engine = Engine::from_game_string(game).unwrap().with_threads(1).with_table_size_mb(1);
+    engine.load_cache(&path).unwrap();
+    let cached = engine.best_move(SearchLimit::Depth(1)).unwrap();
+    assert_eq!(result.best, cached.best);
+    assert_eq!(result.value, cached.value);
+    assert_eq!(result.principal_variation, cached.principal_variation);
+    // A fresh depth 1 search would only have one move in its line.
+    assert_eq!(2, cached.principal_variation.len());
+    assert!(engine.best_move(SearchLimit::Time(Duration::from_millis(10))).is_some());
+
+    // Truncated files are rejected.
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+    assert!(engine.load_cache(&path).is_err());
+    std::fs::write(&path, b"garbage").unwrap();
+    assert!(engine.load_cache(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
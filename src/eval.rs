@@ -2,7 +2,9 @@ use crate::board::*;
 use crate::bug::Bug;
 use crate::hex_grid::*;
 
-use minimax::{Evaluation, Evaluator};
+use minimax::{Evaluation, Evaluator, Game, BEST_EVAL, WORST_EVAL};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
 
 // An evaluator that knows nothing but the rules, and maximally explores the tree.
 pub struct DumbEvaluator;
@@ -14,16 +16,343 @@ impl Evaluator for DumbEvaluator {
     }
 }
 
+// The evaluator searches use, chosen on the command line.
+#[derive(Clone)]
+pub(crate) enum AnyEvaluator {
+    Dumb,
+    Basic(BasicEvaluator),
+    #[cfg(feature = "nnue")]
+    Neural(crate::NeuralEvaluator),
+}
+
+impl Evaluator for AnyEvaluator {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        match self {
+            AnyEvaluator::Dumb => DumbEvaluator.evaluate(board),
+            AnyEvaluator::Basic(eval) => eval.evaluate(board),
+            #[cfg(feature = "nnue")]
+            AnyEvaluator::Neural(eval) => eval.evaluate(board),
+        }
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        match self {
+            AnyEvaluator::Dumb => {}
+            AnyEvaluator::Basic(eval) => eval.generate_noisy_moves(board, moves),
+            #[cfg(feature = "nnue")]
+            AnyEvaluator::Neural(_) => placement_followups(board, moves),
+        }
+    }
+}
+
+// Shifts every evaluation toward the color the engine is playing by the
+// contempt. Draws are always scored 0, so with positive contempt the engine
+// plays on rather than take a draw from an even position, and with negative
+// contempt it steers toward draws.
+#[derive(Clone)]
+pub(crate) struct Contempt<E> {
+    eval: E,
+    contempt: Evaluation,
+    // The color the engine is searching for, set before each search.
+    engine_color: Arc<AtomicU8>,
+}
+
+impl<E> Contempt<E> {
+    pub(crate) fn new(eval: E, contempt: Evaluation, engine_color: Arc<AtomicU8>) -> Self {
+        Self { eval, contempt, engine_color }
+    }
+}
+
+impl<E: Evaluator<G = Rules>> Evaluator for Contempt<E> {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        let value = self.eval.evaluate(board);
+        if self.contempt == 0 {
+            return value;
+        }
+        if board.to_move() as u8 == self.engine_color.load(Ordering::Relaxed) {
+            value.saturating_add(self.contempt)
+        } else {
+            value.saturating_sub(self.contempt)
+        }
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        self.eval.generate_noisy_moves(board, moves);
+    }
+}
+
+// Adds noise to evaluations, to weaken the engine for casual players. The
+// noise is a hash of the position and a seed, so the same position always
+// gets the same value within a search and transpositions stay consistent.
+#[derive(Clone)]
+pub(crate) struct Noise<E> {
+    eval: E,
+    amplitude: Evaluation,
+    seed: u64,
+}
+
+impl<E> Noise<E> {
+    pub(crate) fn new(eval: E, amplitude: Evaluation, seed: u64) -> Self {
+        Self { eval, amplitude, seed }
+    }
+}
+
+impl<E: Evaluator<G = Rules>> Evaluator for Noise<E> {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        let value = self.eval.evaluate(board);
+        if self.amplitude <= 0 {
+            return value;
+        }
+        // The splitmix64 finalizer, to spread the hash bits.
+        let mut x = Rules::zobrist_hash(board) ^ self.seed;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        let span = 2 * self.amplitude as u64 + 1;
+        let noise = (x % span) as Evaluation - self.amplitude;
+        value.saturating_add(noise)
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        self.eval.generate_noisy_moves(board, moves);
+    }
+}
+
+// How far past the horizon to search when the last move left a queen one move
+// from being surrounded: a reply, and the move that could finish it.
+const THREAT_EXTENSION_PLIES: u8 = 2;
+
+// Extends the search at leaves where the last move put a queen one move from
+// being surrounded, rather than trusting a static evaluation of a position
+// that may be lost next move. The minimax crate has no hook for extensions,
+// so this searches THREAT_EXTENSION_PLIES more with full width from the leaf
+// as part of evaluating it. Unlike quiescence search, it looks at every move
+// and only starts on queen threats.
+#[derive(Clone)]
+pub(crate) struct ThreatExtension<E> {
+    eval: E,
+    enabled: bool,
+}
+
+impl<E: Evaluator<G = Rules>> ThreatExtension<E> {
+    pub(crate) fn new(eval: E, enabled: bool) -> Self {
+        Self { eval, enabled }
+    }
+
+    // Whether the last move filled or climbed onto the ring of a queen that
+    // now has five neighbors.
+    fn threatens_queen(board: &Board) -> bool {
+        let Some(&Turn::Place(end, _) | &Turn::Move(_, end)) = board.turn_history.last() else {
+            return false;
+        };
+        let surrounded = board.queens_surrounded();
+        [Color::White, Color::Black].into_iter().any(|color| {
+            board.queens[color as usize].is_some_and(|queen| {
+                surrounded[color as usize] == 5 && (end == queen || adjacent(queen).contains(&end))
+            })
+        })
+    }
+
+    fn negamax(
+        &self, board: &mut Board, depth: u8, mut alpha: Evaluation, beta: Evaluation,
+    ) -> Evaluation {
+        if let Some(winner) = Rules::get_winner(board) {
+            return winner.evaluate();
+        }
+        if depth == 0 {
+            return self.eval.evaluate(board);
+        }
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        let mut best = WORST_EVAL;
+        for m in moves {
+            board.apply(m);
+            let value = -self.negamax(board, depth - 1, -beta, -alpha);
+            board.undo(m);
+            best = best.max(value);
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+impl<E: Evaluator<G = Rules>> Evaluator for ThreatExtension<E> {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        if !self.enabled || !Self::threatens_queen(board) {
+            return self.eval.evaluate(board);
+        }
+        self.negamax(&mut board.clone(), THREAT_EXTENSION_PLIES, WORST_EVAL, BEST_EVAL)
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        self.eval.generate_noisy_moves(board, moves);
+    }
+}
+
+// Counts the positions evaluated, which node limits use as the size of a
+// search, as the minimax crate only reports its own counts after a search.
+#[derive(Clone)]
+pub(crate) struct NodeCounter<E> {
+    eval: E,
+    // Without a node limit, skip the shared counter in the hot path.
+    nodes: Option<Arc<AtomicU64>>,
+}
+
+impl<E> NodeCounter<E> {
+    pub(crate) fn new(eval: E, nodes: Option<Arc<AtomicU64>>) -> Self {
+        Self { eval, nodes }
+    }
+}
+
+impl<E: Evaluator<G = Rules>> Evaluator for NodeCounter<E> {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        if let Some(nodes) = &self.nodes {
+            nodes.fetch_add(1, Ordering::Relaxed);
+        }
+        self.eval.evaluate(board)
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        self.eval.generate_noisy_moves(board, moves);
+    }
+}
+
 // An evaluator that counts movable pieces and how close to death the queen is.
 #[derive(Copy, Clone)]
 pub struct BasicEvaluator {
     aggression: Evaluation,
-    queen_liberty_factor: Evaluation,
-    movable_queen_value: Evaluation,
-    movable_bug_factor: Evaluation,
+    // Blended by GamePhase: opening, midgame, and endgame.
+    queen_liberty_factor: [Evaluation; 3],
+    movable_bug_factor: [Evaluation; 3],
     unplayed_bug_factor: Evaluation,
     // Bonus for defensive pillbug or placeability thereof.
     pillbug_defense_bonus: Evaluation,
+    // Value of each bug when it is free to move, indexed by Bug.
+    bug_values: [Evaluation; 8],
+    // Value of each legal move of a bug, indexed by Phase and Bug. Bugs with no
+    // weight skip move generation.
+    mobility_values: [[Evaluation; 8]; 2],
+    // Cost of having a bug frozen in place, indexed by Bug.
+    pinned_values: [Evaluation; 8],
+    // Cost of having a bug covered by an enemy beetle or mosquito.
+    covered_values: [Evaluation; 8],
+    // Value of each empty hex the queen can slide to right now.
+    queen_escape_factor: Evaluation,
+    // Extra cost of each enemy tile next to the queen, beyond the liberty it
+    // takes, as the owner can't choose to move it away.
+    queen_enemy_ring_factor: Evaluation,
+    // Cost of each enemy beetle next to the queen that could climb onto it.
+    queen_beetle_threat: Evaluation,
+    // Which moves quiescence search plays past the horizon.
+    noisy_moves: NoisyMoves,
+}
+
+// Moves that quiescence search keeps playing so the search doesn't stop in
+// the middle of something.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NoisyMoves {
+    // Let each player move a bug they just placed.
+    Placements,
+    // Moves that take a liberty next to either queen, or put a bug on or off
+    // a queen, so the search doesn't stop halfway through a surround race.
+    QueenThreats,
+}
+
+// Mobility matters differently while a player still has bugs to place.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Phase {
+    Placement = 0,
+    Movement = 1,
+}
+
+// How far along the game is, as shares of the opening (bugs still in hand),
+// the midgame, and the endgame race to surround a queen. The shares add up to
+// PHASE_SCALE, and weights that depend on the phase are blended by them, so
+// the evaluation shifts smoothly instead of jumping between phases.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GamePhase(pub [i32; 3]);
+
+pub const PHASE_SCALE: i32 = 256;
+
+impl GamePhase {
+    const NAMES: [&'static str; 3] = ["opening", "midgame", "endgame"];
+
+    pub fn of(board: &Board) -> Self {
+        // The endgame starts when either queen has three neighbors, and is in
+        // full swing at five.
+        let surrounded = board.queens_surrounded();
+        let most_surrounded = [Color::White, Color::Black]
+            .into_iter()
+            .filter(|&color| board.remaining[color as usize][Bug::Queen as usize] == 0)
+            .map(|color| surrounded[color as usize] as i32)
+            .max()
+            .unwrap_or(0);
+        let endgame = (most_surrounded - 2).clamp(0, 3) * PHASE_SCALE / 3;
+
+        // The rest is split between the opening and midgame by how many bugs
+        // are left to place.
+        let mut in_hand = 0;
+        let mut total = 0;
+        for bug in Bug::iter_all() {
+            total += 2 * board.starting[bug as usize] as i32;
+            in_hand += (board.remaining[0][bug as usize] + board.remaining[1][bug as usize]) as i32;
+        }
+        let opening = (PHASE_SCALE - endgame) * in_hand / total.max(1);
+        GamePhase([opening, PHASE_SCALE - endgame - opening, endgame])
+    }
+
+    // A weight for this phase, from its value in each phase.
+    fn blend(self, weights: [Evaluation; 3]) -> Evaluation {
+        self.scale(1, weights)
+    }
+
+    // The value multiplied by the blended weight, without rounding the weight.
+    fn scale(self, value: Evaluation, weights: [Evaluation; 3]) -> Evaluation {
+        let weight: i32 = weights.iter().zip(self.0).map(|(&w, share)| w as i32 * share).sum();
+        (value as i32 * weight / PHASE_SCALE) as Evaluation
+    }
+}
+
+impl std::fmt::Display for GamePhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (name, share)) in Self::NAMES.iter().zip(self.0).enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{} {}%", name, share * 100 / PHASE_SCALE)?;
+        }
+        Ok(())
+    }
+}
+
+impl Phase {
+    fn of(board: &Board, color: Color) -> Self {
+        if board.remaining[color as usize].iter().any(|&n| n > 0) {
+            Phase::Placement
+        } else {
+            Phase::Movement
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Placement => "placement",
+            Phase::Movement => "movement",
+        }
+    }
 }
 
 // Ideas:
@@ -44,30 +373,124 @@ impl BasicEvaluator {
         let aggression = aggression.clamp(1, 5) as Evaluation;
         Self {
             aggression,
-            queen_liberty_factor: aggression * 10,
-            movable_queen_value: aggression * 4,
-            movable_bug_factor: 2,
+            // Surrounding the queen is slow to undo, so guard it early.
+            queen_liberty_factor: [aggression * 14, aggression * 10, aggression * 10],
+            // Once queens are under attack, mobility matters less than
+            // getting to the queen.
+            movable_bug_factor: [2, 2, 1],
             unplayed_bug_factor: 1,
             pillbug_defense_bonus: aggression * 40,
+            // Mostly made up. All I know is that ants are good.
+            // Mosquitos are valued as the bugs they can currently mimic.
+            bug_values: [aggression * 4, 2, 2, 7, 6, 8, 6, 5],
+            // Only the queen's room to run is counted by default; counting
+            // everything else's moves is slow and not yet tuned.
+            mobility_values: [[1, 0, 0, 0, 0, 0, 0, 0], [2, 0, 0, 0, 0, 0, 0, 0]],
+            // A frozen queen can't run, and a frozen ant or mosquito loses
+            // the most reach. Spiders and grasshoppers lose little.
+            pinned_values: [aggression * 2, 1, 1, 6, 3, 5, 3, 2],
+            covered_values: [aggression * 6, 2, 2, 8, 5, 7, 5, 4],
+            queen_escape_factor: aggression * 2,
+            queen_enemy_ring_factor: aggression * 2,
+            queen_beetle_threat: aggression * 4,
+            noisy_moves: NoisyMoves::Placements,
         }
     }
 
+    pub(crate) fn set_noisy_moves(&mut self, noisy_moves: NoisyMoves) {
+        self.noisy_moves = noisy_moves;
+    }
+
     pub(crate) fn aggression(&self) -> u8 {
         self.aggression as u8
     }
 
     fn value(&self, bug: Bug) -> Evaluation {
-        // Mostly made up. All I know is that ants are good.
-        match bug {
-            Bug::Queen => self.movable_queen_value,
-            Bug::Ant => 7,
-            Bug::Beetle => 6,
-            Bug::Grasshopper => 2,
-            Bug::Spider => 2,
-            Bug::Mosquito => 8, // See below.
-            Bug::Ladybug => 6,
-            Bug::Pillbug => 5,
+        self.bug_values[bug as usize]
+    }
+
+    // Names and current values of all tunable weights.
+    pub fn weights(&self) -> Vec<(String, Evaluation)> {
+        let mut weights = Vec::new();
+        for (i, phase) in GamePhase::NAMES.iter().enumerate() {
+            weights.push((format!("queen_liberty_factor_{}", phase), self.queen_liberty_factor[i]));
+            weights.push((format!("movable_bug_factor_{}", phase), self.movable_bug_factor[i]));
+        }
+        weights.push(("unplayed_bug_factor".to_owned(), self.unplayed_bug_factor));
+        weights.push(("pillbug_defense_bonus".to_owned(), self.pillbug_defense_bonus));
+        weights.push(("queen_escape_factor".to_owned(), self.queen_escape_factor));
+        weights.push(("queen_enemy_ring_factor".to_owned(), self.queen_enemy_ring_factor));
+        weights.push(("queen_beetle_threat".to_owned(), self.queen_beetle_threat));
+        for bug in Bug::iter_all() {
+            weights.push((format!("{}_value", bug.name()), self.value(bug)));
         }
+        for bug in Bug::iter_all() {
+            weights.push((format!("{}_pinned", bug.name()), self.pinned_values[bug as usize]));
+            weights.push((format!("{}_covered", bug.name()), self.covered_values[bug as usize]));
+        }
+        for phase in [Phase::Placement, Phase::Movement] {
+            for bug in Bug::iter_all() {
+                weights.push((
+                    format!("{}_mobility_{}", bug.name(), phase.name()),
+                    self.mobility_values[phase as usize][bug as usize],
+                ));
+            }
+        }
+        weights
+    }
+
+    // Override a weight by name. Returns false if there is no such weight.
+    pub fn set_weight(&mut self, name: &str, value: Evaluation) -> bool {
+        match name {
+            // The same weight in every phase.
+            "queen_liberty_factor" => self.queen_liberty_factor = [value; 3],
+            "movable_bug_factor" => self.movable_bug_factor = [value; 3],
+            "unplayed_bug_factor" => self.unplayed_bug_factor = value,
+            "pillbug_defense_bonus" => self.pillbug_defense_bonus = value,
+            "queen_escape_factor" => self.queen_escape_factor = value,
+            "queen_enemy_ring_factor" => self.queen_enemy_ring_factor = value,
+            "queen_beetle_threat" => self.queen_beetle_threat = value,
+            _ => {
+                let find_bug = |bug_name: &str| Bug::iter_all().find(|bug| bug.name() == bug_name);
+                if let Some(bug) = name.strip_suffix("_value").and_then(find_bug) {
+                    self.bug_values[bug as usize] = value;
+                    return true;
+                }
+                for (i, phase) in GamePhase::NAMES.iter().enumerate() {
+                    match name.strip_suffix(phase).and_then(|name| name.strip_suffix('_')) {
+                        Some("queen_liberty_factor") => {
+                            self.queen_liberty_factor[i] = value;
+                            return true;
+                        }
+                        Some("movable_bug_factor") => {
+                            self.movable_bug_factor[i] = value;
+                            return true;
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(bug) = name.strip_suffix("_pinned").and_then(find_bug) {
+                    self.pinned_values[bug as usize] = value;
+                    return true;
+                }
+                if let Some(bug) = name.strip_suffix("_covered").and_then(find_bug) {
+                    self.covered_values[bug as usize] = value;
+                    return true;
+                }
+                for phase in [Phase::Placement, Phase::Movement] {
+                    let bug = name
+                        .strip_suffix(phase.name())
+                        .and_then(|name| name.strip_suffix("_mobility_"))
+                        .and_then(find_bug);
+                    if let Some(bug) = bug {
+                        self.mobility_values[phase as usize][bug as usize] = value;
+                        return true;
+                    }
+                }
+                return false;
+            }
+        }
+        true
     }
 }
 
@@ -97,12 +520,70 @@ fn test_placeable() {
     assert!(!placeable(&b, Direction::NW.apply(START_HEX), Color::Black));
 }
 
-impl Evaluator for BasicEvaluator {
-    type G = Rules;
+// Contribution of one tile on the board to the evaluation.
+#[derive(Clone, Debug)]
+pub struct PieceTerm {
+    pub hex: Hex,
+    // UHP piece name, e.g. "wA1".
+    pub name: String,
+    // Whether the tile cannot move, either from the one hive rule or being boxed in.
+    pub pinned: bool,
+    pub score: Evaluation,
+}
 
-    fn evaluate(&self, board: &Board) -> Evaluation {
+// Per-term contributions to an evaluation, all from the perspective of the
+// player to move. The terms sum to the total.
+#[derive(Clone, Debug, Default)]
+pub struct EvalBreakdown {
+    // Which phase weights were used.
+    pub phase: GamePhase,
+    // Value of bugs still in hand.
+    pub unplayed: Evaluation,
+    // Differential of how surrounded each queen is.
+    pub queen_surrounding: Evaluation,
+    // Differential of queen escape routes and threats around it.
+    pub queen_safety: Evaluation,
+    pub pillbug_defense: Evaluation,
+    // Differential of bugs covered by enemy bugs.
+    pub covered: Evaluation,
+    pub pieces: Vec<PieceTerm>,
+    pub total: Evaluation,
+}
+
+impl std::fmt::Display for EvalBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "total {}", self.total)?;
+        writeln!(f, "phase {}", self.phase)?;
+        writeln!(f, "unplayed {}", self.unplayed)?;
+        writeln!(f, "queensurrounding {}", self.queen_surrounding)?;
+        writeln!(f, "queensafety {}", self.queen_safety)?;
+        writeln!(f, "pillbugdefense {}", self.pillbug_defense)?;
+        write!(f, "covered {}", self.covered)?;
+        for piece in self.pieces.iter() {
+            let state = if piece.pinned { "pinned" } else { "mobile" };
+            write!(f, "\n{} {} {}", piece.name, state, piece.score)?;
+        }
+        Ok(())
+    }
+}
+
+impl BasicEvaluator {
+    // Explain why the engine likes or dislikes a position.
+    pub fn explain_eval(&self, board: &Board) -> EvalBreakdown {
+        let mut breakdown = EvalBreakdown::default();
+        breakdown.total = self.evaluate_terms(board, Some(&mut breakdown));
+        breakdown
+    }
+
+    fn evaluate_terms(
+        &self, board: &Board, mut breakdown: Option<&mut EvalBreakdown>,
+    ) -> Evaluation {
+        let phase = GamePhase::of(board);
+        let queen_liberty_factor = phase.blend(self.queen_liberty_factor);
         let mut buf = [0; 6];
         let mut immovable = board.find_cut_vertexes();
+        // Reused for each piece's moves.
+        let mut moves = Vec::new();
 
         let mut score = 0;
         let mut pillbug_defense = [false; 2];
@@ -116,6 +597,9 @@ impl Evaluator for BasicEvaluator {
                 * self.unplayed_bug_factor
                 * self.value(bug);
         }
+        if let Some(breakdown) = breakdown.as_mut() {
+            breakdown.unplayed = score;
+        }
 
         for &hex in board.occupied_hexes[0].iter().chain(board.occupied_hexes[1].iter()) {
             let node = board.node(hex);
@@ -161,13 +645,15 @@ impl Evaluator for BasicEvaluator {
 
             // TODO: transpose this loop, i.e. categorize queen liberties after the bug loop.
             // Count libs for more if they are not crawlable (e.g. behind a gate)
-            if adjacent(friendly_queen).contains(&hex) {
+            if let Some(friendly_queen) =
+                friendly_queen.filter(|&queen| adjacent(queen).contains(&hex))
+            {
                 // Filling friendly queen's liberty.
                 if immovable.get(hex) && !node.is_stacked() {
-                    queen_score[node.color() as usize] -= self.queen_liberty_factor;
+                    queen_score[node.color() as usize] -= queen_liberty_factor;
                 } else {
                     // Lower penalty for being able to leave.
-                    queen_score[node.color() as usize] -= self.queen_liberty_factor / 2;
+                    queen_score[node.color() as usize] -= queen_liberty_factor / 2;
                 }
                 if pillbug_powers && board.node(friendly_queen).clipped_height() == 1 {
                     let best_escape = adjacent(hex)
@@ -189,11 +675,11 @@ impl Evaluator for BasicEvaluator {
 
             let enemy_queen = board.queens[node.color().other()];
 
-            if adjacent(enemy_queen).contains(&hex) {
+            if let Some(enemy_queen) = enemy_queen.filter(|&queen| adjacent(queen).contains(&hex)) {
                 // Discourage liberty filling by valuable bugs, by setting their score to zero when filling a liberty.
                 bug_score = 0;
                 // A little extra boost for filling opponent's queen, as we will never choose to move.
-                queen_score[node.color().other()] -= self.queen_liberty_factor * 12 / 10;
+                queen_score[node.color().other()] -= queen_liberty_factor * 12 / 10;
                 if pillbug_powers {
                     let best_unescape = adjacent(hex)
                         .into_iter()
@@ -207,17 +693,39 @@ impl Evaluator for BasicEvaluator {
                         .min()
                         .unwrap_or(6);
                     if best_unescape < 3 {
-                        queen_score[node.color().other()] = -self.queen_liberty_factor;
+                        queen_score[node.color().other()] = -queen_liberty_factor;
                     }
                 }
             }
 
-            if !node.is_stacked() && immovable.get(hex) {
-                // Pinned bugs are worthless.
+            let pinned = !node.is_stacked() && immovable.get(hex);
+            if let Some(breakdown) = breakdown.as_mut() {
+                let mut name = String::new();
+                board.tile_name(node, &mut name);
+                breakdown.pieces.push(PieceTerm { hex, name, pinned, score: 0 });
+            }
+            if pinned {
+                // Pinned bugs are worse than worthless, more so for the bugs
+                // that would otherwise do the most.
+                let mut pinned_score = -self.pinned_values[node.bug() as usize];
+                if node.color() != board.to_move() {
+                    pinned_score = -pinned_score;
+                }
+                score += pinned_score;
+                if let Some(breakdown) = breakdown.as_mut() {
+                    breakdown.pieces.last_mut().unwrap().score = pinned_score;
+                }
                 continue;
             }
 
-            bug_score *= self.movable_bug_factor;
+            bug_score = phase.scale(bug_score, self.movable_bug_factor);
+            let mobility_value =
+                self.mobility_values[Phase::of(board, node.color()) as usize][node.bug() as usize];
+            if mobility_value != 0 {
+                moves.clear();
+                board.generate_piece_movements(hex, &mut moves);
+                bug_score += mobility_value * moves.len() as Evaluation;
+            }
             if node.color() != board.to_move() {
                 bug_score = -bug_score;
                 // Make low-aggression mode value opponent movability higher than ours.
@@ -228,7 +736,22 @@ impl Evaluator for BasicEvaluator {
                 }
             }
             score += bug_score;
+            if let Some(breakdown) = breakdown.as_mut() {
+                breakdown.pieces.last_mut().unwrap().score = bug_score;
+            }
+        }
+
+        // Bugs held down under an enemy beetle or mosquito.
+        let mut covered_score = 0;
+        for under in board.get_underworld() {
+            let covered = under.node();
+            let top = board.node(under.hex());
+            if top.color() != covered.color() {
+                let value = self.covered_values[covered.bug() as usize];
+                covered_score += if covered.color() == board.to_move() { -value } else { value };
+            }
         }
+        score += covered_score;
 
         let mut pillbug_defense_score = self.pillbug_defense_bonus
             * (pillbug_defense[board.to_move() as usize] as Evaluation
@@ -237,11 +760,12 @@ impl Evaluator for BasicEvaluator {
         // Check for backup defensive pillbug placeability option, discounted value
         pillbug_defense = [false; 2];
         for &color in &[Color::Black, Color::White] {
-            if board.node(board.queens[color as usize]).clipped_height() == 1
+            let Some(queen) = board.queens[color as usize] else {
+                continue;
+            };
+            if board.node(queen).clipped_height() == 1
                 && board.remaining[color as usize][Bug::Pillbug as usize] > 0
-                && adjacent(board.queens[color as usize])
-                    .iter()
-                    .any(|&lib| placeable(board, lib, color))
+                && adjacent(queen).iter().any(|&lib| placeable(board, lib, color))
             {
                 pillbug_defense[color as usize] = true;
             }
@@ -252,52 +776,322 @@ impl Evaluator for BasicEvaluator {
 
         let queen_score =
             queen_score[board.to_move() as usize] - queen_score[board.to_move().other()];
-        queen_score + pillbug_defense_score + score
+        let queen_safety =
+            [Color::White, Color::Black].map(|color| self.queen_safety(board, &immovable, color));
+        let queen_safety =
+            queen_safety[board.to_move() as usize] - queen_safety[board.to_move().other()];
+        if let Some(breakdown) = breakdown {
+            breakdown.phase = phase;
+            breakdown.queen_surrounding = queen_score;
+            breakdown.queen_safety = queen_safety;
+            breakdown.covered = covered_score;
+            breakdown.pillbug_defense = pillbug_defense_score;
+        }
+        queen_score + queen_safety + pillbug_defense_score + score
     }
 
-    // The idea here is to use quiescence search to avoid ending on a
-    // placement. This is based on the hypothesis that new pieces are placed
-    // with the intention of moving them on the next turn. Stopping the search
-    // just after placing a piece can give bad results because it would
-    // usually pins one of your own pieces and doesn't put the new piece where
-    // it will be useful. Thus, each player can get a bonus move to move a
-    // piece that they have just placed (but not other pieces).
-    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
-        if board.turn_history.len() < 4 || board.get_remaining()[Bug::Queen as usize] == 0 {
-            // Wait until movements are at least possible.
-            return;
+    // How well the queen of this color can get away from an attack, beyond
+    // how many of its neighbors are filled.
+    fn queen_safety(&self, board: &Board, immovable: &HexSet, color: Color) -> Evaluation {
+        let Some(queen) = board.queens[color as usize] else {
+            return 0;
+        };
+        let mut safety = 0;
+        // A covered or pinned queen has nowhere to go.
+        if !board.node(queen).is_stacked() && !immovable.get(queen) {
+            let mut buf = [0; 6];
+            let escapes = board.slidable_adjacent(&mut buf, queen, queen).count();
+            safety += self.queen_escape_factor * escapes as Evaluation;
         }
-        let enemy_last_move = board.turn_history[board.turn_history.len() - 1];
-        let my_last_move = board.turn_history[board.turn_history.len() - 2];
-
-        if let Turn::Place(hex, _) = my_last_move {
-            // Drop attack is quiet enough.
-            if !adjacent(board.queens[board.to_move().other()]).contains(&hex) {
-                // TODO: just generate from this spot (ignoring throws?).
-                board.generate_movements(moves);
-                moves.retain(|m| if let Turn::Move(start, _) = *m { start == hex } else { false });
-                // If the piece became pinned or covered, this will return no
-                // moves, which means the search will terminate.
-                return;
+        for adj in adjacent(queen) {
+            let node = board.node(adj);
+            if !node.occupied() || node.color() == color {
+                continue;
+            }
+            safety -= self.queen_enemy_ring_factor;
+            // Anything on top of a stack can climb, and so can a free beetle.
+            let climber = node.is_stacked() || (node.bug() == Bug::Beetle && !immovable.get(adj));
+            if climber && !board.node(queen).is_stacked() {
+                safety -= self.queen_beetle_threat;
             }
         }
+        safety
+    }
+}
 
-        if let Turn::Place(hex, _) = enemy_last_move {
-            if !adjacent(board.queens[board.to_move() as usize]).contains(&hex) {
-                // We didn't just place, but opponent did. Do some movement to
-                // give them a chance to quiesce.
-                board.generate_movements(moves);
-            }
+impl Evaluator for BasicEvaluator {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        // Nobody can win, however the tiles stand.
+        if board.dead_drawn() {
+            return 0;
         }
+        self.evaluate_terms(board, None)
+    }
+
+    fn generate_noisy_moves(&self, board: &Board, moves: &mut Vec<Turn>) {
+        match self.noisy_moves {
+            NoisyMoves::Placements => placement_followups(board, moves),
+            NoisyMoves::QueenThreats => queen_threats(board, moves),
+        }
+    }
+}
+
+// The idea here is to use quiescence search to avoid ending on a
+// placement. This is based on the hypothesis that new pieces are placed
+// with the intention of moving them on the next turn. Stopping the search
+// just after placing a piece can give bad results because it would
+// usually pins one of your own pieces and doesn't put the new piece where
+// it will be useful. Thus, each player can get a bonus move to move a
+// piece that they have just placed (but not other pieces).
+fn placement_followups(board: &Board, moves: &mut Vec<Turn>) {
+    if board.turn_history.len() < 4 || board.get_remaining()[Bug::Queen as usize] == 0 {
+        // Wait until movements are at least possible.
+        return;
+    }
+    let enemy_last_move = board.turn_history[board.turn_history.len() - 1];
+    let my_last_move = board.turn_history[board.turn_history.len() - 2];
+
+    if let Turn::Place(hex, _) = my_last_move {
+        // Drop attack is quiet enough.
+        if !board.queens[board.to_move().other()]
+            .is_some_and(|queen| adjacent(queen).contains(&hex))
+        {
+            // TODO: just generate from this spot (ignoring throws?).
+            board.generate_movements(moves);
+            moves.retain(|m| if let Turn::Move(start, _) = *m { start == hex } else { false });
+            // If the piece became pinned or covered, this will return no
+            // moves, which means the search will terminate.
+            return;
+        }
+    }
+
+    if let Turn::Place(hex, _) = enemy_last_move {
+        if !board.queens[board.to_move() as usize]
+            .is_some_and(|queen| adjacent(queen).contains(&hex))
+        {
+            // We didn't just place, but opponent did. Do some movement to
+            // give them a chance to quiesce.
+            board.generate_movements(moves);
+        }
+    }
 
-        // If no one just placed something, return nothing and stop the search.
+    // If no one just placed something, return nothing and stop the search.
+}
+
+// Moves that change how surrounded a queen is: filling an empty hex next to
+// it, or climbing on or off it. Moving between two hexes next to the same
+// queen takes no new liberty, unless the bug leaves a stack behind.
+fn queen_threats(board: &Board, moves: &mut Vec<Turn>) {
+    let queens = board.queens;
+    if queens == [None, None] {
+        return;
     }
+    Rules::generate_moves(board, moves);
+    moves.retain(|&turn| {
+        let (start, end) = match turn {
+            Turn::Place(hex, _) => (None, hex),
+            Turn::Move(start, end) => (Some(start), end),
+            Turn::Pass => return false,
+        };
+        queens.iter().flatten().any(|&queen| {
+            if end == queen || (start == Some(queen) && board.node(queen).is_stacked()) {
+                return true;
+            }
+            let ring = adjacent(queen);
+            !board.occupied(end)
+                && ring.contains(&end)
+                && !start
+                    .is_some_and(|start| ring.contains(&start) && !board.node(start).is_stacked())
+        })
+    });
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_explain_eval() {
+        let board = Board::from_game_string(
+            r"Base+MLP;InProgress;White[5];wP;bB1 -wP;wQ wP\;bQ -bB1;wA1 wQ/;bA1 /bQ;wA1 bQ/;bA1 wP/",
+        )
+        .unwrap();
+        let eval = BasicEvaluator::default();
+        let breakdown = eval.explain_eval(&board);
+        assert_eq!(eval.evaluate(&board), breakdown.total);
+        let piece_total: Evaluation = breakdown.pieces.iter().map(|piece| piece.score).sum();
+        assert_eq!(
+            breakdown.total,
+            breakdown.unplayed
+                + breakdown.queen_surrounding
+                + breakdown.queen_safety
+                + breakdown.pillbug_defense
+                + breakdown.covered
+                + piece_total
+        );
+        assert_eq!(6, breakdown.pieces.len());
+        // Pinned pieces cost their side, by bug.
+        for piece in breakdown.pieces.iter().filter(|piece| piece.pinned) {
+            let bug = board.node(piece.hex).bug();
+            let sign = if board.node(piece.hex).color() == board.to_move() { -1 } else { 1 };
+            assert_eq!(sign * eval.pinned_values[bug as usize], piece.score, "{}", piece.name);
+        }
+        assert!(breakdown.pieces.iter().any(|piece| piece.pinned));
+        assert!(breakdown.to_string().starts_with(&format!("total {}", breakdown.total)));
+    }
+
+    #[test]
+    fn test_mobility() {
+        let board = Board::from_game_string(
+            r"Base+MLP;InProgress;White[5];wP;bB1 -wP;wQ wP\;bQ -bB1;wA1 wQ/;bA1 /bQ;wA1 bQ/;bA1 wP/",
+        )
+        .unwrap();
+        let mut eval = BasicEvaluator::default();
+        let before = eval.explain_eval(&board);
+        assert!(eval.weights().contains(&("ant_mobility_placement".to_owned(), 0)));
+        assert!(eval.set_weight("ant_mobility_placement", 3));
+        assert!(!eval.set_weight("ant_mobility_sometimes", 3));
+        let after = eval.explain_eval(&board);
+
+        // Each free ant gains 3 per move, for its own side.
+        let mut expected = 0;
+        for (old, new) in before.pieces.iter().zip(after.pieces.iter()) {
+            let mut moves = Vec::new();
+            if new.name.starts_with(['w', 'b']) && new.name[1..].starts_with('A') && !new.pinned {
+                board.generate_piece_movements(new.hex, &mut moves);
+                assert!(!moves.is_empty());
+            }
+            let sign = if new.name.starts_with('w') { 1 } else { -1 };
+            assert_eq!(old.score + sign * 3 * moves.len() as Evaluation, new.score, "{}", new.name);
+            expected += sign * 3 * moves.len() as Evaluation;
+        }
+        assert_ne!(0, expected);
+        assert_eq!(before.total + expected, after.total);
+    }
+
+    #[test]
+    fn test_covered() {
+        // Black's beetle climbs onto its own queen, then onto white's.
+        let mut board = Board::default();
+        board.apply(Turn::Place(loc_to_hex((0, 0)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((1, 0)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((-1, 0)), Bug::Ant));
+        board.apply(Turn::Place(loc_to_hex((2, 0)), Bug::Beetle));
+        board.apply(Turn::Pass);
+        board.apply(Turn::Move(loc_to_hex((2, 0)), loc_to_hex((1, 0))));
+        let eval = BasicEvaluator::default();
+        // Covering your own bugs is free.
+        assert_eq!(0, eval.explain_eval(&board).covered);
+
+        board.apply(Turn::Pass);
+        board.apply(Turn::Move(loc_to_hex((1, 0)), loc_to_hex((0, 0))));
+        let breakdown = eval.explain_eval(&board);
+        assert_eq!(Color::White, board.to_move());
+        assert_eq!(-eval.covered_values[Bug::Queen as usize], breakdown.covered);
+        assert_eq!(eval.evaluate(&board), breakdown.total);
+    }
+
+    #[test]
+    fn test_queen_safety() {
+        let eval = BasicEvaluator::default();
+        let escape = eval.queen_escape_factor;
+        let ring = eval.queen_enemy_ring_factor;
+        let mut board = Board::default();
+        board.apply(Turn::Place(loc_to_hex((0, 0)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((1, 0)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((-1, 0)), Bug::Ant));
+        // The white queen is pinned between its ant and the black queen,
+        // which can slide either way around it.
+        assert_eq!(Color::Black, board.to_move());
+        let (white, black) = (-ring, 2 * escape - ring);
+        assert_eq!(black - white, eval.explain_eval(&board).queen_safety);
+
+        // Now both queens are pinned.
+        board.apply(Turn::Place(loc_to_hex((2, 0)), Bug::Beetle));
+        assert_eq!(0, eval.explain_eval(&board).queen_safety);
+
+        // The black beetle climbs next to the white queen, threatening it.
+        board.apply(Turn::Pass);
+        board.apply(Turn::Move(loc_to_hex((2, 0)), loc_to_hex((1, 0))));
+        assert_eq!(Color::White, board.to_move());
+        let breakdown = eval.explain_eval(&board);
+        let (white, black) = (-ring - eval.queen_beetle_threat, -ring);
+        assert_eq!(white - black, breakdown.queen_safety);
+        assert_eq!(eval.evaluate(&board), breakdown.total);
+    }
+
+    #[test]
+    fn test_queen_threats() {
+        // White's beetle sits on its own queen, black's beetle is next to
+        // black's queen.
+        let mut board = Board::from_game_string(
+            "Base;InProgress;Black[4];wB1;bB1 wB1-;wQ -wB1;bQ bB1-;wB2 -wQ;bB2 bQ-;wB2 wQ",
+        )
+        .unwrap();
+        let mut eval = BasicEvaluator::default();
+        eval.set_noisy_moves(NoisyMoves::QueenThreats);
+        let noisy = |board: &Board| {
+            let mut moves = Vec::new();
+            eval.generate_noisy_moves(board, &mut moves);
+            moves.into_iter().map(|m| board.to_move_string(m)).collect::<Vec<_>>()
+        };
+        let mut all = Vec::new();
+        Rules::generate_moves(&board, &mut all);
+        let moves = noisy(&board);
+        assert!(moves.len() < all.len());
+        // Covering a queen.
+        assert!(moves.contains(&"bB2 bQ".to_owned()));
+        // Filling a liberty.
+        assert!(moves.contains(&r"bA1 bQ\".to_owned()));
+        // A placement away from both queens is quiet.
+        assert!(!moves.contains(&r"bA1 bB2/".to_owned()));
+
+        board.apply(board.from_move_string(r"bA1 bQ\").unwrap());
+        let mut moves = Vec::new();
+        eval.generate_noisy_moves(&board, &mut moves);
+        // Uncovering a queen.
+        let queen = board.queens[Color::White as usize].unwrap();
+        assert_eq!(
+            6,
+            moves.iter().filter(|&&m| matches!(m, Turn::Move(start, _) if start == queen)).count()
+        );
+        // Placing away from both queens is quiet.
+        assert!(moves
+            .iter()
+            .all(|m| !matches!(m, Turn::Place(hex, _) if !adjacent(queen).contains(hex))));
+    }
+
+    #[test]
+    fn test_game_phase() {
+        let board = Board::from_game_type("Base+MLP").unwrap();
+        assert_eq!(GamePhase([PHASE_SCALE, 0, 0]), GamePhase::of(&board));
+
+        // Queens are placed but not under attack, with most bugs in hand.
+        let board = Board::from_game_string(
+            r"Base+MLP;InProgress;White[5];wP;bB1 -wP;wQ wP\;bQ -bB1;wA1 wQ/;bA1 /bQ;wA1 bQ/;bA1 wP/",
+        )
+        .unwrap();
+        let GamePhase([opening, midgame, endgame]) = GamePhase::of(&board);
+        assert_eq!(PHASE_SCALE, opening + midgame + endgame);
+        assert!(opening > midgame && midgame > 0, "{} {}", opening, midgame);
+
+        // The black queen has five neighbors.
+        let mut board = Board::default();
+        board.apply(Turn::Place(loc_to_hex((0, 0)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((1, 0)), Bug::Spider));
+        board.apply(Turn::Place(loc_to_hex((-1, 1)), Bug::Ant));
+        board.apply(Turn::Place(loc_to_hex((0, 1)), Bug::Ant));
+        board.apply(Turn::Place(loc_to_hex((1, 2)), Bug::Grasshopper));
+        board.apply(Turn::Place(loc_to_hex((1, 1)), Bug::Queen));
+        board.apply(Turn::Place(loc_to_hex((2, 2)), Bug::Beetle));
+        assert_eq!(GamePhase([0, 0, PHASE_SCALE]), GamePhase::of(&board));
+        assert_eq!(10, GamePhase::of(&board).blend([30, 20, 10]));
+        assert_eq!(15, GamePhase([128, 128, 0]).blend([10, 20, 30]));
+        assert_eq!(7, GamePhase([128, 128, 0]).scale(5, [1, 2, 0]));
+    }
+
     #[test]
     fn test_minimax() {
         use minimax::{Negamax, Strategy};
@@ -316,12 +1110,12 @@ mod tests {
         board.apply(Turn::Place(loc_to_hex((2, 2)), Bug::Beetle));
         board.apply(Turn::Pass);
         for depth in 1..3 {
-            let mut strategy = Negamax::new(DumbEvaluator {}, depth);
-            let m = strategy.choose_move(&mut board);
+            let mut strategy = Negamax::new(DumbEvaluator, depth);
+            let m = strategy.choose_move(&board);
             assert_eq!(Some(Turn::Move(loc_to_hex((-1, 1)), loc_to_hex((2, 1)))), m);
 
             let mut strategy = Negamax::new(BasicEvaluator::default(), depth);
-            let m = strategy.choose_move(&mut board);
+            let m = strategy.choose_move(&board);
             assert_eq!(Some(Turn::Move(loc_to_hex((-1, 1)), loc_to_hex((2, 1)))), m);
         }
 
@@ -337,8 +1131,59 @@ mod tests {
         board.apply(Turn::Pass);
         for depth in 1..3 {
             let mut strategy = Negamax::new(BasicEvaluator::default(), depth);
-            let m = strategy.choose_move(&mut board);
+            let m = strategy.choose_move(&board);
             assert_eq!(Some(Turn::Move(loc_to_hex((0, 0)), loc_to_hex((0, -1)))), m);
         }
     }
+
+    #[test]
+    fn test_contempt() {
+        let engine_color = Arc::new(AtomicU8::new(Color::Black as u8));
+        let eval = Contempt::new(DumbEvaluator, 30, engine_color.clone());
+        let mut board = Board::default();
+        // White to move, and the engine is black.
+        assert_eq!(-30, eval.evaluate(&board));
+        board.apply(Turn::Place(loc_to_hex((0, 0)), Bug::Queen));
+        assert_eq!(30, eval.evaluate(&board));
+        engine_color.store(Color::White as u8, Ordering::Relaxed);
+        assert_eq!(-30, eval.evaluate(&board));
+        let eval = Contempt::new(DumbEvaluator, 0, engine_color);
+        assert_eq!(0, eval.evaluate(&board));
+    }
+
+    #[test]
+    fn test_noise() {
+        let mut board = Board::default();
+        let noise = Noise::new(DumbEvaluator, 20, 7);
+        let mut values = Vec::new();
+        for bug in [Bug::Queen, Bug::Ant, Bug::Spider, Bug::Beetle] {
+            board.apply(Turn::Place(START_HEX, bug));
+            let value = noise.evaluate(&board);
+            assert!((-20..=20).contains(&value));
+            // The same position always gets the same noise.
+            assert_eq!(value, noise.evaluate(&board.clone()));
+            values.push(value);
+            board.undo(Turn::Place(START_HEX, bug));
+        }
+        values.dedup();
+        assert!(values.len() > 1, "{:?}", values);
+        assert_eq!(0, Noise::new(DumbEvaluator, 0, 7).evaluate(&board));
+    }
+
+    #[test]
+    fn test_threat_extension() {
+        // White just placed a grasshopper that leaves its queen with five
+        // neighbors, and black's ant can fill the last one.
+        let board = Board::from_game_string(r"Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\").unwrap();
+        assert_eq!(5, board.queens_surrounded()[Color::White as usize]);
+        assert_eq!(0, ThreatExtension::new(DumbEvaluator, false).evaluate(&board));
+        assert_eq!(BEST_EVAL, ThreatExtension::new(DumbEvaluator, true).evaluate(&board));
+
+        // Nothing happens without a threat.
+        let board =
+            Board::from_game_string("Base;InProgress;White[3];wG1;bG1 wG1-;wQ -wG1;bQ bG1-")
+                .unwrap();
+        let eval = ThreatExtension::new(BasicEvaluator::default(), true);
+        assert_eq!(BasicEvaluator::default().evaluate(&board), eval.evaluate(&board));
+    }
 }
@@ -0,0 +1,255 @@
+use crate::notation::{Result, UhpError};
+use crate::Board;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+// A finished or unfinished game with enough detail to archive it and replay
+// it later. The text format is modeled after PGN:
+//
+//   [GameType "Base+MLP"]
+//   [White "nokamute"]
+//   [Black "human"]
+//   [TimeControl "5m+3s"]
+//   [Result "WhiteWins"]
+//   [Termination "human resigns"]
+//   1. wS1 {clk 299.512}
+//   1... bG1 wS1- {clk 298.004} {eval -12}
+//
+// Each move is a UHP MoveString on its own line, followed by the time left on
+// the mover's clock in timed games, and its eval when the mover gave one.
+// Records in one file are separated by blank lines.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameRecord {
+    pub game_type: String,
+    pub white: String,
+    pub black: String,
+    // In the --clock format, e.g. "5m+3s".
+    pub time_control: Option<String>,
+    // A UHP GameStateString, e.g. "WhiteWins".
+    pub result: String,
+    // Why the game ended early, when it wasn't played out, e.g. by
+    // resignation or adjudication.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub termination: Option<String>,
+    pub moves: Vec<String>,
+    // Time left on the mover's clock after each move; empty for untimed games.
+    pub clocks: Vec<Duration>,
+    // Value of each move for White, by the line the mover expected, or None
+    // where it gave none; empty when no move has one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub evals: Vec<Option<i16>>,
+}
+
+impl GameRecord {
+    // Record of the moves played on the board so far.
+    pub fn from_board(board: &Board, white: &str, black: &str) -> Self {
+        let log = board.game_log();
+        GameRecord {
+            game_type: board.game_type(),
+            white: white.to_owned(),
+            black: black.to_owned(),
+            time_control: None,
+            result: board.game_state_string().to_owned(),
+            termination: None,
+            moves: log.split(';').filter(|m| !m.is_empty()).map(str::to_owned).collect(),
+            clocks: Vec::new(),
+            evals: Vec::new(),
+        }
+    }
+
+    // Replay the moves from the start, checking that each is legal.
+    pub fn to_board(&self) -> Result<Board> {
+        let mut board = Board::from_game_type(&self.game_type)?;
+        for move_string in self.moves.iter() {
+            let m = board.from_move_string(move_string)?;
+            board.apply_untrusted(m)?;
+        }
+        Ok(board)
+    }
+
+    pub fn parse(input: &str) -> Result<Self> {
+        let err = |line: &str| UhpError::InvalidGameString(line.to_owned());
+        let mut record = GameRecord {
+            game_type: String::new(),
+            white: String::new(),
+            black: String::new(),
+            time_control: None,
+            result: "NotStarted".to_owned(),
+            termination: None,
+            moves: Vec::new(),
+            clocks: Vec::new(),
+            evals: Vec::new(),
+        };
+        for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(tag) = line.strip_prefix('[') {
+                let (name, value) = tag
+                    .strip_suffix("\"]")
+                    .and_then(|tag| tag.split_once(" \""))
+                    .ok_or_else(|| err(line))?;
+                let value = value.to_owned();
+                match name {
+                    "GameType" => record.game_type = value,
+                    "White" => record.white = value,
+                    "Black" => record.black = value,
+                    "TimeControl" => record.time_control = Some(value),
+                    "Result" => record.result = value,
+                    "Termination" => record.termination = Some(value),
+                    // Skip tags from newer versions.
+                    _ => {}
+                }
+                continue;
+            }
+
+            // Strip the move number, e.g. "12. " or "12... ", and the
+            // comments after the move, e.g. " {clk 298.004}".
+            let (_, rest) = line.split_once(' ').ok_or_else(|| err(line))?;
+            let (move_string, comments) =
+                rest.split_once(" {").map_or((rest, None), |(m, comments)| (m, Some(comments)));
+            let (mut clock, mut eval) = (None, None);
+            for comment in comments.into_iter().flat_map(|comments| comments.split(" {")) {
+                let (name, value) = comment
+                    .strip_suffix('}')
+                    .and_then(|comment| comment.split_once(' '))
+                    .ok_or_else(|| err(line))?;
+                match name {
+                    "clk" => {
+                        let secs = value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|secs| *secs >= 0.0)
+                            .ok_or_else(|| err(line))?;
+                        clock = Some(Duration::from_secs_f64(secs));
+                    }
+                    "eval" => eval = Some(value.parse::<i16>().map_err(|_| err(line))?),
+                    // Skip comments from newer versions.
+                    _ => {}
+                }
+            }
+            // Clocks are either on every move or none of them.
+            if clock.is_some() != (record.clocks.len() == record.moves.len())
+                && !record.moves.is_empty()
+            {
+                return Err(err(line));
+            }
+            record.moves.push(move_string.to_owned());
+            record.clocks.extend(clock);
+            record.evals.push(eval);
+        }
+        if record.evals.iter().all(Option::is_none) {
+            record.evals.clear();
+        }
+        if record.game_type.is_empty() {
+            return Err(err(input));
+        }
+        Ok(record)
+    }
+
+    // Parse every record in a file of records separated by blank lines.
+    pub fn parse_all(input: &str) -> Result<Vec<Self>> {
+        let mut records = Vec::new();
+        let mut current = String::new();
+        for line in input.lines().chain(core::iter::once("")) {
+            if line.trim().is_empty() {
+                if !current.is_empty() {
+                    records.push(Self::parse(&current)?);
+                    current.clear();
+                }
+            } else {
+                current.push_str(line);
+                current.push('\n');
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl core::fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "[GameType \"{}\"]", self.game_type)?;
+        writeln!(f, "[White \"{}\"]", self.white)?;
+        writeln!(f, "[Black \"{}\"]", self.black)?;
+        if let Some(time_control) = &self.time_control {
+            writeln!(f, "[TimeControl \"{}\"]", time_control)?;
+        }
+        writeln!(f, "[Result \"{}\"]", self.result)?;
+        if let Some(termination) = &self.termination {
+            writeln!(f, "[Termination \"{}\"]", termination)?;
+        }
+        for (i, move_string) in self.moves.iter().enumerate() {
+            let number =
+                if i % 2 == 0 { format!("{}.", i / 2 + 1) } else { format!("{}...", i / 2 + 1) };
+            write!(f, "{} {}", number, move_string)?;
+            if let Some(clock) = self.clocks.get(i) {
+                write!(f, " {{clk {:.3}}}", clock.as_secs_f64())?;
+            }
+            if let Some(Some(eval)) = self.evals.get(i) {
+                write!(f, " {{eval {:+}}}", eval)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const GAME: &str =
+        r"Base;InProgress;White[5];wG1;bG1 wG1-;wQ -wG1;bQ bG1-;wA1 \wQ;bA1 bQ/;wA2 /wG1;bA2 bQ\";
+
+    #[test]
+    fn test_game_log_round_trip() {
+        let board = Board::from_game_string(GAME).unwrap();
+        // The log may name destinations relative to different pieces than
+        // the input did, but replaying it gives the same game and log.
+        let replayed =
+            Board::from_game_string(&format!("Base;InProgress;White[5];{}", board.game_log()))
+                .unwrap();
+        assert_eq!(board.game_log(), replayed.game_log());
+        assert_eq!(board.game_string(), replayed.game_string());
+        assert_eq!(board.valid_moves(), replayed.valid_moves());
+    }
+
+    #[test]
+    fn test_game_record_round_trip() {
+        let board = Board::from_game_string(GAME).unwrap();
+        let mut record = GameRecord::from_board(&board, "nokamute", "mzinga");
+        assert_eq!(8, record.moves.len());
+        assert_eq!("InProgress", record.result);
+        assert_eq!(board.game_log(), record.to_board().unwrap().game_log());
+
+        let text = record.to_string();
+        assert!(text.contains("\n1... bG1 wG1-\n"), "{}", text);
+        assert_eq!(record, GameRecord::parse(&text).unwrap());
+
+        // With clocks, and several records in one file.
+        record.time_control = Some("5m+3s".to_string());
+        record.termination = Some("mzinga resigns".to_string());
+        record.clocks = (0..8).map(|i| Duration::from_millis(300_000 - 1500 * i)).collect();
+        let text = format!("{}\n{}\n", record, GameRecord::from_board(&board, "a", "b"));
+        let records = GameRecord::parse_all(&text).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!(record, records[0]);
+        assert_eq!("a", records[1].white);
+        assert!(records[1].clocks.is_empty());
+
+        // Evals on some of the moves.
+        record.evals = (0..8).map(|i| (i % 3 != 0).then_some(i * 7 - 20)).collect();
+        let text = record.to_string();
+        assert!(text.contains("\n1... bG1 wG1- {clk 298.500} {eval -13}\n"), "{}", text);
+        assert_eq!(record, GameRecord::parse(&text).unwrap());
+        assert!(GameRecord::parse("[GameType \"Base\"]\n1. wS1 {eval}").is_err());
+
+        // Illegal moves are caught on replay.
+        record.moves[7] = "bA2 wQ-".to_string();
+        assert!(record.to_board().is_err());
+        assert!(GameRecord::parse("1. wS1").is_err());
+        assert!(GameRecord::parse("[GameType \"Base\"]\n1. wS1 {clk x}").is_err());
+    }
+}
@@ -111,6 +111,7 @@ const HEXSET_NUM_WORDS: usize = GRID_SIZE / 32;
 const HEXSET_SHIFT: u32 = GRID_SIZE.trailing_zeros() - 5;
 const HEXSET_MASK: usize = HEXSET_NUM_WORDS - 1;
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub(crate) struct HexSet {
     table: [u32; HEXSET_NUM_WORDS],
 }
@@ -127,4 +128,83 @@ impl HexSet {
     pub(crate) fn get(&self, hex: Hex) -> bool {
         (self.table[hex as usize & HEXSET_MASK] >> (hex as u32 >> HEXSET_SHIFT)) & 1 != 0
     }
+
+    pub(crate) fn unset(&mut self, hex: Hex) {
+        self.table[hex as usize & HEXSET_MASK] &= !(1 << (hex as u32 >> HEXSET_SHIFT));
+    }
+
+    pub(crate) fn union(&self, other: &HexSet) -> HexSet {
+        let mut out = *self;
+        for (word, other) in out.table.iter_mut().zip(other.table.iter()) {
+            *word |= other;
+        }
+        out
+    }
+
+    pub(crate) fn difference(&self, other: &HexSet) -> HexSet {
+        let mut out = *self;
+        for (word, other) in out.table.iter_mut().zip(other.table.iter()) {
+            *word &= !other;
+        }
+        out
+    }
+
+    // Hexes next to any hex in the set.
+    pub(crate) fn adjacent(&self) -> HexSet {
+        let mut out = HexSet::new();
+        for &dir in Direction::all() {
+            // Move every hex in the set one step in this direction, wrapping
+            // around the grid like Direction::apply. Hexes are spread across
+            // words by their low bits, so the low bits of the offset pick the
+            // word and the high bits rotate within it, with a carry for words
+            // that wrap around.
+            let words = dir as usize & HEXSET_MASK;
+            let bits = dir as u32 >> HEXSET_SHIFT;
+            for (i, &word) in self.table.iter().enumerate() {
+                let carry = (i + words >= HEXSET_NUM_WORDS) as u32;
+                out.table[(i + words) & HEXSET_MASK] |= word.rotate_left(bits + carry);
+            }
+        }
+        out
+    }
+
+    pub(crate) fn iter(&self) -> HexSetIter<'_> {
+        HexSetIter { set: self, word: 0, bits: self.table[0] }
+    }
+}
+
+pub(crate) struct HexSetIter<'a> {
+    set: &'a HexSet,
+    word: usize,
+    bits: u32,
+}
+
+impl Iterator for HexSetIter<'_> {
+    type Item = Hex;
+
+    fn next(&mut self) -> Option<Hex> {
+        while self.bits == 0 {
+            self.word += 1;
+            if self.word == HEXSET_NUM_WORDS {
+                return None;
+            }
+            self.bits = self.set.table[self.word];
+        }
+        let bit = self.bits.trailing_zeros();
+        self.bits &= self.bits - 1;
+        Some(((bit as usize) << HEXSET_SHIFT | self.word) as Hex)
+    }
+}
+
+#[test]
+fn test_hexset_adjacent() {
+    for hex in [0, START_HEX, GRID_MASK, ROW_SIZE - 1, GRID_MASK - ROW_SIZE] {
+        let mut set = HexSet::new();
+        set.set(hex);
+        let mut expected = adjacent(hex);
+        expected.sort_unstable();
+        let mut found = set.adjacent().iter().collect::<alloc::vec::Vec<_>>();
+        found.sort_unstable();
+        assert_eq!(expected.to_vec(), found);
+    }
 }
@@ -0,0 +1,371 @@
+// A JSON over HTTP interface for game website backends. Requests are
+// stateless: each one carries the game as a UHP GameString (or position
+// string) in its query, or in a form encoded POST body, for example
+//
+//   GET /newgame?type=Base%2BMLP
+//   GET /legalmoves?game=Base;InProgress;White[2];wS1
+//   GET /play?game=...&move=bG1%20wS1-
+//   GET /bestmove?game=...&movetime=5s   (or depth=4)
+//   GET /eval?game=...
+//
+// Errors come back as status 400 with {"error": "..."}.
+
+use crate::notation::{Result, UhpError};
+use crate::{parse_duration, serve_connections, Board, PlayerConfig, Rules, Slots};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+// The whole request, headers and body, so a line without an end can't grow
+// past it either.
+const MAX_REQUEST_SIZE: usize = 64 << 10;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONNECTIONS: usize = 64;
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(1);
+// Keeps one request from holding the engine indefinitely, at any depth.
+pub(crate) const MAX_MOVE_TIME: Duration = Duration::from_secs(60);
+pub(crate) const MAX_DEPTH: u8 = 20;
+// Each search gets a fresh table, so they're kept small.
+const REQUEST_TABLE_BYTES: usize = 16 << 20;
+
+// Searches running at once, each with its own table and search threads.
+static SEARCHES: Slots = Slots::new();
+
+pub fn http_serve(mut config: PlayerConfig, addr: &str) {
+    eprintln!("Serving JSON over HTTP on {}", addr);
+    config.opts.table_byte_size = config.opts.table_byte_size.min(REQUEST_TABLE_BYTES);
+    serve_connections(config, addr, MAX_CONNECTIONS, serve_connection);
+}
+
+fn serve_connection(stream: TcpStream, config: PlayerConfig) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?).take(MAX_REQUEST_SIZE as u64);
+    let mut stream = stream;
+    respond(&mut reader, &mut stream, &config)
+}
+
+fn respond(
+    reader: &mut impl BufRead, writer: &mut impl Write, config: &PlayerConfig,
+) -> io::Result<()> {
+    let (status, body) = match read_request(reader)? {
+        Some((path, params)) => match route(config, &path, &params) {
+            Some(Ok(body)) => (200, body),
+            Some(Err(err)) => (400, format!("{{\"error\":{}}}", json_string(&error_message(err)))),
+            None => (404, "{\"error\":\"not found\"}".to_owned()),
+        },
+        None => (400, "{\"error\":\"bad request\"}".to_owned()),
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Bad Request",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+// The path and decoded parameters of a GET or form POST request.
+type Params = Vec<(String, String)>;
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<(String, Params)>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    if !request_line.ends_with('\n') {
+        return Ok(None);
+    }
+    let mut tokens = request_line.split_whitespace();
+    let (method, target) = match (tokens.next(), tokens.next()) {
+        (Some(method), Some(target)) => (method.to_owned(), target.to_owned()),
+        _ => return Ok(None),
+    };
+    let mut content_length = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().unwrap_or(0);
+            }
+        }
+    }
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let mut params = parse_query(query);
+    if method == "POST" {
+        if content_length > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        let mut body = Vec::new();
+        reader.take(content_length as u64).read_to_end(&mut body)?;
+        if body.len() < content_length {
+            return Ok(None);
+        }
+        params.extend(parse_query(&String::from_utf8_lossy(&body)));
+    } else if method != "GET" {
+        return Ok(None);
+    }
+    Ok(Some((path.to_owned(), params)))
+}
+
+fn parse_query(query: &str) -> Params {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let hex = |i: usize| bytes.get(i).and_then(|&b| (b as char).to_digit(16));
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(i + 1), hex(i + 2)) {
+            (b'+', _, _) => out.push(b' '),
+            (b'%', Some(high), Some(low)) => {
+                out.push((high * 16 + low) as u8);
+                i += 2;
+            }
+            (b, _, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn param<'a>(params: &'a Params, name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+fn route(config: &PlayerConfig, path: &str, params: &Params) -> Option<Result<String>> {
+    let game = || {
+        param(params, "game").ok_or_else(|| UhpError::InvalidGameString("missing game".to_owned()))
+    };
+    Some(match path {
+        "/newgame" => new_game(param(params, "type").unwrap_or("Base")),
+        "/legalmoves" => game().and_then(legal_moves),
+        "/play" => game().and_then(|game| {
+            let m = param(params, "move")
+                .ok_or_else(|| UhpError::InvalidMove("missing move".to_owned()))?;
+            play(game, m)
+        }),
+        "/bestmove" => game().and_then(|game| {
+            best_move(config, game, param(params, "movetime"), param(params, "depth"))
+        }),
+        "/eval" => game().and_then(|game| eval(config, game)),
+        _ => return None,
+    })
+}
+
+fn new_game(game_type: &str) -> Result<String> {
+    let board = Board::from_game_type(game_type)?;
+    Ok(format!("{{\"game\":{}}}", json_string(&board.game_string())))
+}
+
+fn legal_moves(game: &str) -> Result<String> {
+    let board = Board::from_game_or_position_string(game)?;
+    let mut moves = Vec::new();
+    if Rules::get_winner(&board).is_none() {
+        Rules::generate_moves(&board, &mut moves);
+    }
+    let moves = moves.into_iter().map(|m| board.to_move_string(m)).collect::<Vec<_>>();
+    Ok(format!("{{\"moves\":{}}}", json_strings(&moves)))
+}
+
+fn play(game: &str, move_string: &str) -> Result<String> {
+    let mut board = Board::from_game_or_position_string(game)?;
+    let m = board.from_move_string(move_string)?;
+    board.apply_untrusted(m)?;
+    Ok(format!("{{\"game\":{}}}", json_string(&board.game_string())))
+}
+
+fn best_move(
+    config: &PlayerConfig, game: &str, movetime: Option<&str>, depth: Option<&str>,
+) -> Result<String> {
+    let board = Board::from_game_or_position_string(game)?;
+    if Rules::get_winner(&board).is_some() {
+        return Err(UhpError::EngineError("the game is over".to_owned()));
+    }
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+    let _slot = SEARCHES.acquire((cores / config.search_threads()).max(1));
+    let mut player = config.new_player();
+    let setup = if game.contains(';') { board.game_type() } else { game.to_owned() };
+    player.new_game(&setup)?;
+    for &turn in &board.turn_history {
+        player.play_move(turn)?;
+    }
+    if let Some(depth) = depth {
+        let depth = depth.parse::<u8>().ok().filter(|&depth| depth > 0 && depth <= MAX_DEPTH);
+        let depth =
+            depth.ok_or_else(|| UhpError::InvalidOption(format!("depth 1 to {}", MAX_DEPTH)))?;
+        player.set_limits(Some(depth), Some(MAX_MOVE_TIME));
+    } else {
+        let time = match movetime {
+            Some(time) => parse_duration(time)
+                .ok_or_else(|| UhpError::InvalidOption(format!("movetime {}", time)))?,
+            None => DEFAULT_MOVE_TIME,
+        };
+        player.set_timeout(time.min(MAX_MOVE_TIME));
+    }
+    let m = player.generate_move()?;
+    let pv = board.line_move_strings(&player.principal_variation());
+    Ok(format!(
+        "{{\"move\":{},\"pv\":{}}}",
+        json_string(&board.to_move_string(m)),
+        json_strings(&pv)
+    ))
+}
+
+fn eval(config: &PlayerConfig, game: &str) -> Result<String> {
+    let board = Board::from_game_or_position_string(game)?;
+    let breakdown = config.eval.explain_eval(&board);
+    let pieces = breakdown
+        .pieces
+        .iter()
+        .map(|piece| {
+            format!(
+                "{{\"piece\":{},\"pinned\":{},\"score\":{}}}",
+                json_string(&piece.name),
+                piece.pinned,
+                piece.score
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok(format!(
+        "{{\"eval\":{},\"phase\":{},\"unplayed\":{},\"queen_surrounding\":{},\"queen_safety\":{},\"pillbug_defense\":{},\"covered\":{},\"pieces\":[{}]}}",
+        breakdown.total,
+        json_string(&breakdown.phase.to_string()),
+        breakdown.unplayed,
+        breakdown.queen_surrounding,
+        breakdown.queen_safety,
+        breakdown.pillbug_defense,
+        breakdown.covered,
+        pieces.join(",")
+    ))
+}
+
+fn error_message(err: UhpError) -> String {
+    match err {
+        UhpError::IoError(err) => err.to_string(),
+        UhpError::InvalidMove(message)
+        | UhpError::InvalidGameString(message)
+        | UhpError::InvalidGameType(message)
+        | UhpError::InvalidOption(message)
+        | UhpError::EngineError(message) => message,
+        err => err.to_string(),
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn json_strings(strings: &[String]) -> String {
+    format!("[{}]", strings.iter().map(|s| json_string(s)).collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(request: &str) -> (u16, String) {
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        let mut response = Vec::new();
+        respond(&mut request.as_bytes(), &mut response, &config).unwrap();
+        let response = String::from_utf8(response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())), "{}", head);
+        (status, body.to_owned())
+    }
+
+    fn get(target: &str) -> (u16, String) {
+        request(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target))
+    }
+
+    #[test]
+    fn test_http_api() {
+        assert_eq!(
+            (200, r#"{"game":"Base+MLP;NotStarted;White[1]"}"#.to_owned()),
+            get("/newgame?type=Base%2BMLP")
+        );
+        let (status, body) = get("/legalmoves?game=Base;InProgress;White[2];wS1;bG1+wS1-");
+        assert_eq!(200, status);
+        assert!(
+            body.starts_with(r#"{"moves":["wQ "#) && body.contains(r#""wA1 -wS1""#),
+            "{}",
+            body
+        );
+        assert_eq!(
+            (200, r#"{"game":"Base;InProgress;White[2];wS1;bG1 wS1-"}"#.to_owned()),
+            get("/play?game=Base;InProgress;Black[1];wS1&move=bG1%20wS1-")
+        );
+
+        // A long game string fits in a POST body.
+        let body = "game=Base;InProgress;White[2];wS1;bG1+wS1-&depth=2";
+        let (status, body) = request(&format!(
+            "POST /bestmove HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        assert_eq!(200, status, "{}", body);
+        assert!(body.starts_with(r#"{"move":"w"#) && body.contains(r#""pv":["w"#), "{}", body);
+
+        let (status, body) = get("/eval?game=Base;InProgress;White[2];wS1;bG1+wS1-");
+        assert_eq!(200, status);
+        assert!(body.starts_with(r#"{"eval":"#) && body.contains(r#"{"piece":"bG1","#), "{}", body);
+
+        assert_eq!(400, get("/play?game=Base;InProgress;White[1]&move=wQ2").0);
+        assert_eq!(400, get("/bestmove?game=Base&movetime=fast").0);
+        assert_eq!(400, get("/legalmoves").0);
+        assert_eq!(404, get("/uhp").0);
+        assert_eq!(400, request("DELETE /eval HTTP/1.1\r\n\r\n").0);
+        // Requests are cut off at the size limit, even without a line end.
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        let huge = format!("GET /newgame HTTP/1.1\r\nX-Padding: {}", "a".repeat(MAX_REQUEST_SIZE));
+        let mut reader = huge.as_bytes().take(MAX_REQUEST_SIZE as u64);
+        let mut response = Vec::new();
+        respond(&mut reader, &mut response, &config).unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 400 "));
+        assert_eq!(400, request("GET /newgame HTTP/1.1\r\nHost: localhost").0);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!("wA1 -wQ", percent_decode("wA1+-wQ"));
+        assert_eq!("Base+MLP", percent_decode("Base%2bMLP"));
+        assert_eq!("100%", percent_decode("100%"));
+        assert_eq!(r#""a\"b\\""#, json_string("a\"b\\"));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{adjacent, Board, Bug, Hex, Rules, Turn};
+use alloc::vec::Vec;
+use core::fmt;
+
+// Why a move isn't legal, to explain rejected moves to players.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IllegalReason {
+    // Pass is the only legal move.
+    MustPass,
+    // Passing while there are other moves.
+    PassNotAllowed,
+    // Placing a bug the player has none left of.
+    NotInHand,
+    // The tournament opening rule.
+    QueenFirstTurn,
+    // Placing anything but the queen on a player's fourth turn without her.
+    QueenRequired,
+    // Moving a tile before the player's queen is out.
+    QueenNotPlaced,
+    // Moving the queen while the variant keeps her in place.
+    QueenHeld,
+    // Moving from an empty hex.
+    NoTile,
+    // Moving the other player's tile without a pillbug to throw it.
+    WrongColor,
+    // Moving the tile that was moved on the last turn.
+    Stunned,
+    // Lifting a tile that holds the hive together.
+    BreaksHive,
+    // Placing a tile on top of another.
+    HexOccupied,
+    // Climbing with a bug that can't.
+    DestinationOccupied,
+    // Squeezing between two tiles, or stacks, that block the way.
+    GateBlocked,
+    // Placing next to the other player's tiles.
+    PlacementTouchesEnemy,
+    // Placing away from the player's own tiles.
+    PlacementNotTouchingOwn,
+    // Placing the second tile of the game away from the first.
+    PlacementApart,
+    // The tile can move, but not there.
+    CantReach,
+    // The tile has nowhere to move.
+    CantMove,
+}
+
+impl fmt::Display for IllegalReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IllegalReason::MustPass => "There are no legal moves, so the player must pass",
+            IllegalReason::PassNotAllowed => "A player can only pass without a legal move",
+            IllegalReason::NotInHand => "That tile is not in hand",
+            IllegalReason::QueenFirstTurn => "The queen can't be placed on a player's first turn",
+            IllegalReason::QueenRequired => "The queen must be placed by a player's fourth turn",
+            IllegalReason::QueenNotPlaced => "The queen must be placed before any tile can move",
+            IllegalReason::QueenHeld => {
+                "The queen can't move until all of a player's tiles are placed"
+            }
+            IllegalReason::NoTile => "There is no tile there to move",
+            IllegalReason::WrongColor => "Only a pillbug can move the other player's tiles",
+            IllegalReason::Stunned => "A tile moved on the last turn can't move on this one",
+            IllegalReason::BreaksHive => "That tile can't move without splitting the hive",
+            IllegalReason::HexOccupied => "A new tile must go on an empty hex",
+            IllegalReason::DestinationOccupied => "Only beetles can climb on top of other tiles",
+            IllegalReason::GateBlocked => "That tile can't squeeze through the gap",
+            IllegalReason::PlacementTouchesEnemy | IllegalReason::PlacementNotTouchingOwn => {
+                "A new tile must touch its own color and not the other"
+            }
+            IllegalReason::PlacementApart => "The second tile must touch the first",
+            IllegalReason::CantReach => "That tile can't reach there",
+            IllegalReason::CantMove => "That tile can't move",
+        })
+    }
+}
+
+impl Board {
+    // Why the turn isn't legal here, or None if it is.
+    pub fn why_illegal(&self, turn: Turn) -> Option<IllegalReason> {
+        let mut moves = Vec::new();
+        Rules::generate_moves(self, &mut moves);
+        (!moves.contains(&turn)).then(|| self.illegal_reason(turn, &moves))
+    }
+
+    // Why the turn isn't among the legal moves given.
+    pub(crate) fn illegal_reason(&self, turn: Turn, moves: &[Turn]) -> IllegalReason {
+        let color = self.to_move();
+        match turn {
+            _ if moves == [Turn::Pass] => IllegalReason::MustPass,
+            Turn::Pass => IllegalReason::PassNotAllowed,
+            Turn::Place(hex, bug) => {
+                let touches = |color: usize| {
+                    adjacent(hex)
+                        .iter()
+                        .any(|&adj| self.occupied(adj) && self.node(adj).color() as usize == color)
+                };
+                if self.remaining[color as usize][bug as usize] == 0 {
+                    IllegalReason::NotInHand
+                } else if bug == Bug::Queen && self.turn_num < 2 && self.variant.tournament_opening
+                {
+                    IllegalReason::QueenFirstTurn
+                } else if bug != Bug::Queen && self.queen_required() {
+                    IllegalReason::QueenRequired
+                } else if self.occupied(hex) {
+                    IllegalReason::HexOccupied
+                } else if self.turn_num < 2 {
+                    IllegalReason::PlacementApart
+                } else if touches(color.other()) {
+                    IllegalReason::PlacementTouchesEnemy
+                } else if !touches(color as usize) {
+                    IllegalReason::PlacementNotTouchingOwn
+                } else {
+                    IllegalReason::CantReach
+                }
+            }
+            Turn::Move(start, end) => {
+                if !self.occupied(start) {
+                    return IllegalReason::NoTile;
+                }
+                if self.queens[color as usize].is_none() {
+                    return IllegalReason::QueenNotPlaced;
+                }
+                let node = self.node(start);
+                if node.color() != color && !self.throwable(start) {
+                    return IllegalReason::WrongColor;
+                }
+                if node.color() == color && self.queen_held(node) {
+                    return IllegalReason::QueenHeld;
+                }
+                if matches!(self.turn_history.last(), Some(Turn::Move(_, dest)) if *dest == start) {
+                    return IllegalReason::Stunned;
+                }
+                if !node.is_stacked() && self.find_cut_vertexes().get(start) {
+                    return IllegalReason::BreaksHive;
+                }
+                if self.occupied(end) && !self.climber(start) {
+                    return IllegalReason::DestinationOccupied;
+                }
+                if self.gate_blocked(start, end) {
+                    return IllegalReason::GateBlocked;
+                }
+                if moves.iter().any(|&turn| matches!(turn, Turn::Move(from, _) if from == start)) {
+                    IllegalReason::CantReach
+                } else {
+                    IllegalReason::CantMove
+                }
+            }
+        }
+    }
+
+    // Whether a pillbug of the player to move, or a mosquito copying one, is
+    // next to the tile.
+    fn throwable(&self, hex: Hex) -> bool {
+        let color = self.to_move();
+        adjacent(hex).iter().any(|&adj| {
+            let node = self.node(adj);
+            node.occupied()
+                && !node.is_stacked()
+                && node.color() == color
+                && (node.bug() == Bug::Pillbug
+                    || node.bug() == Bug::Mosquito
+                        && adjacent(adj).iter().any(|&near| {
+                            let near = self.node(near);
+                            near.occupied() && near.bug() == Bug::Pillbug
+                        }))
+        })
+    }
+
+    // Whether the tile at hex can climb: a beetle, a tile already on the
+    // hive, or a mosquito next to a beetle.
+    fn climber(&self, hex: Hex) -> bool {
+        let node = self.node(hex);
+        node.is_stacked()
+            || node.bug() == Bug::Beetle
+            || node.bug() == Bug::Mosquito
+                && adjacent(hex).iter().any(|&adj| {
+                    let adj = self.node(adj);
+                    adj.occupied() && adj.bug() == Bug::Beetle
+                })
+    }
+
+    // Whether the tiles on both sides of a step between neighboring hexes
+    // stand higher than the tile would on either end, the freedom to move
+    // rule.
+    fn gate_blocked(&self, start: Hex, end: Hex) -> bool {
+        let neighbors = adjacent(start);
+        let Some(i) = neighbors.iter().position(|&hex| hex == end) else {
+            return false;
+        };
+        let barrier = (self.height(start) - 1).max(self.height(end));
+        self.height(neighbors[(i + 1) % 6]) > barrier
+            && self.height(neighbors[(i + 5) % 6]) > barrier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loc_to_hex, Color};
+
+    #[test]
+    fn test_why_illegal() {
+        let mut board = Board::from_game_type("Base").unwrap();
+        let start = loc_to_hex((0, 0));
+        let first = Turn::Place(start, Bug::Spider);
+        assert_eq!(None, board.why_illegal(first));
+        assert_eq!(
+            Some(IllegalReason::QueenFirstTurn),
+            board.why_illegal(Turn::Place(start, Bug::Queen))
+        );
+        board.apply(first);
+        assert_eq!(
+            Some(IllegalReason::PlacementApart),
+            board.why_illegal(Turn::Place(loc_to_hex((2, 0)), Bug::Spider))
+        );
+        board.apply(Turn::Place(loc_to_hex((1, 0)), Bug::Spider));
+        assert_eq!(
+            Some(IllegalReason::QueenNotPlaced),
+            board.why_illegal(Turn::Move(start, loc_to_hex((1, 1))))
+        );
+        assert_eq!(
+            Some(IllegalReason::HexOccupied),
+            board.why_illegal(Turn::Place(start, Bug::Ant))
+        );
+        assert_eq!(
+            Some(IllegalReason::PlacementTouchesEnemy),
+            board.why_illegal(Turn::Place(loc_to_hex((0, -1)), Bug::Ant))
+        );
+        assert_eq!(
+            Some(IllegalReason::PlacementNotTouchingOwn),
+            board.why_illegal(Turn::Place(loc_to_hex((-3, 0)), Bug::Ant))
+        );
+        assert_eq!(Some(IllegalReason::PassNotAllowed), board.why_illegal(Turn::Pass));
+
+        let game = "Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-";
+        let board = Board::from_game_string(game).unwrap();
+        let hex = |piece: &str| {
+            let Ok(Turn::Move(_, hex)) = board.from_move_string(&format!("wB1 {}", piece)) else {
+                panic!("{}", piece);
+            };
+            hex
+        };
+        let (queen, spider, ant) = (hex("wQ"), hex("wS1"), hex("bA1"));
+        assert_eq!(
+            Some(IllegalReason::NotInHand),
+            board.why_illegal(Turn::Place(loc_to_hex((-3, 0)), Bug::Queen))
+        );
+        assert_eq!(
+            Some(IllegalReason::NoTile),
+            board.why_illegal(Turn::Move(loc_to_hex((-3, 0)), queen))
+        );
+        assert_eq!(
+            Some(IllegalReason::WrongColor),
+            board.why_illegal(Turn::Move(ant, loc_to_hex((-3, 0))))
+        );
+        assert_eq!(
+            Some(IllegalReason::BreaksHive),
+            board.why_illegal(Turn::Move(spider, loc_to_hex((-3, 0))))
+        );
+        assert_eq!(
+            Some(IllegalReason::DestinationOccupied),
+            board.why_illegal(Turn::Move(queen, spider))
+        );
+        assert_eq!(
+            Some(IllegalReason::CantReach),
+            board.why_illegal(Turn::Move(queen, loc_to_hex((-3, 0))))
+        );
+
+        // The queen has to squeeze between two tiles to get into the hole.
+        let board = Board::from_position_string(
+            "Base White[6] wA1@0,-1 wA2@1,-1 wQ@0,0 wA3@2,0 bA1@1,1 bQ@2,1 w:GGGSSBB b:GGGSSAABB",
+        )
+        .unwrap();
+        let queen = board.queens[Color::White as usize].unwrap();
+        let hole = adjacent(queen)[2];
+        assert!(!board.occupied(hole));
+        assert_eq!(Some(IllegalReason::GateBlocked), board.why_illegal(Turn::Move(queen, hole)));
+
+        // After three turns without the queen, she must go down.
+        let game = "Base;InProgress;White[4];wS1;bS1 wS1-;wA1 -wS1;bA1 bS1-;wG1 -wA1;bG1 bA1-";
+        let board = Board::from_game_string(game).unwrap();
+        let hex = loc_to_hex((-4, 0));
+        assert_eq!(
+            Some(IllegalReason::QueenRequired),
+            board.why_illegal(Turn::Place(hex, Bug::Ant))
+        );
+    }
+}
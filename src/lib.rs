@@ -1,28 +1,112 @@
-#[macro_use]
-extern crate lazy_static;
+// Without the engine feature, only the board, move generation, and rules are
+// built. They only need core and alloc, so bare metal targets can use them
+// without std. Hosted targets still link std, which the cdylib requires.
+#![cfg_attr(all(not(feature = "engine"), target_os = "none"), no_std)]
 
+extern crate alloc;
+
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod analyze;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use analyze::*;
 mod board;
 pub use board::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod boardspace;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use boardspace::*;
 mod bug;
 pub use bug::*;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "engine")]
+mod clock;
+#[cfg(feature = "engine")]
+pub use clock::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
 mod cli;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
 pub use cli::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod convert;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use convert::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod engine;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use engine::*;
+#[cfg(feature = "engine")]
 mod eval;
+#[cfg(feature = "engine")]
 pub use eval::*;
+mod game_record;
+pub use game_record::*;
 mod hex_grid;
 pub use hex_grid::*;
+mod illegal;
+pub use illegal::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod http_server;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use http_server::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod mcts;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use mcts::*;
+#[cfg(feature = "nnue")]
+mod nnue;
 mod notation;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "nnue")]
+pub use nnue::*;
+pub use notation::{GameResult, Surround, UhpError};
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
 mod perft;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
 pub use perft::*;
+#[cfg(feature = "engine")]
 mod player;
+#[cfg(feature = "engine")]
 pub use player::*;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod puzzles;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use puzzles::*;
+mod render;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod search_log;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod selfplay;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use selfplay::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod solve;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use solve::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod suite;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use suite::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod tablebase;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use tablebase::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod tournament;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use tournament::*;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod tune;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use tune::*;
+#[cfg(all(feature = "engine", unix))]
+mod tui;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
 mod uhp_client;
+#[cfg(feature = "engine")]
 mod uhp_server;
+#[cfg(feature = "engine")]
 pub use uhp_server::*;
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "engine", target_arch = "wasm32"))]
 mod wasm;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+mod ws_server;
+#[cfg(all(feature = "engine", not(target_arch = "wasm32")))]
+pub use ws_server::*;
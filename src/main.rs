@@ -11,9 +11,71 @@ fn help() {
 commands:
  cli:   Interactive interface to a board
  uhp:   Run as a Universal Hive Protocol engine
- play [--game-type=] [--depth=] [--timeout=] [player1] [player2]:
-        Play a game, with each player being "human", "ai|nokamute",
-        or a path to a UHP engine
+ serve [--ws=address:port] [--http=address:port]:
+        --ws serves the Universal Hive Protocol over WebSockets, e.g.
+        on 0.0.0.0:8080, with a separate game and engine for each
+        connection. Each message is a command, answered by one message.
+        --http serves a JSON API: /newgame?type=, /legalmoves?game=,
+        /play?game=&move=, /bestmove?game=&movetime=|depth=, and
+        /eval?game=, with games given as UHP GameStrings
+ play [--game-type=] [--depth=] [--timeout=] [--clock=] [--record=] [player1] [player2]:
+        Play a game, with each player being "human", "tui" (human with
+        arrow key move entry), "ai|nokamute", or a path to a UHP engine.
+        --record appends the game to a file
+ tournament [--game-type=] [--games=] [--jobs=] [--depth=] [--timeout=] [--record=] [--report=] [--adjudicate=] player1 player2 [players...]:
+        Play a round robin of --games games per pair of players,
+        alternating colors, and summarize results with Elo estimates.
+        --jobs plays that many games at once, each job with its own
+        players, and UHP engines running in its own temp directory
+        --report appends each game to a file with numbered moves and
+        the evals of the players' expected lines
+        --adjudicate ends games early by any of these rules:
+          moves=N: draw after N moves
+          resign=V/N: win when both players' lines score V or more for
+            the same side for N moves in a row
+          queen: win when a queen with 5 neighbors can't be defended
+          tablebase: end small endgames with their exact result
+          shutout: draw when neither player can ever win
+ analyze [--depth=] [--timeout=] [--blunder=] game_string:
+        Search the position before every move of a game, and print the
+        value before and after each move, the best move, and blunders
+        that lose more than --blunder (default 100)
+ render [--output=] game_string:
+        Draw the position as an SVG image, to stdout or the --output file
+ suite [--depth=] [--timeout=] suite_file:
+        Search each position of a test suite, one per line as
+        "GameString | bm move[, move...] | id name", and report
+        which were solved and how long it took to find the best move
+ selfplay [--game-type=] [--games=] [--max-moves=] [--depth=] [--timeout=] [--output=]:
+        Play the engine against itself and write every position as a
+        line of JSON with the search score, the move played, and the
+        game result, as training data. Use --random-plies to vary
+        the openings. Games end in a draw after --max-moves (200)
+ solve [--depth=] game_or_position_string:
+        Search every line for a forced queen surround by the side to
+        move within --depth plies (default 5), both sides' moves
+        counted, and print the quickest, with the longest defense
+ puzzles [--surround-in=] [--games=] [--game-type=] [--depth=] [--timeout=] [--output=] [games_file]:
+        Find positions where the side to move can force a queen
+        surround within --surround-in (default 2, at most 3) of its own
+        moves, by only one first move, in the games of games_file (one
+        UHP GameString per line) or in --games self-play games. Puzzles
+        are written as test suite lines with the winning line
+ tablebase [--max-positions=] [--output=] [--probe=] game_or_position_string:
+        Solve a small endgame, where both queens have 4+ neighbors and
+        at most 4 bugs can move or are in hand, and the positions it
+        leads to that still qualify, and print the result. --output
+        saves the table, and --probe looks the position up in a saved
+        table instead
+ convert [--from=uhp|boardspace|record] [--to=uhp|record] [--output=] paths...:
+        Convert game archives between UHP GameStrings, one per line,
+        boardspace.net records, one per file, and nokamute game records.
+        Directories are searched for files, and the input format is
+        guessed from each file unless --from is given. Malformed games
+        are reported and skipped. --to defaults to uhp
+ tune corpus_file [--iterations=]:
+        Fit evaluation weights to the results of finished games,
+        given as one UHP GameString per line
  perft [game_state]:
         Count the number of board states at each depth
  perft-cheating [game_state]:
@@ -21,16 +83,43 @@ commands:
  perft-debug game_state depth engine_command
         Find discrepancies between nokamute and another UHP engine
         from the specified starting position at the specified depth
+ movegen-diff [--game-type=] [--games=] [--max-moves=] engine_command...:
+        Play random games against another UHP engine, such as Mzinga,
+        comparing validmoves and game results after every move, and
+        print the game string of the first position where they differ
 
 engine flags:
  --verbose
- --strategy=iterative|mcts|mtdf|random
+ --preset=instant (depth 2 within about 50ms, ignoring --depth and --timeout)
+ --strategy=iterative|mcts|hybrid|mtdf|random
+ --background-ponder (iterative only)
  --table-mb=[int]
+ --max-memory-mb=[int] (shrink the table so it and the search threads fit in this)
+ --max-nodes=[int] (iterative only; don't start a depth likely to evaluate more positions)
+ --log-file=[file] (append every search's limits, depth, nodes, score, and PV as JSON lines)
  --num-threads=[int]|all
  --aspiration-window=[int]
  --double-step
+ --eval=basic|dumb|nnue (nnue needs --nnue-weights=[file] and the nnue feature)
+ --eval-weights=[name=int,...] (override basic weights, e.g. from tune)
+ --contempt=[int] (avoid draws against weaker opponents, or seek them if negative)
+ --threat-extension (search 2 more plies past leaves where a queen is one move from surrounded)
+ --resign (resign once the search finds the game lost, in play and tournament)
+ --accept-draws (accept draw offers unless ahead, in play and tournament)
+ --strength=[1-10] (play weaker: shallower, noisier, sometimes random; 10 is full strength)
  --quiet-search
- --null-move-pruning"#,
+ --quiet-moves=placements|threats (with --quiet-search)
+ --null-move-pruning
+ --widening=[plies/moves] (iterative only; past the first plies, search only the first moves)
+ --move-history
+ --draw-value=[0-1] (mcts and hybrid only)
+ --rollouts=random|hive (mcts only; hive weights rollout moves toward queen pressure)
+ --leaf-depth=[int] (hybrid only; alpha-beta depth scoring each new leaf, default 2)
+ --seed=[int] (repeatable random openings, random players, and mcts rollouts)
+ --random-plies=[int] (pick the first moves at random among near-best moves)
+ --temperature=[float] (with --random-plies, softmax over move scores instead)
+ --uhp-timeout=[duration] (for UHP engine players, default 30s)
+ --uhp-depth-timeout=[duration] (for UHP engine players' --depth searches, default 10m)"#,
         env!("CARGO_PKG_VERSION")
     );
 }
@@ -38,13 +127,30 @@ engine flags:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
     let (config, args) = configure_player().unwrap();
-    match args.get(0).unwrap_or(&"uhp".to_owned()).as_ref() {
+    match args.first().unwrap_or(&"uhp".to_owned()).as_ref() {
         "cli" => {
             terminal_game_interface(config);
         }
         "uhp" => {
             uhp_serve(config);
         }
+        "serve" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let ws: Option<String> = args.opt_value_from_str("--ws").unwrap();
+            let http: Option<String> = args.opt_value_from_str("--http").unwrap();
+            match (ws, http) {
+                (Some(ws), Some(http)) => {
+                    let ws_config = config.clone();
+                    std::thread::spawn(move || ws_serve(ws_config, &ws));
+                    http_serve(config, &http);
+                }
+                (Some(ws), None) => ws_serve(config, &ws),
+                (None, Some(http)) => http_serve(config, &http),
+                (None, None) => println!("serve requires --ws=address:port or --http=address:port"),
+            }
+        }
         "play" => {
             let mut args = pico_args::Arguments::from_vec(
                 args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
@@ -55,12 +161,178 @@ fn main() {
                 .unwrap_or_else(|| "Base+MLP".to_owned());
             let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
             let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let clock: Option<String> = args.opt_value_from_str("--clock").unwrap();
+            let record: Option<String> = args.opt_value_from_str("--record").unwrap();
             let args =
                 args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
 
             let player1 = args.get(1).map(|s| s.as_ref()).unwrap_or("human");
             let player2 = args.get(2).map(|s| s.as_ref()).unwrap_or("ai");
-            play_game(config, &game_type, player1, player2, depth, timeout);
+            play_game(config, &game_type, player1, player2, depth, timeout, clock, record);
+        }
+        "tournament" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let game_type = args
+                .opt_value_from_str("--game-type")
+                .unwrap()
+                .unwrap_or_else(|| "Base+MLP".to_owned());
+            let games: usize = args.opt_value_from_str("--games").unwrap().unwrap_or(10);
+            let jobs: usize = args.opt_value_from_str("--jobs").unwrap().unwrap_or(1);
+            let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
+            let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let record: Option<String> = args.opt_value_from_str("--record").unwrap();
+            let report: Option<String> = args.opt_value_from_str("--report").unwrap();
+            let adjudicate: Option<String> = args.opt_value_from_str("--adjudicate").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            tournament(
+                config,
+                &game_type,
+                &args[1..],
+                games,
+                jobs,
+                depth,
+                timeout,
+                record,
+                report,
+                adjudicate,
+            );
+        }
+        "analyze" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
+            let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let threshold: i32 = args.opt_value_from_str("--blunder").unwrap().unwrap_or(100);
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("analyze requires a game string");
+                return;
+            }
+            analyze_main(config, &args[1], depth, timeout, threshold);
+        }
+        "render" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let output: Option<String> = args.opt_value_from_str("--output").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("render requires a game string");
+                return;
+            }
+            render_main(&args[1], output);
+        }
+        "suite" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
+            let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("suite requires a suite file");
+                return;
+            }
+            suite_main(config, &args[1], depth, timeout);
+        }
+        "selfplay" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let game_type = args
+                .opt_value_from_str("--game-type")
+                .unwrap()
+                .unwrap_or_else(|| "Base+MLP".to_owned());
+            let games: usize = args.opt_value_from_str("--games").unwrap().unwrap_or(10);
+            let max_moves: u16 = args.opt_value_from_str("--max-moves").unwrap().unwrap_or(200);
+            let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
+            let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let output: Option<String> = args.opt_value_from_str("--output").unwrap();
+            selfplay_main(config, &game_type, games, max_moves, depth, timeout, output);
+        }
+        "solve" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let depth: u8 = args.opt_value_from_str("--depth").unwrap().unwrap_or(5);
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("solve requires a game string");
+                return;
+            }
+            solve_main(&args[1], depth);
+        }
+        "puzzles" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let max_moves: u8 = args.opt_value_from_str("--surround-in").unwrap().unwrap_or(2);
+            let games: usize = args.opt_value_from_str("--games").unwrap().unwrap_or(10);
+            let game_type = args
+                .opt_value_from_str("--game-type")
+                .unwrap()
+                .unwrap_or_else(|| "Base+MLP".to_owned());
+            let depth: Option<u8> = args.opt_value_from_str("--depth").unwrap();
+            let timeout: Option<String> = args.opt_value_from_str("--timeout").unwrap();
+            let output: Option<String> = args.opt_value_from_str("--output").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            let input = args.get(1).cloned();
+            puzzles_main(config, input, max_moves, &game_type, games, depth, timeout, output);
+        }
+        "tablebase" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let max_positions: usize = args
+                .opt_value_from_str("--max-positions")
+                .unwrap()
+                .unwrap_or(DEFAULT_MAX_POSITIONS);
+            let output: Option<String> = args.opt_value_from_str("--output").unwrap();
+            let probe: Option<String> = args.opt_value_from_str("--probe").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("tablebase requires a game string");
+                return;
+            }
+            tablebase_main(&args[1], max_positions, output, probe);
+        }
+        "convert" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let from: Option<String> = args.opt_value_from_str("--from").unwrap();
+            let to = args.opt_value_from_str("--to").unwrap().unwrap_or_else(|| "uhp".to_owned());
+            let output: Option<String> = args.opt_value_from_str("--output").unwrap();
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("convert requires files or directories of games");
+                return;
+            }
+            convert_main(&args[1..], from, &to, output);
+        }
+        "tune" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let iterations: usize = args.opt_value_from_str("--iterations").unwrap().unwrap_or(100);
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("tune requires a corpus file");
+                return;
+            }
+            tune_main(&args[1], iterations);
         }
         "perft" => {
             // For engine performance comparisons.
@@ -86,6 +358,24 @@ fn main() {
             let game_type = &args[1];
             perft_debug(&args[3..], game_type, depth);
         }
+        "movegen-diff" => {
+            let mut args = pico_args::Arguments::from_vec(
+                args.iter().map(|s| s.into()).collect::<Vec<OsString>>(),
+            );
+            let game_type = args
+                .opt_value_from_str("--game-type")
+                .unwrap()
+                .unwrap_or_else(|| "Base+MLP".to_owned());
+            let games: usize = args.opt_value_from_str("--games").unwrap().unwrap_or(100);
+            let max_moves: usize = args.opt_value_from_str("--max-moves").unwrap().unwrap_or(200);
+            let args =
+                args.finish().into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>();
+            if args.len() < 2 {
+                println!("movegen-diff requires an engine command");
+                return;
+            }
+            movegen_diff_main(config, &args[1..], &game_type, games, max_moves);
+        }
         _ => {
             help();
         }
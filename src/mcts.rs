@@ -0,0 +1,739 @@
+use crate::{adjacent, BasicEvaluator, Board, Bug, Hex, Rules, Turn};
+use minimax::{Evaluation, Game, MCTSOptions, Negamax, Strategy, Winner};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Same encoding as the minimax crate: proven results are WIN or LOSS, and
+// results from deeper in a rollout are +1, -1, or 0.
+const WIN: i32 = i32::MAX;
+const LOSS: i32 = -WIN;
+
+// Counts of how rollouts ended.
+#[derive(Default)]
+pub struct RolloutStats {
+    rollouts: AtomicU64,
+    // Rollouts ending in a draw by repetition or stalemate.
+    draws: AtomicU64,
+    // Rollouts cut off at the max rollout depth, which are also scored as draws.
+    truncated: AtomicU64,
+}
+
+impl RolloutStats {
+    pub fn rollouts(&self) -> u64 {
+        self.rollouts.load(Relaxed)
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws.load(Relaxed)
+    }
+
+    pub fn truncated(&self) -> u64 {
+        self.truncated.load(Relaxed)
+    }
+
+    // Fraction of rollouts scored as draws, including truncated rollouts.
+    pub fn draw_rate(&self) -> f64 {
+        (self.draws() + self.truncated()) as f64 / self.rollouts().max(1) as f64
+    }
+
+    fn reset(&self) {
+        self.rollouts.store(0, Relaxed);
+        self.draws.store(0, Relaxed);
+        self.truncated.store(0, Relaxed);
+    }
+}
+
+// How rollouts pick their moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rollouts {
+    // Uniformly at random.
+    #[default]
+    Random,
+    // At random, but weighted toward what Hive players actually do.
+    Hive,
+}
+
+// Random rollouts where a draw is worth draw_value to both sides,
+// on the scale of 0 for a loss and 1 for a win. Hive games tend to shuffle
+// into repetitions, so values below 0.5 push the search toward decisive lines.
+struct DrawScoringPolicy {
+    draw_value: f64,
+    max_rollout_depth: u32,
+    stats: Arc<RolloutStats>,
+    // Derive each rollout's random moves from this, the position, and the
+    // rollout number, to replay the same rollouts.
+    seed: Option<u64>,
+    rollouts: Rollouts,
+    // Score leaves with an alpha-beta search this deep instead of playing
+    // them out.
+    leaf_search: Option<(BasicEvaluator, u8)>,
+}
+
+// Leaf scores this far ahead for the player who just moved count as a win
+// about 73% of the time.
+const LEAF_SCALE: f64 = 100.0;
+
+impl DrawScoringPolicy {
+    // The tree only accumulates integer results, so score draws as +1 or -1
+    // often enough that the expected result matches the draw value.
+    fn draw_score(&self, rng: &mut impl Rng) -> i32 {
+        let value = 2.0 * self.draw_value - 1.0;
+        if rng.gen::<f64>() < value.abs() {
+            value.signum() as i32
+        } else {
+            0
+        }
+    }
+
+    // Plays a game out from the position, and scores it for the player who
+    // just moved.
+    fn rollout(&self, board: &Board) -> i32 {
+        let rollout = self.stats.rollouts.fetch_add(1, Relaxed);
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(
+                seed ^ Rules::zobrist_hash(board) ^ rollout.wrapping_mul(0x9e3779b97f4a7c15),
+            ),
+            None => StdRng::from_rng(rand::thread_rng()).unwrap(),
+        };
+        if let Some((eval, depth)) = self.leaf_search {
+            return self.search_leaf(board, eval, depth, &mut rng);
+        }
+        // Each thread keeps a board to play rollouts on, to copy into rather
+        // than allocating a clone with the whole game history every time.
+        SCRATCH.with(|scratch| {
+            let mut scratch = scratch.borrow_mut();
+            scratch.board.restore(board);
+            let Scratch { board, moves } = &mut *scratch;
+            self.play_out(board, moves, &mut rng)
+        })
+    }
+
+    // Scores the position by a shallow search, like a value network would,
+    // so the tree sees tactics that random play wanders past. The tree only
+    // takes win or loss results, so the score picks one at random, with the
+    // odds of a win rising smoothly with the score.
+    fn search_leaf(&self, board: &Board, eval: BasicEvaluator, depth: u8, rng: &mut StdRng) -> i32 {
+        match Rules::get_winner(board) {
+            Some(Winner::PlayerJustMoved) => return WIN,
+            Some(Winner::PlayerToMove) => return LOSS,
+            Some(Winner::Draw) => {
+                self.stats.draws.fetch_add(1, Relaxed);
+                return self.draw_score(rng);
+            }
+            None => {}
+        }
+        let mut search = Negamax::new(eval, depth.max(1));
+        search.choose_move(board);
+        // The search scores the position for the player to move.
+        let value: Evaluation = search.root_value();
+        let win_chance = 1.0 / (1.0 + (value as f64 / LEAF_SCALE).exp());
+        if rng.gen::<f64>() < win_chance {
+            1
+        } else {
+            -1
+        }
+    }
+
+    // Plays random moves until the game ends or the rollout is cut off.
+    fn play_out(&self, board: &mut Board, moves: &mut Vec<Turn>, rng: &mut StdRng) -> i32 {
+        let mut sign = 1;
+        let mut depth = 0;
+        loop {
+            if let Some(winner) = Rules::get_winner(board) {
+                return match winner {
+                    Winner::PlayerJustMoved if depth == 0 => WIN,
+                    Winner::PlayerToMove if depth == 0 => LOSS,
+                    Winner::PlayerJustMoved => sign,
+                    Winner::PlayerToMove => -sign,
+                    Winner::Draw => {
+                        self.stats.draws.fetch_add(1, Relaxed);
+                        self.draw_score(rng)
+                    }
+                };
+            }
+            if depth == self.max_rollout_depth {
+                self.stats.truncated.fetch_add(1, Relaxed);
+                return self.draw_score(rng);
+            }
+            moves.clear();
+            let m = match self.rollouts {
+                Rollouts::Random => random_move(board, moves, rng),
+                Rollouts::Hive => hive_move(board, moves, rng),
+            };
+            board.apply(m);
+            sign = -sign;
+            depth += 1;
+        }
+    }
+}
+
+fn random_move(board: &mut Board, moves: &mut Vec<Turn>, rng: &mut impl Rng) -> Turn {
+    Rules::generate_moves(board, moves);
+    *moves.choose(rng).unwrap()
+}
+
+// Uniformly random moves play out games of pieces wandering aimlessly, which
+// say little about who is ahead. Weight them toward getting the queen down
+// early and piling onto the enemy queen, and away from pulling pieces off her.
+fn hive_move(board: &mut Board, moves: &mut Vec<Turn>, rng: &mut impl Rng) -> Turn {
+    Rules::generate_moves(board, moves);
+    let enemy_queen = board.queens[board.to_move().other()];
+    let near_queen =
+        |hex: Hex| enemy_queen.is_some_and(|queen| hex == queen || adjacent(queen).contains(&hex));
+    let weight = |turn: Turn| match turn {
+        Turn::Place(_, Bug::Queen) => 32,
+        Turn::Move(start, end) => match (near_queen(start), near_queen(end)) {
+            (false, true) => 16,
+            (true, false) => 1,
+            _ => 4,
+        },
+        _ => 4,
+    };
+    let total: u32 = moves.iter().map(|&turn| weight(turn)).sum();
+    let mut pick = rng.gen_range(0..total);
+    for &turn in moves.iter() {
+        if pick < weight(turn) {
+            return turn;
+        }
+        pick -= weight(turn);
+    }
+    unreachable!()
+}
+
+// A board and move list for each thread to play rollouts on.
+struct Scratch {
+    board: Board,
+    moves: Vec<Turn>,
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Scratch> =
+        RefCell::new(Scratch { board: Board::default(), moves: Vec::new() });
+}
+
+// Exploration constant of the UCT formula.
+const EXPLORATION: f32 = 1.0;
+
+// Past this many nodes, leaves are rolled out without being expanded, to
+// bound the memory of long searches and of trees kept across moves.
+const MAX_NODES: usize = 1 << 21;
+
+// A node of the search tree. Scores and proven results are for the player who
+// made the node's move: +1 for each rollout won and -1 for each lost.
+#[derive(Clone)]
+struct Node {
+    turn: Turn,
+    visits: u32,
+    score: i64,
+    // WIN or LOSS once proven, otherwise 0.
+    proven: i32,
+    // Once expanded, the children are nodes[first..first + count].
+    children: Option<(u32, u32)>,
+}
+
+impl Node {
+    fn new(turn: Turn) -> Self {
+        Node { turn, visits: 0, score: 0, proven: 0, children: None }
+    }
+
+    fn uct_score(&self, exploration: f32, log_parent_visits: f32) -> f32 {
+        // Moves proven to lose are only played without another choice.
+        if self.proven == LOSS {
+            return -1.0;
+        }
+        if self.proven == WIN {
+            return f32::INFINITY;
+        }
+        if self.visits == 0 {
+            return if exploration > 0.0 { f32::INFINITY } else { 0.0 };
+        }
+        let visits = self.visits as f32;
+        let win_ratio = (self.score as f32 + visits) / (2.0 * visits);
+        win_ratio + exploration * (2.0 * log_parent_visits / visits).sqrt()
+    }
+}
+
+// The search tree, with the root at index 0 standing for the position the
+// tree was grown from.
+struct Tree {
+    nodes: Vec<Node>,
+    root: Board,
+}
+
+impl Tree {
+    fn new(board: &Board) -> Self {
+        let mut tree = Tree { nodes: vec![Node::new(Turn::Pass)], root: board.clone() };
+        tree.expand(0, board);
+        tree
+    }
+
+    fn expand(&mut self, index: usize, board: &Board) {
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        let first = self.nodes.len() as u32;
+        self.nodes.extend(moves.iter().map(|&turn| Node::new(turn)));
+        self.nodes[index].children = Some((first, moves.len() as u32));
+    }
+
+    fn children(&self, index: usize) -> std::ops::Range<usize> {
+        match self.nodes[index].children {
+            Some((first, count)) => first as usize..(first + count) as usize,
+            None => 0..0,
+        }
+    }
+
+    // The first child with the best UCT score.
+    fn best_child(&self, index: usize, exploration: f32) -> Option<usize> {
+        let log_visits = (self.nodes[index].visits as f32).log2().max(0.0);
+        let mut best = None;
+        let mut best_score = f32::NEG_INFINITY;
+        for child in self.children(index) {
+            let score = self.nodes[child].uct_score(exploration, log_visits);
+            if best.is_none() || score > best_score {
+                best = Some(child);
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    // The subtree for a position the moves played since the root lead to,
+    // or None if the board didn't come from the root or the tree doesn't
+    // reach that far.
+    fn reroot(&self, board: &Board) -> Option<Tree> {
+        let played = board.turn_history.strip_prefix(self.root.turn_history.as_slice())?;
+        let mut replay = self.root.clone();
+        let mut index = 0;
+        for &turn in played {
+            index = self.children(index).find(|&child| self.nodes[child].turn == turn)?;
+            replay.apply(turn);
+        }
+        if Rules::zobrist_hash(&replay) != Rules::zobrist_hash(board)
+            || self.nodes[index].children.is_none()
+        {
+            return None;
+        }
+        // Copy the subtree breadth first, keeping each node's children together.
+        let mut nodes = vec![self.nodes[index].clone()];
+        let mut copied = vec![(index, 0)];
+        let mut next = 0;
+        while let Some(&(old, new)) = copied.get(next) {
+            next += 1;
+            if let Some((_, count)) = self.nodes[old].children {
+                let first = nodes.len();
+                for child in self.children(old) {
+                    copied.push((child, nodes.len()));
+                    nodes.push(self.nodes[child].clone());
+                }
+                nodes[new].children = Some((first as u32, count));
+            }
+        }
+        Some(Tree { nodes, root: board.clone() })
+    }
+
+    // Walks down from the root by UCT to a leaf, expanding it, and returns
+    // the path taken and the result if it is already known. The board ends
+    // up at the last node on the path. Each node on the path but a proven
+    // one takes a virtual loss, to steer other threads elsewhere until the
+    // result is in.
+    fn select(&mut self, board: &mut Board, path: &mut Vec<usize>) -> Option<i32> {
+        board.restore(&self.root);
+        path.clear();
+        let mut index = 0;
+        loop {
+            path.push(index);
+            let node = &mut self.nodes[index];
+            if node.proven != 0 {
+                return Some(node.proven);
+            }
+            node.visits += 1;
+            node.score -= 1;
+            if node.children.is_none() {
+                if path.len() > 1 {
+                    match Rules::get_winner(board) {
+                        Some(Winner::PlayerJustMoved) => return Some(WIN),
+                        Some(Winner::PlayerToMove) => return Some(LOSS),
+                        Some(Winner::Draw) => return Some(0),
+                        None => {}
+                    }
+                }
+                if self.nodes.len() >= MAX_NODES {
+                    return None;
+                }
+                self.expand(index, board);
+                // Roll out from one of the new children.
+                let child = self.best_child(index, EXPLORATION)?;
+                board.apply(self.nodes[child].turn);
+                path.push(child);
+                self.nodes[child].visits += 1;
+                self.nodes[child].score -= 1;
+                return None;
+            }
+            index = self.best_child(index, EXPLORATION)?;
+            board.apply(self.nodes[index].turn);
+        }
+    }
+
+    // Adds the result for the last node on the path to every node on it,
+    // from each one's point of view, and propagates proven results.
+    fn backpropagate(&mut self, path: &[usize], mut result: i32) {
+        for (i, &index) in path.iter().enumerate().rev() {
+            if i + 1 < path.len() {
+                result = match result {
+                    // Any move that wins makes the parent a loser.
+                    WIN => LOSS,
+                    // The parent wins only once all its moves lose.
+                    LOSS if self.children(index).all(|child| self.nodes[child].proven == LOSS) => {
+                        WIN
+                    }
+                    LOSS => -1,
+                    result => -result,
+                };
+            }
+            let node = &mut self.nodes[index];
+            if node.proven != 0 {
+                // Reached without a virtual loss.
+                continue;
+            }
+            if result == WIN || result == LOSS {
+                node.proven = result;
+            } else {
+                // Take back the virtual loss.
+                node.score += result as i64 + 1;
+            }
+        }
+    }
+
+    fn principal_variation(&self) -> Vec<Turn> {
+        let mut pv = Vec::new();
+        let mut index = 0;
+        while let Some(best) = self.best_child(index, 0.0) {
+            if self.nodes[best].visits == 0 && self.nodes[best].proven == 0 {
+                break;
+            }
+            pv.push(self.nodes[best].turn);
+            index = best;
+        }
+        pv
+    }
+}
+
+// Monte Carlo tree search with configurable rollouts and draw scoring, that
+// reports how many rollouts ended in draws. The tree is kept between
+// searches: when the next position follows from the last by the moves
+// played since, as a player's positions do, the search carries on from that
+// part of the tree rather than starting over.
+pub struct MctsSearch {
+    policy: DrawScoringPolicy,
+    stats: Arc<RolloutStats>,
+    verbose: bool,
+    num_threads: usize,
+    // Rollouts per search, or 0 to search until the timeout.
+    max_rollouts: u32,
+    timeout: Duration,
+    tree: Option<Tree>,
+    pv: Vec<Turn>,
+    // Visits the root had from earlier searches when the last one began.
+    reused: u32,
+}
+
+impl MctsSearch {
+    pub fn new(opts: MCTSOptions, max_rollout_depth: u32, draw_value: f64) -> Self {
+        Self::new_with_seed(opts, max_rollout_depth, draw_value, None)
+    }
+
+    // With a seed and one thread, searches are the same from run to run.
+    pub fn new_with_seed(
+        opts: MCTSOptions, max_rollout_depth: u32, draw_value: f64, seed: Option<u64>,
+    ) -> Self {
+        Self::new_with_rollouts(opts, max_rollout_depth, draw_value, seed, Rollouts::Random)
+    }
+
+    pub fn new_with_rollouts(
+        opts: MCTSOptions, max_rollout_depth: u32, draw_value: f64, seed: Option<u64>,
+        rollouts: Rollouts,
+    ) -> Self {
+        let stats = Arc::new(RolloutStats::default());
+        let policy = DrawScoringPolicy {
+            draw_value,
+            max_rollout_depth,
+            stats: stats.clone(),
+            seed,
+            rollouts,
+            leaf_search: None,
+        };
+        Self {
+            policy,
+            stats,
+            verbose: opts.verbose,
+            num_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            max_rollouts: 0,
+            timeout: Duration::from_secs(5),
+            tree: None,
+            pv: Vec::new(),
+            reused: 0,
+        }
+    }
+
+    // Print rollout statistics after each search.
+    pub fn verbose(mut self) -> Self {
+        self.verbose = true;
+        self
+    }
+
+    // Hybrid search: instead of rollouts, score each new leaf with an
+    // alpha-beta search of this depth using the evaluator.
+    pub fn with_leaf_search(mut self, eval: BasicEvaluator, depth: u8) -> Self {
+        self.policy.leaf_search = Some((eval, depth));
+        self
+    }
+
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    // Statistics from the most recent search.
+    pub fn stats(&self) -> &RolloutStats {
+        &self.stats
+    }
+
+    // Root visits carried over from earlier searches into the last one.
+    pub fn reused_visits(&self) -> u32 {
+        self.reused
+    }
+
+    pub fn set_max_rollouts(&mut self, rollouts: u32) {
+        self.max_rollouts = rollouts;
+    }
+
+    fn search(&self, tree: &Mutex<Tree>) {
+        let deadline = (self.max_rollouts == 0).then(|| Instant::now() + self.timeout);
+        let started = AtomicU64::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..self.num_threads {
+                scope.spawn(|| {
+                    let mut board = tree.lock().unwrap().root.snapshot();
+                    let mut path = Vec::new();
+                    loop {
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                            || self.max_rollouts > 0
+                                && started.fetch_add(1, Relaxed) >= self.max_rollouts as u64
+                        {
+                            break;
+                        }
+                        let known = {
+                            let mut tree = tree.lock().unwrap();
+                            if tree.nodes[0].proven != 0 {
+                                break;
+                            }
+                            tree.select(&mut board, &mut path)
+                        };
+                        // Rollouts run without the lock, so threads only
+                        // wait on each other to walk the tree.
+                        let result = known.unwrap_or_else(|| self.policy.rollout(&board));
+                        tree.lock().unwrap().backpropagate(&path, result);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl Strategy<Rules> for MctsSearch {
+    fn choose_move(&mut self, board: &Board) -> Option<Turn> {
+        self.stats.reset();
+        let tree = self
+            .tree
+            .take()
+            .and_then(|tree| tree.reroot(board))
+            .unwrap_or_else(|| Tree::new(board));
+        self.reused = tree.nodes[0].visits;
+        let tree = Mutex::new(tree);
+        self.search(&tree);
+        let tree = tree.into_inner().unwrap();
+        self.pv = tree.principal_variation();
+        let m = tree.best_child(0, 0.0).map(|best| tree.nodes[best].turn);
+        if self.verbose {
+            eprintln!(
+                "{} rollouts ({} visits reused), {:.1}% draws ({} drawn, {} truncated)",
+                self.stats.rollouts(),
+                self.reused,
+                100.0 * self.stats.draw_rate(),
+                self.stats.draws(),
+                self.stats.truncated()
+            );
+            eprintln!("Principal variation: {}", board.line_move_strings(&self.pv).join(", "));
+        }
+        self.tree = Some(tree);
+        m
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.max_rollouts = 0;
+        self.timeout = timeout;
+    }
+
+    fn set_max_depth(&mut self, depth: u8) {
+        // Some arbitrary function of rollouts, as in the minimax crate.
+        self.max_rollouts = 5u32.saturating_pow(depth as u32);
+    }
+
+    fn principal_variation(&self) -> Vec<Turn> {
+        self.pv.clone()
+    }
+}
+
+#[test]
+fn test_draw_value() {
+    // Every rollout is cut off immediately and scored as a draw.
+    let opts = MCTSOptions::default();
+    let board = Board::default();
+    for (draw_value, expected) in [(1.0, 1), (0.5, 0), (0.0, -1)] {
+        let stats = Arc::new(RolloutStats::default());
+        let policy = DrawScoringPolicy {
+            draw_value,
+            max_rollout_depth: 0,
+            stats: stats.clone(),
+            seed: None,
+            rollouts: Rollouts::Random,
+            leaf_search: None,
+        };
+        for _ in 0..10 {
+            assert_eq!(expected, policy.rollout(&board));
+        }
+        assert_eq!(10, stats.rollouts());
+        assert_eq!(10, stats.truncated());
+        assert_eq!(1.0, stats.draw_rate());
+    }
+
+    let mut search = MctsSearch::new(opts, 0, 0.3);
+    search.set_max_rollouts(20);
+    assert!(search.choose_move(&board).is_some());
+    assert_eq!(1.0, search.stats().draw_rate());
+}
+
+#[test]
+fn test_seeded_rollouts() {
+    let board = Board::default();
+    let rollouts = |seed| {
+        let stats = Arc::new(RolloutStats::default());
+        let policy = DrawScoringPolicy {
+            draw_value: 0.5,
+            max_rollout_depth: 30,
+            stats,
+            seed,
+            rollouts: Rollouts::Hive,
+            leaf_search: None,
+        };
+        (0..20).map(|_| policy.rollout(&board)).collect::<Vec<_>>()
+    };
+    assert_eq!(rollouts(Some(1546)), rollouts(Some(1546)));
+}
+
+#[test]
+fn test_hive_rollouts() {
+    let mut rng = StdRng::seed_from_u64(1591);
+    let mut moves = Vec::new();
+    // White's queen is still in hand.
+    let mut board =
+        crate::Board::from_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wA1 -wS1;bQ bG1-")
+            .unwrap();
+    let queens = (0..100)
+        .filter(|_| {
+            moves.clear();
+            matches!(hive_move(&mut board, &mut moves, &mut rng), Turn::Place(_, Bug::Queen))
+        })
+        .count();
+    let queen_moves =
+        moves.iter().filter(|turn| matches!(turn, Turn::Place(_, Bug::Queen))).count();
+    // Several times as often as uniformly random moves would.
+    assert!(queens * moves.len() > 2 * 100 * queen_moves, "{} of 100", queens);
+
+    // The ant mostly goes next to the black queen.
+    let board = &mut crate::Board::from_game_string(
+        "Base;InProgress;White[4];wS1;bG1 wS1-;wQ -wS1;bQ bG1-;wA1 /wS1;bA1 bQ-",
+    )
+    .unwrap();
+    let black_queen = board.queens[1].unwrap();
+    let attacks = (0..100)
+        .filter(|_| {
+            moves.clear();
+            match hive_move(board, &mut moves, &mut rng) {
+                Turn::Move(_, end) => adjacent(black_queen).contains(&end),
+                _ => false,
+            }
+        })
+        .count();
+    assert!(attacks > 30, "{} of 100", attacks);
+}
+
+#[test]
+fn test_tree_reuse() {
+    let mut board =
+        Board::from_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-").unwrap();
+    let mut search =
+        MctsSearch::new_with_seed(MCTSOptions::default(), 30, 0.5, Some(1592)).with_num_threads(1);
+    search.set_max_rollouts(500);
+    let m = search.choose_move(&board).unwrap();
+    assert_eq!(0, search.reused_visits());
+    assert_eq!(500, search.stats().rollouts());
+    let pv = search.principal_variation();
+    assert_eq!(Some(&m), pv.first());
+
+    // After the expected reply, the search picks up where it left off.
+    board.apply(m);
+    board.apply(pv[1]);
+    search.choose_move(&board).unwrap();
+    assert!(search.reused_visits() > 0);
+
+    // A different game starts over.
+    let other = Board::from_game_string("Base;InProgress;White[2];wS1;bS1 wS1-").unwrap();
+    search.choose_move(&other).unwrap();
+    assert_eq!(0, search.reused_visits());
+}
+
+#[test]
+fn test_proven_win() {
+    // Black's ant can fill the last hex around the white queen.
+    let board = Board::from_game_string(r"Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\").unwrap();
+    let mut search = MctsSearch::new(MCTSOptions::default(), 30, 0.5).with_num_threads(2);
+    search.set_timeout(Duration::from_secs(10));
+    let m = search.choose_move(&board).unwrap();
+    let mut after = board.clone();
+    after.apply(m);
+    assert_eq!(Some(Winner::PlayerJustMoved), Rules::get_winner(&after));
+    // Proving the win ends the search long before the timeout.
+    assert!(search.stats().rollouts() < 10000);
+}
+
+#[test]
+fn test_leaf_search() {
+    // Black can fill the last hex around the white queen, so White, who just
+    // moved, loses every leaf a one ply search scores.
+    let board = Board::from_game_string(r"Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\").unwrap();
+    let policy = DrawScoringPolicy {
+        draw_value: 0.5,
+        max_rollout_depth: 0,
+        stats: Arc::new(RolloutStats::default()),
+        seed: Some(1593),
+        rollouts: Rollouts::Random,
+        leaf_search: Some((BasicEvaluator::default(), 1)),
+    };
+    assert!((0..10).all(|_| policy.rollout(&board) == -1));
+
+    // And the hybrid search finds the win.
+    let mut search = MctsSearch::new(MCTSOptions::default(), 0, 0.5)
+        .with_leaf_search(BasicEvaluator::default(), 1)
+        .with_num_threads(1);
+    search.set_max_rollouts(200);
+    let m = search.choose_move(&board).unwrap();
+    let mut after = board.clone();
+    after.apply(m);
+    assert_eq!(Some(Winner::PlayerJustMoved), Rules::get_winner(&after));
+}
@@ -0,0 +1,201 @@
+use crate::board::*;
+use crate::bug::Bug;
+use crate::hex_grid::*;
+use crate::notation::Result;
+use minimax::{Evaluation, Evaluator};
+use std::sync::Arc;
+
+// A learned evaluation: a small feed-forward network over hand-crafted
+// features of the position, with weights trained offline, for example on the
+// output of selfplay. Unlike a real NNUE, nothing is updated incrementally;
+// the features are cheap enough to recompute at every leaf.
+//
+// The weights file is whitespace separated text:
+//
+//   nokamute-nnue <hidden>
+//   <hidden x NUM_FEATURES input weights, one hidden unit at a time>
+//   <hidden biases>
+//   <hidden output weights>
+//   <output bias>
+//
+// Lines starting with # are skipped. The output is in the same units as
+// BasicEvaluator, for the player to move.
+
+// Features for each side, the player to move's first.
+const SIDE_FEATURES: usize = 28;
+pub const NUM_FEATURES: usize = 2 * SIDE_FEATURES;
+// Offsets within each side's features.
+const IN_HAND: usize = 0;
+const FREE: usize = 8;
+// Pinned by the one hive rule, or covered by a stack.
+const STUCK: usize = 16;
+const NEXT_TO_OWN_QUEEN: usize = 24;
+const NEXT_TO_ENEMY_QUEEN: usize = 25;
+const ON_TOP_OF_STACK: usize = 26;
+// Empty hexes around the side's own queen, or 6 before it's placed.
+const QUEEN_LIBERTIES: usize = 27;
+
+// Keeps learned values clear of the win and loss values.
+const MAX_OUTPUT: f32 = 10000.0;
+
+struct Network {
+    hidden: usize,
+    input_weights: Vec<f32>,
+    hidden_biases: Vec<f32>,
+    output_weights: Vec<f32>,
+    output_bias: f32,
+}
+
+#[derive(Clone)]
+pub struct NeuralEvaluator {
+    network: Arc<Network>,
+}
+
+fn invalid_weights(reason: &str) -> crate::UhpError {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid network weights: {}", reason),
+    )
+    .into()
+}
+
+impl NeuralEvaluator {
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut tokens = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(str::split_whitespace);
+        if tokens.next() != Some("nokamute-nnue") {
+            return Err(invalid_weights("missing nokamute-nnue header"));
+        }
+        let hidden = tokens
+            .next()
+            .and_then(|token| token.parse::<usize>().ok())
+            .filter(|&hidden| hidden > 0)
+            .ok_or_else(|| invalid_weights("bad hidden layer size"))?;
+        let values = tokens
+            .map(|token| token.parse::<f32>().map_err(|_| invalid_weights(token)))
+            .collect::<Result<Vec<_>>>()?;
+        if values.len() != hidden * (NUM_FEATURES + 2) + 1 {
+            return Err(invalid_weights(&format!(
+                "expected {} weights for {} hidden units, found {}",
+                hidden * (NUM_FEATURES + 2) + 1,
+                hidden,
+                values.len()
+            )));
+        }
+        let (input_weights, rest) = values.split_at(hidden * NUM_FEATURES);
+        let (hidden_biases, rest) = rest.split_at(hidden);
+        let (output_weights, rest) = rest.split_at(hidden);
+        Ok(Self {
+            network: Arc::new(Network {
+                hidden,
+                input_weights: input_weights.to_vec(),
+                hidden_biases: hidden_biases.to_vec(),
+                output_weights: output_weights.to_vec(),
+                output_bias: rest[0],
+            }),
+        })
+    }
+
+    // The network's inputs for the position, from the point of view of the
+    // player to move.
+    pub fn features(board: &Board) -> [f32; NUM_FEATURES] {
+        let mut features = [0.0; NUM_FEATURES];
+        let me = board.to_move() as usize;
+        let side = |color: Color| if color as usize == me { 0 } else { SIDE_FEATURES };
+        let pinned = board.find_cut_vertexes();
+
+        for color in [Color::White, Color::Black] {
+            let base = side(color);
+            for bug in Bug::iter_all() {
+                features[base + IN_HAND + bug as usize] =
+                    board.remaining[color as usize][bug as usize] as f32;
+            }
+            features[base + QUEEN_LIBERTIES] = match board.queens[color as usize] {
+                Some(queen) => {
+                    adjacent(queen).iter().filter(|&&adj| !board.occupied(adj)).count() as f32
+                }
+                None => 6.0,
+            };
+        }
+        for &hex in board.occupied_hexes.iter().flatten() {
+            let node = board.node(hex);
+            let base = side(node.color());
+            let stuck = if pinned.get(hex) { STUCK } else { FREE };
+            features[base + stuck + node.bug() as usize] += 1.0;
+            if node.is_stacked() {
+                features[base + ON_TOP_OF_STACK] += 1.0;
+            }
+            for color in [Color::White, Color::Black] {
+                if board.queens[color as usize].is_some_and(|queen| adjacent(queen).contains(&hex))
+                {
+                    let feature =
+                        if color == node.color() { NEXT_TO_OWN_QUEEN } else { NEXT_TO_ENEMY_QUEEN };
+                    features[base + feature] += 1.0;
+                }
+            }
+        }
+        for tile in board.get_underworld().iter() {
+            let node = tile.node();
+            features[side(node.color()) + STUCK + node.bug() as usize] += 1.0;
+        }
+        features
+    }
+}
+
+impl Evaluator for NeuralEvaluator {
+    type G = Rules;
+
+    fn evaluate(&self, board: &Board) -> Evaluation {
+        let network = &self.network;
+        let features = Self::features(board);
+        let mut output = network.output_bias;
+        for unit in 0..network.hidden {
+            let weights = &network.input_weights[unit * NUM_FEATURES..(unit + 1) * NUM_FEATURES];
+            let activation = weights.iter().zip(features.iter()).map(|(w, x)| w * x).sum::<f32>()
+                + network.hidden_biases[unit];
+            output += network.output_weights[unit] * activation.max(0.0);
+        }
+        output.clamp(-MAX_OUTPUT, MAX_OUTPUT) as Evaluation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One hidden unit that counts the player to move's free ants.
+    fn ant_counter() -> String {
+        let mut weights = vec!["0"; NUM_FEATURES];
+        weights[FREE + Bug::Ant as usize] = "1";
+        format!("# free ants\nnokamute-nnue 1\n{}\n0\n100\n-5", weights.join(" "))
+    }
+
+    #[test]
+    fn test_neural_evaluator() {
+        let eval = NeuralEvaluator::parse(&ant_counter()).unwrap();
+        assert_eq!(-5, eval.evaluate(&Board::default()));
+        let board = Board::from_game_string(
+            "Base;InProgress;White[4];wA1;bA1 wA1-;wQ -wA1;bQ bA1-;wA2 -wQ;bG1 bQ-",
+        )
+        .unwrap();
+        // A line of tiles. White to move has a free ant at the end, one pinned
+        // in the middle, and one in hand.
+        let features = NeuralEvaluator::features(&board);
+        assert_eq!(1.0, features[FREE + Bug::Ant as usize]);
+        assert_eq!(1.0, features[STUCK + Bug::Ant as usize]);
+        assert_eq!(1.0, features[IN_HAND + Bug::Ant as usize]);
+        assert_eq!(2.0, features[SIDE_FEATURES + NEXT_TO_OWN_QUEEN]);
+        assert_eq!(4.0, features[QUEEN_LIBERTIES]);
+        assert_eq!(95, eval.evaluate(&board));
+
+        assert!(NeuralEvaluator::parse("nokamute-nnue 1\n1 2 3").is_err());
+        assert!(NeuralEvaluator::parse(&ant_counter().replace("100", "x")).is_err());
+        assert!(NeuralEvaluator::parse(&ant_counter().replace("nokamute-nnue", "nnue")).is_err());
+    }
+}
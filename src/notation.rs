@@ -1,9 +1,18 @@
-extern crate minimax;
-use crate::{adjacent, Board, Bug, Color, Direction, Hex, Node, Rules, Turn, START_HEX};
-use minimax::Game;
+use crate::board::{split_offset, valid_starting};
+#[cfg(any(test, feature = "engine"))]
+use crate::Variant;
+use crate::{
+    adjacent, hex_to_loc, Board, Bug, Color, Direction, Hex, Node, Rules, Turn, Winner, GRID_MASK,
+    ROW_SIZE, START_HEX,
+};
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
 #[derive(Debug)]
 pub enum UhpError {
+    #[cfg(feature = "engine")]
     IoError(std::io::Error),
     UnknownPiece(String),
     InvalidGameString(String),
@@ -13,23 +22,120 @@ pub enum UhpError {
     GameNotStarted,
     UnrecognizedCommand(String),
     EngineError(String),
+    // An external engine didn't respond in time.
+    EngineTimeout(String),
+    // An external engine exited, with the last of its stderr.
+    EngineCrashed(String),
     TooManyUndos,
+    TooManyRedos,
+    // A move was asked for or played after the game ended.
+    GameOver,
 }
 
+// The message after err in UHP responses, or after invalidmove for invalid
+// moves.
+impl core::fmt::Display for UhpError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "engine")]
+            UhpError::IoError(err) => write!(f, "I/O error: {}", err),
+            UhpError::UnknownPiece(piece) => write!(f, "Unknown piece: {}", piece),
+            UhpError::InvalidGameString(game) => write!(f, "Invalid game string: {}", game),
+            UhpError::InvalidGameType(game_type) => write!(f, "Invalid game type: {}", game_type),
+            UhpError::InvalidMove(reason) => write!(f, "{}", reason),
+            UhpError::InvalidOption(option) => write!(f, "Invalid option: {}", option),
+            UhpError::GameNotStarted => write!(f, "No game in progress, start one with newgame"),
+            UhpError::UnrecognizedCommand(command) => {
+                write!(f, "Unrecognized command or arguments: {}", command)
+            }
+            UhpError::EngineError(message) => write!(f, "Engine error: {}", message),
+            UhpError::EngineTimeout(message) => write!(f, "Engine timed out: {}", message),
+            UhpError::EngineCrashed(message) => write!(f, "Engine crashed: {}", message),
+            UhpError::TooManyUndos => write!(f, "Can't undo more turns than have been played"),
+            UhpError::TooManyRedos => write!(f, "No undone turns left to redo"),
+            UhpError::GameOver => write!(f, "The game is over"),
+        }
+    }
+}
+
+#[cfg(feature = "engine")]
+impl std::error::Error for UhpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UhpError::IoError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "engine")]
 impl From<std::io::Error> for UhpError {
     fn from(error: std::io::Error) -> Self {
         UhpError::IoError(error)
     }
 }
 
-pub type Result<T> = std::result::Result<T, UhpError>;
+pub type Result<T> = core::result::Result<T, UhpError>;
+
+// A queen that has been surrounded, ending the game.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Surround {
+    // Piece name of the queen, e.g. "bQ".
+    pub queen: String,
+    pub hex: Hex,
+    // Names of the top tiles on each side of the queen, clockwise from the NW.
+    pub surrounding: Vec<String>,
+}
+
+// How a finished game ended.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameResult {
+    // None for a draw.
+    pub winner: Option<Color>,
+    // Empty if the game was drawn by repetition.
+    pub surrounded: Vec<Surround>,
+    // MoveString of the last move of the game.
+    pub final_move: String,
+}
+
+impl core::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.winner {
+            Some(color) => write!(f, "{:?}Wins", color)?,
+            None => write!(f, "Draw")?,
+        }
+        if self.surrounded.is_empty() {
+            write!(f, " by repetition")?;
+        }
+        for surround in self.surrounded.iter() {
+            let (x, y) = hex_to_loc(surround.hex);
+            write!(
+                f,
+                "; {} at ({}, {}) surrounded by {}",
+                surround.queen,
+                x,
+                y,
+                surround.surrounding.join(",")
+            )?;
+        }
+        write!(f, "; final move {}", self.final_move)
+    }
+}
 
 impl Board {
-    // New board from UHP GameTypeString, e.g. "Base+MLP"
+    // New board from UHP GameTypeString, e.g. "Base+MLP". As an extension,
+    // bug counts after a colon change the piece set, e.g. "Base+M:A1G2" for
+    // one ant and two grasshoppers each.
     pub fn from_game_type(game_type: &str) -> Result<Self> {
         let err = || UhpError::InvalidGameType(game_type.to_owned());
+        let (expansions, counts) = match game_type.split_once(':') {
+            Some((expansions, counts)) => (expansions, Some(counts)),
+            None => (game_type, None),
+        };
         let mut starting = [1, 3, 2, 3, 2, 0, 0, 0];
-        let mut toks = game_type.split('+');
+        let mut toks = expansions.split('+');
         if toks.next().ok_or_else(err)? != "Base" {
             return Err(err());
         }
@@ -43,17 +149,44 @@ impl Board {
                 }
             }
         }
+        if toks.next().is_some() {
+            return Err(err());
+        }
+        if let Some(counts) = counts {
+            let mut chars = counts.chars();
+            if counts.is_empty() {
+                return Err(err());
+            }
+            while let Some(c) = chars.next() {
+                let bug = Bug::from_char(c).filter(|_| c.is_ascii_uppercase()).ok_or_else(err)?;
+                let count = chars.next().and_then(|c| c.to_digit(10)).ok_or_else(err)?;
+                starting[bug as usize] = count as u8;
+            }
+        }
+        if !valid_starting(&starting) {
+            return Err(err());
+        }
         Ok(Board::new(starting))
     }
 
-    pub(super) fn hex_name(&self, hex: Hex, out: &mut String) {
+    // Names the destination of a turn. A tile moving from the start hex can't
+    // be its own reference; if it leaves a stack, the tile beneath it is used.
+    pub(super) fn hex_name(&self, hex: Hex, start: Option<Hex>, out: &mut String) {
         if self.occupied(hex) {
             self.tile_name(self.node(hex), out);
             return;
         }
         // Name this relative to an adjacent tile.
-        for (dir, adj) in (0..6).zip(adjacent(hex).into_iter()) {
-            if self.occupied(adj) {
+        for (dir, adj) in (0..6).zip(adjacent(hex)) {
+            let node = if Some(adj) == start {
+                match self.tile_beneath(adj) {
+                    Some(node) => node,
+                    None => continue,
+                }
+            } else {
+                self.node(adj)
+            };
+            if node.occupied() {
                 // Reverse directions; they're from the other bug's perspective.
                 out.push_str(match dir {
                     3 => "\\",
@@ -61,7 +194,7 @@ impl Board {
                     1 => "/",
                     _ => "",
                 });
-                self.tile_name(self.node(adj), out);
+                self.tile_name(node, out);
                 out.push_str(match dir {
                     4 => "/",
                     5 => "-",
@@ -74,13 +207,22 @@ impl Board {
         out.push_str("??");
     }
 
+    // The tile that would be on top of the hex if its top tile moved away.
+    fn tile_beneath(&self, hex: Hex) -> Option<Node> {
+        self.get_underworld()
+            .iter()
+            .rev()
+            .find(|under| under.hex() == hex)
+            .map(|under| under.node())
+    }
+
     pub(super) fn tile_name(&self, node: Node, out: &mut String) {
         out.push(match node.color() {
             Color::White => 'w',
             Color::Black => 'b',
         });
         out.push(node.bug().to_char().to_ascii_uppercase());
-        if matches!(node.bug(), Bug::Ant | Bug::Grasshopper | Bug::Beetle | Bug::Spider) {
+        if self.numbered(node.bug()) {
             out.push(char::from_digit(node.bug_num() as u32, 10).unwrap());
         }
     }
@@ -91,10 +233,8 @@ impl Board {
             Color::Black => 'b',
         });
         out.push(bug.to_char().to_ascii_uppercase());
-        if matches!(bug, Bug::Ant | Bug::Grasshopper | Bug::Beetle | Bug::Spider) {
-            let bug_num =
-                Bug::initial_quantity()[bug as usize] - self.get_remaining()[bug as usize] + 1;
-            out.push(char::from_digit(bug_num as u32, 10).unwrap());
+        if self.numbered(bug) {
+            out.push(char::from_digit(self.next_bug_num(bug) as u32, 10).unwrap());
         }
     }
 
@@ -117,13 +257,62 @@ impl Board {
         out.push(' ');
 
         match m {
-            Turn::Move(_, end) => self.hex_name(end, &mut out),
-            Turn::Place(hex, _) => self.hex_name(hex, &mut out),
+            Turn::Move(start, end) => self.hex_name(end, Some(start), &mut out),
+            Turn::Place(hex, _) => self.hex_name(hex, None, &mut out),
             Turn::Pass => unreachable!(),
         }
         out
     }
 
+    // A move as the tile and the hexes it moves between, in the engine's
+    // offsets from the first tile: "wA1@1,0" for a placement, "wA1@0,0>1,0"
+    // for a move.
+    fn to_coordinate_string(&self, m: Turn) -> String {
+        let loc = |hex| {
+            let (x, y) = hex_to_loc(hex);
+            format!("{},{}", x, y)
+        };
+        let mut out = String::new();
+        match m {
+            Turn::Move(start, end) => {
+                self.tile_name(self.node(start), &mut out);
+                format!("{}@{}>{}", out, loc(start), loc(end))
+            }
+            Turn::Place(hex, bug) => {
+                self.new_tile_name(bug, &mut out);
+                format!("{}@{}", out, loc(hex))
+            }
+            Turn::Pass => "pass".to_string(),
+        }
+    }
+
+    // UHP move strings for a line of play from this position, such as a
+    // principal variation, up to the first move that isn't legal.
+    pub fn line_move_strings(&self, line: &[Turn]) -> Vec<String> {
+        self.walk_line(line, Board::to_move_string)
+    }
+
+    // The same line as coordinate strings.
+    pub fn line_coordinates(&self, line: &[Turn]) -> Vec<String> {
+        self.walk_line(line, Board::to_coordinate_string)
+    }
+
+    fn walk_line(&self, line: &[Turn], name: fn(&Board, Turn) -> String) -> Vec<String> {
+        let mut board = self.clone();
+        let mut names = Vec::new();
+        let mut moves = Vec::new();
+        for &turn in line {
+            moves.clear();
+            Rules::generate_moves(&board, &mut moves);
+            if !moves.contains(&turn) {
+                break;
+            }
+            names.push(name(&board, turn));
+            board.apply(turn);
+        }
+        names
+    }
+
     pub fn game_string(&self) -> String {
         let mut out = self.game_type();
         out.push(';');
@@ -139,35 +328,48 @@ impl Board {
 
     pub fn game_type(&self) -> String {
         let mut game_type = "Base".to_string();
-        let mosquito = self.game_type_bits & (1 << Bug::Mosquito as u32) != 0;
-        let ladybug = self.game_type_bits & (1 << Bug::Ladybug as u32) != 0;
-        let pillbug = self.game_type_bits & (1 << Bug::Pillbug as u32) != 0;
-        if mosquito || ladybug || pillbug {
+        let expansions = [Bug::Mosquito, Bug::Ladybug, Bug::Pillbug]
+            .into_iter()
+            .filter(|&bug| self.starting[bug as usize] > 0)
+            .collect::<Vec<_>>();
+        if !expansions.is_empty() {
             game_type.push('+');
         }
-        if mosquito {
-            game_type.push('M');
+        let mut standard = *Bug::initial_quantity();
+        for bug in [Bug::Mosquito, Bug::Ladybug, Bug::Pillbug] {
+            if expansions.contains(&bug) {
+                game_type.push(bug.to_char().to_ascii_uppercase());
+            } else {
+                standard[bug as usize] = 0;
+            }
         }
-        if ladybug {
-            game_type.push('L');
+        // Then any counts that differ from that set.
+        let mut counts = String::new();
+        for bug in Bug::iter_all() {
+            let count = self.starting[bug as usize];
+            if count != standard[bug as usize] {
+                counts.push(bug.to_char().to_ascii_uppercase());
+                counts.push(char::from_digit(count as u32, 10).unwrap());
+            }
         }
-        if pillbug {
-            game_type.push('P');
+        if !counts.is_empty() {
+            game_type.push(':');
+            game_type.push_str(&counts);
         }
         game_type
     }
 
     pub fn game_state_string(&self) -> &'static str {
-        if self.turn_history.is_empty() {
+        if self.turn_num == 0 {
             return "NotStarted";
         }
         match Rules::get_winner(self) {
-            Some(minimax::Winner::Draw) => "Draw",
-            Some(minimax::Winner::PlayerToMove) => match self.to_move() {
+            Some(Winner::Draw) => "Draw",
+            Some(Winner::PlayerToMove) => match self.to_move() {
                 Color::Black => "BlackWins",
                 Color::White => "WhiteWins",
             },
-            Some(minimax::Winner::PlayerJustMoved) => match self.to_move() {
+            Some(Winner::PlayerJustMoved) => match self.to_move() {
                 Color::White => "BlackWins",
                 Color::Black => "WhiteWins",
             },
@@ -175,8 +377,58 @@ impl Board {
         }
     }
 
+    // Describe how the game ended, or None if it is still in progress.
+    pub fn game_result(&self) -> Option<GameResult> {
+        let winner = match Rules::get_winner(self)? {
+            Winner::Draw => None,
+            Winner::PlayerJustMoved => Some(self.to_move().other()),
+            Winner::PlayerToMove => Some(self.to_move() as usize),
+        }
+        .map(|color| if color == Color::White as usize { Color::White } else { Color::Black });
+
+        let mut surrounded = Vec::new();
+        for (color, count) in self.queens_surrounded().into_iter().enumerate() {
+            if count < 6 {
+                continue;
+            }
+            // Only a placed queen can be surrounded.
+            let Some(hex) = self.queens[color] else {
+                continue;
+            };
+            let mut queen = String::new();
+            match self
+                .get_underworld()
+                .iter()
+                .find(|under| under.hex() == hex && under.node().bug() == Bug::Queen)
+            {
+                // Covered by a beetle.
+                Some(under) => self.tile_name(under.node(), &mut queen),
+                None => self.tile_name(self.node(hex), &mut queen),
+            }
+            let surrounding = adjacent(hex)
+                .iter()
+                .map(|&adj| {
+                    let mut name = String::new();
+                    self.tile_name(self.node(adj), &mut name);
+                    name
+                })
+                .collect();
+            surrounded.push(Surround { queen, hex, surrounding });
+        }
+
+        let final_move = match self.turn_history.last() {
+            Some(&turn) => {
+                let mut before = self.clone();
+                before.undo(turn);
+                before.to_move_string(turn)
+            }
+            None => String::new(),
+        };
+        Some(GameResult { winner, surrounded, final_move })
+    }
+
     fn turn_string(&self) -> String {
-        format!("{:?}[{}]", self.to_move(), self.turn_history.len() / 2 + 1)
+        format!("{:?}[{}]", self.to_move(), self.turn_num / 2 + 1)
     }
 
     pub fn game_log(&self) -> String {
@@ -193,12 +445,43 @@ impl Board {
         log
     }
 
+    // The game log for people to read, one numbered line per turn with
+    // White's move and then Black's, e.g. " 3. wA2 bQ\    bB1 -wQ". Moves
+    // with an eval, as in GameRecord::evals, are followed by it, e.g. "(+12)".
+    pub fn pretty_game_log(&self, evals: &[Option<i16>]) -> String {
+        let mut board = Board::from_game_type(&self.game_type()).unwrap();
+        let mut moves = Vec::new();
+        for (i, &m) in self.turn_history.iter().enumerate() {
+            let mut move_string = board.to_move_string(m);
+            if let Some(Some(eval)) = evals.get(i) {
+                move_string.push_str(&format!(" ({:+})", eval));
+            }
+            moves.push(move_string);
+            board.apply(m);
+        }
+        let white_width = moves.iter().step_by(2).map(String::len).max().unwrap_or(0);
+        let number_width = moves.len().div_ceil(2).to_string().len();
+        let mut log = String::new();
+        for (i, turn) in moves.chunks(2).enumerate() {
+            let line = format!(
+                "{:>number_width$}. {:white_width$}  {}",
+                i + 1,
+                turn[0],
+                turn.get(1).map_or("", String::as_str)
+            );
+            log.push_str(line.trim_end());
+            log.push('\n');
+        }
+        log.pop();
+        log
+    }
+
     // From e.g. "wB2-", returns color, bug, num, dir (White, Beetle, 2, NW)
-    fn parse_piece_name(
+    pub(crate) fn parse_piece_name(
         &self, mut piece_string: &str,
     ) -> Option<(Color, Bug, u8, Option<Direction>)> {
         let first = piece_string.chars().next()?;
-        let last = piece_string.chars().rev().next()?;
+        let last = piece_string.chars().next_back()?;
         let dir = if "\\-/".contains(first) {
             piece_string = &piece_string[1..];
             Some(match first {
@@ -226,7 +509,7 @@ impl Board {
             _ => return None,
         };
         let bug = Bug::from_char(chars.next()?)?;
-        let bug_num = if Bug::initial_quantity()[bug as usize] > 1 {
+        let bug_num = if self.numbered(bug) {
             char::to_digit(chars.next()?, 10)? as u8
         } else if chars.next().is_some() {
             return None;
@@ -260,54 +543,78 @@ impl Board {
     // https://github.com/jonthysell/Mzinga/wiki/UniversalHiveProtocol#movestring
     #[allow(clippy::wrong_self_convention)]
     pub(crate) fn from_move_string(&self, move_string: &str) -> Result<Turn> {
-        let err = || UhpError::InvalidMove(move_string.to_owned());
+        let err = |reason: &str| UhpError::InvalidMove(format!("{}: {}", move_string, reason));
+        let move_string = move_string.trim();
         if move_string == "pass" {
             return Ok(Turn::Pass);
         }
-        let tokens = move_string.split(' ').collect::<Vec<_>>();
-        let (color, bug, bug_num, dir) = self.parse_piece_name(tokens[0]).ok_or_else(err)?;
+        let tokens = move_string.split_whitespace().collect::<Vec<_>>();
+        if tokens.is_empty() || tokens.len() > 2 {
+            return Err(err("expected a piece and a reference tile"));
+        }
+        let (color, bug, bug_num, dir) =
+            self.parse_piece_name(tokens[0]).ok_or_else(|| err("unknown piece"))?;
         if dir.is_some() {
-            return Err(err());
+            return Err(err("the piece to move can't have a direction"));
+        }
+
+        let start = self.find_bug(color, bug, bug_num);
+        if let Some(start) = start {
+            // Only the top of a stack can move.
+            let top = self.node(start);
+            if (top.color(), top.bug(), top.bug_num()) != (color, bug, bug_num) {
+                return Err(err("the piece is covered by a beetle"));
+            }
+        } else {
+            if color != self.to_move() {
+                return Err(err("the piece isn't in the hand of the player to move"));
+            }
+            if bug_num != self.next_bug_num(bug) {
+                return Err(err("bugs of a kind are placed in order of their number"));
+            }
         }
-        if self.turn_history.is_empty() {
+
+        // The first tile goes down on its own.
+        if self.occupied_hexes.iter().all(|hexes| hexes.is_empty()) {
             if tokens.len() != 1 {
-                return Err(err());
+                return Err(err("the first tile has nothing to be placed next to"));
             }
             return Ok(Turn::Place(START_HEX, bug));
         }
         if tokens.len() != 2 {
-            return Err(err());
-        }
-        let start: Option<Hex> = self.find_bug(color, bug, bug_num);
-        let end: Hex = {
-            let (color, bug, bug_num, dir) = self.parse_piece_name(tokens[1]).ok_or_else(err)?;
-            let hex = self.find_bug(color, bug, bug_num).ok_or_else(err)?;
-            if let Some(dir) = dir {
-                dir.apply(hex)
-            } else {
-                hex
-            }
-        };
-        if let Some(start) = start {
-            if self.occupied(start) {
-                return Ok(Turn::Move(start, end));
-            }
+            return Err(err("missing a reference tile"));
         }
-        if color != self.to_move() {
-            return Err(err());
+        let (ref_color, ref_bug, ref_bug_num, dir) =
+            self.parse_piece_name(tokens[1]).ok_or_else(|| err("unknown reference tile"))?;
+        if (ref_color, ref_bug, ref_bug_num) == (color, bug, bug_num) {
+            return Err(err("a piece can't be its own reference tile"));
         }
-        let expected_bug_num =
-            Bug::initial_quantity()[bug as usize] - self.get_remaining()[bug as usize] + 1;
-        if bug_num != expected_bug_num {
-            return Err(err());
+        let reference = self
+            .find_bug(ref_color, ref_bug, ref_bug_num)
+            .ok_or_else(|| err("the reference tile isn't on the board"))?;
+        let end = match dir {
+            Some(dir) => dir.apply(reference),
+            None => reference,
+        };
+        match start {
+            Some(start) => Ok(Turn::Move(start, end)),
+            None if dir.is_none() => Err(err("a new tile can't be placed on top of another")),
+            None => Ok(Turn::Place(end, bug)),
         }
-        Ok(Turn::Place(end, bug))
     }
 
+    #[cfg(any(test, feature = "engine"))]
     pub(crate) fn from_game_string(s: &str) -> Result<Self> {
+        Board::from_game_string_with(s, Variant::default())
+    }
+
+    // Replays the game under the given house rules.
+    #[cfg(any(test, feature = "engine"))]
+    fn from_game_string_with(s: &str, variant: Variant) -> Result<Self> {
         let mut toks = s.split(';');
         let game_type = toks.next().ok_or_else(|| UhpError::InvalidGameString(s.to_owned()))?;
         let mut board = Board::from_game_type(game_type)?;
+        board.set_variant(variant);
         // We don't actually care about the game state, but
         // we'll just treat this like a game type if it's
         if toks.next().is_none() {
@@ -324,16 +631,215 @@ impl Board {
         Ok(board)
     }
 
+    // A single line description of a position, without the moves that led to
+    // it: the game type, the turn, every tile at its offset in steps east and
+    // southwest of the white queen (or the starting hex before the queen is
+    // placed), and what's left in each hand. Stacked tiles are listed from
+    // the bottom up. For example:
+    //
+    //   Base Black[4] wQ@0,0 wB2@0,0 wB1@1,0 bB1@2,0 bQ@3,0 bB2@4,0 w:GGGSSAAA b:GGGSSAAA
+    //
+    // Repeated positions and which bug moved last are not recorded.
+    pub fn to_position_string(&self) -> String {
+        let origin = self.queens[Color::White as usize].unwrap_or(START_HEX);
+        let mut hexes: Vec<Hex> = self.occupied_hexes.iter().flatten().copied().collect();
+        hexes.sort_unstable_by_key(|&hex| {
+            let (x, y) = split_offset(hex.wrapping_sub(origin));
+            (y, x)
+        });
+        let mut out = self.game_type();
+        out.push(' ');
+        out.push_str(&self.turn_string());
+        for hex in hexes {
+            let (x, y) = split_offset(hex.wrapping_sub(origin));
+            let under = self.get_underworld().iter().filter(|under| under.hex() == hex);
+            for node in under.map(|under| under.node()).chain([self.node(hex)]) {
+                out.push(' ');
+                self.tile_name(node, &mut out);
+                out.push_str(&format!("@{},{}", x, y));
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            out.push_str(&format!(" {}", hand_string(color, &self.remaining[color as usize])));
+        }
+        out
+    }
+
+    pub fn from_position_string(s: &str) -> Result<Self> {
+        let err = || UhpError::InvalidGameString(s.to_owned());
+        let mut toks = s.split_whitespace();
+        let mut board = Board::from_game_type(toks.next().ok_or_else(err)?)?;
+        let (color, turn) =
+            toks.next().and_then(|t| t.strip_suffix(']')?.split_once('[')).ok_or_else(err)?;
+        let turn = turn.parse::<u16>().ok().filter(|&turn| turn > 0).ok_or_else(err)?;
+        let black = match color {
+            "White" => 0,
+            "Black" => 1,
+            _ => return Err(err()),
+        };
+        let initial = board.remaining;
+        // Which numbers of each bug are on the board, as bits.
+        let mut numbers = [[0u8; 8]; 2];
+        let mut hands = Vec::new();
+        // Offsets wrap around the grid, like split_offset.
+        let limit = -(ROW_SIZE as i32 / 2)..ROW_SIZE as i32 / 2;
+        for tok in toks {
+            let Some((name, offset)) = tok.split_once('@') else {
+                hands.push(tok);
+                continue;
+            };
+            let mut chars = name.chars();
+            let color = match chars.next() {
+                Some('w') => Color::White,
+                Some('b') => Color::Black,
+                _ => return Err(err()),
+            };
+            let bug = chars.next().and_then(Bug::from_char).ok_or_else(err)?;
+            let bug_num = match chars.next() {
+                Some(c) => c.to_digit(10).ok_or_else(err)? as u8,
+                None => 1,
+            };
+            if chars.next().is_some()
+                || bug_num == 0
+                || bug_num > board.starting[bug as usize]
+                || board.remaining[color as usize][bug as usize] == 0
+                || numbers[color as usize][bug as usize] & 1 << bug_num != 0
+            {
+                return Err(err());
+            }
+            numbers[color as usize][bug as usize] |= 1 << bug_num;
+            let (x, y) = offset.split_once(',').ok_or_else(err)?;
+            let (x, y) =
+                (x.parse::<i32>().map_err(|_| err())?, y.parse::<i32>().map_err(|_| err())?);
+            if !limit.contains(&x) || !limit.contains(&y) {
+                return Err(err());
+            }
+            let hex = START_HEX.wrapping_add((x + y * ROW_SIZE as i32) as Hex) & GRID_MASK;
+            if board.occupied(hex) && board.get_underworld().len() == 8 {
+                return Err(err());
+            }
+            board.set_up_tile(hex, bug, bug_num, color);
+        }
+        // Bugs are numbered in the order they were placed, so there can't be
+        // gaps.
+        for color in 0..2 {
+            for bug in Bug::iter_all() {
+                let placed = initial[color][bug as usize] - board.remaining[color][bug as usize];
+                if numbers[color][bug as usize] as u32 != ((1 << placed) - 1) << 1 {
+                    return Err(err());
+                }
+            }
+        }
+        // The hands are optional, but must agree with the tiles.
+        for hand in hands {
+            let color = match hand.split_once(':') {
+                Some(("w", _)) => Color::White,
+                Some(("b", _)) => Color::Black,
+                _ => return Err(err()),
+            };
+            if hand != hand_string(color, &board.remaining[color as usize]) {
+                return Err(err());
+            }
+        }
+        if !board.is_connected() {
+            return Err(err());
+        }
+        let turn_num = (turn - 1) * 2 + black;
+        // Each tile takes a turn to place.
+        for (color, turns) in [(0, turn_num.div_ceil(2)), (1, turn_num / 2)] {
+            let placed: u8 =
+                initial[color].iter().zip(board.remaining[color]).map(|(a, b)| a - b).sum();
+            if placed as u16 > turns {
+                return Err(err());
+            }
+        }
+        board.set_up_turn(turn_num);
+        Ok(board)
+    }
+
+    // Either a GameString or a position string, told apart by the GameString's
+    // semicolons and the position string's spaces.
+    #[cfg(feature = "engine")]
+    pub(crate) fn from_game_or_position_string(s: &str) -> Result<Self> {
+        Board::from_game_or_position_string_with(s, Variant::default())
+    }
+
+    #[cfg(feature = "engine")]
+    pub(crate) fn from_game_or_position_string_with(s: &str, variant: Variant) -> Result<Self> {
+        let mut board = if !s.contains(';') && s.trim().contains(' ') {
+            Board::from_position_string(s)?
+        } else {
+            Board::from_game_string_with(s, variant)?
+        };
+        board.set_variant(variant);
+        Ok(board)
+    }
+
+    // Whether every tile touches the rest of the hive.
+    pub(super) fn is_connected(&self) -> bool {
+        let hexes: Vec<Hex> = self.occupied_hexes.iter().flatten().copied().collect();
+        let Some(&start) = hexes.first() else {
+            return true;
+        };
+        let mut seen = alloc::vec![start];
+        let mut i = 0;
+        while i < seen.len() {
+            for adj in adjacent(seen[i]) {
+                if self.occupied(adj) && !seen.contains(&adj) {
+                    seen.push(adj);
+                }
+            }
+            i += 1;
+        }
+        seen.len() == hexes.len()
+    }
+
     pub(crate) fn apply_untrusted(&mut self, m: Turn) -> Result<()> {
         let mut moves = Vec::new();
         Rules::generate_moves(self, &mut moves);
-        if !moves.contains(&m) {
-            return Err(UhpError::InvalidMove("That is not a valid move".to_string()));
+        self.check_move(m, &moves)?;
+        self.apply(m);
+        Ok(())
+    }
+
+    // Whether the move is among the legal moves given, and if not, why.
+    pub(crate) fn check_move(&self, m: Turn, moves: &[Turn]) -> Result<()> {
+        if moves.contains(&m) {
+            return Ok(());
+        }
+        Err(UhpError::InvalidMove(self.illegal_reason(m, moves).to_string()))
+    }
+
+    // Applies a move whether or not the rules allow it, for setting up test
+    // positions. It still has to make sense on the board: a placement needs
+    // the tile in hand and an empty hex, and a move needs a tile to move.
+    #[cfg(feature = "engine")]
+    pub(crate) fn apply_permissive(&mut self, m: Turn) -> Result<()> {
+        let err = |reason: &str| Err(UhpError::InvalidMove(reason.to_string()));
+        match m {
+            Turn::Place(hex, bug) => {
+                if self.get_remaining()[bug as usize] == 0 {
+                    return err("That tile is not in hand");
+                }
+                if self.occupied(hex) {
+                    return err("That hex is not empty");
+                }
+            }
+            Turn::Move(start, end) => {
+                if !self.occupied(start) || start == end {
+                    return err("That tile can't move there");
+                }
+                if self.occupied(end) && self.underworld_full() {
+                    return err("That stack is too high");
+                }
+            }
+            Turn::Pass => {}
         }
         self.apply(m);
         Ok(())
     }
 
+    #[cfg(any(test, feature = "engine"))]
     pub(crate) fn undo_count(&mut self, count: usize) -> Result<()> {
         for _ in 0..count {
             let m = self.turn_history.last().copied().ok_or(UhpError::TooManyUndos)?;
@@ -342,10 +848,12 @@ impl Board {
         Ok(())
     }
 
+    #[cfg(any(all(test, feature = "serde"), feature = "engine"))]
     pub(crate) fn last_move(&self) -> Option<Turn> {
         self.turn_history.last().copied()
     }
 
+    #[cfg(any(test, feature = "engine"))]
     pub(crate) fn valid_moves(&self) -> String {
         let mut moves = Vec::new();
         Rules::generate_moves(self, &mut moves);
@@ -361,21 +869,56 @@ impl Board {
     }
 }
 
+// A hand in position strings, like "w:QAAB", or "b:-" when empty.
+fn hand_string(color: Color, remaining: &[u8; 8]) -> String {
+    let mut out = String::from(match color {
+        Color::White => "w:",
+        Color::Black => "b:",
+    });
+    for bug in Bug::iter_all() {
+        for _ in 0..remaining[bug as usize] {
+            out.push(bug.to_char().to_ascii_uppercase());
+        }
+    }
+    if out.len() == 2 {
+        out.push('-');
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Rules;
-    use minimax::{Game, Strategy};
+    use rand::seq::SliceRandom;
+
+    #[test]
+    fn test_game_result() {
+        let mut board = Board::from_game_string(r"Base;BlackWins;Black[5];wS1;bG1 wS1\;wQ wS1/;bQ bG1-;wS2 wQ-;bQ wQ\;wA1 \wQ;bG1 /wA1;wA2 \wS2").unwrap();
+        let result = board.game_result().unwrap();
+        assert_eq!(Some(Color::Black), result.winner);
+        assert_eq!(1, result.surrounded.len());
+        assert_eq!("wQ", result.surrounded[0].queen);
+        assert_eq!(6, result.surrounded[0].surrounding.len());
+        assert_eq!(r"wA2 \wS2", result.final_move);
+        assert!(result.to_string().starts_with("BlackWins; wQ at"));
+
+        board.undo_count(1).unwrap();
+        assert_eq!(None, board.game_result());
+    }
 
     #[test]
     fn test_move_string_round_trip() {
         let mut board = Board::from_game_type("Base+MLP").unwrap();
-        let mut rand = minimax::Random::<Rules>::new();
+        let mut rng = rand::thread_rng();
+        let mut moves = Vec::new();
         for iter in 0..20 {
             let mut depth = 0;
             for _ in 0..20 {
                 depth += 1;
-                let m = rand.choose_move(&board).unwrap();
+                moves.clear();
+                Rules::generate_moves(&board, &mut moves);
+                let m = *moves.choose(&mut rng).unwrap();
                 let move_string = board.to_move_string(m);
                 assert_eq!(
                     m,
@@ -392,4 +935,177 @@ mod tests {
             board.undo_count(depth).unwrap();
         }
     }
+
+    #[test]
+    fn test_custom_piece_set() {
+        let board = Board::from_game_type("Base+M:A1G2").unwrap();
+        assert_eq!([1, 2, 2, 1, 2, 1, 0, 0], board.starting);
+        assert_eq!("Base+M:G2A1", board.game_type());
+        assert_eq!("Base+M:M2", Board::from_game_type("Base:M2").unwrap().game_type());
+        assert_eq!("Base+L", Board::from_game_type("Base+L:L1").unwrap().game_type());
+        assert_eq!("Base:G0S0A0B0", Board::from_game_type("Base:A0B0G0S0").unwrap().game_type());
+        for game_type in
+            ["Base:", "Base:Q0", "Base:Q2", "Base:A4", "Base:a1", "Base:A", "Base+MLP:B3"]
+        {
+            assert!(Board::from_game_type(game_type).is_err(), "{}", game_type);
+        }
+
+        // A lone ant is still numbered, and a second mosquito is numbered.
+        let mut board =
+            Board::from_game_string("Base+M:A1M2;InProgress;White[3];wA1;bM1 wA1-;wM1 -wA1")
+                .unwrap();
+        assert_eq!(0, board.remaining[Color::White as usize][Bug::Ant as usize]);
+        assert!(board.from_move_string("bA2 bM1-").is_err());
+        let m = board.from_move_string("bM2 bM1-").unwrap();
+        assert_eq!("bM2 bM1-", board.to_move_string(m));
+        board.apply_untrusted(m).unwrap();
+        let game_string = board.game_string();
+        assert!(game_string.starts_with("Base+M:A1M2;InProgress;White[3];"), "{}", game_string);
+        assert!(game_string.ends_with(&board.game_log()), "{}", game_string);
+        assert_eq!(game_string, Board::from_game_string(&game_string).unwrap().game_string());
+        let position = board.to_position_string();
+        assert!(position.starts_with("Base+M:A1M2 "), "{}", position);
+        assert_eq!(position, Board::from_position_string(&position).unwrap().to_position_string());
+        assert_eq!(Ok(()), board.validate());
+    }
+
+    #[test]
+    fn test_move_string_edge_cases() {
+        let reason = |board: &Board, move_string: &str| match board.from_move_string(move_string) {
+            Err(UhpError::InvalidMove(message)) => message,
+            other => panic!("{} parsed as {:?}", move_string, other),
+        };
+
+        let board = Board::default();
+        assert_eq!(Turn::Pass, board.from_move_string("pass").unwrap());
+        assert_eq!(Turn::Place(START_HEX, Bug::Ant), board.from_move_string("wA1").unwrap());
+        assert!(reason(&board, "wA1 wQ-").contains("nothing to be placed next to"));
+        assert!(reason(&board, "bA1").contains("player to move"));
+        assert!(reason(&board, "wA2").contains("in order"));
+        assert!(reason(&board, "wX1").contains("unknown piece"));
+        assert!(reason(&board, "").contains("expected"));
+
+        // wB1 climbs on top of wQ.
+        let mut board = Board::from_game_string(
+            "Base;InProgress;White[5];wS1;bB1 wS1-;wQ -wS1;bQ bB1-;wB1 -wQ;bA1 bQ-;wB1 wQ;bA2 bA1-",
+        )
+        .unwrap();
+        assert!(reason(&board, "wQ \\wS1").contains("covered by a beetle"));
+        assert!(reason(&board, "wG1").contains("missing a reference"));
+        assert!(reason(&board, "wG1 wS1").contains("on top"));
+        assert!(reason(&board, "wB1 wB1-").contains("own reference"));
+        assert!(reason(&board, "wG1 bA3-").contains("isn't on the board"));
+
+        // Stepping off the stack, the beetle can only be placed relative to
+        // the queen beneath it, not itself.
+        let off = board.from_move_string("wB1 -wQ").unwrap();
+        assert_eq!("wB1 -wQ", board.to_move_string(off));
+        board.apply_untrusted(off).unwrap();
+        assert_eq!("wB1 wQ", board.to_move_string(board.from_move_string("wB1 wQ").unwrap()));
+    }
+
+    #[test]
+    fn test_line_strings() {
+        let mut board = Board::from_game_string("Base;InProgress;White[2];wS1;bG1 wS1-").unwrap();
+        let mut line = Vec::new();
+        for move_string in ["wQ -wS1", "bQ bG1-"] {
+            let turn = board.from_move_string(move_string).unwrap();
+            board.apply(turn);
+            line.push(turn);
+        }
+        let mut moves = Vec::new();
+        Rules::generate_moves(&board, &mut moves);
+        let queen_move = *moves.iter().find(|m| matches!(m, Turn::Move(..))).unwrap();
+        line.push(queen_move);
+        board.undo_count(2).unwrap();
+
+        let strings = board.line_move_strings(&line);
+        assert_eq!(3, strings.len());
+        assert_eq!(["wQ -wS1", "bQ bG1-"], strings[..2]);
+        assert!(strings[2].starts_with("wQ "));
+        let coordinates = board.line_coordinates(&line);
+        assert_eq!(["wQ@-1,0", "bQ@2,0"], coordinates[..2]);
+        assert!(coordinates[2].starts_with("wQ@-1,0>"), "{}", coordinates[2]);
+
+        // The line stops at the first illegal move.
+        line.insert(1, line[0]);
+        assert_eq!(vec!["wQ -wS1"], board.line_move_strings(&line));
+        assert!(board.line_coordinates(&line[3..]).is_empty());
+    }
+
+    #[test]
+    fn test_pretty_game_log() {
+        let board = Board::from_game_string(
+            r"Base;InProgress;Black[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-;wA1 \wQ",
+        )
+        .unwrap();
+        assert_eq!(
+            "1. wS1      bG1 wS1-\n2. wQ -wS1  bQ bG1-\n3. wA1 \\wQ",
+            board.pretty_game_log(&[])
+        );
+        assert_eq!(
+            "1. wS1            bG1 wS1-\n2. wQ -wS1 (+12)  bQ bG1- (-3)\n3. wA1 \\wQ",
+            board.pretty_game_log(&[None, None, Some(12), Some(-3)])
+        );
+        assert_eq!("", Board::default().pretty_game_log(&[]));
+    }
+
+    #[test]
+    fn test_position_string() {
+        let board = Board::from_game_string(
+            "Base;InProgress;Black[4];wB1;bB1 wB1-;wQ -wB1;bQ bB1-;wB2 -wQ;bB2 bQ-;wB2 wQ",
+        )
+        .unwrap();
+        let position = board.to_position_string();
+        assert_eq!(
+            "Base Black[4] wQ@0,0 wB2@0,0 wB1@1,0 bB1@2,0 bQ@3,0 bB2@4,0 w:GGGSSAAA b:GGGSSAAA",
+            position
+        );
+        let restored = Board::from_position_string(&position).unwrap();
+        assert_eq!(position, restored.to_position_string());
+
+        assert!(Board::from_position_string("Base White[1] w:QGGGSSAAABB b:QGGGSSAAABB").is_ok());
+        // Numbers out of order.
+        assert!(Board::from_position_string("Base White[2] wA2@0,0 bA1@1,0").is_err());
+        // Disconnected.
+        assert!(Board::from_position_string("Base White[2] wA1@0,0 bA1@2,0").is_err());
+        // Hand doesn't match.
+        assert!(Board::from_position_string("Base White[2] wA1@0,0 bA1@1,0 w:QAA").is_err());
+        // More tiles than turns.
+        assert!(Board::from_position_string("Base White[2] wA1@0,0 bA1@1,0 wA2@-1,0").is_err());
+        // Not in the game type.
+        assert!(Board::from_position_string("Base White[2] wM@0,0 bA1@1,0").is_err());
+    }
+
+    #[test]
+    fn test_position_string_round_trip() {
+        let mut board = Board::from_game_type("Base+ML").unwrap();
+        let mut rng = rand::thread_rng();
+        let mut moves = Vec::new();
+        let move_strings = |board: &Board| {
+            let mut moves = Vec::new();
+            Rules::generate_moves(board, &mut moves);
+            let mut strings: Vec<String> = moves.iter().map(|&m| board.to_move_string(m)).collect();
+            strings.sort();
+            strings
+        };
+        for _ in 0..20 {
+            let mut depth = 0;
+            for _ in 0..20 {
+                depth += 1;
+                moves.clear();
+                Rules::generate_moves(&board, &mut moves);
+                board.apply(*moves.choose(&mut rng).unwrap());
+                if Rules::get_winner(&board).is_some() {
+                    break;
+                }
+                let position = board.to_position_string();
+                let restored = Board::from_position_string(&position).unwrap();
+                assert_eq!(position, restored.to_position_string());
+                assert_eq!(board.to_move(), restored.to_move());
+                assert_eq!(move_strings(&board), move_strings(&restored), "{}", position);
+            }
+            board.undo_count(depth).unwrap();
+        }
+    }
 }
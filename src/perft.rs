@@ -1,6 +1,10 @@
+use crate::notation::Result;
+use crate::player::exit;
 use crate::uhp_client::UhpClient;
-use crate::{Board, Rules, Turn};
-use minimax::{Game, Strategy};
+use crate::{Board, PlayerConfig, Rules, Turn};
+use minimax::Strategy;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 
 fn standard_games(game_string: &str) -> &str {
     match game_string {
@@ -81,6 +85,94 @@ pub fn perft_debug(engine_cmd: &[String], game_string: &str, depth: usize) {
     }
 }
 
+// The first position where nokamute and a reference engine disagree.
+pub(crate) struct Divergence {
+    // Reproduces the position.
+    pub(crate) game_string: String,
+    pub(crate) reason: String,
+}
+
+// Differences between our moves and a reference engine's validmoves output,
+// compared as moves rather than strings, as engines may name different
+// neighbors for the same destination.
+fn compare_moves(board: &Board, engine_moves: &str) -> Option<String> {
+    let mut ours = Vec::new();
+    Rules::generate_moves(board, &mut ours);
+    let mut theirs = Vec::new();
+    let mut engine_only = Vec::new();
+    for move_string in engine_moves.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+        match board.from_move_string(move_string) {
+            Ok(turn) => theirs.push(turn),
+            // Can't even be parsed here.
+            Err(_) => engine_only.push(move_string.to_owned()),
+        }
+    }
+    let only = |a: &[Turn], b: &[Turn]| {
+        let mut only = a.iter().filter(|turn| !b.contains(turn)).copied().collect::<Vec<_>>();
+        only.sort_unstable();
+        only.dedup();
+        only.into_iter().map(|turn| board.to_move_string(turn)).collect::<Vec<_>>()
+    };
+    let nokamute_only = only(&ours, &theirs);
+    engine_only.extend(only(&theirs, &ours));
+    if nokamute_only.is_empty() && engine_only.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "nokamute only: {}\nengine only: {}",
+        nokamute_only.join(";"),
+        engine_only.join(";")
+    ))
+}
+
+// Walks random games with a reference engine, comparing the valid moves of
+// every position and the result after every move.
+pub(crate) fn diff_movegen(
+    engine: &mut UhpClient, game_type: &str, games: usize, max_moves: usize, rng: &mut StdRng,
+) -> Result<Option<Divergence>> {
+    let mut moves = Vec::new();
+    for _ in 0..games {
+        engine.new_game(game_type)?;
+        let mut board = Board::from_game_string(game_type)?;
+        for _ in 0..max_moves {
+            if let Some(reason) = compare_moves(&board, &engine.raw_generate_moves()?) {
+                return Ok(Some(Divergence { game_string: board.game_string(), reason }));
+            }
+            moves.clear();
+            Rules::generate_moves(&board, &mut moves);
+            let turn = *moves.choose(rng).unwrap();
+            board.apply(turn);
+            let winner = Rules::get_winner(&board);
+            let engine_winner = engine.apply(turn)?;
+            if winner != engine_winner {
+                let reason =
+                    format!("game over: nokamute {:?}, engine {:?}", winner, engine_winner);
+                return Ok(Some(Divergence { game_string: board.game_string(), reason }));
+            }
+            if winner.is_some() {
+                break;
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn movegen_diff_main(
+    config: PlayerConfig, engine_cmd: &[String], game_type: &str, games: usize, max_moves: usize,
+) {
+    let mut engine = UhpClient::new_with_timeout(engine_cmd, config.uhp_timeout)
+        .unwrap_or_else(|err| exit(format!("Could not start {}: {:?}", engine_cmd[0], err)));
+    let mut rng = config.rng();
+    match diff_movegen(&mut engine, game_type, games, max_moves, &mut rng) {
+        Ok(None) => println!("No differences in {} games", games),
+        Ok(Some(divergence)) => {
+            println!("First difference at {}\n{}", divergence.game_string, divergence.reason);
+            std::process::exit(1);
+        }
+        Err(err) => exit(format!("Engine failed: {}", err)),
+    }
+}
+
 fn dump_difference(board: &mut Board, iter: usize, nokamute_moves: &[Turn], engine_moves: &[Turn]) {
     println!(
         "iteration {} found discrepancy: {} vs {} moves",
@@ -147,11 +239,34 @@ fn test_perft() {
     assert_eq!(move_counts, vec![1, 7, 294, 6678, 151686]);
 }
 
+#[test]
+fn test_movegen_diff() {
+    use rand::SeedableRng;
+
+    // Agrees on the first position, then offers the same moves for black.
+    let engine = "echo ok; while read cmd arg; do case $cmd in \
+        newgame) echo 'Base;NotStarted;White[1]';; \
+        validmoves) echo 'wS1;wB1;wG1;wA1';; \
+        play) echo \"Base;InProgress;Black[1];$arg\";; \
+        esac; echo ok; done";
+    let cmd = ["sh".to_owned(), "-c".to_owned(), engine.to_owned()];
+    let mut client = UhpClient::new(&cmd).unwrap();
+    let mut rng = StdRng::seed_from_u64(1575);
+    let divergence = diff_movegen(&mut client, "Base", 3, 10, &mut rng).unwrap().unwrap();
+    assert!(divergence.game_string.starts_with("Base;InProgress;Black[1];w"));
+    let board = Board::from_game_string(&divergence.game_string).unwrap();
+    assert_eq!(24, board.valid_moves().split(';').count());
+    assert!(divergence.reason.contains("\nengine only: wS1;wB1;wG1;wA1"), "{}", divergence.reason);
+
+    let board = Board::from_game_type("Base").unwrap();
+    assert_eq!(None, compare_moves(&board, "wA1;wG1;wB1;wS1"));
+    assert!(compare_moves(&board, "wA1;wG1;wB1;wS1;wQ;junk").unwrap().ends_with("junk;wQ"));
+}
+
 // Regression suite for bugs caught by perft-debug.
 
 #[test]
 fn test_winner_fail() {
-    use minimax::Game;
     let b = Board::from_game_string(r#"Base+MLP;InProgress;Black[99];wP;bB1 \wP;wG1 wP-;bA1 -bB1;wL /wP;bP /bA1;wQ wP\;bQ -bA1;wQ \wG1;bG1 /bP;wL \bB1;bB2 bP\;wQ bA1\;bL -bQ;wL bB1/;wQ bL\;wG1 /wP"#).unwrap();
     assert_eq!(None, Rules::get_winner(&b));
 }
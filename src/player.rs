@@ -2,106 +2,803 @@ extern crate minimax;
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::cli::CliPlayer;
+use crate::notation::UhpError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::search_log::{SearchEntry, SearchLog};
 #[cfg(not(target_arch = "wasm32"))]
 use crate::uhp_client::UhpPlayer;
-use crate::{BasicEvaluator, Board, Bug, Rules, Turn};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    move_budget, parse_duration, qualifies, Tablebase, TablebaseOutcome, TimeControl,
+    DEFAULT_MAX_POSITIONS,
+};
+use crate::{
+    AnyEvaluator, BasicEvaluator, Board, Bug, Color, Contempt, GameRecord, NodeCounter, Noise,
+    Rules, ThreatExtension, Turn, Variant, Widening,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{MctsSearch, Rollouts};
 use minimax::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Instant, SystemTime};
 
 // A player that can play one color's moves.
 pub(crate) trait Player {
     fn name(&self) -> String;
-    fn new_game(&mut self, game_type: &str);
-    fn play_move(&mut self, m: Turn);
+    // External engines can fail at any time, which forfeits the game.
+    fn new_game(&mut self, game_type: &str) -> Result<(), UhpError>;
+    fn play_move(&mut self, m: Turn) -> Result<(), UhpError>;
     fn undo_move(&mut self, m: Turn);
-    fn generate_move(&mut self) -> Turn;
+    fn generate_move(&mut self) -> Result<Turn, UhpError>;
+    // Like generate_move, but the player may also resign, or offer a draw
+    // along with its move.
+    fn decide(&mut self) -> Result<Decision, UhpError> {
+        self.generate_move().map(Decision::Play)
+    }
+    // Whether to take the draw the opponent offered with its last move.
+    fn accepts_draw(&mut self) -> bool {
+        false
+    }
     fn principal_variation(&self) -> Vec<Turn> {
         Vec::new()
     }
     fn set_max_depth(&mut self, _depth: u8) {}
     fn set_timeout(&mut self, _time: Duration) {}
+    // Stop at whichever limit comes first, or with neither, search until
+    // stopped. Players that only take one limit at a time go by the time.
+    fn set_limits(&mut self, depth: Option<u8>, time: Option<Duration>) {
+        match (depth, time) {
+            (Some(depth), None) => self.set_max_depth(depth),
+            (_, Some(time)) => self.set_timeout(time),
+            (None, None) => {}
+        }
+    }
+    // For another thread to stop the search, or end its pondering.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_search_token(&mut self, _token: Arc<SearchToken>) {}
+    // When pondering, how many of the predicted opponent replies were played,
+    // out of how many predictions.
+    fn ponder_stats(&self) -> Option<(u32, u32)> {
+        None
+    }
+    // Like generate_move, but searches one depth at a time and reports the
+    // line found after each.
+    fn generate_move_with_info(
+        &mut self, _report: &mut dyn FnMut(SearchInfo),
+    ) -> Result<Turn, UhpError> {
+        self.generate_move()
+    }
+}
+
+// A player's answer when it's asked to move.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Decision {
+    Play(Turn),
+    // Play the move, and offer a draw that the opponent can accept instead
+    // of replying.
+    OfferDraw(Turn),
+    Resign,
+    // Undo the player's last move and the opponent's reply, to move again.
+    Takeback,
+}
+
+// Shared with a search on another thread, which checks it between depths.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub(crate) struct SearchToken {
+    stop: AtomicBool,
+    // Searching on the opponent's predicted move, with the search limits
+    // waiting until it's played.
+    pondering: AtomicBool,
+    // How many ponder searches have been read ahead, and how many of them
+    // have had their ponderhit read, so that a ponderhit read before its
+    // search starts still ends it.
+    ponders_read: AtomicU32,
+    ponderhits: AtomicU32,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn face_off(
-    game_type: &str, mut player1: Box<dyn Player>, mut player2: Box<dyn Player>,
-) -> Option<String> {
+impl SearchToken {
+    // Ends the search after the depth it's on, keeping the best move so far.
+    pub(crate) fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.pondering.store(false, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    // A ponder search was read, to start once the commands before it are done.
+    pub(crate) fn ponder_read(&self) {
+        self.ponders_read.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Ends the ponder search in progress, or the one read last if it hasn't
+    // started yet.
+    pub(crate) fn ponderhit(&self) {
+        self.ponderhits.store(self.ponders_read.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    // Starts pondering for the given ponder search, counting from 1 in the
+    // order read, unless its ponderhit already came.
+    pub(crate) fn start_pondering(&self, ponder: u32) {
+        self.pondering.store(true, Ordering::SeqCst);
+        if self.ponderhits.load(Ordering::SeqCst) >= ponder {
+            self.pondering.store(false, Ordering::SeqCst);
+        }
+    }
+
+    // Once a search is over, however it ended.
+    pub(crate) fn end_pondering(&self) {
+        self.pondering.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) fn pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+}
+
+// Progress of a search, after finishing a depth.
+pub(crate) struct SearchInfo {
+    pub(crate) depth: u8,
+    // Since the start of the search.
+    pub(crate) elapsed: Duration,
+    pub(crate) pv: Vec<Turn>,
+}
+
+// Static evaluation at the end of the line, for the player to move at the
+// start of it.
+pub(crate) fn line_value(board: &Board, eval: &BasicEvaluator, line: &[Turn]) -> Evaluation {
+    let mut board = board.clone();
+    for &turn in line.iter() {
+        board.apply(turn);
+    }
+    // For the player to move at the end of the line.
+    let value = match Rules::get_winner(&board) {
+        Some(Winner::PlayerToMove) => BEST_EVAL,
+        Some(Winner::PlayerJustMoved) => -BEST_EVAL,
+        Some(Winner::Draw) => 0,
+        None => eval.evaluate(&board),
+    };
+    if line.len().is_multiple_of(2) {
+        value
+    } else {
+        -value
+    }
+}
+
+// Rules for ending games early, when the result is clear or the game is going
+// nowhere. Parsed from a list like "moves=300,resign=800/4,queen".
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Adjudication {
+    // Draw after this many moves, counting both players.
+    max_moves: Option<u16>,
+    // Win when both players' principal variations score the game at least
+    // this much for the same side, for this many moves in a row.
+    resign: Option<(Evaluation, u32)>,
+    // Win when a queen has five neighbors and a shallow search finds no
+    // defense.
+    queen: bool,
+    // End small endgames with their exact result from a tablebase.
+    tablebase: bool,
+    // Draw when neither player can ever win (see Board::dead_drawn).
+    shutout: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Adjudication {
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        let mut rules = Self::default();
+        for rule in input.split(',') {
+            match rule.split_once('=') {
+                Some(("moves", moves)) => rules.max_moves = Some(moves.parse().ok()?),
+                Some(("resign", resign)) => {
+                    let (value, moves) = resign.split_once('/')?;
+                    rules.resign = Some((value.parse().ok()?, moves.parse().ok()?));
+                }
+                None if rule == "queen" => rules.queen = true,
+                None if rule == "tablebase" => rules.tablebase = true,
+                None if rule == "shutout" => rules.shutout = true,
+                _ => return None,
+            }
+        }
+        Some(rules)
+    }
+}
+
+// How deep to look for a defense of a queen with five neighbors.
+#[cfg(not(target_arch = "wasm32"))]
+const QUEEN_DEFENSE_DEPTH: u8 = 3;
+
+// Applies the adjudication rules to one game as it's played.
+#[cfg(not(target_arch = "wasm32"))]
+struct Adjudicator {
+    rules: Adjudication,
+    eval: BasicEvaluator,
+    // Each player's value of their latest principal variation, for themselves.
+    values: [Option<Evaluation>; 2],
+    // The player both values favor, and for how many moves in a row.
+    streak: Option<(usize, u32)>,
+    // Solved from an earlier position of the game, which covers everything
+    // reachable from it.
+    tablebase: Option<Tablebase>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Adjudicator {
+    fn new(rules: Adjudication) -> Self {
+        Self {
+            rules,
+            eval: BasicEvaluator::default(),
+            values: [None; 2],
+            streak: None,
+            tablebase: None,
+        }
+    }
+
+    // Forgets the values and streak, which were for moves that no longer
+    // happened.
+    fn takeback(&mut self) {
+        self.values = [None; 2];
+        self.streak = None;
+    }
+
+    // Scores the line the player gave for its move m, before it's applied.
+    // Players that don't report a line starting with their move have no
+    // value, which keeps the resign rule from applying.
+    fn record_line(&mut self, board: &Board, p: usize, m: Turn, line: &[Turn]) {
+        self.values[p] = (line.first() == Some(&m)).then(|| line_value(board, &self.eval, line));
+    }
+
+    // After each move, the winner (or None for a draw) and the reason, if the
+    // game should end here.
+    fn adjudicate(&mut self, board: &Board) -> Option<(Option<usize>, String)> {
+        if let Some(max_moves) = self.rules.max_moves {
+            if board.turn_num >= max_moves {
+                return Some((None, format!("draw after {} moves", max_moves)));
+            }
+        }
+        if self.rules.shutout && board.dead_drawn() {
+            return Some((None, "draw by shutout".to_owned()));
+        }
+        if let (Some((threshold, moves)), [Some(white), Some(black)]) =
+            (self.rules.resign, self.values)
+        {
+            let leader = if white >= threshold && black <= -threshold {
+                Some(0)
+            } else if black >= threshold && white <= -threshold {
+                Some(1)
+            } else {
+                None
+            };
+            self.streak = match (leader, self.streak) {
+                (Some(leader), Some((prev, count))) if leader == prev => Some((leader, count + 1)),
+                (Some(leader), _) => Some((leader, 1)),
+                (None, _) => None,
+            };
+            if let Some((leader, count)) = self.streak {
+                if count >= moves {
+                    let reason = format!(
+                        "both players scored it {}+ for {:?} for {} moves",
+                        threshold,
+                        [Color::White, Color::Black][leader],
+                        count
+                    );
+                    return Some((Some(leader), reason));
+                }
+            }
+        }
+        if self.rules.queen {
+            let surrounded = board.queens_surrounded();
+            for color in [Color::White, Color::Black] {
+                let placed = board.remaining[color as usize][Bug::Queen as usize] == 0;
+                if !placed || surrounded[color as usize] != 5 {
+                    continue;
+                }
+                let opts = IterativeOptions::new().with_table_byte_size(1 << 20);
+                let mut search = IterativeSearch::new(self.eval, opts);
+                search.set_max_depth(QUEEN_DEFENSE_DEPTH);
+                search.choose_move(&board.for_search());
+                // For the player to move.
+                let loser = match search.root_value() {
+                    BEST_EVAL => board.to_move().other(),
+                    v if v == -BEST_EVAL => board.to_move() as usize,
+                    _ => continue,
+                };
+                if loser == color as usize {
+                    let reason = format!("{:?} queen can't be defended", color);
+                    return Some((Some(1 - loser), reason));
+                }
+            }
+        }
+        if self.rules.tablebase && qualifies(board) {
+            let mut outcome = self.tablebase.as_ref().and_then(|tablebase| tablebase.probe(board));
+            if outcome.is_none() {
+                // Retried at every qualifying position if it's too big, as
+                // each move can leave fewer bugs free.
+                self.tablebase = Tablebase::generate(board, DEFAULT_MAX_POSITIONS);
+                outcome = self.tablebase.as_ref().and_then(|tablebase| tablebase.probe(board));
+            }
+            let to_move = board.to_move();
+            match outcome {
+                Some(TablebaseOutcome::Draw) => {
+                    return Some((None, "tablebase draw".to_owned()));
+                }
+                Some(TablebaseOutcome::Win(plies)) => {
+                    let reason = format!("{:?} wins in {} plies by tablebase", to_move, plies);
+                    return Some((Some(to_move as usize), reason));
+                }
+                Some(TablebaseOutcome::Loss(plies)) => {
+                    let winner = [Color::White, Color::Black][to_move.other()];
+                    let reason = format!("{:?} wins in {} plies by tablebase", winner, plies);
+                    return Some((Some(to_move.other()), reason));
+                }
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+// How much face_off prints as a game goes.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Verbosity {
+    // Nothing, for games running alongside others.
+    Quiet,
+    // How the game ended and its log.
+    Summary,
+    // The board before every move, and the clocks.
+    Full,
+}
+
+// Plays a game between the two players, returning the index of the winner or
+// None for a draw, along with a record of the game. With a clock, each
+// player's move time comes out of their remaining time, and running out loses
+// the game.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn face_off(
+    game_type: &str, players: &mut [Box<dyn Player>; 2], clock: Option<TimeControl>,
+    adjudication: Adjudication, output: Verbosity,
+) -> (Option<usize>, GameRecord) {
     let mut b = Board::from_game_type(game_type).unwrap();
-    player1.new_game(game_type);
-    player2.new_game(game_type);
-    let mut players = [player1, player2];
+    let mut adjudicator = Adjudicator::new(adjudication);
+    let mut remaining = clock.map(|clock| [clock.total; 2]);
+    let mut clocks = Vec::new();
+    let mut evals = Vec::new();
+    let names = [players[0].name(), players[1].name()];
+    let record = |b: &Board, clocks: &[Duration], evals: &[Option<i16>], winner: Option<usize>| {
+        let mut record = GameRecord::from_board(b, &names[0], &names[1]);
+        record.time_control = clock.map(|clock| clock.to_string());
+        record.clocks = clocks.to_vec();
+        if evals.iter().any(Option::is_some) {
+            record.evals = evals.to_vec();
+        }
+        if let Some(p) = winner {
+            // Forfeits aren't visible on the board.
+            record.result = ["WhiteWins", "BlackWins"][p].to_owned();
+        }
+        record
+    };
+    // Ends the game early, recording why.
+    let end = |winner, b: &Board, clocks: &Vec<Duration>, evals, termination: String| {
+        if output != Verbosity::Quiet {
+            println!("{}", termination);
+            println!("Game log: {}", b.game_log());
+        }
+        let mut record = record(b, clocks, evals, winner);
+        if winner.is_none() {
+            record.result = "Draw".to_owned();
+        }
+        record.termination = Some(termination);
+        (winner, record)
+    };
+    let forfeit = |p: usize, b: &Board, clocks: &Vec<Duration>, evals, reason: String| {
+        end(Some(1 - p), b, clocks, evals, format!("{} forfeits: {}", names[p], reason))
+    };
+    for (p, player) in players.iter_mut().enumerate() {
+        if let Err(err) = player.new_game(game_type) {
+            return forfeit(p, &b, &clocks, &evals, err.to_string());
+        }
+    }
     let mut p = 0;
     loop {
-        b.println();
-        println!("{} ({:?}) to move", players[p].name(), b.to_move());
-        let m = players[p].generate_move();
+        if output == Verbosity::Full {
+            b.println();
+            println!("{} ({:?}) to move", players[p].name(), b.to_move());
+        }
+        if let (Some(clock), Some(remaining)) = (clock, remaining) {
+            players[p].set_timeout(move_budget(remaining[p], clock.increment, &b));
+        }
+        let start = Instant::now();
+        let (m, draw_offered) = match players[p].decide() {
+            Ok(Decision::Play(m)) => (m, false),
+            Ok(Decision::OfferDraw(m)) => (m, true),
+            Ok(Decision::Resign) => {
+                let termination = format!("{} resigns", names[p]);
+                return end(Some(1 - p), &b, &clocks, &evals, termination);
+            }
+            Ok(Decision::Takeback) => {
+                if b.turn_num >= 2 {
+                    for _ in 0..2 {
+                        let m = b.last_move().unwrap();
+                        b.undo(m);
+                        for player in players.iter_mut() {
+                            player.undo_move(m);
+                        }
+                    }
+                    clocks.truncate(b.turn_num as usize);
+                    evals.truncate(b.turn_num as usize);
+                    // Each player's clock goes back to what it read after
+                    // their last remaining move.
+                    if let (Some(clock), Some(remaining)) = (clock, remaining.as_mut()) {
+                        *remaining = [clock.total; 2];
+                        for (i, &left) in clocks.iter().enumerate().rev().take(2) {
+                            remaining[i % 2] = left;
+                        }
+                    }
+                    adjudicator.takeback();
+                    if output == Verbosity::Full {
+                        println!("{} takes back a move", names[p]);
+                    }
+                }
+                continue;
+            }
+            Err(err) => return forfeit(p, &b, &clocks, &evals, err.to_string()),
+        };
+        if let (Some(clock), Some(remaining)) = (clock, remaining.as_mut()) {
+            let elapsed = start.elapsed();
+            if elapsed > remaining[p] {
+                return forfeit(p, &b, &clocks, &evals, "ran out of time".to_owned());
+            }
+            remaining[p] = remaining[p] - elapsed + clock.increment;
+            clocks.push(remaining[p]);
+            if output == Verbosity::Full {
+                println!("{} has {:.1}s left", players[p].name(), remaining[p].as_secs_f64());
+            }
+        }
         let mut moves = Vec::new();
         Rules::generate_moves(&b, &mut moves);
         if !moves.contains(&m) {
-            println!("{} played an illegal move: {}", players[p].name(), b.to_move_string(m));
-            println!("Game log: {}", b.game_log());
-            return Some(players[1 - p].name());
+            clocks.truncate(b.turn_num as usize);
+            let reason = format!("illegal move {}", b.to_move_string(m));
+            return forfeit(p, &b, &clocks, &evals, reason);
+        }
+        let pv = players[p].principal_variation();
+        if output == Verbosity::Full && !pv.is_empty() {
+            println!("{} expects: {}", players[p].name(), b.line_move_strings(&pv).join(";"));
         }
+        adjudicator.record_line(&b, p, m, &pv);
+        // Records keep evals for White.
+        evals.push(adjudicator.values[p].map(|value| if p == 0 { value } else { -value }));
         b.apply(m);
         if let Some(winner) = Rules::get_winner(&b) {
-            b.println();
-            println!("Game log: {}", b.game_log());
-            return match winner {
+            if output == Verbosity::Full {
+                b.println();
+            }
+            if output != Verbosity::Quiet {
+                println!("Game log: {}", b.game_log());
+                if let Some(result) = b.game_result() {
+                    println!("Outcome: {}", result);
+                }
+            }
+            let winner = match winner {
                 minimax::Winner::Draw => None,
-                minimax::Winner::PlayerJustMoved => Some(players[p].name()),
-                minimax::Winner::PlayerToMove => Some(players[1 - p].name()),
+                minimax::Winner::PlayerJustMoved => Some(p),
+                minimax::Winner::PlayerToMove => Some(1 - p),
             };
+            return (winner, record(&b, &clocks, &evals, None));
+        }
+        if let Some((winner, reason)) = adjudicator.adjudicate(&b) {
+            return end(winner, &b, &clocks, &evals, format!("Adjudicated: {}", reason));
+        }
+        for player in [p, 1 - p] {
+            if let Err(err) = players[player].play_move(m) {
+                return forfeit(player, &b, &clocks, &evals, err.to_string());
+            }
+        }
+        if draw_offered {
+            if output == Verbosity::Full {
+                println!("{} offers a draw", names[p]);
+            }
+            if players[1 - p].accepts_draw() {
+                let termination = format!("Draw agreed after {} offered", names[p]);
+                return end(None, &b, &clocks, &evals, termination);
+            }
         }
-        players[p].play_move(m);
         p = 1 - p;
-        players[p].play_move(m);
     }
 }
 
+// Appends the record to the archive file given by --record.
 #[cfg(not(target_arch = "wasm32"))]
-fn get_player(name: &str, config: &PlayerConfig) -> Box<dyn Player> {
+pub(crate) fn save_record(path: &str, record: &GameRecord) {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", record))
+        .unwrap_or_else(|err| exit(format!("Could not write game record to {}: {}", path, err)));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn get_player(name: &str, config: &PlayerConfig) -> Box<dyn Player> {
+    get_player_in_dir(name, config, None)
+}
+
+// Like get_player, but UHP engines run in the given working directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn get_player_in_dir(
+    name: &str, config: &PlayerConfig, dir: Option<&std::path::Path>,
+) -> Box<dyn Player> {
     match name {
         "nokamute" => config.new_player(),
         "ai" => config.new_player(),
-        "human" => Box::new(CliPlayer::new()),
+        "human" => Box::new(CliPlayer::with_hints(config.new_player())),
+        #[cfg(unix)]
+        "tui" => Box::new(crate::tui::TuiPlayer::new()),
         // Try to launch this as a UHP server
-        _ => Box::new(UhpPlayer::new(name).unwrap()),
+        _ => Box::new(
+            UhpPlayer::new(name, config.uhp_timeout, config.uhp_depth_timeout, dir).unwrap_or_else(
+                |err| exit(format!("Could not start UHP engine {}: {:?}", name, err)),
+            ),
+        ),
     }
 }
 
+// Applies the --depth or --timeout limits from the command line.
 #[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn set_limits(player: &mut dyn Player, depth: Option<u8>, timeout: &Option<String>) {
+    if let Some(depth) = depth {
+        player.set_max_depth(depth);
+    } else if let Some(input) = timeout {
+        player.set_timeout(parse_timeout(input));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn parse_timeout(input: &str) -> Duration {
+    parse_duration(input)
+        .unwrap_or_else(|| exit("Could not parse --timeout (add units)".to_string()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(clippy::too_many_arguments)]
 pub fn play_game(
     config: PlayerConfig, game_type: &str, name1: &str, name2: &str, depth: Option<u8>,
-    timeout: Option<String>,
+    timeout: Option<String>, clock: Option<String>, record: Option<String>,
 ) {
-    let mut player1 = get_player(name1, &config);
-    let mut player2 = get_player(name2, &config);
-    if let Some(depth) = depth {
-        player1.set_max_depth(depth);
-        player2.set_max_depth(depth);
-    } else if let Some(input) = timeout {
-        let timeout = if input.ends_with('s') {
-            input[..input.len() - 1].parse::<u64>().map(Duration::from_secs)
-        } else if input.ends_with('m') {
-            input[..input.len() - 1].parse::<u64>().map(|m| Duration::from_secs(m * 60))
-        } else {
-            exit("Could not parse --timeout (add units)".to_string());
-        }
-        .unwrap_or_else(|_| exit("Could not parse --timeout (add units)".to_string()));
-        player1.set_timeout(timeout);
-        player2.set_timeout(timeout);
+    let clock = clock.map(|input| {
+        TimeControl::parse(&input)
+            .unwrap_or_else(|| exit(format!("Could not parse --clock={} (e.g. 5m+3s)", input)))
+    });
+    let mut players = [get_player(name1, &config), get_player(name2, &config)];
+    for player in players.iter_mut() {
+        set_limits(player.as_mut(), depth, &timeout);
     }
-    match face_off(game_type, player1, player2) {
+    let (winner, game_record) =
+        face_off(game_type, &mut players, clock, Adjudication::default(), Verbosity::Full);
+    match winner {
         None => println!("Game over: draw."),
-        Some(name) => println!("Game over: {} won.", name),
+        Some(p) => println!("Game over: {} won.", players[p].name()),
+    }
+    if let Some(path) = record {
+        save_record(&path, &game_record);
+    }
+    for (player, color) in players.iter().zip(["White", "Black"]) {
+        if let Some((hits, predictions)) = player.ponder_stats() {
+            println!("{} ({}) ponder hits: {}/{}", player.name(), color, hits, predictions);
+        }
+    }
+}
+
+// Search depth of the instant preset, which stops short of it after
+// INSTANT_TIME.
+const INSTANT_DEPTH: u8 = 2;
+const INSTANT_TIME: Duration = Duration::from_millis(50);
+// Instant endgame tablebases give up past this many positions, to fit in the
+// time.
+#[cfg(not(target_arch = "wasm32"))]
+const INSTANT_TABLEBASE_POSITIONS: usize = 5_000;
+
+// About how many times as many nodes each depth takes as the one before, to
+// predict whether the next depth fits in a node limit.
+#[cfg(not(target_arch = "wasm32"))]
+const NODE_GROWTH: u64 = 4;
+
+// Memory each search thread needs beyond the shared transposition table, for
+// its boards, move lists, and countermove tables, with room to spare.
+const SEARCH_THREAD_BYTES: usize = 512 << 10;
+
+// The smallest transposition table a memory budget shrinks the table to.
+const MIN_TABLE_BYTES: usize = 64 << 10;
+
+// How far below the best move a move can score and still be picked at random
+// in the opening, about half a queen liberty.
+const REASONABLE_MARGIN: Evaluation = 20;
+
+// Randomizes the first moves of a game, so that self-play games don't all
+// repeat the same opening. Each move is picked either uniformly among those
+// that score near the best in a two ply search, or with a softmax over the
+// scores at some temperature.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct OpeningVariety {
+    // How many plies from the start of the game to randomize.
+    pub(crate) plies: u16,
+    // In evaluation units: higher spreads the choice across worse moves.
+    pub(crate) temperature: Option<f64>,
+}
+
+impl OpeningVariety {
+    fn choose(&self, board: &Board, eval: &BasicEvaluator, rng: &mut StdRng) -> Option<Turn> {
+        if board.turn_num >= self.plies {
+            return None;
+        }
+        let scores = shallow_scores(board, eval);
+        let best = scores.iter().map(|&(_, score)| score).max()?;
+        match self.temperature {
+            Some(temperature) => {
+                let weights =
+                    scores.iter().map(|&(_, score)| ((score - best) as f64 / temperature).exp());
+                let index = WeightedIndex::new(weights).ok()?.sample(rng);
+                Some(scores[index].0)
+            }
+            None => scores
+                .iter()
+                .filter(|&&(_, score)| score >= best.saturating_sub(REASONABLE_MARGIN))
+                .collect::<Vec<_>>()
+                .choose(rng)
+                .map(|&&(turn, _)| turn),
+        }
+    }
+}
+
+// Weakens the engine for casual players, from level 1 to full strength at
+// level 10. Lower levels cap the search depth, add noise to evaluations, and
+// now and then play one of the best few moves of a two ply search at random.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Strength {
+    pub(crate) level: u8,
+    pub(crate) max_depth: Option<u8>,
+    // Largest evaluation noise, in either direction.
+    pub(crate) noise: Evaluation,
+    // How many of the best moves to pick among, and how often.
+    pub(crate) top_moves: usize,
+    pub(crate) random_chance: f64,
+}
+
+impl Default for Strength {
+    fn default() -> Self {
+        Self::level(MAX_STRENGTH).unwrap()
+    }
+}
+
+pub(crate) const MAX_STRENGTH: u8 = 10;
+
+impl Strength {
+    pub(crate) fn level(level: u8) -> Option<Self> {
+        let (max_depth, noise, top_moves, random_chance) = match level {
+            1 => (Some(1), 150, 4, 0.5),
+            2 => (Some(1), 100, 3, 0.4),
+            3 => (Some(2), 80, 3, 0.3),
+            4 => (Some(2), 60, 3, 0.2),
+            5 => (Some(3), 40, 2, 0.15),
+            6 => (Some(3), 30, 2, 0.1),
+            7 => (Some(4), 20, 2, 0.05),
+            8 => (Some(5), 10, 1, 0.0),
+            9 => (Some(6), 5, 1, 0.0),
+            10 => (None, 0, 1, 0.0),
+            _ => return None,
+        };
+        Some(Strength { level, max_depth, noise, top_moves, random_chance })
+    }
+
+    // Sometimes one of the best few moves at random, though never instead of
+    // a win.
+    fn choose(&self, board: &Board, eval: &BasicEvaluator, rng: &mut StdRng) -> Option<Turn> {
+        if self.top_moves < 2 || !rng.gen_bool(self.random_chance) {
+            return None;
+        }
+        let mut scores = shallow_scores(board, eval);
+        scores.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        if scores.first()?.1 == BEST_EVAL {
+            return None;
+        }
+        scores.truncate(self.top_moves);
+        scores.choose(rng).map(|&(turn, _)| turn)
     }
 }
 
+// Each legal move's value for the player to move, assuming the opponent
+// replies with the move that evaluates best for them.
+fn shallow_scores(board: &Board, eval: &BasicEvaluator) -> Vec<(Turn, Evaluation)> {
+    let mut moves = Vec::new();
+    Rules::generate_moves(board, &mut moves);
+    let mut after = board.clone();
+    moves
+        .into_iter()
+        .map(|m| {
+            after.apply(m);
+            let mut replies = Vec::new();
+            if Rules::get_winner(&after).is_none() {
+                Rules::generate_moves(&after, &mut replies);
+            }
+            let score = replies
+                .iter()
+                .map(|&reply| line_value(&after, eval, &[reply]))
+                .max()
+                .map_or_else(|| -line_value(&after, eval, &[]), |value| -value);
+            after.undo(m);
+            (m, score)
+        })
+        .collect()
+}
+
 struct NokamutePlayer {
     board: Board,
     strategy: Box<dyn Strategy<Rules>>,
     random_opening: bool,
+    // Ignore requested search limits and answer as quickly as possible.
+    instant: bool,
+    // The instant preset's last endgame tablebase.
+    #[cfg(not(target_arch = "wasm32"))]
+    tablebase: Option<Tablebase>,
+    // The strategy keeps searching in the background after each move.
+    ponder: bool,
+    // The turn number and move of the expected opponent reply.
+    predicted: Option<(u16, Turn)>,
+    ponder_hits: u32,
+    ponder_predictions: u32,
+    // The last requested limits: a depth, a timeout, both, or neither to
+    // search until stopped.
+    max_depth: Option<u8>,
+    timeout: Option<Duration>,
     name: String,
+    // For random openings.
+    rng: StdRng,
+    variety: OpeningVariety,
+    eval: BasicEvaluator,
+    // Shared with the strategy's evaluator, for contempt.
+    engine_color: Arc<AtomicU8>,
+    variant: Variant,
+    widening: Option<Widening>,
+    // Resign once the search finds no way to avoid losing.
+    resign: bool,
+    // Accept draw offers unless ahead.
+    accept_draws: bool,
+    strength: Strength,
+    // Stop deepening before a depth that would likely evaluate more than this
+    // many positions in all.
+    #[cfg(not(target_arch = "wasm32"))]
+    max_nodes: Option<u64>,
+    // Positions evaluated in the current search, counted by the strategy's
+    // evaluator when there is a node limit.
+    #[cfg(not(target_arch = "wasm32"))]
+    nodes: Arc<AtomicU64>,
+    #[cfg(not(target_arch = "wasm32"))]
+    token: Arc<SearchToken>,
+    #[cfg(not(target_arch = "wasm32"))]
+    search_log: Option<SearchLog>,
+    // The deepest depth search_depths finished, for the search log.
+    #[cfg(not(target_arch = "wasm32"))]
+    depth_reached: u8,
 }
 
 impl NokamutePlayer {
@@ -113,7 +810,246 @@ impl NokamutePlayer {
         name: &str, mut strategy: Box<dyn Strategy<Rules>>, random_opening: bool,
     ) -> Self {
         strategy.set_timeout(Duration::from_secs(5));
-        NokamutePlayer { board: Board::default(), strategy, random_opening, name: name.to_owned() }
+        NokamutePlayer {
+            board: Board::default(),
+            strategy,
+            random_opening,
+            instant: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            tablebase: None,
+            ponder: false,
+            predicted: None,
+            ponder_hits: 0,
+            ponder_predictions: 0,
+            max_depth: None,
+            timeout: Some(Duration::from_secs(5)),
+            name: name.to_owned(),
+            rng: StdRng::from_entropy(),
+            variety: OpeningVariety::default(),
+            eval: BasicEvaluator::default(),
+            engine_color: Arc::new(AtomicU8::new(Color::White as u8)),
+            variant: Variant::default(),
+            widening: None,
+            resign: false,
+            accept_draws: false,
+            strength: Strength::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            max_nodes: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            nodes: Arc::new(AtomicU64::new(0)),
+            #[cfg(not(target_arch = "wasm32"))]
+            token: Arc::new(SearchToken::default()),
+            #[cfg(not(target_arch = "wasm32"))]
+            search_log: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            depth_reached: 0,
+        }
+    }
+
+    // A shallow search that should finish within ~50ms even on slow devices.
+    // Natively, it stops before a depth that could overrun INSTANT_TIME, and
+    // small endgames are played from a tablebase. On wasm, there's no clock
+    // between depths, so only the depth limits it. There's no opening book to
+    // probe; openings come from the random opening and variety settings.
+    fn new_instant(eval: SearchEvaluator, random_opening: bool) -> Self {
+        let opts = IterativeOptions::new().with_countermoves().with_table_byte_size(1 << 20);
+        let mut player = Self::new(Box::new(IterativeSearch::new(eval, opts)), random_opening);
+        // After new, which sets a default timeout.
+        player.strategy.set_max_depth(INSTANT_DEPTH);
+        player.max_depth = Some(INSTANT_DEPTH);
+        player.timeout = Some(INSTANT_TIME);
+        player.instant = true;
+        player
+    }
+
+    // With the instant preset, plays small endgames from a tablebase,
+    // generated again whenever the position isn't in the last one. Generation
+    // gives up at the deadline, leaving the rest of the time to the search.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn endgame_move(&mut self, deadline: Instant) -> Option<Turn> {
+        if !self.instant || !qualifies(&self.board) {
+            return None;
+        }
+        if self.tablebase.as_ref().and_then(|tablebase| tablebase.probe(&self.board)).is_none() {
+            self.tablebase =
+                Tablebase::generate_until(&self.board, INSTANT_TABLEBASE_POSITIONS, Some(deadline));
+        }
+        self.tablebase.as_ref()?.best_move(&self.board)
+    }
+
+    // Ignore minimax and just throw out a random jumpy bug for the first
+    // move, and a random queen placement for the second.
+    fn random_opening_move(&mut self) -> Option<Turn> {
+        if !self.random_opening || self.board.turn_num >= 4 {
+            return self.variety.choose(&self.board, &self.eval, &mut self.rng);
+        }
+        let mut moves = Vec::new();
+        Rules::generate_moves(&self.board, &mut moves);
+        moves.retain(|&turn| match turn {
+            Turn::Place(_, bug) if self.board.turn_num < 2 => {
+                matches!(bug, Bug::Beetle | Bug::Grasshopper | Bug::Ladybug | Bug::Pillbug)
+            }
+            Turn::Place(_, bug) => bug == Bug::Queen,
+            _ => false,
+        });
+        moves.choose(&mut self.rng).copied()
+    }
+
+    // Runs a search, and records it in the search log if there is one.
+    fn logged(
+        &mut self, search: impl FnOnce(&mut Self) -> Result<Turn, UhpError>,
+    ) -> Result<Turn, UhpError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(log) = self.search_log.clone() {
+            let board = self.board.clone();
+            let (max_depth, timeout, max_nodes) = (self.max_depth, self.timeout, self.max_nodes);
+            let start = SystemTime::now();
+            self.nodes.store(0, Ordering::Relaxed);
+            self.depth_reached = 0;
+            let m = search(self)?;
+            let mut pv = self.strategy.principal_variation();
+            // Random moves aren't searched.
+            if pv.first() != Some(&m) {
+                pv.clear();
+            }
+            let entry = SearchEntry {
+                player: &self.name,
+                board: &board,
+                max_depth,
+                timeout,
+                max_nodes,
+                depth: self.depth_reached.max(pv.len() as u8),
+                nodes: self.nodes.load(Ordering::Relaxed),
+                pv: &pv,
+                turn: m,
+                start,
+                end: SystemTime::now(),
+            };
+            log.record(&entry, &self.eval);
+            return Ok(m);
+        }
+        search(self)
+    }
+
+    // A random opening move, or at a reduced strength, sometimes one of the
+    // best few moves.
+    fn random_move(&mut self) -> Option<Turn> {
+        self.random_opening_move()
+            .or_else(|| self.strength.choose(&self.board, &self.eval, &mut self.rng))
+    }
+
+    // Reapplies the current limits under the strength's depth cap.
+    fn set_strength(&mut self, strength: Strength) {
+        self.strength = strength;
+        match (self.max_depth, self.timeout) {
+            (Some(depth), None) => self.set_max_depth(depth),
+            (None, Some(time)) => self.set_timeout(time),
+            _ => {}
+        }
+    }
+
+    fn predict_reply(&mut self) {
+        if self.ponder {
+            self.predicted = self
+                .strategy
+                .principal_variation()
+                .get(1)
+                .map(|&reply| (self.board.turn_num + 1, reply));
+            if self.predicted.is_some() {
+                self.ponder_predictions += 1;
+            }
+        }
+    }
+
+    // Iterative deepening driven from here rather than by the strategy, to
+    // check limits of our own between depths.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn search_depths(&mut self, report: &mut dyn FnMut(SearchInfo)) -> Turn {
+        if let Some(turn) = self.random_move() {
+            return turn;
+        }
+        let start = Instant::now();
+        if let Some(turn) = self.endgame_move(start + INSTANT_TIME / 2) {
+            return turn;
+        }
+        self.engine_color.store(self.board.to_move() as u8, Ordering::Relaxed);
+        let root = self.board.for_search_with(self.widening);
+        // While pondering, the limits wait for the ponderhit.
+        let mut pondering = self.token.pondering();
+        let mut limit_start = start;
+        self.nodes.store(0, Ordering::Relaxed);
+        let mut m = None;
+        let mut depth = 0;
+        let mut depth_time = Duration::ZERO;
+        let mut depth_nodes = 0;
+        while depth < 99 {
+            if m.is_some() && self.token.stopped() {
+                break;
+            }
+            if pondering && !self.token.pondering() {
+                pondering = false;
+                limit_start = Instant::now();
+                self.nodes.store(0, Ordering::Relaxed);
+            }
+            if !pondering && m.is_some() && depth >= self.max_depth.unwrap_or(99) {
+                break;
+            }
+            if let (Some(max_nodes), false) = (self.max_nodes, pondering) {
+                // The same goes for nodes, with each depth taking several
+                // times as many as the last.
+                let nodes = self.nodes.load(Ordering::Relaxed);
+                if m.is_some() && nodes + depth_nodes * NODE_GROWTH > max_nodes {
+                    break;
+                }
+            }
+            if let (Some(timeout), false) = (self.timeout, pondering) {
+                let left = timeout.saturating_sub(limit_start.elapsed());
+                // Depth-limited searches can't be interrupted, so stop well
+                // before the next depth could overrun the time. Give the
+                // strategy the rest of the time and let it go as deep as it
+                // can, starting from what's in the table, unless that could
+                // overrun a depth or node limit.
+                let open_ended = self.max_depth.is_none() && self.max_nodes.is_none();
+                if depth_time * 16 > left {
+                    if (!left.is_zero() && open_ended) || m.is_none() {
+                        self.strategy.set_timeout(left.max(Duration::from_millis(1)));
+                        m = self.strategy.choose_move(&root);
+                        let pv = self.strategy.principal_variation();
+                        depth = depth.max(pv.len() as u8);
+                        self.depth_reached = depth;
+                        report(SearchInfo { depth, elapsed: start.elapsed(), pv });
+                    }
+                    break;
+                }
+            }
+            depth += 1;
+            let depth_start = Instant::now();
+            let nodes_before = self.nodes.load(Ordering::Relaxed);
+            self.strategy.set_max_depth(depth);
+            m = self.strategy.choose_move(&root);
+            depth_time = depth_start.elapsed();
+            depth_nodes = self.nodes.load(Ordering::Relaxed).saturating_sub(nodes_before);
+            let pv = self.strategy.principal_variation();
+            // No point going deeper once the game is decided.
+            let mut board = root.clone();
+            for &turn in pv.iter() {
+                board.apply(turn);
+            }
+            let decided = Rules::get_winner(&board).is_some();
+            self.depth_reached = depth;
+            report(SearchInfo { depth, elapsed: start.elapsed(), pv });
+            if decided {
+                break;
+            }
+        }
+        // Restore the requested limit.
+        match (self.max_depth, self.timeout) {
+            (Some(depth), _) => self.strategy.set_max_depth(depth),
+            (None, Some(time)) => self.strategy.set_timeout(time),
+            (None, None) => {}
+        }
+        self.predict_reply();
+        m.unwrap()
     }
 }
 
@@ -122,47 +1058,89 @@ impl Player for NokamutePlayer {
         self.name.clone()
     }
 
-    fn new_game(&mut self, game_string: &str) {
-        self.board = Board::from_game_string(game_string).unwrap();
+    fn new_game(&mut self, game_string: &str) -> Result<(), UhpError> {
+        self.board = Board::from_game_or_position_string_with(game_string, self.variant)?;
+        self.predicted = None;
+        Ok(())
     }
 
-    fn play_move(&mut self, m: Turn) {
+    fn play_move(&mut self, m: Turn) -> Result<(), UhpError> {
+        if let Some((turn_num, reply)) = self.predicted {
+            if turn_num == self.board.turn_num {
+                self.predicted = None;
+                if m == reply {
+                    // The background search was on the right line, and its
+                    // results are in the shared table.
+                    self.ponder_hits += 1;
+                }
+            }
+        }
         self.board.apply(m);
+        Ok(())
     }
 
     fn undo_move(&mut self, m: Turn) {
         self.board.undo(m);
     }
 
-    fn generate_move(&mut self) -> Turn {
-        if self.random_opening {
-            // Ignore minimax and just throw out a random jumpy bug for the first move.
-            if self.board.turn_num < 2 {
-                loop {
-                    let turn =
-                        minimax::Random::<Rules>::default().choose_move(&self.board).unwrap();
-                    if let Turn::Place(_, bug) = turn {
-                        if matches!(
-                            bug,
-                            Bug::Beetle | Bug::Grasshopper | Bug::Ladybug | Bug::Pillbug
-                        ) {
-                            return turn;
-                        }
-                    }
-                }
-            } else if self.board.turn_num < 4 {
-                loop {
-                    let turn =
-                        minimax::Random::<Rules>::default().choose_move(&self.board).unwrap();
-                    if let Turn::Place(_, bug) = turn {
-                        if bug == Bug::Queen {
-                            return turn;
-                        }
-                    }
-                }
+    fn generate_move(&mut self) -> Result<Turn, UhpError> {
+        if Rules::get_winner(&self.board).is_some() {
+            return Err(UhpError::GameOver);
+        }
+        // Node limits, combined limits, stops, and ponderhits are checked
+        // between depths.
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.max_nodes.is_some()
+            || self.max_depth.is_some() == self.timeout.is_some()
+            || self.token.pondering()
+        {
+            return self.generate_move_with_info(&mut |_| {});
+        }
+        self.logged(|player| {
+            if let Some(turn) = player.random_move() {
+                return Ok(turn);
             }
+            player.engine_color.store(player.board.to_move() as u8, Ordering::Relaxed);
+            let m = player
+                .strategy
+                .choose_move(&player.board.for_search_with(player.widening))
+                .unwrap();
+            player.predict_reply();
+            Ok(m)
+        })
+    }
+
+    fn decide(&mut self) -> Result<Decision, UhpError> {
+        let m = self.generate_move()?;
+        let pv = self.strategy.principal_variation();
+        if self.resign
+            && pv.first() == Some(&m)
+            && line_value(&self.board, &self.eval, &pv) == WORST_EVAL
+        {
+            return Ok(Decision::Resign);
         }
-        self.strategy.choose_move(&self.board).unwrap()
+        Ok(Decision::Play(m))
+    }
+
+    fn accepts_draw(&mut self) -> bool {
+        self.accept_draws && line_value(&self.board, &self.eval, &[]) <= 0
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn generate_move_with_info(
+        &mut self, report: &mut dyn FnMut(SearchInfo),
+    ) -> Result<Turn, UhpError> {
+        if Rules::get_winner(&self.board).is_some() {
+            return Err(UhpError::GameOver);
+        }
+        self.logged(|player| {
+            let m = player.search_depths(report);
+            // A ponder search only answers once it's no longer pondering.
+            while player.token.pondering() && !player.token.stopped() {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            Ok(m)
+        })
     }
 
     fn principal_variation(&self) -> Vec<Turn> {
@@ -170,35 +1148,185 @@ impl Player for NokamutePlayer {
     }
 
     fn set_max_depth(&mut self, depth: u8) {
-        self.strategy.set_max_depth(depth);
+        if !self.instant {
+            let depth = self.strength.max_depth.map_or(depth, |cap| depth.min(cap));
+            self.strategy.set_max_depth(depth);
+            self.max_depth = Some(depth);
+            self.timeout = None;
+        }
     }
 
     fn set_timeout(&mut self, time: Duration) {
-        self.strategy.set_timeout(time);
+        if let (Some(depth), false) = (self.strength.max_depth, self.instant) {
+            // Searched a depth at a time, up to the strength's depth cap.
+            self.strategy.set_max_depth(depth);
+            self.max_depth = Some(depth);
+            self.timeout = Some(time);
+        } else if !self.instant {
+            self.strategy.set_timeout(time);
+            self.max_depth = None;
+            self.timeout = Some(time);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_limits(&mut self, depth: Option<u8>, time: Option<Duration>) {
+        if !self.instant {
+            // Both or neither are searched a depth at a time.
+            let depth = match (depth, self.strength.max_depth) {
+                (Some(depth), Some(cap)) => Some(depth.min(cap)),
+                (depth, cap) => depth.or(cap),
+            };
+            self.max_depth = depth;
+            self.timeout = time;
+            match (depth, time) {
+                (Some(depth), _) => self.strategy.set_max_depth(depth),
+                (None, Some(time)) => self.strategy.set_timeout(time),
+                (None, None) => {}
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn set_search_token(&mut self, token: Arc<SearchToken>) {
+        self.token = token;
+    }
+
+    fn ponder_stats(&self) -> Option<(u32, u32)> {
+        self.ponder.then_some((self.ponder_hits, self.ponder_predictions))
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn exit(msg: String) -> ! {
+pub(crate) fn exit(msg: String) -> ! {
     eprintln!("{}", msg);
     std::process::exit(1)
 }
 
+// Uniformly random moves.
 #[cfg(not(target_arch = "wasm32"))]
+struct RandomMoves {
+    rng: StdRng,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Strategy<Rules> for RandomMoves {
+    fn choose_move(&mut self, board: &Board) -> Option<Turn> {
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        moves.choose(&mut self.rng).copied()
+    }
+}
+
+// What searches evaluate leaves with, built from the config.
+pub(crate) type SearchEvaluator = NodeCounter<ThreatExtension<Contempt<Noise<AnyEvaluator>>>>;
+
+// Which evaluator searches use.
+#[derive(Clone)]
+pub(crate) enum EvalKind {
+    // Only wins and losses, for testing search.
+    Dumb,
+    // The handcrafted evaluation in PlayerConfig::eval.
+    Basic,
+    #[cfg(feature = "nnue")]
+    Neural(crate::NeuralEvaluator),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
 pub(crate) enum PlayerStrategy {
     Iterative(ParallelOptions),
     Random,
-    Mcts(MCTSOptions),
+    Mcts(MCTSOptions, u32, f64, Rollouts),
+    // MCTS scoring leaves with an alpha-beta search of this depth.
+    Hybrid(MCTSOptions, u8, f64),
 }
 
+#[derive(Clone)]
 pub struct PlayerConfig {
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) num_threads: Option<usize>,
     pub(crate) opts: IterativeOptions,
+    // Copies of options set in opts, which the minimax crate keeps private,
+    // for the config command to report.
+    pub(crate) aspiration_window: Option<Evaluation>,
+    pub(crate) null_move_depth: Option<u8>,
+    pub(crate) mtdf: bool,
+    // Late move pruning for iterative searches.
+    pub(crate) widening: Option<Widening>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) strategy: PlayerStrategy,
     pub(crate) eval: BasicEvaluator,
+    pub(crate) eval_kind: EvalKind,
+    // How much worse than an even position a draw looks to the engine.
+    pub(crate) contempt: Evaluation,
+    // Search past the horizon when a queen is one move from surrounded.
+    pub(crate) threat_extension: bool,
     pub(crate) random_opening: bool,
+    pub(crate) variety: OpeningVariety,
+    // Resource-light preset for mobile and web deployments.
+    pub(crate) instant: bool,
+    // UHP bestmove time is the remaining game clock rather than a move limit.
+    pub(crate) game_clock: bool,
+    pub(crate) clock_increment: Duration,
+    // Print info lines on the progress of UHP bestmove searches.
+    pub(crate) search_info: bool,
+    // Reject UHP play commands with illegal moves. Without it, any move that
+    // fits on the board is played, to set up arbitrary test positions.
+    pub(crate) strict_moves: bool,
+    // House rules, such as forbidding the queen on a player's first turn.
+    pub(crate) variant: Variant,
+    // Resign lost games, and accept draw offers when not ahead.
+    pub(crate) resign: bool,
+    pub(crate) accept_draws: bool,
+    pub(crate) strength: Strength,
+    // How long to wait for external UHP engines to respond, beyond search time.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) uhp_timeout: Duration,
+    // ...and how long their depth limited searches may take.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) uhp_depth_timeout: Duration,
+    // Seeds random openings, random players, and MCTS rollouts, so games can
+    // be replayed. Threaded searches and the minimax crate's own tie-breaking
+    // are not covered.
+    pub(crate) seed: Option<u64>,
+    // Limits iterative searches to about this many evaluated positions.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) max_nodes: Option<u64>,
+    // Where to record every search the engine performs.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) search_log: Option<SearchLog>,
+    // Bytes for the transposition table and the search threads together,
+    // which shrinks the table if needed.
+    pub(crate) max_memory: Option<usize>,
+}
+
+// Late move pruning from "plies/moves": full width for that many plies, and
+// then that many moves at each node.
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_widening(input: &str) -> Option<Widening> {
+    let (plies, moves) = input.split_once('/')?;
+    let widening = Widening { full_plies: plies.parse().ok()?, moves: moves.parse().ok()? };
+    (widening.moves > 0).then_some(widening)
+}
+
+// Overrides handcrafted evaluation weights from a list like
+// "queen_liberty_factor=40,ant_value=9", the format tune prints.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn set_weights(eval: &mut BasicEvaluator, input: &str) -> Result<(), String> {
+    for assignment in input.split(',').filter(|s| !s.is_empty()) {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("Expected name=value in --eval-weights, got {}", assignment))?;
+        let value = value
+            .trim()
+            .parse::<Evaluation>()
+            .map_err(|_| format!("Could not parse the value of {}", assignment))?;
+        if !eval.set_weight(name.trim(), value) {
+            return Err(format!("Unknown evaluation weight: {}", name));
+        }
+    }
+    Ok(())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -208,7 +1336,8 @@ pub fn configure_player() -> Result<(PlayerConfig, Vec<String>), pico_args::Erro
     let mut config = PlayerConfig::new();
 
     // Configure common minimax options.
-    if args.contains(["-v", "--verbose"]) {
+    let verbose = args.contains(["-v", "--verbose"]);
+    if verbose {
         config.opts = config.opts.verbose();
     }
     let table_size: Option<usize> = args.opt_value_from_str("--table_mb")?;
@@ -218,16 +1347,62 @@ pub fn configure_player() -> Result<(PlayerConfig, Vec<String>), pico_args::Erro
     let window_arg: Option<u32> = args.opt_value_from_str("--aspiration-window")?;
     if let Some(window) = window_arg {
         config.opts = config.opts.with_aspiration_window(window as minimax::Evaluation);
+        config.aspiration_window = Some(window as minimax::Evaluation);
     }
     if args.contains("--double-step") {
         config.opts = config.opts.with_double_step_increment();
     }
+    if args.contains("--move-history") {
+        config.opts = config.opts.with_countermove_history();
+    }
     if args.contains("--null-move-pruning") {
         config.opts = config.opts.with_null_move_depth(3);
+        config.null_move_depth = Some(3);
+    }
+    let widening: Option<String> = args.opt_value_from_str("--widening")?;
+    config.widening = widening.map(|input| {
+        parse_widening(&input)
+            .unwrap_or_else(|| exit(format!("Could not parse --widening={} (e.g. 3/12)", input)))
+    });
+    config.threat_extension = args.contains("--threat-extension");
+    config.resign = args.contains("--resign");
+    config.accept_draws = args.contains("--accept-draws");
+    let strength: Option<u8> = args.opt_value_from_str("--strength")?;
+    if let Some(level) = strength {
+        config.strength = Strength::level(level)
+            .unwrap_or_else(|| exit(format!("--strength must be 1 to {}", MAX_STRENGTH)));
     }
     if args.contains("--quiet-search") {
         config.opts = config.opts.with_quiescence_search_depth(2);
     }
+    let eval: Option<String> = args.opt_value_from_str("--eval")?;
+    config.eval_kind = match eval.as_deref() {
+        None | Some("basic") => EvalKind::Basic,
+        Some("dumb") => EvalKind::Dumb,
+        #[cfg(feature = "nnue")]
+        Some("nnue") => {
+            let path: String = args
+                .opt_value_from_str("--nnue-weights")?
+                .unwrap_or_else(|| exit("--eval=nnue needs --nnue-weights=file".to_string()));
+            EvalKind::Neural(crate::NeuralEvaluator::load(&path).unwrap_or_else(|err| {
+                exit(format!("Could not load network weights from {}: {:?}", path, err))
+            }))
+        }
+        #[cfg(not(feature = "nnue"))]
+        Some("nnue") => exit("Built without the nnue feature".to_string()),
+        Some(eval) => exit(format!("Unrecognized evaluator: {}", eval)),
+    };
+    config.contempt = args.opt_value_from_str("--contempt")?.unwrap_or(0);
+    let eval_weights: Option<String> = args.opt_value_from_str("--eval-weights")?;
+    if let Some(input) = eval_weights {
+        set_weights(&mut config.eval, &input).unwrap_or_else(|err| exit(err));
+    }
+    let quiet_moves: Option<String> = args.opt_value_from_str("--quiet-moves")?;
+    match quiet_moves.as_deref() {
+        None | Some("placements") => {}
+        Some("threats") => config.eval.set_noisy_moves(crate::NoisyMoves::QueenThreats),
+        Some(moves) => exit(format!("Unrecognized quiet moves: {}", moves)),
+    }
 
     // 0 for num_cpu threads; >0 for specific count.
     config.num_threads = args.opt_value_from_str("--num-threads")?.map(|thread_arg: String| {
@@ -240,13 +1415,73 @@ pub fn configure_player() -> Result<(PlayerConfig, Vec<String>), pico_args::Erro
         }
     });
 
+    let uhp_timeout: Option<String> = args.opt_value_from_str("--uhp-timeout")?;
+    if let Some(input) = uhp_timeout {
+        config.uhp_timeout = parse_duration(&input)
+            .unwrap_or_else(|| exit("Could not parse --uhp-timeout (add units)".to_string()));
+    }
+    let uhp_depth_timeout: Option<String> = args.opt_value_from_str("--uhp-depth-timeout")?;
+    if let Some(input) = uhp_depth_timeout {
+        config.uhp_depth_timeout = parse_duration(&input)
+            .unwrap_or_else(|| exit("Could not parse --uhp-depth-timeout (add units)".to_string()));
+    }
+
+    config.max_nodes = args.opt_value_from_str("--max-nodes")?;
+    let log_file: Option<String> = args.opt_value_from_str("--log-file")?;
+    config.search_log = log_file.map(|path| {
+        SearchLog::open(&path)
+            .unwrap_or_else(|err| exit(format!("Could not open log file {}: {}", path, err)))
+    });
+    let max_memory: Option<usize> = args.opt_value_from_str("--max-memory-mb")?;
+    config.max_memory = max_memory.map(|mb| mb.checked_shl(20).unwrap());
+
+    config.seed = args.opt_value_from_str("--seed")?;
+    config.variety.plies = args.opt_value_from_str("--random-plies")?.unwrap_or(0);
+    config.variety.temperature = args.opt_value_from_str("--temperature")?;
+    if config.variety.temperature.is_some_and(|temperature| temperature <= 0.0) {
+        exit("--temperature must be positive".to_string());
+    }
+
+    let preset: Option<String> = args.opt_value_from_str("--preset")?;
+    match preset.as_deref() {
+        None => {}
+        Some("instant") => config.instant = true,
+        Some(preset) => exit(format!("Unrecognized preset: {}", preset)),
+    }
+
     // Configure specific strategy.
     let strategy: Option<String> = args.opt_value_from_str("--strategy")?;
     config.strategy = match strategy.as_deref().unwrap_or("iterative") {
         "random" => PlayerStrategy::Random,
-        "mcts" => PlayerStrategy::Mcts(MCTSOptions::default().with_max_rollout_depth(200)),
+        name @ ("mcts" | "hybrid") => {
+            let mut opts = MCTSOptions::default();
+            if verbose {
+                opts = opts.verbose();
+            }
+            // Value of a draw to either side, from 0 (loss) to 1 (win).
+            let draw_value: f64 = args.opt_value_from_str("--draw-value")?.unwrap_or(0.5);
+            if !(0.0..=1.0).contains(&draw_value) {
+                exit(format!("--draw-value={} must be between 0 and 1", draw_value));
+            }
+            if name == "hybrid" {
+                let leaf_depth: u8 = args.opt_value_from_str("--leaf-depth")?.unwrap_or(2);
+                if leaf_depth == 0 {
+                    exit("--leaf-depth must be at least 1".to_owned());
+                }
+                PlayerStrategy::Hybrid(opts, leaf_depth, draw_value)
+            } else {
+                let rollouts: Option<String> = args.opt_value_from_str("--rollouts")?;
+                let rollouts = match rollouts.as_deref() {
+                    None | Some("random") => Rollouts::Random,
+                    Some("hive") => Rollouts::Hive,
+                    Some(rollouts) => exit(format!("Unrecognized rollouts: {}", rollouts)),
+                };
+                PlayerStrategy::Mcts(opts, 200, draw_value, rollouts)
+            }
+        }
         "mtdf" => {
             config.opts = config.opts.with_mtdf();
+            config.mtdf = true;
             config.num_threads = Some(1);
             PlayerStrategy::Iterative(ParallelOptions::new())
         }
@@ -274,37 +1509,235 @@ impl PlayerConfig {
             #[cfg(not(target_arch = "wasm32"))]
             num_threads: None,
             opts: IterativeOptions::new().with_countermoves().with_table_byte_size(100 << 20),
+            aspiration_window: None,
+            null_move_depth: None,
+            mtdf: false,
+            widening: None,
             #[cfg(not(target_arch = "wasm32"))]
             strategy: PlayerStrategy::Iterative(ParallelOptions::new()),
             eval: BasicEvaluator::default(),
+            eval_kind: EvalKind::Basic,
+            contempt: 0,
+            threat_extension: false,
             random_opening: false,
+            variety: OpeningVariety::default(),
+            instant: false,
+            game_clock: false,
+            clock_increment: Duration::ZERO,
+            search_info: false,
+            strict_moves: true,
+            variant: Variant::default(),
+            resign: false,
+            accept_draws: false,
+            strength: Strength::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            uhp_timeout: Duration::from_secs(30),
+            #[cfg(not(target_arch = "wasm32"))]
+            uhp_depth_timeout: crate::uhp_client::DEPTH_TIMEOUT,
+            seed: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            max_nodes: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            search_log: None,
+            max_memory: None,
+        }
+    }
+
+    fn evaluator(
+        &self, engine_color: &Arc<AtomicU8>, nodes: Option<Arc<AtomicU64>>,
+    ) -> SearchEvaluator {
+        let eval = match &self.eval_kind {
+            EvalKind::Dumb => AnyEvaluator::Dumb,
+            EvalKind::Basic => AnyEvaluator::Basic(self.eval),
+            #[cfg(feature = "nnue")]
+            EvalKind::Neural(eval) => AnyEvaluator::Neural(eval.clone()),
+        };
+        // Without a seed, each player gets its own noise.
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let eval = Noise::new(eval, self.strength.noise, seed);
+        let eval = Contempt::new(eval, self.contempt, engine_color.clone());
+        NodeCounter::new(ThreatExtension::new(eval, self.threat_extension), nodes)
+    }
+
+    pub(crate) fn search_threads(&self) -> usize {
+        #[cfg(not(target_arch = "wasm32"))]
+        return match self.num_threads {
+            Some(num_threads) if num_threads > 0 => num_threads,
+            _ => ParallelOptions::new().num_threads(),
+        };
+        #[cfg(target_arch = "wasm32")]
+        return 1;
+    }
+
+    // The search options, with the transposition table shrunk to fit in the
+    // memory budget after what the search threads need. The minimax crate
+    // rounds the table up to a power of two entries, so a table of at most
+    // half of what's left can only round up to all of it.
+    pub(crate) fn search_opts(&self) -> IterativeOptions {
+        let mut opts = self.opts;
+        if let Some(max_memory) = self.max_memory {
+            let left = max_memory.saturating_sub(SEARCH_THREAD_BYTES * self.search_threads());
+            let fits = if left >= 2 { 1 << (left / 2).ilog2() } else { 0 };
+            opts.table_byte_size = opts.table_byte_size.min(fits).max(MIN_TABLE_BYTES);
+        }
+        opts
+    }
+
+    // The search configuration players get, after presets and the memory
+    // budget, as name;value lines.
+    pub(crate) fn describe(&self) -> Vec<String> {
+        fn or_none<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "none".to_owned(), |value| value.to_string())
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let strategy = match &self.strategy {
+            _ if self.instant => "instant".to_owned(),
+            PlayerStrategy::Iterative(_) if self.mtdf => "mtdf".to_owned(),
+            PlayerStrategy::Iterative(parallel_opts) if parallel_opts.background_pondering => {
+                "iterative background-ponder".to_owned()
+            }
+            PlayerStrategy::Iterative(_) => "iterative".to_owned(),
+            PlayerStrategy::Random => "random".to_owned(),
+            PlayerStrategy::Mcts(_, _, draw_value, rollouts) => {
+                let rollouts = format!("{:?}", rollouts).to_lowercase();
+                format!("mcts rollouts={} draw-value={}", rollouts, draw_value)
+            }
+            PlayerStrategy::Hybrid(_, leaf_depth, draw_value) => {
+                format!("hybrid leaf-depth={} draw-value={}", leaf_depth, draw_value)
+            }
+        };
+        #[cfg(target_arch = "wasm32")]
+        let strategy = if self.instant { "instant" } else { "iterative" };
+        let eval = match &self.eval_kind {
+            EvalKind::Dumb => "dumb",
+            EvalKind::Basic => "basic",
+            #[cfg(feature = "nnue")]
+            EvalKind::Neural(_) => "nnue",
+        };
+        let weights = self
+            .eval
+            .weights()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        let mut lines = vec![
+            format!("Strategy;{}", strategy),
+            format!("NumThreads;{}", self.search_threads()),
+            format!("TableSizeMiB;{}", self.search_opts().table_byte_size >> 20),
+            format!("MaxMemoryMiB;{}", or_none(self.max_memory.map(|bytes| bytes >> 20))),
+            format!("AspirationWindow;{}", or_none(self.aspiration_window)),
+            format!("NullMoveDepth;{}", or_none(self.null_move_depth)),
+            format!(
+                "Widening;{}",
+                or_none(self.widening.map(|w| format!("{}/{}", w.full_plies, w.moves)))
+            ),
+            format!("Eval;{}", eval),
+            format!("EvalWeights;{}", weights.join(",")),
+            format!("Contempt;{}", self.contempt),
+            format!("ThreatExtension;{}", self.threat_extension),
+            format!("Strength;{}", self.strength.level),
+            format!("Seed;{}", or_none(self.seed)),
+        ];
+        #[cfg(not(target_arch = "wasm32"))]
+        lines.push(format!("MaxNodes;{}", or_none(self.max_nodes)));
+        lines
+    }
+
+    pub(crate) fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
         }
     }
 
     #[cfg(target_arch = "wasm32")]
     pub(crate) fn new_player(&self) -> Box<dyn Player> {
-        Box::new(NokamutePlayer::new(
-            Box::new(IterativeSearch::new(self.eval, self.opts)),
-            self.random_opening,
-        ))
+        let engine_color = Arc::new(AtomicU8::new(Color::White as u8));
+        let mut player = if self.instant {
+            NokamutePlayer::new_instant(self.evaluator(&engine_color, None), self.random_opening)
+        } else {
+            NokamutePlayer::new(
+                Box::new(IterativeSearch::new(
+                    self.evaluator(&engine_color, None),
+                    self.search_opts(),
+                )),
+                self.random_opening,
+            )
+        };
+        player.rng = self.rng();
+        player.variety = self.variety;
+        player.eval = self.eval;
+        player.engine_color = engine_color;
+        player.variant = self.variant;
+        player.widening = self.widening;
+        player.resign = self.resign;
+        player.accept_draws = self.accept_draws;
+        player.set_strength(self.strength);
+        Box::new(player)
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) fn new_player(&self) -> Box<dyn Player> {
-        Box::new(match &self.strategy {
+        let engine_color = Arc::new(AtomicU8::new(Color::White as u8));
+        let nodes = Arc::new(AtomicU64::new(0));
+        // Only count nodes for a node limit or the search log.
+        let counter =
+            (self.max_nodes.is_some() || self.search_log.is_some()).then(|| nodes.clone());
+        let mut player = if self.instant {
+            NokamutePlayer::new_instant(self.evaluator(&engine_color, None), self.random_opening)
+        } else {
+            self.new_search_player(&engine_color, counter)
+        };
+        player.rng = self.rng();
+        player.variety = self.variety;
+        player.eval = self.eval;
+        player.engine_color = engine_color;
+        player.variant = self.variant;
+        player.widening = self.widening;
+        player.resign = self.resign;
+        player.accept_draws = self.accept_draws;
+        player.set_strength(self.strength);
+        player.max_nodes = self.max_nodes;
+        player.nodes = nodes;
+        player.search_log = self.search_log.clone();
+        Box::new(player)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new_search_player(
+        &self, engine_color: &Arc<AtomicU8>, nodes: Option<Arc<AtomicU64>>,
+    ) -> NokamutePlayer {
+        match &self.strategy {
             PlayerStrategy::Random => NokamutePlayer::new_with_name(
                 "random",
-                #[allow(clippy::box_default)] // Clippy is broken
-                Box::new(minimax::Random::default()),
+                Box::new(RandomMoves { rng: self.rng() }),
                 self.random_opening,
             ),
-            PlayerStrategy::Mcts(opts) => {
-                let mut opts = opts.clone();
+            PlayerStrategy::Mcts(..) | PlayerStrategy::Hybrid(..) => {
+                let mut search = match &self.strategy {
+                    PlayerStrategy::Hybrid(opts, leaf_depth, draw_value) => {
+                        MctsSearch::new_with_seed(opts.clone(), 0, *draw_value, self.seed)
+                            .with_leaf_search(self.eval, *leaf_depth)
+                    }
+                    PlayerStrategy::Mcts(opts, max_rollout_depth, draw_value, rollouts) => {
+                        MctsSearch::new_with_rollouts(
+                            opts.clone(),
+                            *max_rollout_depth,
+                            *draw_value,
+                            self.seed,
+                            *rollouts,
+                        )
+                    }
+                    _ => unreachable!(),
+                };
                 let num_threads = self.num_threads.unwrap_or(0);
                 if num_threads > 0 {
-                    opts = opts.with_num_threads(num_threads);
+                    search = search.with_num_threads(num_threads);
+                }
+                if self.opts.verbose {
+                    search = search.verbose();
                 }
-                NokamutePlayer::new(Box::new(MonteCarloTreeSearch::new(opts)), self.random_opening)
+                NokamutePlayer::new(Box::new(search), self.random_opening)
             }
             PlayerStrategy::Iterative(parallel_opts) => {
                 let mut parallel_opts = *parallel_opts;
@@ -312,15 +1745,358 @@ impl PlayerConfig {
                 if num_threads > 0 {
                     parallel_opts = parallel_opts.with_num_threads(num_threads);
                 }
-                NokamutePlayer::new(
-                    if num_threads == 1 {
-                        Box::new(IterativeSearch::new(self.eval, self.opts))
+                // Only the parallel search can ponder, even with one thread.
+                let mut player = NokamutePlayer::new(
+                    if num_threads == 1 && !parallel_opts.background_pondering {
+                        Box::new(IterativeSearch::new(
+                            self.evaluator(engine_color, nodes),
+                            self.search_opts(),
+                        ))
                     } else {
-                        Box::new(ParallelSearch::new(self.eval, self.opts, parallel_opts))
+                        Box::new(ParallelSearch::new(
+                            self.evaluator(engine_color, nodes),
+                            self.search_opts(),
+                            parallel_opts,
+                        ))
                     },
                     self.random_opening,
-                )
+                );
+                player.ponder = parallel_opts.background_pondering;
+                player
             }
-        })
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_adjudication() {
+        assert_eq!(None, Adjudication::parse("moves=ten"));
+        assert_eq!(None, Adjudication::parse("resign=800"));
+        let rules = Adjudication::parse("moves=6,resign=800/2,queen,tablebase,shutout").unwrap();
+        assert_eq!(Some(6), rules.max_moves);
+        assert_eq!(Some((800, 2)), rules.resign);
+        assert!(rules.queen);
+        assert!(rules.tablebase);
+        assert!(rules.shutout);
+
+        // Too short for anyone to win.
+        let mut config = PlayerConfig::new();
+        config.strategy = PlayerStrategy::Random;
+        let mut players = [config.new_player(), config.new_player()];
+        let (winner, record) = face_off(
+            "Base",
+            &mut players,
+            None,
+            Adjudication::parse("moves=6").unwrap(),
+            Verbosity::Quiet,
+        );
+        assert_eq!(None, winner);
+        assert_eq!("Draw", record.result);
+        assert_eq!(6, record.moves.len());
+
+        // Both sides agree black is winning, twice in a row.
+        let board = Board::default();
+        let mut adjudicator = Adjudicator::new(Adjudication::parse("resign=800/2").unwrap());
+        adjudicator.values = [Some(-900), Some(1000)];
+        assert_eq!(None, adjudicator.adjudicate(&board));
+        adjudicator.values = [Some(-900), Some(700)];
+        assert_eq!(None, adjudicator.adjudicate(&board));
+        adjudicator.values = [Some(-900), Some(900)];
+        assert_eq!(None, adjudicator.adjudicate(&board));
+        assert_eq!(Some(1), adjudicator.adjudicate(&board).unwrap().0);
+
+        // Black's ant can fill the white queen's last liberty.
+        let board = Board::from_game_string(r"Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\").unwrap();
+        let mut adjudicator = Adjudicator::new(Adjudication::parse("queen").unwrap());
+        assert_eq!(Some(1), adjudicator.adjudicate(&board).unwrap().0);
+
+        // Nothing can move or be placed, so it's a draw however it's scored.
+        let mosquito = |x, color| ((x, 0), Bug::Mosquito, color);
+        let board = Board::from_tiles(
+            &[
+                mosquito(-3, Color::White),
+                mosquito(-2, Color::White),
+                mosquito(-1, Color::White),
+                ((0, 0), Bug::Queen, Color::White),
+                ((1, 0), Bug::Queen, Color::Black),
+                mosquito(2, Color::Black),
+                mosquito(3, Color::Black),
+                mosquito(4, Color::Black),
+            ],
+            8,
+        );
+        assert_eq!(0, BasicEvaluator::default().evaluate(&board));
+        let mut adjudicator = Adjudicator::new(Adjudication::parse("shutout").unwrap());
+        assert_eq!(Some((None, "draw by shutout".to_owned())), adjudicator.adjudicate(&board));
+    }
+
+    // Random moves, and after the given number of its own moves, a
+    // resignation, draw offer, or takeback.
+    struct Scripted {
+        player: Box<dyn Player>,
+        moves: usize,
+        then: &'static str,
+        accept: bool,
+        undos: Rc<Cell<usize>>,
+    }
+
+    impl Player for Scripted {
+        fn name(&self) -> String {
+            "scripted".to_owned()
+        }
+        fn new_game(&mut self, game_type: &str) -> Result<(), UhpError> {
+            self.player.new_game(game_type)
+        }
+        fn play_move(&mut self, m: Turn) -> Result<(), UhpError> {
+            self.player.play_move(m)
+        }
+        fn undo_move(&mut self, m: Turn) {
+            self.undos.set(self.undos.get() + 1);
+            self.player.undo_move(m);
+        }
+        fn generate_move(&mut self) -> Result<Turn, UhpError> {
+            self.player.generate_move()
+        }
+        fn decide(&mut self) -> Result<Decision, UhpError> {
+            if self.moves > 0 {
+                self.moves -= 1;
+                return self.generate_move().map(Decision::Play);
+            }
+            match std::mem::take(&mut self.then) {
+                "resign" => Ok(Decision::Resign),
+                "draw" => self.generate_move().map(Decision::OfferDraw),
+                "takeback" => Ok(Decision::Takeback),
+                _ => self.generate_move().map(Decision::Play),
+            }
+        }
+        fn accepts_draw(&mut self) -> bool {
+            self.accept
+        }
+    }
+
+    #[test]
+    fn test_resign_and_draw() {
+        let mut config = PlayerConfig::new();
+        config.strategy = PlayerStrategy::Random;
+        let undos = Rc::new(Cell::new(0));
+        let scripted = |moves, then, accept| -> Box<dyn Player> {
+            let player = config.new_player();
+            Box::new(Scripted { player, moves, then, accept, undos: undos.clone() })
+        };
+        let mut players = [scripted(2, "resign", false), scripted(0, "", false)];
+        let (winner, record) =
+            face_off("Base", &mut players, None, Adjudication::default(), Verbosity::Quiet);
+        assert_eq!(Some(1), winner);
+        assert_eq!("BlackWins", record.result);
+        assert_eq!(Some("scripted resigns"), record.termination.as_deref());
+        assert_eq!(4, record.moves.len());
+
+        // Black takes White's offer with its second move.
+        let mut players = [scripted(1, "draw", false), scripted(0, "", true)];
+        let (winner, record) =
+            face_off("Base", &mut players, None, Adjudication::default(), Verbosity::Quiet);
+        assert_eq!(None, winner);
+        assert_eq!("Draw", record.result);
+        assert!(record.termination.unwrap().starts_with("Draw agreed"));
+        assert_eq!(3, record.moves.len());
+
+        // Declined offers keep the game going.
+        let mut players = [scripted(1, "draw", false), scripted(0, "", false)];
+        let rules = Adjudication::parse("moves=6").unwrap();
+        let (_, record) = face_off("Base", &mut players, None, rules, Verbosity::Quiet);
+        assert_eq!(6, record.moves.len());
+        assert_eq!(Some("Adjudicated: draw after 6 moves"), record.termination.as_deref());
+
+        // Taking back White's second move and Black's reply, on both boards.
+        let mut players = [scripted(2, "takeback", false), scripted(0, "", false)];
+        let (_, record) = face_off("Base", &mut players, None, rules, Verbosity::Quiet);
+        assert_eq!(6, record.moves.len());
+        assert!(record.to_board().is_ok());
+        assert_eq!(4, undos.get());
+
+        // The clocks lose the increments of the moves taken back.
+        let mut players = [scripted(2, "takeback", false), scripted(0, "", false)];
+        let clock = TimeControl::parse("60s+10s").unwrap();
+        let (_, record) = face_off("Base", &mut players, Some(clock), rules, Verbosity::Quiet);
+        assert_eq!(6, record.clocks.len());
+        for (i, left) in record.clocks.iter().enumerate() {
+            assert!(*left <= clock.total + clock.increment * (i as u32 / 2 + 1));
+        }
+
+        // And the resign rule starts its count over.
+        let mut adjudicator = Adjudicator::new(Adjudication::parse("resign=800/2").unwrap());
+        adjudicator.values = [Some(-900), Some(900)];
+        assert_eq!(None, adjudicator.adjudicate(&Board::default()));
+        adjudicator.takeback();
+        assert_eq!([None; 2], adjudicator.values);
+        assert_eq!(None, adjudicator.streak);
+
+        // White has to pass, and Black surrounds its queen next.
+        config.strategy = PlayerStrategy::Iterative(ParallelOptions::new());
+        config.num_threads = Some(1);
+        config.resign = true;
+        config.accept_draws = true;
+        let position = "Base White[8] wQ@0,0 bQ@-1,-1 bA1@0,-1 bA2@1,0 bA3@1,1 bG1@0,1 bB1@-2,-1";
+        let mut player = config.new_player();
+        player.set_max_depth(2);
+        player.new_game(position).unwrap();
+        assert_eq!(Decision::Resign, player.decide().unwrap());
+        assert!(player.accepts_draw());
+        player.new_game(&position.replace("White", "Black")).unwrap();
+        assert!(!player.accepts_draw());
+        assert!(matches!(player.decide().unwrap(), Decision::Play(_)));
+    }
+
+    #[test]
+    fn test_search_limits() {
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(2);
+        assert_eq!(100 << 20, config.search_opts().table_byte_size);
+        // 3 MiB is left after the threads, and half of it rounds down to 1 MiB.
+        config.max_memory = Some(4 << 20);
+        assert_eq!(1 << 20, config.search_opts().table_byte_size);
+        config.max_memory = Some(1 << 20);
+        assert_eq!(MIN_TABLE_BYTES, config.search_opts().table_byte_size);
+
+        // A search that would take minutes stops after a few depths.
+        config.num_threads = Some(1);
+        config.max_nodes = Some(5000);
+        let mut player = config.new_player();
+        player
+            .new_game(r"Base;InProgress;White[4];wG1;bG1 wG1-;wQ -wG1;bQ bG1-;wS1 \wQ;bS1 bQ\")
+            .unwrap();
+        player.set_max_depth(30);
+        let m = player.generate_move().unwrap();
+        let pv = player.principal_variation();
+        assert_eq!(Some(&m), pv.first());
+        assert!((2..10).contains(&pv.len()), "{}", pv.len());
+
+        // Late move pruning, as plies searched in full and moves after.
+        assert_eq!(None, parse_widening("3"));
+        assert_eq!(None, parse_widening("3/0"));
+        config.widening = parse_widening("2/8");
+        assert_eq!(Some(Widening { full_plies: 2, moves: 8 }), config.widening);
+        assert!(config.describe().contains(&"Widening;2/8".to_owned()));
+        let mut player = config.new_player();
+        player.new_game("Base").unwrap();
+        player.set_max_depth(4);
+        let m = player.generate_move().unwrap();
+        assert_eq!(Some(&m), player.principal_variation().first());
+
+        // Instant moves stay quick in endgames small enough for a tablebase.
+        // The margin is for slow debug builds and busy test machines.
+        config.widening = None;
+        config.instant = true;
+        let mut player = config.new_player();
+        player
+            .new_game(
+                "Base White[30] wQ@0,0 bQ@1,0 wG1@0,-1 bG1@1,1 wB1@-1,0 wS1@-2,0 wA1@-3,0 \
+                 wA2@-4,0 wA3@-5,0 wS2@-6,0 bA1@-6,1 wB2@-6,2 wG2@-6,3 bB1@2,0 bS1@3,0 bA2@4,0 \
+                 bA3@5,0 bS2@6,0 bB2@6,-1 wG3@6,-2 bG2@6,-3 bG3@6,-4",
+            )
+            .unwrap();
+        let start = Instant::now();
+        player.generate_move().unwrap();
+        assert!(start.elapsed() < INSTANT_TIME * 10, "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_opening_variety() {
+        let eval = BasicEvaluator::default();
+        let board =
+            Board::from_game_string("Base;InProgress;White[3];wG1;bG1 wG1-;wQ -wG1;bQ bG1-")
+                .unwrap();
+        let scores = shallow_scores(&board, &eval);
+        let best = scores.iter().map(|&(_, score)| score).max().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let uniform = OpeningVariety { plies: 6, temperature: None };
+        for _ in 0..20 {
+            let turn = uniform.choose(&board, &eval, &mut rng).unwrap();
+            let score = scores.iter().find(|&&(m, _)| m == turn).unwrap().1;
+            assert!(score >= best - REASONABLE_MARGIN);
+        }
+        // Cold enough to always play the best move.
+        let cold = OpeningVariety { plies: 6, temperature: Some(0.001) };
+        let turn = cold.choose(&board, &eval, &mut rng).unwrap();
+        assert_eq!(best, scores.iter().find(|&&(m, _)| m == turn).unwrap().1);
+        // Hot enough to play almost anything.
+        let hot = OpeningVariety { plies: 6, temperature: Some(1e6) };
+        let mut seen = Vec::new();
+        for _ in 0..50 {
+            seen.push(hot.choose(&board, &eval, &mut rng).unwrap());
+        }
+        seen.sort_by_key(|&m| board.to_move_string(m));
+        seen.dedup();
+        assert!(seen.len() > 5);
+        // Only the first plies.
+        assert_eq!(
+            None,
+            OpeningVariety { plies: 4, temperature: None }.choose(&board, &eval, &mut rng)
+        );
+    }
+
+    #[test]
+    fn test_strength() {
+        assert_eq!(None, Strength::level(0));
+        assert_eq!(None, Strength::level(MAX_STRENGTH + 1));
+        assert_eq!(Strength::level(MAX_STRENGTH), Some(Strength::default()));
+
+        // The depth cap holds under any requested limit.
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        config.strength = Strength::level(3).unwrap();
+        let mut player = config.new_player();
+        player
+            .new_game(r"Base;InProgress;White[4];wG1;bG1 wG1-;wQ -wG1;bQ bG1-;wS1 \wQ;bS1 bQ\")
+            .unwrap();
+        player.set_max_depth(8);
+        player.generate_move().unwrap();
+        assert!(player.principal_variation().len() <= 2);
+        player.set_timeout(Duration::from_secs(10));
+        player.generate_move().unwrap();
+        assert!(player.principal_variation().len() <= 2);
+
+        // Random moves come from the best few.
+        let eval = BasicEvaluator::default();
+        let board =
+            Board::from_game_string("Base;InProgress;White[3];wG1;bG1 wG1-;wQ -wG1;bQ bG1-")
+                .unwrap();
+        let mut scores = shallow_scores(&board, &eval);
+        scores.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        let strength = Strength { random_chance: 1.0, ..Strength::level(1).unwrap() };
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let turn = strength.choose(&board, &eval, &mut rng).unwrap();
+            let rank = scores.iter().position(|&(m, _)| m == turn).unwrap();
+            assert!(scores[rank].1 >= scores[strength.top_moves - 1].1);
+        }
+        assert_eq!(None, Strength::default().choose(&board, &eval, &mut rng));
+    }
+
+    #[test]
+    fn test_set_weights() {
+        let mut eval = BasicEvaluator::default();
+        set_weights(&mut eval, "queen_liberty_factor=40, unplayed_bug_factor=3").unwrap();
+        let weights = eval.weights();
+        let weight = |name: &str| weights.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(40, weight("queen_liberty_factor_midgame"));
+        assert_eq!(3, weight("unplayed_bug_factor"));
+
+        // Everything tune prints can be read back.
+        let listed = weights.iter().map(|(name, value)| format!("{}={}", name, value));
+        let mut copy = BasicEvaluator::new(1);
+        set_weights(&mut copy, &listed.collect::<Vec<_>>().join(",")).unwrap();
+        assert_eq!(weights, copy.weights());
+
+        assert!(set_weights(&mut eval, "queen_liberty_factor").is_err());
+        assert!(set_weights(&mut eval, "queen_liberty_factor=lots").is_err());
+        assert!(set_weights(&mut eval, "no_such_weight=1").is_err());
     }
 }
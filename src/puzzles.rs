@@ -0,0 +1,239 @@
+use crate::notation::Result;
+use crate::player::{exit, set_limits, Player, PlayerConfig};
+use crate::solve::Solver;
+use crate::{Board, Rules};
+use minimax::Winner;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+// Puzzles mined from games: positions where the side to move can force a
+// queen surround within a few of its own moves, and only one first move does
+// it. The forced win is proven by the solver (see solve.rs). Each puzzle is
+// written as a line of a test suite (see suite.rs), with the winning line
+// added for people:
+//
+//   GameString | bm move | pv move; reply; move | id surround in 2, game 3 move 41
+//
+// so `nokamute suite` can check that the engine finds the solutions.
+
+pub(crate) struct Puzzle {
+    pub(crate) game_string: String,
+    // How many of the winner's own moves the surround takes.
+    pub(crate) moves: u8,
+    pub(crate) solution: Vec<String>,
+}
+
+// The quickest forced surround for the side to move in at most max_moves of
+// its own moves, if it's the only first move that wins that fast.
+pub(crate) fn find_puzzle(board: &Board, max_moves: u8) -> Option<Puzzle> {
+    if Rules::get_winner(board).is_some() {
+        return None;
+    }
+    let mut board = board.clone();
+    let mut solver = Solver::default();
+    let (moves, line) = (1..=max_moves)
+        .find_map(|moves| solver.win_within(&mut board, 2 * moves - 1).map(|line| (moves, line)))?;
+
+    // Any other first move winning as fast spoils the puzzle.
+    let mut first_moves = Vec::new();
+    Rules::generate_moves(&board, &mut first_moves);
+    let plies = 2 * moves - 1;
+    for &m in first_moves.iter().filter(|&&m| m != line[0]) {
+        board.apply(m);
+        let wins = match Rules::get_winner(&board) {
+            Some(winner) => winner == Winner::PlayerJustMoved,
+            None => plies > 1 && solver.all_lose(&mut board, plies - 1).is_some(),
+        };
+        board.undo(m);
+        if wins {
+            return None;
+        }
+    }
+
+    let game_string = board.game_string();
+    let solution = board.line_move_strings(&line);
+    Some(Puzzle { game_string, moves, solution })
+}
+
+// Whether the position is worth searching: the queen of the player not to
+// move has enough neighbors to be surrounded in max_moves moves.
+fn candidate(board: &Board, max_moves: u8) -> bool {
+    let defender = board.to_move().other();
+    board.queens[defender].is_some()
+        && board.queens_surrounded()[defender] + max_moves as usize >= 6
+}
+
+// Puzzles from every position of the game, skipping positions already seen.
+fn scan_game(
+    game_string: &str, max_moves: u8, seen: &mut HashSet<u64>,
+) -> Result<Vec<(usize, Puzzle)>> {
+    let game = Board::from_game_string(game_string)?;
+    let mut board = Board::from_game_type(&game.game_type())?;
+    board.set_variant(game.variant());
+    let mut puzzles: Vec<(usize, Puzzle)> = Vec::new();
+    // Every position, up to the last of an unfinished game.
+    let turns = game.turn_history.iter().map(Some).chain([None]);
+    for (i, turn) in turns.enumerate() {
+        // The same position can come up in other games, shifted or turned.
+        if candidate(&board, max_moves)
+            && seen.insert(board.canonical_hash() ^ board.to_move() as u64)
+        {
+            if let Some(puzzle) = find_puzzle(&board, max_moves) {
+                // While the loser shuffles tiles elsewhere, the same
+                // solution keeps working. Keep the first.
+                let repeat = puzzles.last().is_some_and(|(move_num, last)| {
+                    move_num + 2 == i + 1 && last.solution == puzzle.solution
+                });
+                if !repeat {
+                    puzzles.push((i + 1, puzzle));
+                }
+            }
+        }
+        if let Some(&turn) = turn {
+            board.apply(turn);
+        }
+    }
+    Ok(puzzles)
+}
+
+// A self-play game as a GameString.
+fn selfplay_game(
+    players: &mut [Box<dyn Player>; 2], game_type: &str, max_plies: u16,
+) -> Result<String> {
+    let mut board = Board::from_game_type(game_type)?;
+    for player in players.iter_mut() {
+        player.new_game(game_type)?;
+    }
+    while board.turn_num < max_plies && Rules::get_winner(&board).is_none() {
+        let m = players[board.turn_num as usize % 2].generate_move()?;
+        board.apply(m);
+        for player in players.iter_mut() {
+            player.play_move(m)?;
+        }
+    }
+    Ok(board.game_string())
+}
+
+fn write_puzzles(
+    games: impl Iterator<Item = Result<String>>, max_moves: u8, out: &mut impl Write,
+) -> Result<usize> {
+    let mut seen = HashSet::new();
+    let mut count = 0;
+    for (i, game) in games.enumerate() {
+        let game_num = i + 1;
+        let puzzles = match game.and_then(|game| scan_game(&game, max_moves, &mut seen)) {
+            Ok(puzzles) => puzzles,
+            Err(err) => {
+                eprintln!("Skipping game {}: {}", game_num, err);
+                continue;
+            }
+        };
+        for (move_num, puzzle) in puzzles {
+            writeln!(
+                out,
+                "{} | bm {} | pv {} | id surround in {}, game {} move {}",
+                puzzle.game_string,
+                puzzle.solution[0],
+                puzzle.solution.join("; "),
+                puzzle.moves,
+                game_num,
+                move_num
+            )?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn puzzles_main(
+    config: PlayerConfig, input: Option<String>, max_moves: u8, game_type: &str, games: usize,
+    depth: Option<u8>, timeout: Option<String>, output: Option<String>,
+) {
+    if !(1..=3).contains(&max_moves) {
+        exit("--surround-in must be from 1 to 3".to_string());
+    }
+    let games: Box<dyn Iterator<Item = Result<String>>> = match input {
+        Some(path) => {
+            let text = fs::read_to_string(&path)
+                .unwrap_or_else(|err| exit(format!("Could not read {}: {}", path, err)));
+            let lines = text
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| Ok(line.to_owned()))
+                .collect::<Vec<_>>();
+            Box::new(lines.into_iter())
+        }
+        None => {
+            // Deep enough to mean something, shallow enough for many games.
+            let depth = if timeout.is_none() { depth.or(Some(3)) } else { depth };
+            let mut players = [config.new_player(), config.new_player()];
+            for player in players.iter_mut() {
+                set_limits(player.as_mut(), depth, &timeout);
+            }
+            let game_type = game_type.to_owned();
+            Box::new((0..games).map(move |_| selfplay_game(&mut players, &game_type, 200)))
+        }
+    };
+    let result = match &output {
+        Some(path) => File::create(path).map_err(Into::into).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            let count = write_puzzles(games, max_moves, &mut out)?;
+            out.flush()?;
+            Ok(count)
+        }),
+        None => write_puzzles(games, max_moves, &mut io::stdout().lock()),
+    };
+    match result {
+        Ok(count) => eprintln!("Found {} puzzles", count),
+        Err(err) => exit(format!("Puzzle search failed: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::suite::parse_position;
+
+    const GAME: &str = r"Base;InProgress;White[15];wB1;bB1 -wB1;wB2 wB1\;bQ /bB1;wS1 wB2-;bG1 \bB1;wQ wS1/;bA1 \bG1;wA1 -wQ;bA1 wQ-;wA1 /bQ;bA1 \wQ;wA2 /wB2;bA1 wQ-;wA2 -bQ;bA1 wQ/;wA3 -wQ;bA2 \bG1;wA3 -bA2;bA3 bG1/;wA3 bB1\;bA2 wQ\;wB1 bB1;bA3 -bA1;wG1 \wB2;bG1 wA3\;wG1 bQ\;bG1 /bA3";
+
+    #[test]
+    fn test_find_puzzle() {
+        let mut board = Board::from_game_string(GAME).unwrap();
+        // The beetle steps down into the black queen's last open hex.
+        let puzzle = find_puzzle(&board, 2).unwrap();
+        assert_eq!(1, puzzle.moves);
+        assert_eq!(vec![r"wB1 -bB1"], puzzle.solution);
+
+        // Two moves earlier, only the grasshopper's jump sets that up.
+        board.undo_count(2).unwrap();
+        assert!(find_puzzle(&board, 1).is_none());
+        let puzzle = find_puzzle(&board, 2).unwrap();
+        assert_eq!(2, puzzle.moves);
+        assert_eq!(r"wG1 bQ\", puzzle.solution[0]);
+        assert_eq!(3, puzzle.solution.len());
+        assert_eq!(board.game_string(), puzzle.game_string);
+
+        // Black moves next, with nothing to win.
+        board.undo_count(1).unwrap();
+        assert!(find_puzzle(&board, 2).is_none());
+    }
+
+    #[test]
+    fn test_write_puzzles() {
+        let games = [Ok(GAME.to_owned()), Ok("Chess".to_owned())];
+        let mut out = Vec::new();
+        assert_eq!(2, write_puzzles(games.into_iter(), 2, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert!(lines[0].ends_with("| id surround in 2, game 1 move 27"), "{}", lines[0]);
+        assert!(lines[1].ends_with("| id surround in 1, game 1 move 29"), "{}", lines[1]);
+        // Puzzles are test suite positions.
+        for (i, line) in lines.iter().enumerate() {
+            let position = parse_position(line, i + 1).unwrap();
+            assert_eq!(1, position.best_moves.len());
+        }
+    }
+}
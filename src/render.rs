@@ -0,0 +1,136 @@
+use crate::board::*;
+use crate::hex_grid::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+// Draws positions as SVG images, for sharing in bug reports and write-ups.
+
+// Distance from the center of a hex to its corners, in pixels.
+const RADIUS: f32 = 30.0;
+const SQRT_3: f32 = 1.732_050_8;
+
+// Center of a hex in pixels, for steps east and southwest of some origin.
+fn center((x, y): (i32, i32)) -> (f32, f32) {
+    (SQRT_3 * RADIUS * (x as f32 - y as f32 / 2.0), 1.5 * RADIUS * y as f32)
+}
+
+fn hexagon(out: &mut String, (cx, cy): (f32, f32), style: &str) {
+    out.push_str("<polygon points=\"");
+    // Pointy topped, starting from the top corner.
+    let corners =
+        [(0.0, -1.0), (0.866, -0.5), (0.866, 0.5), (0.0, 1.0), (-0.866, 0.5), (-0.866, -0.5)];
+    for (dx, dy) in corners {
+        let _ = write!(out, "{:.1},{:.1} ", cx + dx * RADIUS, cy + dy * RADIUS);
+    }
+    let _ = writeln!(out, "\" {}/>", style);
+}
+
+impl Board {
+    // An SVG image of the board. Each stack shows the UHP name of its top
+    // tile, with the tiles underneath listed below it, and the last move is
+    // outlined.
+    pub fn to_svg(&self) -> String {
+        let origin = self.occupied_hexes.iter().flatten().next().copied().unwrap_or(START_HEX);
+        let mut hexes: Vec<Hex> = self.occupied_hexes.iter().flatten().copied().collect();
+        hexes.sort_unstable();
+        let (from, to) = match self.turn_history.last() {
+            Some(&Turn::Move(start, end)) => (Some(start), Some(end)),
+            Some(&Turn::Place(hex, _)) => (None, Some(hex)),
+            _ => (None, None),
+        };
+        let mut shown = hexes.clone();
+        shown.extend(from);
+        shown.push(origin);
+        let centers: Vec<(f32, f32)> =
+            shown.iter().map(|&hex| center(split_offset(hex.wrapping_sub(origin)))).collect();
+        let margin = RADIUS * 1.5;
+        let min_x = centers.iter().map(|c| c.0).fold(f32::MAX, f32::min) - margin;
+        let min_y = centers.iter().map(|c| c.1).fold(f32::MAX, f32::min) - margin;
+        let max_x = centers.iter().map(|c| c.0).fold(f32::MIN, f32::max) + margin;
+        let max_y = centers.iter().map(|c| c.1).fold(f32::MIN, f32::max) + margin;
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{:.1} {:.1} {:.1} {:.1}\" font-family=\"sans-serif\" text-anchor=\"middle\">",
+            min_x,
+            min_y,
+            max_x - min_x,
+            max_y - min_y
+        );
+        if let Some(start) = from.filter(|&hex| !self.occupied(hex)) {
+            let c = center(split_offset(start.wrapping_sub(origin)));
+            hexagon(
+                &mut out,
+                c,
+                "fill=\"none\" stroke=\"#e08000\" stroke-width=\"3\" stroke-dasharray=\"6 4\"",
+            );
+        }
+        for &hex in hexes.iter() {
+            let node = self.node(hex);
+            let (cx, cy) = center(split_offset(hex.wrapping_sub(origin)));
+            let (fill, ink) = match node.color() {
+                Color::White => ("#f4ecd8", "#222"),
+                Color::Black => ("#2a2a2a", "#eee"),
+            };
+            let stroke = if Some(hex) == to {
+                "stroke=\"#e08000\" stroke-width=\"4\""
+            } else {
+                "stroke=\"#666\" stroke-width=\"1.5\""
+            };
+            hexagon(&mut out, (cx, cy), &alloc::format!("fill=\"{}\" {}", fill, stroke));
+            let mut name = String::new();
+            self.tile_name(node, &mut name);
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\" font-size=\"16\" font-weight=\"bold\">{}</text>",
+                cx,
+                cy + 5.0,
+                ink,
+                name
+            );
+            // Tiles underneath, from the top down.
+            let mut under = String::new();
+            for tile in self.get_underworld().iter().rev().filter(|tile| tile.hex() == hex) {
+                under.push(' ');
+                self.tile_name(tile.node(), &mut under);
+            }
+            if !under.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\" font-size=\"9\">on{}</text>",
+                    cx,
+                    cy + 18.0,
+                    ink,
+                    under
+                );
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_svg() {
+        assert!(Board::default().to_svg().starts_with("<svg"));
+
+        // A white beetle on the white queen, after moving there.
+        let board = Board::from_game_string(
+            "Base;InProgress;Black[4];wB1;bB1 wB1-;wQ -wB1;bQ bB1-;wB2 -wQ;bB2 bQ-;wB2 wQ",
+        )
+        .unwrap();
+        let svg = board.to_svg();
+        assert_eq!(6, svg.matches("<polygon").count());
+        assert_eq!(1, svg.matches("stroke-dasharray").count());
+        assert_eq!(1, svg.matches("stroke-width=\"4\"").count());
+        assert!(svg.contains(">wB2</text>"));
+        assert!(svg.contains(">on wQ</text>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+}
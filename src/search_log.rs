@@ -0,0 +1,128 @@
+use crate::{json_string, json_strings, line_score, BasicEvaluator, Board, Rules, Turn};
+use minimax::Game;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A record of every search the engine performs, one JSON object per line, so
+// bot operators can work out later why a deployed engine played a move:
+//
+//   {"start_ms":1700000000000,"end_ms":1700000004987,"player":"nokamute",
+//    "hash":"8f3a...","game":"Base;InProgress;White[4];...",
+//    "limits":{"depth":null,"time_ms":5000,"nodes":null},"depth":7,
+//    "nodes":123456,"score":"12","pv":["wA1 -bQ","bG2 wA1-"],"move":"wA1 -bQ"}
+//
+// (on a single line). Timestamps are milliseconds since the Unix epoch, the
+// hash is the position's Zobrist hash, and the score is for the player to
+// move, as in UHP info lines. Nodes are positions evaluated. Moves picked
+// without a search, like random openings, have depth 0 and no PV. Players
+// share the file, so tournaments log both sides.
+#[derive(Clone)]
+pub(crate) struct SearchLog {
+    file: Arc<Mutex<File>>,
+}
+
+// What one search was asked to do and what it found.
+pub(crate) struct SearchEntry<'a> {
+    pub(crate) player: &'a str,
+    // The position searched, before the move.
+    pub(crate) board: &'a Board,
+    pub(crate) max_depth: Option<u8>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) max_nodes: Option<u64>,
+    pub(crate) depth: u8,
+    pub(crate) nodes: u64,
+    pub(crate) pv: &'a [Turn],
+    pub(crate) turn: Turn,
+    pub(crate) start: SystemTime,
+    pub(crate) end: SystemTime,
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_owned(), |value| value.to_string())
+}
+
+impl SearchEntry<'_> {
+    pub(crate) fn to_json(&self, eval: &BasicEvaluator) -> String {
+        format!(
+            "{{\"start_ms\":{},\"end_ms\":{},\"player\":{},\"hash\":\"{:016x}\",\"game\":{},\
+             \"limits\":{{\"depth\":{},\"time_ms\":{},\"nodes\":{}}},\"depth\":{},\"nodes\":{},\
+             \"score\":{},\"pv\":{},\"move\":{}}}",
+            unix_millis(self.start),
+            unix_millis(self.end),
+            json_string(self.player),
+            Rules::zobrist_hash(self.board),
+            json_string(&self.board.game_string()),
+            json_option(self.max_depth),
+            json_option(self.timeout.map(|time| time.as_millis())),
+            json_option(self.max_nodes),
+            self.depth,
+            self.nodes,
+            json_string(&line_score(self.board, eval, self.pv)),
+            json_strings(&self.board.line_move_strings(self.pv)),
+            json_string(&self.board.to_move_string(self.turn)),
+        )
+    }
+}
+
+impl SearchLog {
+    // Appends to the file, creating it if needed.
+    pub(crate) fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(SearchLog { file: Arc::new(Mutex::new(file)) })
+    }
+
+    pub(crate) fn record(&self, entry: &SearchEntry, eval: &BasicEvaluator) {
+        let line = entry.to_json(eval);
+        let mut file = self.file.lock().unwrap();
+        // A full disk shouldn't stop the game.
+        if let Err(err) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            eprintln!("Could not write to the search log: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlayerConfig;
+
+    #[test]
+    fn test_search_log() {
+        let path = std::env::temp_dir().join(format!("nokamute-log-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        config.search_log = Some(SearchLog::open(path).unwrap());
+        let mut player = config.new_player();
+        player.new_game("Base;InProgress;White[3];wG1;bG1 wG1-;wQ -wG1;bQ bG1-").unwrap();
+        player.set_max_depth(2);
+        let m = player.generate_move().unwrap();
+        player.play_move(m).unwrap();
+        player.set_timeout(Duration::from_millis(50));
+        player.generate_move().unwrap();
+
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(2, lines.len(), "{}", log);
+        let mut board =
+            Board::from_game_string("Base;InProgress;White[3];wG1;bG1 wG1-;wQ -wG1;bQ bG1-")
+                .unwrap();
+        assert!(lines[0].starts_with("{\"start_ms\":"), "{}", lines[0]);
+        assert!(lines[0].contains(&format!("\"hash\":\"{:016x}\"", Rules::zobrist_hash(&board))));
+        assert!(lines[0].contains("\"limits\":{\"depth\":2,\"time_ms\":null,\"nodes\":null}"));
+        assert!(lines[0].contains("\"depth\":2,"), "{}", lines[0]);
+        let expected = format!("\"move\":{}}}", json_string(&board.to_move_string(m)));
+        assert!(lines[0].ends_with(&expected), "{}", lines[0]);
+        board.apply(m);
+        assert!(lines[1].contains(&json_string(&board.game_string())), "{}", lines[1]);
+        assert!(lines[1].contains("\"time_ms\":50,"), "{}", lines[1]);
+        assert!(!lines[1].contains("\"nodes\":0,"), "{}", lines[1]);
+    }
+}
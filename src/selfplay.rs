@@ -0,0 +1,155 @@
+use crate::notation::Result;
+use crate::player::{exit, line_value, set_limits, Player, PlayerConfig};
+use crate::{Board, Rules, Turn};
+use minimax::{Evaluation, Winner};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Self-play games as training data for learned evaluations. Every position of
+// every game becomes one line of JSON:
+//
+//   {"game":1,"position":"Base White[1] w:QGGGSSAAABB b:QGGGSSAAABB","to_move":"White","score":6,"move":"wB1","result":"BlackWins"}
+//
+// The position is a position string (see Board::to_position_string). The
+// score is the value of the searching player's line for the side to move, or
+// null when the player reported no line. The result is how the game ended, as
+// a UHP GameState, with games cut off at the move limit counted as draws.
+
+struct Sample {
+    position: String,
+    to_move: &'static str,
+    score: Option<Evaluation>,
+    turn: String,
+}
+
+// Quotes a string for JSON. Move strings can contain backslashes.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Plays one game between the players, returning each position with the move
+// chosen from it, and the result.
+fn play_game(
+    config: &PlayerConfig, players: &mut [Box<dyn Player>; 2], game_type: &str, max_moves: u16,
+) -> Result<(Vec<Sample>, &'static str)> {
+    let mut board = Board::from_game_type(game_type)?;
+    for player in players.iter_mut() {
+        player.new_game(game_type)?;
+    }
+    let mut samples = Vec::new();
+    let mut p = 0;
+    while board.turn_num < max_moves {
+        let m: Turn = players[p].generate_move()?;
+        let line = players[p].principal_variation();
+        let score = (line.first() == Some(&m)).then(|| line_value(&board, &config.eval, &line));
+        samples.push(Sample {
+            position: board.to_position_string(),
+            to_move: ["White", "Black"][board.to_move() as usize],
+            score,
+            turn: board.to_move_string(m),
+        });
+        board.apply(m);
+        for player in players.iter_mut() {
+            player.play_move(m)?;
+        }
+        if let Some(winner) = Rules::get_winner(&board) {
+            let result = match winner {
+                Winner::Draw => "Draw",
+                Winner::PlayerJustMoved => ["WhiteWins", "BlackWins"][p],
+                Winner::PlayerToMove => ["BlackWins", "WhiteWins"][p],
+            };
+            return Ok((samples, result));
+        }
+        p = 1 - p;
+    }
+    Ok((samples, "Draw"))
+}
+
+fn write_games(
+    config: &PlayerConfig, game_type: &str, games: usize, max_moves: u16, depth: Option<u8>,
+    timeout: &Option<String>, out: &mut impl Write,
+) -> Result<()> {
+    let mut players = [config.new_player(), config.new_player()];
+    for player in players.iter_mut() {
+        set_limits(player.as_mut(), depth, timeout);
+    }
+    for game in 1..=games {
+        let (samples, result) = play_game(config, &mut players, game_type, max_moves)?;
+        for sample in samples {
+            let score = sample.score.map_or_else(|| "null".to_owned(), |score| score.to_string());
+            writeln!(
+                out,
+                "{{\"game\":{},\"position\":{},\"to_move\":\"{}\",\"score\":{},\"move\":{},\"result\":\"{}\"}}",
+                game,
+                json_string(&sample.position),
+                sample.to_move,
+                score,
+                json_string(&sample.turn),
+                result
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn selfplay_main(
+    config: PlayerConfig, game_type: &str, games: usize, max_moves: u16, depth: Option<u8>,
+    timeout: Option<String>, output: Option<String>,
+) {
+    // Deep enough to mean something, shallow enough for many games.
+    let depth = if timeout.is_none() { depth.or(Some(3)) } else { depth };
+    let result = match &output {
+        Some(path) => File::create(path).map_err(Into::into).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write_games(&config, game_type, games, max_moves, depth, &timeout, &mut out)?;
+            out.flush().map_err(Into::into)
+        }),
+        None => write_games(
+            &config,
+            game_type,
+            games,
+            max_moves,
+            depth,
+            &timeout,
+            &mut io::stdout().lock(),
+        ),
+    };
+    if let Err(err) = result {
+        exit(format!("Self-play failed: {}", err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_games() {
+        assert_eq!(r#""bA1 \\wQ""#, json_string(r"bA1 \wQ"));
+
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        let mut out = Vec::new();
+        write_games(&config, "Base", 2, 6, Some(1), &None, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(12, lines.len());
+        assert!(lines[0].starts_with(r#"{"game":1,"position":"Base White[1] "#), "{}", lines[0]);
+        assert!(lines[1].contains(r#""to_move":"Black""#), "{}", lines[1]);
+        assert!(lines[11].starts_with(r#"{"game":2,"#));
+        assert!(lines.iter().all(|line| line.ends_with(r#""result":"Draw"}"#)));
+        assert!(!lines.iter().any(|line| line.contains(r#""score":null"#)));
+    }
+}
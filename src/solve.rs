@@ -0,0 +1,168 @@
+use crate::player::exit;
+use crate::{adjacent, Board, Rules, Turn};
+use minimax::{Game, Winner};
+use std::collections::HashMap;
+use std::time::Instant;
+
+// Exact search for forced queen surrounds. Alpha-beta with a heuristic
+// evaluation can miss a forced win past its horizon or trust a line the
+// evaluation misjudges, so puzzles and `nokamute solve` search every reply
+// instead, with no evaluation at all.
+//
+// The winner is the side to move at the root, and plies count both sides'
+// moves, so a surround in 2 moves takes 3 plies. Positions proven to hold
+// out are remembered by their Zobrist hash, which includes the side to move
+// and the tile that just moved.
+#[derive(Default)]
+pub(crate) struct Solver {
+    // Positions where the side to move can't win within this many plies.
+    no_win: HashMap<u64, u8>,
+    // Positions where the side to move doesn't lose within this many plies.
+    holds: HashMap<u64, u8>,
+    pub(crate) nodes: u64,
+}
+
+impl Solver {
+    // The side to move's quickest forced surround within max_plies.
+    pub(crate) fn quickest_win(&mut self, board: &Board, max_plies: u8) -> Option<Vec<Turn>> {
+        if Rules::get_winner(board).is_some() {
+            return None;
+        }
+        let mut board = board.clone();
+        (1..=max_plies).step_by(2).find_map(|plies| self.win_within(&mut board, plies))
+    }
+
+    // A line by which the side to move surrounds the other queen within
+    // plies, whatever the replies. Immediate wins are tried first, so a win
+    // in one is found as such.
+    pub(crate) fn win_within(&mut self, board: &mut Board, plies: u8) -> Option<Vec<Turn>> {
+        if plies == 0 {
+            return None;
+        }
+        let hash = Rules::zobrist_hash(board);
+        if self.no_win.get(&hash).is_some_and(|&searched| searched >= plies) {
+            return None;
+        }
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        self.nodes += 1;
+        for &m in moves.iter() {
+            board.apply(m);
+            let won = Rules::get_winner(board) == Some(Winner::PlayerJustMoved);
+            board.undo(m);
+            if won {
+                return Some(vec![m]);
+            }
+        }
+        if plies >= 3 {
+            // Tiles landing next to the other queen are the likeliest to
+            // force a win.
+            if let Some(queen) = board.queens[board.to_move().other()] {
+                let near = adjacent(queen);
+                moves.sort_by_key(|m| match m {
+                    Turn::Move(_, end) | Turn::Place(end, _) => !near.contains(end),
+                    Turn::Pass => true,
+                });
+            }
+            for &m in moves.iter() {
+                board.apply(m);
+                let line = if Rules::get_winner(board).is_none() {
+                    self.all_lose(board, plies - 1)
+                } else {
+                    None
+                };
+                board.undo(m);
+                if let Some(mut line) = line {
+                    line.insert(0, m);
+                    return Some(line);
+                }
+            }
+        }
+        self.no_win.insert(hash, plies);
+        None
+    }
+
+    // Whether every move of the side to move loses within plies, and if so
+    // the line after the reply that holds out longest.
+    pub(crate) fn all_lose(&mut self, board: &mut Board, plies: u8) -> Option<Vec<Turn>> {
+        let hash = Rules::zobrist_hash(board);
+        if self.holds.get(&hash).is_some_and(|&searched| searched >= plies) {
+            return None;
+        }
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        self.nodes += 1;
+        let mut longest: Option<Vec<Turn>> = None;
+        for &m in moves.iter() {
+            board.apply(m);
+            let line = match Rules::get_winner(board) {
+                // Surrounding their own queen.
+                Some(Winner::PlayerToMove) => Some(Vec::new()),
+                Some(_) => None,
+                None => self.win_within(board, plies - 1),
+            };
+            board.undo(m);
+            let Some(mut line) = line else {
+                self.holds.insert(hash, plies);
+                return None;
+            };
+            line.insert(0, m);
+            if longest.as_ref().is_none_or(|longest| line.len() > longest.len()) {
+                longest = Some(line);
+            }
+        }
+        longest
+    }
+}
+
+pub fn solve_main(game_string: &str, max_plies: u8) {
+    let board = Board::from_game_or_position_string(game_string)
+        .unwrap_or_else(|err| exit(format!("Could not load position: {}", err)));
+    let mut solver = Solver::default();
+    let start = Instant::now();
+    let line = solver.quickest_win(&board, max_plies);
+    let elapsed = start.elapsed();
+    match line {
+        Some(line) => {
+            let moves = line.len().div_ceil(2);
+            println!(
+                "Surround in {} move{} ({} plies): {}",
+                moves,
+                if moves == 1 { "" } else { "s" },
+                line.len(),
+                board.line_move_strings(&line).join("; ")
+            );
+        }
+        None => println!("No forced surround within {} plies", max_plies),
+    }
+    println!("Searched {} positions in {:.3}s", solver.nodes, elapsed.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quickest_win() {
+        let game = r"Base;InProgress;White[14];wB1;bB1 -wB1;wB2 wB1\;bQ /bB1;wS1 wB2-;bG1 \bB1;wQ wS1/;bA1 \bG1;wA1 -wQ;bA1 wQ-;wA1 /bQ;bA1 \wQ;wA2 /wB2;bA1 wQ-;wA2 -bQ;bA1 wQ/;wA3 -wQ;bA2 \bG1;wA3 -bA2;bA3 bG1/;wA3 bB1\;bA2 wQ\;wB1 bB1;bA3 -bA1;wG1 \wB2;bG1 wA3\";
+        let board = Board::from_game_string(game).unwrap();
+        let mut solver = Solver::default();
+        assert_eq!(None, solver.quickest_win(&board, 2));
+        let line = solver.quickest_win(&board, 5).unwrap();
+        // Every defense loses as quickly, so any of them may be given.
+        assert_eq!(3, line.len());
+        assert_eq!(r"wG1 bQ\", board.to_move_string(line[0]));
+
+        // The line wins against the defense it gives.
+        let mut after = board.clone();
+        for &m in line.iter() {
+            after.apply(m);
+        }
+        assert_eq!(Some(Winner::PlayerJustMoved), Rules::get_winner(&after));
+
+        // Black, to move the turn before, can't stop it, but can't win either.
+        let mut board = board;
+        board.undo_count(1).unwrap();
+        assert_eq!(None, Solver::default().quickest_win(&board, 3));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::notation::{Result, UhpError};
+use crate::player::{exit, parse_timeout, PlayerConfig, SearchInfo};
+use crate::{Board, Rules, Turn};
+use std::fs;
+use std::time::{Duration, Instant};
+
+// Test suites of positions with known best moves, like chess EPD suites, for
+// regression testing changes to search strength. Each line of a suite file is
+// a UHP GameString followed by fields separated by " | ":
+//
+//   GameString | bm move[, move...] | id name
+//
+// Puzzles (see puzzles.rs) also give the winning line in a pv field, which
+// the suite doesn't check.
+// The engine solves a position if it plays one of the bm moves. Blank lines
+// and lines starting with # are skipped.
+
+pub(crate) struct TestPosition {
+    pub(crate) id: String,
+    pub(crate) game_string: String,
+    pub(crate) best_moves: Vec<Turn>,
+}
+
+pub(crate) fn parse_position(line: &str, line_num: usize) -> Result<TestPosition> {
+    let err = || UhpError::InvalidGameString(line.to_owned());
+    let mut fields = line.split(" | ");
+    let game_string = fields.next().ok_or_else(err)?.trim().to_owned();
+    let board = Board::from_game_string(&game_string)?;
+    let mut legal = Vec::new();
+    Rules::generate_moves(&board, &mut legal);
+    let mut position =
+        TestPosition { id: format!("line {}", line_num), game_string, best_moves: Vec::new() };
+    for field in fields {
+        let (op, value) = field.trim().split_once(' ').ok_or_else(err)?;
+        match op {
+            "bm" => {
+                for m in value.split(',') {
+                    let turn = board.from_move_string(m.trim())?;
+                    if !legal.contains(&turn) {
+                        return Err(UhpError::InvalidMove(m.trim().to_owned()));
+                    }
+                    position.best_moves.push(turn);
+                }
+            }
+            "id" => position.id = value.trim().to_owned(),
+            // The whole solution, as puzzles give it, for people.
+            "pv" => {}
+            _ => return Err(err()),
+        }
+    }
+    if position.best_moves.is_empty() {
+        return Err(err());
+    }
+    Ok(position)
+}
+
+pub(crate) fn load_suite(path: &str) -> Result<Vec<TestPosition>> {
+    let mut positions = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        positions.push(parse_position(line, i + 1)?);
+    }
+    Ok(positions)
+}
+
+pub(crate) struct SolveResult {
+    pub(crate) played: String,
+    // When the search first settled on a best move for good, or None if it
+    // didn't play one.
+    pub(crate) solved: Option<(u8, Duration)>,
+}
+
+pub(crate) fn solve_position(
+    config: &PlayerConfig, position: &TestPosition, depth: Option<u8>, timeout: Option<Duration>,
+) -> Result<SolveResult> {
+    // A fresh player for each position, so nothing carries over in the table.
+    let mut player = config.new_player();
+    player.new_game(&position.game_string)?;
+    match (depth, timeout) {
+        (Some(depth), _) => player.set_max_depth(depth),
+        (None, Some(timeout)) => player.set_timeout(timeout),
+        (None, None) => player.set_max_depth(4),
+    }
+    let start = Instant::now();
+    let mut solved = None;
+    let m = player.generate_move_with_info(&mut |info: SearchInfo| {
+        if !info.pv.first().is_some_and(|m| position.best_moves.contains(m)) {
+            solved = None;
+        } else if solved.is_none() {
+            solved = Some((info.depth, info.elapsed));
+        }
+    })?;
+    if !position.best_moves.contains(&m) {
+        solved = None;
+    } else if solved.is_none() {
+        // Players without depth by depth reports.
+        solved = Some((depth.unwrap_or(0), start.elapsed()));
+    }
+    let board = Board::from_game_string(&position.game_string)?;
+    Ok(SolveResult { played: board.to_move_string(m), solved })
+}
+
+pub fn suite_main(
+    mut config: PlayerConfig, path: &str, depth: Option<u8>, timeout: Option<String>,
+) {
+    config.random_opening = false;
+    let timeout = timeout.map(|input| parse_timeout(&input));
+    let positions =
+        load_suite(path).unwrap_or_else(|err| exit(format!("Could not load suite: {}", err)));
+    let mut solved = 0;
+    let mut total_time = Duration::ZERO;
+    for position in positions.iter() {
+        let result = solve_position(&config, position, depth, timeout)
+            .unwrap_or_else(|err| exit(format!("Could not search {}: {:?}", position.id, err)));
+        match result.solved {
+            Some((depth, time)) => {
+                solved += 1;
+                total_time += time;
+                println!(
+                    "{:<24} solved  {:>8.3}s  depth {:<2}  {}",
+                    position.id,
+                    time.as_secs_f64(),
+                    depth,
+                    result.played
+                );
+            }
+            None => println!("{:<24} FAILED  played {}", position.id, result.played),
+        }
+    }
+    let average = if solved > 0 { total_time.as_secs_f64() / solved as f64 } else { 0.0 };
+    println!(
+        "Solved {}/{} ({:.0}%), average time to solution {:.3}s",
+        solved,
+        positions.len(),
+        100.0 * solved as f64 / positions.len().max(1) as f64,
+        average
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_position() {
+        // Black's ant can fill the white queen's last liberty.
+        let line = r"Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\ | bm bA1 \wS1 | id ant surrounds";
+        let position = parse_position(line, 1).unwrap();
+        assert_eq!("ant surrounds", position.id);
+        assert_eq!(1, position.best_moves.len());
+
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(1);
+        let result = solve_position(&config, &position, Some(2), None).unwrap();
+        assert_eq!(r"bA1 \wS1", result.played);
+        assert_eq!(Some(1), result.solved.map(|(depth, _)| depth));
+
+        assert!(parse_position("Base;NotStarted;White[1]", 1).is_err());
+        assert!(parse_position("Base;NotStarted;White[1] | bm wQ", 1).is_err());
+    }
+}
@@ -0,0 +1,469 @@
+use crate::notation::{Result, UhpError};
+use crate::player::exit;
+use crate::{Board, Bug, HexSet, Rules, Turn};
+use minimax::Game;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+// Exact results for small endgames where both queens are nearly surrounded
+// and only a few bugs can still move. Positions reachable from a root are
+// enumerated until they stop qualifying, and retrograde analysis works back
+// from the surrounded queens: a position is won if some move reaches a
+// position lost for the opponent, and lost if every move reaches a position
+// won for them. What's left is a draw by repetition if it can't reach a
+// position outside the table, and unknown otherwise.
+//
+// Positions are keyed by zobrist hash, ignoring how they were reached. The
+// file format is a magic string, a little endian u64 count, and that many
+// entries sorted by hash:
+//
+//   u64 hash, u8 outcome (0 draw, 1 win, 2 loss), u16 plies to the end
+//
+// Outcomes are for the player to move, and plies count the fastest win or
+// slowest loss that stays within the table.
+
+// Version 2 keys include the bugs in hand.
+const MAGIC: &[u8] = b"NOKTB\x02";
+
+// Positions only qualify with at least this many neighbors around each queen.
+pub(crate) const MIN_QUEEN_NEIGHBORS: usize = 4;
+// ...and at most this many bugs with legal moves or still in hand.
+pub(crate) const MAX_MOBILE_BUGS: usize = 4;
+// Generation gives up past this many positions.
+pub const DEFAULT_MAX_POSITIONS: usize = 200_000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TablebaseOutcome {
+    Draw,
+    // Win or loss in this many plies.
+    Win(u16),
+    Loss(u16),
+}
+
+pub struct Tablebase {
+    // Sorted by hash.
+    entries: Vec<(u64, TablebaseOutcome)>,
+}
+
+// Whether a position is small enough for a tablebase: both queens are placed
+// with MIN_QUEEN_NEIGHBORS or more, and few bugs have anywhere to go.
+pub fn qualifies(board: &Board) -> bool {
+    // Tables are solved under the published rules, which house rules for
+    // moving tiles would change.
+    if board.variant.queen_moves_last || board.variant.max_stack_height.is_some() {
+        return false;
+    }
+    let queens_placed = (0..2).all(|color| board.remaining[color][Bug::Queen as usize] == 0);
+    if !queens_placed || board.queens_surrounded().iter().any(|&n| n < MIN_QUEEN_NEIGHBORS) {
+        return false;
+    }
+    // Both players' moves, since either can be to move later.
+    let mut board = board.clone();
+    let mut mobile = HexSet::new();
+    let mut moves = Vec::new();
+    for _ in 0..2 {
+        moves.clear();
+        Rules::generate_moves(&board, &mut moves);
+        for &m in moves.iter() {
+            if let Turn::Move(start, _) = m {
+                mobile.set(start);
+            }
+        }
+        board.apply(Turn::Pass);
+    }
+    let in_hand = board.remaining.iter().flatten().map(|&n| n as usize).sum::<usize>();
+    mobile.iter().count() + in_hand <= MAX_MOBILE_BUGS
+}
+
+// The result of a finished game for the player to move, judged only by the
+// queens, since repetitions depend on history.
+fn terminal(board: &Board) -> Option<TablebaseOutcome> {
+    let surrounded = board.queens_surrounded();
+    let me = board.to_move() as usize;
+    match (surrounded[me] == 6, surrounded[1 - me] == 6) {
+        (true, true) => Some(TablebaseOutcome::Draw),
+        (true, false) => Some(TablebaseOutcome::Loss(0)),
+        (false, true) => Some(TablebaseOutcome::Win(0)),
+        (false, false) => None,
+    }
+}
+
+// Works out every position's outcome from the finished games, given the
+// positions that weren't expanded and the positions each move leads to. None
+// is unknown.
+fn solve(
+    mut outcomes: Vec<Option<TablebaseOutcome>>, outside: &[bool], children: &[Vec<u32>],
+) -> Vec<Option<TablebaseOutcome>> {
+    // Retrograde analysis, breadth first from the finished games so that
+    // wins take the shortest route and losses the longest.
+    let mut parents = vec![Vec::new(); outcomes.len()];
+    for (parent, kids) in children.iter().enumerate() {
+        for &child in kids.iter() {
+            parents[child as usize].push(parent as u32);
+        }
+    }
+    let mut unresolved = children.iter().map(|kids| kids.len()).collect::<Vec<_>>();
+    let mut queue = (0..outcomes.len())
+        .filter(|&id| {
+            matches!(outcomes[id], Some(TablebaseOutcome::Win(_) | TablebaseOutcome::Loss(_)))
+        })
+        .collect::<VecDeque<_>>();
+    while let Some(child) = queue.pop_front() {
+        for &parent in parents[child].iter() {
+            let parent = parent as usize;
+            if outcomes[parent].is_some() {
+                continue;
+            }
+            match outcomes[child] {
+                Some(TablebaseOutcome::Loss(plies)) => {
+                    outcomes[parent] = Some(TablebaseOutcome::Win(plies + 1));
+                    queue.push_back(parent);
+                }
+                Some(TablebaseOutcome::Win(plies)) => {
+                    unresolved[parent] -= 1;
+                    if unresolved[parent] == 0 {
+                        outcomes[parent] = Some(TablebaseOutcome::Loss(plies + 1));
+                        queue.push_back(parent);
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    // Unresolved positions that can reach one outside the table might
+    // have any result. The rest can only go around in circles.
+    let mut unknown = outside.to_vec();
+    let mut queue = (0..outcomes.len()).filter(|&id| outside[id]).collect::<Vec<_>>();
+    while let Some(child) = queue.pop() {
+        for &parent in parents[child].iter() {
+            let parent = parent as usize;
+            if outcomes[parent].is_none() && !unknown[parent] {
+                unknown[parent] = true;
+                queue.push(parent);
+            }
+        }
+    }
+
+    outcomes
+        .into_iter()
+        .zip(unknown)
+        .map(|(outcome, unknown)| (!unknown).then(|| outcome.unwrap_or(TablebaseOutcome::Draw)))
+        .collect()
+}
+
+impl Tablebase {
+    // Solves the positions reachable from the board that qualify, or returns
+    // None if there are more than max_positions of them.
+    pub fn generate(board: &Board, max_positions: usize) -> Option<Self> {
+        Self::generate_until(board, max_positions, None)
+    }
+
+    // Like generate, but also gives up if it isn't done by the deadline.
+    pub fn generate_until(
+        board: &Board, max_positions: usize, deadline: Option<Instant>,
+    ) -> Option<Self> {
+        let past = || deadline.is_some_and(|end| Instant::now() >= end);
+        // Every position found so far, numbered in the order they were found.
+        #[derive(Default)]
+        struct Graph {
+            ids: HashMap<u64, u32>,
+            hashes: Vec<u64>,
+            outcomes: Vec<Option<TablebaseOutcome>>,
+            // Positions that don't qualify, which aren't expanded.
+            outside: Vec<bool>,
+            children: Vec<Vec<u32>>,
+        }
+        // A position being expanded, with the next of its moves to try.
+        struct Frame {
+            id: u32,
+            moves: Vec<Turn>,
+            next: usize,
+        }
+        impl Graph {
+            fn visit(&mut self, board: &Board) -> Frame {
+                let id = self.hashes.len() as u32;
+                let outcome = terminal(board);
+                let outside = outcome.is_none() && !qualifies(board);
+                let mut moves = Vec::new();
+                if outcome.is_none() && !outside {
+                    Rules::generate_moves(board, &mut moves);
+                }
+                self.ids.insert(Rules::zobrist_hash(board), id);
+                self.hashes.push(Rules::zobrist_hash(board));
+                self.outcomes.push(outcome);
+                self.outside.push(outside);
+                self.children.push(Vec::new());
+                Frame { id, moves, next: 0 }
+            }
+        }
+
+        // Depth first, applying and undoing moves on one board.
+        let mut board = board.clone();
+        let mut graph = Graph::default();
+        let mut stack = vec![graph.visit(&board)];
+        while let Some(top) = stack.last_mut() {
+            if top.next > 0 {
+                board.undo(top.moves[top.next - 1]);
+            }
+            let Some(&m) = top.moves.get(top.next) else {
+                stack.pop();
+                continue;
+            };
+            top.next += 1;
+            let parent = top.id as usize;
+            board.apply(m);
+            match graph.ids.get(&Rules::zobrist_hash(&board)) {
+                Some(&child) => graph.children[parent].push(child),
+                None => {
+                    if graph.hashes.len() >= max_positions {
+                        return None;
+                    }
+                    // Checking the clock costs more than a position.
+                    if graph.hashes.len() % 64 == 0 && past() {
+                        return None;
+                    }
+                    let frame = graph.visit(&board);
+                    graph.children[parent].push(frame.id);
+                    stack.push(frame);
+                }
+            }
+        }
+        if past() {
+            return None;
+        }
+        let outcomes = solve(graph.outcomes, &graph.outside, &graph.children);
+        let mut entries = graph
+            .hashes
+            .into_iter()
+            .zip(outcomes)
+            .filter_map(|(hash, outcome)| Some((hash, outcome?)))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(hash, _)| hash);
+        Some(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn probe(&self, board: &Board) -> Option<TablebaseOutcome> {
+        let hash = Rules::zobrist_hash(board);
+        self.entries
+            .binary_search_by_key(&hash, |&(hash, _)| hash)
+            .ok()
+            .map(|index| self.entries[index].1)
+    }
+
+    // A move to the best outcome in the table for the player to move: the
+    // fastest win, or a draw. Lost positions are left to the search.
+    pub fn best_move(&self, board: &Board) -> Option<Turn> {
+        let goal = match self.probe(board)? {
+            // Finished games have no move to make.
+            TablebaseOutcome::Win(plies) => TablebaseOutcome::Loss(plies.checked_sub(1)?),
+            TablebaseOutcome::Draw => TablebaseOutcome::Draw,
+            TablebaseOutcome::Loss(_) => return None,
+        };
+        let mut board = board.clone();
+        let mut moves = Vec::new();
+        Rules::generate_moves(&board, &mut moves);
+        moves.into_iter().find(|&m| {
+            board.apply(m);
+            let outcome = self.probe(&board);
+            board.undo(m);
+            outcome == Some(goal)
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut data = Vec::with_capacity(MAGIC.len() + 8 + self.entries.len() * 11);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for &(hash, outcome) in self.entries.iter() {
+            let (kind, plies) = match outcome {
+                TablebaseOutcome::Draw => (0u8, 0u16),
+                TablebaseOutcome::Win(plies) => (1, plies),
+                TablebaseOutcome::Loss(plies) => (2, plies),
+            };
+            data.extend_from_slice(&hash.to_le_bytes());
+            data.push(kind);
+            data.extend_from_slice(&plies.to_le_bytes());
+        }
+        std::fs::File::create(path)?.write_all(&data)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || {
+            UhpError::from(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid tablebase file",
+            ))
+        };
+        let mut data = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut data)?;
+        let rest = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        let (count, mut rest) = rest.split_first_chunk::<8>().ok_or_else(invalid)?;
+        let count = u64::from_le_bytes(*count) as usize;
+        if rest.len() != count.checked_mul(11).ok_or_else(invalid)? {
+            return Err(invalid());
+        }
+        let mut entries = Vec::with_capacity(count);
+        while let Some((entry, tail)) = rest.split_first_chunk::<11>() {
+            let hash = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            let plies = u16::from_le_bytes([entry[9], entry[10]]);
+            let outcome = match entry[8] {
+                0 => TablebaseOutcome::Draw,
+                1 => TablebaseOutcome::Win(plies),
+                2 => TablebaseOutcome::Loss(plies),
+                _ => return Err(invalid()),
+            };
+            entries.push((hash, outcome));
+            rest = tail;
+        }
+        if !entries.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return Err(invalid());
+        }
+        Ok(Self { entries })
+    }
+}
+
+pub fn tablebase_main(
+    game_string: &str, max_positions: usize, output: Option<String>, probe: Option<String>,
+) {
+    let board = Board::from_game_or_position_string(game_string)
+        .unwrap_or_else(|err| exit(format!("Could not parse game string: {}", err)));
+    let tablebase = match probe {
+        Some(path) => Tablebase::load(Path::new(&path))
+            .unwrap_or_else(|err| exit(format!("Could not load {}: {:?}", path, err))),
+        None => {
+            if !qualifies(&board) {
+                exit(format!(
+                    "Tablebases need both queens with {}+ neighbors and at most {} bugs that can move or are in hand",
+                    MIN_QUEEN_NEIGHBORS, MAX_MOBILE_BUGS
+                ));
+            }
+            let tablebase = Tablebase::generate(&board, max_positions).unwrap_or_else(|| {
+                exit(format!("More than {} positions reachable", max_positions))
+            });
+            println!("{} positions", tablebase.len());
+            if let Some(path) = output {
+                tablebase
+                    .save(Path::new(&path))
+                    .unwrap_or_else(|err| exit(format!("Could not write {}: {:?}", path, err)));
+            }
+            tablebase
+        }
+    };
+    match tablebase.probe(&board) {
+        Some(TablebaseOutcome::Draw) => println!("{:?} to move: draw", board.to_move()),
+        Some(TablebaseOutcome::Win(plies)) => {
+            println!("{:?} to move: win in {} plies", board.to_move(), plies)
+        }
+        Some(TablebaseOutcome::Loss(plies)) => {
+            println!("{:?} to move: loss in {} plies", board.to_move(), plies)
+        }
+        None => println!("{:?} to move: unknown", board.to_move()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TablebaseOutcome::*;
+
+    #[test]
+    fn test_solve() {
+        let outcomes = vec![None, Some(Loss(0)), None, None, None, None, None, None, None];
+        let outside = [false, false, false, false, false, false, true, false, false];
+        let children = vec![
+            vec![1, 2],
+            vec![],
+            // Going around in circles.
+            vec![3],
+            vec![2],
+            // Leads out of the table.
+            vec![6],
+            vec![0],
+            vec![],
+            vec![0, 2],
+            vec![0, 4],
+        ];
+        assert_eq!(
+            vec![
+                Some(Win(1)),
+                Some(Loss(0)),
+                Some(Draw),
+                Some(Draw),
+                None,
+                Some(Loss(2)),
+                None,
+                Some(Draw),
+                None
+            ],
+            solve(outcomes, &outside, &children)
+        );
+    }
+
+    #[test]
+    fn test_tablebase() {
+        assert!(!qualifies(&Board::default()));
+        // Both queens have four neighbors, and only the grasshoppers next to
+        // them and the ends of the two lines of bugs can move.
+        let board = Board::from_position_string(
+            "Base White[30] wQ@0,0 bQ@1,0 wG1@0,-1 bG1@1,1 wB1@-1,0 wS1@-2,0 wA1@-3,0 wA2@-4,0 \
+             wA3@-5,0 wS2@-6,0 bA1@-6,1 wB2@-6,2 wG2@-6,3 bB1@2,0 bS1@3,0 bA2@4,0 bA3@5,0 \
+             bS2@6,0 bB2@6,-1 wG3@6,-2 bG2@6,-3 bG3@6,-4",
+        )
+        .unwrap();
+        assert!(qualifies(&board));
+        // Every move frees up more bugs, so nothing is known.
+        let tablebase = Tablebase::generate(&board, 1000).unwrap();
+        assert_eq!(None, tablebase.probe(&board));
+
+        let tablebase = Tablebase {
+            entries: vec![(1, Loss(2)), (Rules::zobrist_hash(&board), Win(3)), (u64::MAX, Draw)],
+        };
+        let path = std::env::temp_dir().join(format!("nokamute-tablebase-{}", std::process::id()));
+        tablebase.save(&path).unwrap();
+        let loaded = Tablebase::load(&path).unwrap();
+        assert_eq!(tablebase.entries, loaded.entries);
+        assert_eq!(Some(Win(3)), loaded.probe(&board));
+        assert_eq!(None, loaded.probe(&Board::default()));
+
+        // The winning move is the one into a lost position.
+        let mut moves = Vec::new();
+        Rules::generate_moves(&board, &mut moves);
+        let mut child = board.clone();
+        let m = *moves.last().unwrap();
+        child.apply(m);
+        let mut entries =
+            vec![(Rules::zobrist_hash(&board), Win(1)), (Rules::zobrist_hash(&child), Loss(0))];
+        entries.sort_unstable_by_key(|&(hash, _)| hash);
+        let tablebase = Tablebase { entries };
+        assert_eq!(Some(m), tablebase.best_move(&board));
+        assert_eq!(None, tablebase.best_move(&child));
+        // A finished game has no move to make.
+        let tablebase = Tablebase { entries: vec![(Rules::zobrist_hash(&board), Win(0))] };
+        assert_eq!(None, tablebase.best_move(&board));
+
+        // Generation gives up at the deadline, however few positions it has.
+        assert!(Tablebase::generate_until(&board, 1000, Some(Instant::now())).is_none());
+        let later = Instant::now() + std::time::Duration::from_secs(60);
+        assert!(Tablebase::generate_until(&board, 1000, Some(later)).is_some());
+
+        // Truncated files are rejected.
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(Tablebase::load(&path).is_err());
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(Tablebase::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
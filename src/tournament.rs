@@ -0,0 +1,429 @@
+use crate::player::{
+    exit, face_off, get_player, get_player_in_dir, save_record, set_limits, Adjudication, Player,
+    PlayerConfig, Verbosity,
+};
+use crate::GameRecord;
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Instant;
+
+// Win/draw/loss record of one player against another.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Record {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // Fraction of points scored, counting draws as half a point.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // Estimated Elo difference and the half-width of its 95% confidence interval.
+    pub fn elo(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        let score = self.score();
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / n;
+        let margin = 1.96 * (variance / n).sqrt();
+        let low = score_to_elo(score - margin);
+        let high = score_to_elo(score + margin);
+        (score_to_elo(score), (high - low) / 2.0)
+    }
+
+    fn add(&mut self, winner: Option<usize>, me: usize) {
+        match winner {
+            None => self.draws += 1,
+            Some(p) if p == me => self.wins += 1,
+            Some(_) => self.losses += 1,
+        }
+    }
+
+    fn reversed(&self) -> Record {
+        Record { wins: self.losses, draws: self.draws, losses: self.wins }
+    }
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn fmt_elo(elo: f64) -> String {
+    if elo.is_finite() {
+        // Adding zero normalizes -0.
+        format!("{:+.0}", elo + 0.0)
+    } else if elo > 0.0 {
+        "+inf".to_owned()
+    } else {
+        "-inf".to_owned()
+    }
+}
+
+// Run a round robin where every pair of players plays the given number of
+// games, alternating colors, then print a summary table. With more than one
+// job, games run in parallel on that many threads, each with its own players
+// and UHP engines started in a scratch directory of their own. A report of
+// every game, with numbered moves and the players' evals, can be appended to
+// a file as they finish.
+#[allow(clippy::needless_range_loop, clippy::too_many_arguments)]
+pub fn tournament(
+    mut config: PlayerConfig, game_type: &str, names: &[String], games: usize, jobs: usize,
+    depth: Option<u8>, timeout: Option<String>, record: Option<String>, report: Option<String>,
+    adjudicate: Option<String>,
+) {
+    if names.len() < 2 {
+        exit("A tournament needs at least 2 players".to_string());
+    }
+    let adjudication = adjudicate.map_or_else(Adjudication::default, |input| {
+        Adjudication::parse(&input).unwrap_or_else(|| {
+            exit(format!(
+                "Could not parse --adjudicate={} (e.g. moves=300,resign=800/4,queen)",
+                input
+            ))
+        })
+    });
+    if jobs > 1 && config.num_threads.is_none() {
+        // The games already keep the cores busy.
+        config.num_threads = Some(1);
+    }
+    let labels = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            if names.iter().filter(|&other| other == name).count() > 1 {
+                format!("{}#{}", name, i + 1)
+            } else {
+                name.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+    let schedule = schedule(names.len(), games);
+    let limits = |player: &mut Box<dyn Player>| set_limits(player.as_mut(), depth, &timeout);
+
+    // records[i][j] is the record of player i against player j.
+    let mut records = vec![vec![Record::default(); names.len()]; names.len()];
+    let mut finish = |pairing: Pairing, winner: Option<usize>, mut game_record: GameRecord| {
+        game_record.white = labels[pairing.white].clone();
+        game_record.black = labels[pairing.black].clone();
+        if let Some(path) = &record {
+            save_record(path, &game_record);
+        }
+        if let Some(path) = &report {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", match_report(pairing.game, &game_record)))
+                .unwrap_or_else(|err| exit(format!("Could not write report to {}: {}", path, err)));
+        }
+        let (i, j) = (pairing.white.min(pairing.black), pairing.white.max(pairing.black));
+        records[i][j].add(winner, i);
+        match winner {
+            None => println!("Result: draw"),
+            Some(p) => println!("Result: {} won", labels[p]),
+        }
+    };
+    let announce = |pairing: Pairing| {
+        println!(
+            "Game {}: {} (White) vs {} (Black)",
+            pairing.game + 1,
+            labels[pairing.white],
+            labels[pairing.black]
+        );
+    };
+
+    if jobs <= 1 {
+        // Players keep their state from game to game.
+        let mut players =
+            names.iter().map(|name| Some(get_player(name, &config))).collect::<Vec<_>>();
+        for player in players.iter_mut().flatten() {
+            limits(player);
+        }
+        for &pairing in schedule.iter() {
+            let (white, black) = (pairing.white, pairing.black);
+            announce(pairing);
+            let mut pair: [Box<dyn Player>; 2] =
+                [players[white].take().unwrap(), players[black].take().unwrap()];
+            let (winner, game_record) =
+                face_off(game_type, &mut pair, None, adjudication, Verbosity::Summary);
+            let [p1, p2] = pair;
+            players[white] = Some(p1);
+            players[black] = Some(p2);
+            finish(pairing, winner.map(|p| [white, black][p]), game_record);
+        }
+    } else {
+        let scratch =
+            std::env::temp_dir().join(format!("nokamute-tournament-{}", std::process::id()));
+        let next = AtomicUsize::new(0);
+        let (sender, results) = channel();
+        let progress = Progress::new(schedule.len());
+        thread::scope(|scope| {
+            for job in 0..jobs.min(schedule.len()) {
+                let (sender, next, schedule, scratch) =
+                    (sender.clone(), &next, &schedule, &scratch);
+                let (config, progress) = (&config, &progress);
+                scope.spawn(move || {
+                    let dir = scratch.join(format!("job-{}", job + 1));
+                    std::fs::create_dir_all(&dir).unwrap_or_else(|err| {
+                        exit(format!("Could not create {}: {}", dir.display(), err))
+                    });
+                    // Each job's own players, started on first use and kept
+                    // from game to game, so that UHP engines start only once.
+                    let mut pool: Vec<Option<Box<dyn Player>>> =
+                        names.iter().map(|_| None).collect();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&pairing) = schedule.get(index) else {
+                            break;
+                        };
+                        progress.started();
+                        let mut pair = [pairing.white, pairing.black].map(|p| {
+                            pool[p].take().unwrap_or_else(|| {
+                                let mut player = get_player_in_dir(&names[p], config, Some(&dir));
+                                limits(&mut player);
+                                player
+                            })
+                        });
+                        let (winner, game_record) =
+                            face_off(game_type, &mut pair, None, adjudication, Verbosity::Quiet);
+                        let [p1, p2] = pair;
+                        pool[pairing.white] = Some(p1);
+                        pool[pairing.black] = Some(p2);
+                        let winner = winner.map(|p| [pairing.white, pairing.black][p]);
+                        if sender.send((pairing, winner, game_record)).is_err() {
+                            break;
+                        }
+                    }
+                    // Shut the engines down before cleaning up after them.
+                    drop(pool);
+                    std::fs::remove_dir_all(&dir).ok();
+                });
+            }
+            drop(sender);
+            for (pairing, winner, game_record) in results {
+                progress.clear();
+                announce(pairing);
+                println!("Game log: {}", game_record.moves.join(";"));
+                finish(pairing, winner, game_record);
+                progress.finished(winner);
+            }
+        });
+        progress.clear();
+        std::fs::remove_dir_all(&scratch).ok();
+    }
+    for i in 0..names.len() {
+        for j in i + 1..names.len() {
+            records[j][i] = records[i][j].reversed();
+        }
+    }
+    print_summary(&labels, &records);
+}
+
+// A game for the --report file: who played, how it ended, and the moves
+// numbered by turn, with their evals for White.
+fn match_report(game: usize, record: &GameRecord) -> String {
+    let log = match record.to_board() {
+        Ok(board) => board.pretty_game_log(&record.evals),
+        Err(_) => record.moves.join(";"),
+    };
+    format!(
+        "Game {}: {} (White) vs {} (Black)\nResult: {}\n{}\n",
+        game + 1,
+        record.white,
+        record.black,
+        record.result,
+        log
+    )
+}
+
+// One game of a round robin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Pairing {
+    // Which game this is between the two players.
+    game: usize,
+    white: usize,
+    black: usize,
+}
+
+// Every game of the round robin, pair by pair, alternating who plays white.
+fn schedule(num_players: usize, games: usize) -> Vec<Pairing> {
+    let mut schedule = Vec::new();
+    for i in 0..num_players {
+        for j in i + 1..num_players {
+            for game in 0..games {
+                let (white, black) = if game % 2 == 0 { (i, j) } else { (j, i) };
+                schedule.push(Pairing { game, white, black });
+            }
+        }
+    }
+    schedule
+}
+
+// A status line on stderr for games running in parallel, when it's a
+// terminal.
+struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    running: AtomicUsize,
+    draws: AtomicUsize,
+    start: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            draws: AtomicUsize::new(0),
+            start: Instant::now(),
+            enabled: io::stderr().is_terminal(),
+        }
+    }
+
+    fn started(&self) {
+        self.running.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Counts a finished game and redraws the line with the results so far.
+    fn finished(&self, winner: Option<usize>) {
+        self.running.fetch_sub(1, Ordering::Relaxed);
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if winner.is_none() {
+            self.draws.fetch_add(1, Ordering::Relaxed);
+        }
+        if !self.enabled {
+            return;
+        }
+        let draws = self.draws.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let left = elapsed / done as f64 * (self.total - done) as f64;
+        eprint!(
+            "\r{}/{} games, {} running, {} decisive, {} drawn, {:.0}s elapsed, ~{:.0}s left",
+            done,
+            self.total,
+            self.running.load(Ordering::Relaxed),
+            done - draws,
+            draws,
+            elapsed,
+            left
+        );
+        io::stderr().flush().ok();
+    }
+
+    // Erases the line so that other output can take its place.
+    fn clear(&self) {
+        if self.enabled {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+fn print_summary(labels: &[String], records: &[Vec<Record>]) {
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0).max(6);
+    println!();
+    println!(
+        "{:width$}  {:>5} {:>5} {:>5} {:>6} {:>6}",
+        "player", "games", "wins", "draws", "losses", "score"
+    );
+    for (i, label) in labels.iter().enumerate() {
+        let mut total = Record::default();
+        for record in records[i].iter() {
+            total.wins += record.wins;
+            total.draws += record.draws;
+            total.losses += record.losses;
+        }
+        println!(
+            "{:width$}  {:>5} {:>5} {:>5} {:>6} {:>5.1}%",
+            label,
+            total.games(),
+            total.wins,
+            total.draws,
+            total.losses,
+            100.0 * total.score()
+        );
+    }
+    println!();
+    for i in 0..labels.len() {
+        for j in i + 1..labels.len() {
+            let record = records[i][j];
+            if record.games() == 0 {
+                continue;
+            }
+            let (elo, margin) = record.elo();
+            let margin =
+                if margin.is_finite() { format!("{:.0}", margin) } else { "inf".to_owned() };
+            println!(
+                "{} vs {}: +{} ={} -{}  Elo {} +/- {}",
+                labels[i],
+                labels[j],
+                record.wins,
+                record.draws,
+                record.losses,
+                fmt_elo(elo),
+                margin
+            );
+        }
+    }
+}
+
+#[test]
+fn test_elo() {
+    let even = Record { wins: 10, draws: 0, losses: 10 };
+    let (elo, margin) = even.elo();
+    assert!(elo.abs() < 1e-9);
+    assert!(margin > 100.0 && margin < 200.0);
+
+    // 75% score is about +191 Elo.
+    let (elo, _) = Record { wins: 30, draws: 0, losses: 10 }.elo();
+    assert_eq!(191, elo.round() as i32);
+    let (elo, _) = Record { wins: 10, draws: 0, losses: 30 }.elo();
+    assert_eq!(-191, elo.round() as i32);
+
+    // More games shrink the error bars.
+    let (_, more_margin) = Record { wins: 100, draws: 0, losses: 100 }.elo();
+    assert!(more_margin < margin);
+
+    let (elo, _) = Record { wins: 4, draws: 0, losses: 0 }.elo();
+    assert_eq!("+inf", fmt_elo(elo));
+    assert_eq!(
+        Record { wins: 1, draws: 2, losses: 3 },
+        Record { wins: 3, draws: 2, losses: 1 }.reversed()
+    );
+}
+
+#[test]
+fn test_schedule() {
+    let schedule = schedule(3, 2);
+    assert_eq!(6, schedule.len());
+    assert_eq!(Pairing { game: 0, white: 0, black: 1 }, schedule[0]);
+    assert_eq!(Pairing { game: 1, white: 1, black: 0 }, schedule[1]);
+    assert_eq!(Pairing { game: 1, white: 2, black: 1 }, schedule[5]);
+}
+
+#[test]
+fn test_match_report() {
+    let board =
+        crate::Board::from_game_string("Base;InProgress;White[3];wS1;bG1 wS1-;wQ -wS1;bQ bG1-")
+            .unwrap();
+    let mut record = GameRecord::from_board(&board, "a", "b#2");
+    record.result = "WhiteWins".to_owned();
+    record.evals = vec![None, Some(-5), Some(40), None];
+    assert_eq!(
+        "Game 3: a (White) vs b#2 (Black)\nResult: WhiteWins\n\
+         1. wS1            bG1 wS1- (-5)\n2. wQ -wS1 (+40)  bQ bG1-\n",
+        match_report(2, &record)
+    );
+}
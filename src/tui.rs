@@ -0,0 +1,358 @@
+extern crate termcolor;
+
+use crate::notation::{Result, UhpError};
+use crate::player::Player;
+use crate::{Board, Bug, Color, Hex, Rules, Turn, ROW_SIZE};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use termcolor::{ColorSpec, WriteColor};
+
+// A full screen interface for a human player: move a cursor over the board
+// with the arrow keys, pick bugs from the hand by letter, and see where the
+// selected bug can go.
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Tab,
+    Cancel,
+    Char(char),
+}
+
+// Reads one key press from a terminal in raw mode, or None at the end of input.
+fn read_key(input: &mut impl Read) -> Option<Key> {
+    let mut byte = [0u8];
+    let mut next = |input: &mut dyn Read| input.read_exact(&mut byte).ok().map(|_| byte[0]);
+    loop {
+        let key = match next(input)? {
+            b'\r' | b'\n' | b' ' => Key::Enter,
+            b'\t' => Key::Tab,
+            // Ctrl-D, which raw mode doesn't turn into end of input.
+            0x04 => return None,
+            0x7f | 0x08 => Key::Cancel,
+            0x1b => {
+                // Arrow keys are ESC [ A through D. A lone escape cancels.
+                if next(input)? != b'[' {
+                    Key::Cancel
+                } else {
+                    match next(input)? {
+                        b'A' => Key::Up,
+                        b'B' => Key::Down,
+                        b'C' => Key::Right,
+                        b'D' => Key::Left,
+                        _ => continue,
+                    }
+                }
+            }
+            byte if byte.is_ascii_graphic() => Key::Char(byte as char),
+            _ => continue,
+        };
+        return Some(key);
+    }
+}
+
+// Puts the terminal in raw mode until dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> io::Result<Self> {
+        let stty = |args: &[&str]| -> io::Result<String> {
+            let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+            if !output.status.success() {
+                return Err(io::Error::other("stdin is not a terminal"));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        };
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        // Hide the cursor; the board draws its own.
+        print!("\x1b[?25l");
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h");
+        io::stdout().flush().ok();
+        Command::new("stty").arg(&self.saved).stdin(Stdio::inherit()).status().ok();
+    }
+}
+
+// Choosing one turn on one board.
+struct TurnPicker<'a> {
+    board: &'a Board,
+    moves: &'a [Turn],
+    // Visible rows and columns of the board grid, in display order.
+    rows: Vec<Hex>,
+    cols: Vec<Hex>,
+    cursor: (usize, usize),
+    // Bug on the board chosen to move.
+    selected: Option<Hex>,
+    // Bug in hand chosen to place.
+    hand: Option<Bug>,
+    message: String,
+}
+
+impl<'a> TurnPicker<'a> {
+    fn new(board: &'a Board, moves: &'a [Turn]) -> Self {
+        let (startr, endr, startc, endc) = board.bounding_box();
+        let span = |start: Hex, end: Hex| {
+            let mut hexes = vec![start];
+            while *hexes.last().unwrap() != end {
+                hexes.push((hexes.last().unwrap() + 1) % ROW_SIZE);
+            }
+            hexes
+        };
+        let rows = span(startr, endr);
+        let cols = span(startc, endc);
+        let cursor = (rows.len() / 2, cols.len() / 2);
+        let mut picker = Self {
+            board,
+            moves,
+            rows,
+            cols,
+            cursor,
+            selected: None,
+            hand: None,
+            message: String::new(),
+        };
+        // With nothing on the board to move, start with a bug in hand.
+        if !moves.iter().any(|m| matches!(m, Turn::Move(..))) {
+            picker.hand = picker.hand_bugs().first().copied();
+        }
+        picker
+    }
+
+    fn cursor_hex(&self) -> Hex {
+        self.cols[self.cursor.1] + self.rows[self.cursor.0] * ROW_SIZE
+    }
+
+    // Bugs that can be placed this turn, in a fixed order.
+    fn hand_bugs(&self) -> Vec<Bug> {
+        Bug::iter_all()
+            .filter(|&bug| self.moves.iter().any(|m| matches!(m, Turn::Place(_, b) if *b == bug)))
+            .collect()
+    }
+
+    // Where the selected bug can move or the bug in hand can be placed.
+    fn destinations(&self) -> Vec<Hex> {
+        self.moves
+            .iter()
+            .filter_map(|&m| match (m, self.selected, self.hand) {
+                (Turn::Move(start, end), Some(selected), _) if start == selected => Some(end),
+                (Turn::Place(hex, bug), None, Some(hand)) if bug == hand => Some(hex),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn handle(&mut self, key: Key) -> Option<Turn> {
+        self.message.clear();
+        let (r, c) = self.cursor;
+        match key {
+            Key::Up => self.cursor.0 = r.saturating_sub(1),
+            Key::Down => self.cursor.0 = (r + 1).min(self.rows.len() - 1),
+            Key::Left => self.cursor.1 = c.saturating_sub(1),
+            Key::Right => self.cursor.1 = (c + 1).min(self.cols.len() - 1),
+            Key::Cancel => {
+                self.selected = None;
+                self.hand = None;
+            }
+            Key::Tab => {
+                let bugs = self.hand_bugs();
+                let next = self.hand.and_then(|hand| bugs.iter().position(|&bug| bug == hand));
+                self.hand = bugs.get(next.map_or(0, |i| (i + 1) % bugs.len())).copied();
+                self.selected = None;
+                if self.hand.is_none() {
+                    self.message = "No bugs can be placed.".to_owned();
+                }
+            }
+            Key::Char(c) => match Bug::from_char(c) {
+                Some(bug) if self.hand_bugs().contains(&bug) => {
+                    self.hand = Some(bug);
+                    self.selected = None;
+                }
+                Some(bug) => self.message = format!("Can't place a {} now.", bug.name()),
+                None => self.message = format!("Unknown key {}.", c),
+            },
+            Key::Enter => {
+                let hex = self.cursor_hex();
+                if self.destinations().contains(&hex) {
+                    return Some(match self.selected {
+                        Some(start) => Turn::Move(start, hex),
+                        None => Turn::Place(hex, self.hand.unwrap()),
+                    });
+                }
+                if self.moves.iter().any(|m| matches!(m, Turn::Move(start, _) if *start == hex)) {
+                    self.selected = Some(hex);
+                    self.hand = None;
+                } else {
+                    self.message = "That bug can't move, and that isn't a destination.".to_owned();
+                }
+            }
+        }
+        None
+    }
+
+    fn render(&self, buf: &mut termcolor::Buffer) -> io::Result<()> {
+        let free_space = "\u{ff0e}";
+        let destinations = self.destinations();
+        let last = self.rows.len() - 1;
+        for (i, &r) in self.rows.iter().enumerate() {
+            // Stagger rows the same way as the plain board printout.
+            let indent = last - i;
+            write!(
+                buf,
+                "{}{}",
+                if indent % 2 == 1 { " " } else { "" },
+                free_space.repeat(indent / 2)
+            )?;
+            for (j, &c) in self.cols.iter().enumerate() {
+                let hex = c + r * ROW_SIZE;
+                let node = self.board.node(hex);
+                let bg = if (i, j) == self.cursor {
+                    Some(termcolor::Color::Yellow)
+                } else if Some(hex) == self.selected {
+                    Some(termcolor::Color::Blue)
+                } else if destinations.contains(&hex) {
+                    Some(termcolor::Color::Green)
+                } else if node.occupied() && node.color() == Color::White {
+                    Some(termcolor::Color::White)
+                } else {
+                    None
+                };
+                if bg.is_some() {
+                    buf.set_color(ColorSpec::new().set_bg(bg))?;
+                }
+                if node.occupied() {
+                    write!(buf, "{}", node.bug().codepoint())?;
+                } else {
+                    write!(buf, "{}", free_space)?;
+                }
+                if bg.is_some() {
+                    buf.reset()?;
+                }
+            }
+            writeln!(buf)?;
+        }
+        writeln!(buf)?;
+        write!(buf, "{:?} to move. Hand:", self.board.to_move())?;
+        for bug in self.hand_bugs() {
+            if Some(bug) == self.hand {
+                buf.set_color(ColorSpec::new().set_bg(Some(termcolor::Color::Green)))?;
+            }
+            write!(buf, " {}{}", bug.codepoint(), bug.name().chars().next().unwrap())?;
+            if Some(bug) == self.hand {
+                buf.reset()?;
+            }
+        }
+        writeln!(buf)?;
+        writeln!(
+            buf,
+            "arrows: move cursor  enter: select or play  letter/tab: bug from hand  backspace: cancel  ctrl-d: quit"
+        )?;
+        writeln!(buf, "{}", self.message)
+    }
+}
+
+pub(crate) struct TuiPlayer {
+    board: Board,
+}
+
+impl TuiPlayer {
+    pub(crate) fn new() -> Self {
+        Self { board: Board::default() }
+    }
+}
+
+impl Player for TuiPlayer {
+    fn name(&self) -> String {
+        "human".to_owned()
+    }
+
+    fn new_game(&mut self, game_type: &str) -> Result<()> {
+        self.board = Board::from_game_type(game_type)?;
+        Ok(())
+    }
+
+    fn play_move(&mut self, turn: Turn) -> Result<()> {
+        self.board.apply(turn);
+        Ok(())
+    }
+
+    fn undo_move(&mut self, turn: Turn) {
+        self.board.undo(turn);
+    }
+
+    fn generate_move(&mut self) -> Result<Turn> {
+        let mut moves = Vec::new();
+        Rules::generate_moves(&self.board, &mut moves);
+        if moves[0] == Turn::Pass {
+            return Ok(Turn::Pass);
+        }
+        let raw = RawMode::enter().map_err(|err| UhpError::EngineError(err.to_string()))?;
+        let mut picker = TurnPicker::new(&self.board, &moves);
+        let mut stdin = io::stdin().lock();
+        loop {
+            let mut buf = termcolor::Buffer::ansi();
+            // Clear the screen and draw from the top.
+            write!(buf, "\x1b[2J\x1b[H")?;
+            picker.render(&mut buf)?;
+            io::stdout().write_all(buf.as_slice())?;
+            io::stdout().flush()?;
+            let Some(key) = read_key(&mut stdin) else {
+                drop(raw);
+                println!();
+                std::process::exit(0);
+            };
+            if let Some(turn) = picker.handle(key) {
+                return Ok(turn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turn_picker() {
+        let keys = |bytes: &[u8]| {
+            let mut input = bytes;
+            std::iter::from_fn(|| read_key(&mut input)).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![Key::Up, Key::Left, Key::Enter, Key::Cancel, Key::Char('a'), Key::Tab],
+            keys(b"\x1b[A\x1b[D\r\x1bxa\t")
+        );
+
+        let board = Board::from_game_string("Base;InProgress;White[2];wA1;bA1 wA1-").unwrap();
+        let mut moves = Vec::new();
+        Rules::generate_moves(&board, &mut moves);
+        let mut picker = TurnPicker::new(&board, &moves);
+        // White can't move yet, so starts with a bug in hand.
+        assert_eq!(Some(Bug::Queen), picker.hand);
+        assert!(picker.handle(Key::Char('s')).is_none());
+        assert_eq!(Some(Bug::Spider), picker.hand);
+        // Walk the cursor to a legal placement and take it.
+        let target = picker.destinations()[0];
+        let mut turn = None;
+        for r in 0..picker.rows.len() {
+            for c in 0..picker.cols.len() {
+                picker.cursor = (r, c);
+                if picker.cursor_hex() == target {
+                    turn = picker.handle(Key::Enter);
+                }
+            }
+        }
+        assert_eq!(Some(Turn::Place(target, Bug::Spider)), turn);
+    }
+}
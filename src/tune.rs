@@ -0,0 +1,166 @@
+use crate::notation::{Result, UhpError};
+use crate::{BasicEvaluator, Board, Color, Rules};
+use minimax::{Evaluation, Evaluator};
+use std::fs;
+
+// Texel-style tuning: fit evaluation weights so that a sigmoid of the static
+// evaluation predicts the final result of the game each position came from.
+
+// A position from a finished game, labeled with the result from white's
+// perspective: 1 for a win, 0.5 for a draw, 0 for a loss.
+pub(crate) struct LabeledPosition {
+    board: Board,
+    result: f64,
+}
+
+// Skip the opening placements, which say little about the result.
+const SKIP_TURNS: usize = 6;
+
+// Extract labeled positions from one UHP GameString. Unfinished games are ignored.
+pub(crate) fn labeled_positions(game_string: &str) -> Result<Vec<LabeledPosition>> {
+    let err = || UhpError::InvalidGameString(game_string.to_owned());
+    let result = match game_string.split(';').nth(1).ok_or_else(err)? {
+        "WhiteWins" => 1.0,
+        "BlackWins" => 0.0,
+        "Draw" => 0.5,
+        _ => return Ok(Vec::new()),
+    };
+    let mut board = Board::from_game_string(game_string)?;
+    let turns = board.turn_history.clone();
+    let mut positions = Vec::new();
+    for &turn in turns.iter().rev() {
+        board.undo(turn);
+        if board.turn_history.len() >= SKIP_TURNS {
+            positions.push(LabeledPosition { board: board.clone(), result });
+        }
+    }
+    Ok(positions)
+}
+
+// Load every finished game in a file of newline separated GameStrings.
+pub(crate) fn load_corpus(path: &str) -> Result<Vec<LabeledPosition>> {
+    let mut positions = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        positions.extend(labeled_positions(line)?);
+    }
+    Ok(positions)
+}
+
+fn white_eval(eval: &BasicEvaluator, board: &Board) -> Evaluation {
+    debug_assert!(Rules::get_winner(board).is_none());
+    let score = eval.evaluate(board);
+    if board.to_move() == Color::White {
+        score
+    } else {
+        -score
+    }
+}
+
+fn sigmoid(k: f64, score: Evaluation) -> f64 {
+    1.0 / (1.0 + (-k * score as f64).exp())
+}
+
+// Mean squared error between predicted and actual results.
+pub(crate) fn error(eval: &BasicEvaluator, positions: &[LabeledPosition], k: f64) -> f64 {
+    let total: f64 = positions
+        .iter()
+        .map(|pos| (pos.result - sigmoid(k, white_eval(eval, &pos.board))).powi(2))
+        .sum();
+    total / positions.len() as f64
+}
+
+// Find the sigmoid scaling that best fits the untuned evaluator, so that
+// tuning changes the weights rather than just their scale.
+fn fit_scale(eval: &BasicEvaluator, positions: &[LabeledPosition]) -> f64 {
+    let mut best = (f64::MAX, 0.0);
+    for i in 1..=200 {
+        let k = i as f64 * 0.0005;
+        let err = error(eval, positions, k);
+        if err < best.0 {
+            best = (err, k);
+        }
+    }
+    best.1
+}
+
+// Coordinate descent over all weights, one step at a time.
+pub(crate) fn tune(
+    mut eval: BasicEvaluator, positions: &[LabeledPosition], max_iterations: usize,
+) -> BasicEvaluator {
+    let k = fit_scale(&eval, positions);
+    let mut best_error = error(&eval, positions, k);
+    println!("scale={} initial error={:.6}", k, best_error);
+    for iteration in 0..max_iterations {
+        let mut improved = false;
+        for (name, value) in eval.weights() {
+            for delta in [1, -1] {
+                let mut candidate = eval;
+                candidate.set_weight(&name, value + delta);
+                let candidate_error = error(&candidate, positions, k);
+                if candidate_error < best_error {
+                    eval = candidate;
+                    best_error = candidate_error;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        println!("iteration {} error={:.6}", iteration + 1, best_error);
+        if !improved {
+            break;
+        }
+    }
+    eval
+}
+
+pub fn tune_main(corpus: &str, max_iterations: usize) {
+    let positions = match load_corpus(corpus) {
+        Ok(positions) => positions,
+        Err(err) => {
+            eprintln!("Could not load corpus: {}", err);
+            return;
+        }
+    };
+    if positions.is_empty() {
+        eprintln!("No positions from finished games found in {}", corpus);
+        return;
+    }
+    println!("Tuning on {} positions", positions.len());
+    let eval = tune(BasicEvaluator::default(), &positions, max_iterations);
+    let weights = eval
+        .weights()
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+    println!("{}", weights.join(","));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // White surrounds its own queen.
+    const GAME: &str = r"Base;BlackWins;Black[5];wS1;bG1 wS1\;wQ wS1/;bQ bG1-;wS2 wQ-;bQ wQ\;wA1 \wQ;bG1 /wA1;wA2 \wS2";
+
+    #[test]
+    fn test_labeled_positions() {
+        let positions = labeled_positions(GAME).unwrap();
+        assert_eq!(3, positions.len());
+        assert!(positions.iter().all(|pos| pos.result == 0.0));
+        assert!(labeled_positions("Base;InProgress;White[1]").unwrap().is_empty());
+        assert!(labeled_positions("Base;WhiteWins;White[2];wQ;bQ bQ").is_err());
+    }
+
+    #[test]
+    fn test_tune_reduces_error() {
+        let positions = labeled_positions(GAME).unwrap();
+        let eval = BasicEvaluator::default();
+        let k = fit_scale(&eval, &positions);
+        let tuned = tune(eval, &positions, 3);
+        assert!(error(&tuned, &positions, k) <= error(&eval, &positions, k));
+    }
+}
@@ -1,42 +1,150 @@
 extern crate minimax;
 
 use crate::notation::{Result, UhpError};
+use crate::player::Decision;
 use crate::{Board, Color, Player, Turn};
 
 use minimax::Winner;
+use std::collections::VecDeque;
 use std::io::{BufRead, BufReader, Write};
 use std::ops::Drop;
-use std::path::Path;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How many lines of the engine's stderr to keep for error reports.
+const STDERR_LINES: usize = 20;
+// The default time allowed for a depth limited search.
+pub(crate) const DEPTH_TIMEOUT: Duration = Duration::from_secs(600);
 
 pub(crate) struct UhpClient {
     proc: Child,
     input: ChildStdin,
-    output: BufReader<ChildStdout>,
+    // Lines of stdout, read on another thread so that waiting can time out.
+    output: Receiver<String>,
+    // The last lines of stderr.
+    stderr: Arc<Mutex<VecDeque<String>>>,
+    stderr_reader: thread::JoinHandle<()>,
     board: Board,
+    // How long to wait for a response, beyond any requested search time.
+    timeout: Duration,
+    // How long a depth limited search may take, as it has no search time.
+    depth_timeout: Duration,
+    // Set after a timeout or crash, when the engine can no longer be trusted.
+    failed: bool,
 }
 
 impl UhpClient {
     pub(crate) fn new(cmd_args: &[String]) -> Result<UhpClient> {
-        let mut proc = Command::new(&cmd_args[0])
+        Self::new_with_timeout(cmd_args, Duration::from_secs(30))
+    }
+
+    pub(crate) fn new_with_timeout(cmd_args: &[String], timeout: Duration) -> Result<UhpClient> {
+        Self::new_in_dir(cmd_args, None, timeout)
+    }
+
+    // Starts the engine in the given working directory, so that engines that
+    // write logs or caches next to themselves don't collide when several run
+    // at once.
+    pub(crate) fn new_in_dir(
+        cmd_args: &[String], dir: Option<&Path>, timeout: Duration,
+    ) -> Result<UhpClient> {
+        let mut command = Command::new(&cmd_args[0]);
+        if let Some(dir) = dir {
+            command.current_dir(dir);
+        }
+        let mut proc = command
             .args(&cmd_args[1..])
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()?;
         let input = proc.stdin.take().unwrap();
-        let output = BufReader::new(proc.stdout.take().unwrap());
-        let mut client = UhpClient { proc, input, output, board: Board::new_core_set() };
+
+        let (sender, output) = channel();
+        let stdout = BufReader::new(proc.stdout.take().unwrap());
+        thread::spawn(move || {
+            // Stops when the engine exits or the client is dropped.
+            for line in stdout.lines().map_while(|line| line.ok()) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr = Arc::new(Mutex::new(VecDeque::new()));
+        let stderr_lines = stderr.clone();
+        let stderr_pipe = BufReader::new(proc.stderr.take().unwrap());
+        let stderr_reader = thread::spawn(move || {
+            for line in stderr_pipe.lines().map_while(|line| line.ok()) {
+                let mut lines = stderr_lines.lock().unwrap();
+                if lines.len() == STDERR_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        });
+
+        let mut client = UhpClient {
+            proc,
+            input,
+            output,
+            stderr,
+            stderr_reader,
+            board: Board::new_core_set(),
+            timeout,
+            depth_timeout: DEPTH_TIMEOUT,
+            failed: false,
+        };
         // Eat the first output
-        client.consume_output()?;
+        client.consume_output(Duration::ZERO)?;
         Ok(client)
     }
 
-    fn consume_output(&mut self) -> Result<Vec<String>> {
+    pub(crate) fn set_depth_timeout(&mut self, depth_timeout: Duration) {
+        self.depth_timeout = depth_timeout;
+    }
+
+    // Whether the engine hung or crashed, and needs to be restarted.
+    pub(crate) fn failed(&self) -> bool {
+        self.failed
+    }
+
+    fn stderr_tail(&self) -> String {
+        // Give the reader a moment to catch up with an engine that just exited.
+        let deadline = Instant::now() + Duration::from_millis(100);
+        while !self.stderr_reader.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.stderr.lock().unwrap().iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+
+    fn consume_output(&mut self, search_time: Duration) -> Result<Vec<String>> {
+        let deadline = Instant::now() + search_time + self.timeout;
         let mut out = Vec::new();
         loop {
-            let mut line = String::new();
-            self.output.read_line(&mut line)?;
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let line = match self.output.recv_timeout(wait) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.failed = true;
+                    return Err(UhpError::EngineTimeout(format!(
+                        "no response after {:.1}s",
+                        (search_time + self.timeout).as_secs_f64()
+                    )));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.failed = true;
+                    let status = self.proc.wait()?;
+                    return Err(UhpError::EngineCrashed(format!(
+                        "{}; stderr: {}",
+                        status,
+                        self.stderr_tail()
+                    )));
+                }
+            };
             if line.trim() == "ok" {
                 return Ok(out);
             }
@@ -47,18 +155,59 @@ impl UhpClient {
         }
     }
 
-    fn command(&mut self, command: &str) -> Result<Vec<String>> {
+    fn command_with_search(&mut self, command: &str, search_time: Duration) -> Result<Vec<String>> {
         let mut line = command.to_owned();
         line.push('\n');
-        self.input.write_all(line.as_bytes())?;
-        self.consume_output()
+        if let Err(err) = self.input.write_all(line.as_bytes()) {
+            // Most likely a broken pipe, so report how the engine exited.
+            self.failed = true;
+            return match self.proc.try_wait()? {
+                Some(status) => Err(UhpError::EngineCrashed(format!(
+                    "{}; stderr: {}",
+                    status,
+                    self.stderr_tail()
+                ))),
+                None => Err(err.into()),
+            };
+        }
+        self.consume_output(search_time)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        self.command_with_search(command, Duration::ZERO)
+    }
+
+    // Whether an engine being reused for another game still answers in step:
+    // nothing left over from earlier commands, and a reply to info.
+    pub(crate) fn healthy(&mut self) -> bool {
+        if self.failed || self.output.try_recv().is_ok() {
+            self.failed = true;
+            return false;
+        }
+        match self.command("info") {
+            Ok(out) => out.first().is_some_and(|line| line.starts_with("id ")),
+            Err(_) => false,
+        }
     }
 
     pub(crate) fn new_game(&mut self, game_type: &str) -> Result<()> {
         let mut command = "newgame ".to_owned();
         command.push_str(game_type);
-        self.command(&command)?;
-        self.board = Board::from_game_string(game_type)?;
+        let out = self.command(&command)?;
+        let board = Board::from_game_string(game_type)?;
+        // The engine echoes the new game; anything else means its replies are
+        // out of step with our commands.
+        let reply = out.first().map(|line| Board::from_game_string(line));
+        if !matches!(reply, Some(Ok(ref echoed)) if echoed.game_string() == board.game_string()) {
+            self.failed = true;
+            return Err(UhpError::EngineError(format!(
+                "expected {} after {}, got: {}",
+                board.game_string(),
+                command,
+                out.join("\n")
+            )));
+        }
+        self.board = board;
         Ok(())
     }
 
@@ -93,7 +242,10 @@ impl UhpClient {
     }
 
     pub(crate) fn raw_generate_moves(&mut self) -> Result<String> {
-        Ok(self.command("validmoves")?[0].clone())
+        self.command("validmoves")?
+            .first()
+            .cloned()
+            .ok_or_else(|| UhpError::EngineError("empty validmoves".to_string()))
     }
 
     // Ask the engine for the next possible moves.
@@ -109,46 +261,88 @@ impl UhpClient {
         self.board.game_log()
     }
 
-    pub(crate) fn best_move(&mut self, timeout: Duration) -> Result<Turn> {
+    pub(crate) fn best_move(&mut self, timeout: Duration) -> Result<Decision> {
         let secs = timeout.as_secs();
         let h = secs / 3600;
         let m = secs % 3600 / 60;
         let s = secs % 60;
-        let move_string =
-            self.command(&format!("bestmove time {:02}:{:02}:{:02}", h, m, s))?.pop().unwrap();
-        self.board.from_move_string(&move_string)
+        let move_string = self
+            .command_with_search(&format!("bestmove time {:02}:{:02}:{:02}", h, m, s), timeout)?
+            .pop()
+            .ok_or_else(|| UhpError::EngineError("empty bestmove".to_string()))?;
+        self.decision(&move_string)
     }
 
-    pub(crate) fn best_move_depth(&mut self, depth: u8) -> Result<Turn> {
-        let move_string = self.command(&format!("bestmove depth {}", depth))?.pop().unwrap();
-        self.board.from_move_string(&move_string)
+    pub(crate) fn best_move_depth(&mut self, depth: u8) -> Result<Decision> {
+        // Depth limited searches can take much longer than other commands,
+        // so they get their own deadline, to still catch engines that hang.
+        let move_string = self
+            .command_with_search(&format!("bestmove depth {}", depth), self.depth_timeout)?
+            .pop()
+            .ok_or_else(|| UhpError::EngineError("empty bestmove".to_string()))?;
+        self.decision(&move_string)
+    }
+
+    // UHP has no resignation, so as an extension, engines can answer bestmove
+    // with "resign" instead of a move.
+    fn decision(&self, move_string: &str) -> Result<Decision> {
+        if move_string.eq_ignore_ascii_case("resign") {
+            return Ok(Decision::Resign);
+        }
+        self.board.from_move_string(move_string).map(Decision::Play)
     }
 }
 
 impl Drop for UhpClient {
     fn drop(&mut self) {
         if let Err(err) = self.proc.kill() {
-            println!("{}", err);
+            eprintln!("{}", err);
         }
+        // Reap the engine, so that it doesn't linger as a zombie.
+        let _ = self.proc.wait();
     }
 }
 
 pub(crate) struct UhpPlayer {
     client: UhpClient,
+    cmd_args: Vec<String>,
     cmd: String,
+    dir: Option<PathBuf>,
     timeout: Option<Duration>,
     depth: Option<u8>,
+    command_timeout: Duration,
+    depth_timeout: Duration,
 }
 
 impl UhpPlayer {
-    pub(crate) fn new(cmd: &str) -> Result<Self> {
+    pub(crate) fn new(
+        cmd: &str, command_timeout: Duration, depth_timeout: Duration, dir: Option<&Path>,
+    ) -> Result<Self> {
+        let mut program = cmd.to_owned();
+        if dir.is_some() && Path::new(cmd).components().count() > 1 {
+            // Relative paths would be looked up from the new working directory.
+            program = std::fs::canonicalize(cmd)?.to_string_lossy().into_owned();
+        }
+        let mut client = UhpClient::new_in_dir(&[program.clone()], dir, command_timeout)?;
+        client.set_depth_timeout(depth_timeout);
         Ok(UhpPlayer {
-            client: UhpClient::new(&[cmd.to_owned()])?,
+            client,
+            cmd_args: vec![program],
             cmd: Path::new(cmd).file_name().unwrap().to_str().unwrap().to_string(),
+            dir: dir.map(Path::to_path_buf),
             timeout: None,
             depth: None,
+            command_timeout,
+            depth_timeout,
         })
     }
+
+    fn restart(&mut self) -> Result<()> {
+        self.client =
+            UhpClient::new_in_dir(&self.cmd_args, self.dir.as_deref(), self.command_timeout)?;
+        self.client.set_depth_timeout(self.depth_timeout);
+        Ok(())
+    }
 }
 
 impl Player for UhpPlayer {
@@ -156,23 +350,41 @@ impl Player for UhpPlayer {
         self.cmd.clone()
     }
 
-    fn new_game(&mut self, game_type: &str) {
-        self.client.new_game(game_type).unwrap();
+    fn new_game(&mut self, game_type: &str) -> Result<()> {
+        // Reuse the running engine when it's still in good shape, and restart
+        // it when it hung, crashed, or fell out of step in the last game.
+        if !self.client.healthy() {
+            self.restart()?;
+        }
+        match self.client.new_game(game_type) {
+            Err(_) if self.client.failed() => {
+                self.restart()?;
+                self.client.new_game(game_type)
+            }
+            result => result,
+        }
     }
 
-    fn play_move(&mut self, m: Turn) {
-        self.client.apply(m).unwrap();
+    fn play_move(&mut self, m: Turn) -> Result<()> {
+        self.client.apply(m).map(|_| ())
     }
 
     fn undo_move(&mut self, _: Turn) {
         self.client.undo(1).unwrap();
     }
 
-    fn generate_move(&mut self) -> Turn {
+    fn generate_move(&mut self) -> Result<Turn> {
+        match self.decide()? {
+            Decision::Play(turn) | Decision::OfferDraw(turn) => Ok(turn),
+            _ => Err(UhpError::EngineError(format!("{} resigned", self.cmd))),
+        }
+    }
+
+    fn decide(&mut self) -> Result<Decision> {
         if let Some(depth) = self.depth {
-            self.client.best_move_depth(depth).unwrap()
+            self.client.best_move_depth(depth)
         } else {
-            self.client.best_move(self.timeout.unwrap_or_else(|| Duration::from_secs(5))).unwrap()
+            self.client.best_move(self.timeout.unwrap_or_else(|| Duration::from_secs(5)))
         }
     }
 
@@ -184,3 +396,82 @@ impl Player for UhpPlayer {
         self.timeout = Some(time);
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> Vec<String> {
+        vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()]
+    }
+
+    #[test]
+    fn test_engine_failures() {
+        // Hangs after startup.
+        let mut client =
+            UhpClient::new_with_timeout(&shell("echo ok; sleep 10"), Duration::from_millis(100))
+                .unwrap();
+        assert!(matches!(client.new_game("Base"), Err(UhpError::EngineTimeout(_))));
+        assert!(client.failed());
+
+        // Crashes on the first command.
+        let mut client = UhpClient::new_with_timeout(
+            &shell("echo ok; read line; echo oops >&2; exit 3"),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        match client.new_game("Base") {
+            Err(UhpError::EngineCrashed(msg)) => assert!(msg.contains("oops"), "{}", msg),
+            other => panic!("{:?}", other.err()),
+        }
+        assert!(client.failed());
+
+        // Hangs in depth limited searches.
+        let mut client = UhpClient::new_with_timeout(
+            &shell("echo ok; while read cmd arg; do case $cmd in bestmove) sleep 10;; esac; echo ok; done"),
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        client.set_depth_timeout(Duration::from_millis(100));
+        assert!(matches!(client.best_move_depth(3), Err(UhpError::EngineTimeout(_))));
+        assert!(client.failed());
+
+        // Answers every command with nothing.
+        let mut client =
+            UhpClient::new(&shell("echo ok; while read line; do echo ok; done")).unwrap();
+        assert!(matches!(client.raw_generate_moves(), Err(UhpError::EngineError(_))));
+    }
+
+    #[test]
+    fn test_engine_reuse() {
+        // Answers info, and echoes newgame with the given reply.
+        let engine = |newgame: &str| {
+            shell(&format!(
+                "echo ok; while read cmd arg; do case $cmd in info) echo id fake;; newgame) echo '{}';; esac; echo ok; done",
+                newgame
+            ))
+        };
+        let cmd_args = engine("Base;NotStarted;White[1]");
+        let mut player = UhpPlayer {
+            client: UhpClient::new(&cmd_args).unwrap(),
+            cmd_args,
+            cmd: "fake".to_owned(),
+            dir: None,
+            timeout: None,
+            depth: None,
+            command_timeout: Duration::from_secs(5),
+            depth_timeout: DEPTH_TIMEOUT,
+        };
+        let pid = player.client.proc.id();
+        player.new_game("Base").unwrap();
+        player.new_game("Base").unwrap();
+        assert_eq!(pid, player.client.proc.id());
+
+        // Replies to newgame with some other game.
+        let mut client = UhpClient::new(&engine("Base;InProgress;Black[1];wQ")).unwrap();
+        assert!(client.healthy());
+        assert!(matches!(client.new_game("Base"), Err(UhpError::EngineError(_))));
+        assert!(client.failed());
+        assert!(!client.healthy());
+    }
+}
@@ -7,19 +7,79 @@ use crate::*;
 use std::io::Write;
 #[cfg(not(target_arch = "wasm32"))]
 use std::io::{stdin, stdout};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{mpsc, Arc};
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
 use std::time::Duration;
 
+// The result of UhpServer::search_step, with the fields of an info line.
+pub struct SearchProgress {
+    pub best_move: String,
+    pub depth: u8,
+    // A number for the player to move, or "win" or "loss".
+    pub score: String,
+    pub pv: Vec<String>,
+    // Whether searching deeper can't change the move: the game is decided,
+    // or the engine doesn't search deeper, as with the Instant option.
+    pub complete: bool,
+}
+
+// Limits on what one command may cost, for sessions open to the network.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+pub(crate) struct CommandCaps {
+    // Deeper bestmove requests are refused, and all of them stop at max_time.
+    pub(crate) max_depth: u8,
+    pub(crate) max_time: Duration,
+    pub(crate) max_perft_depth: u8,
+}
+
 pub struct UhpServer<W: Write> {
     board: Option<Board>,
+    // The game type or position string the game started from, before any
+    // moves.
+    setup: String,
+    // Undone turns that redo can play again, most recently undone last.
+    redo: Vec<Turn>,
     pv_dirty: bool,
     config: PlayerConfig,
     engine: Option<Box<dyn Player>>,
     output: W,
+    // Set as soon as a stop or ponderhit is read, to change a search in
+    // progress. The stop is cleared once the stop command itself is handled.
+    #[cfg(not(target_arch = "wasm32"))]
+    token: Arc<SearchToken>,
+    // Whether anything reads ahead for stops and ponderhits, without which
+    // infinite and ponder searches would never end.
+    #[cfg(not(target_arch = "wasm32"))]
+    reads_ahead: bool,
+    // Ponder commands seen, matching the token's count of those read ahead.
+    #[cfg(not(target_arch = "wasm32"))]
+    ponders: u32,
+    #[cfg(not(target_arch = "wasm32"))]
+    caps: Option<CommandCaps>,
 }
 
 impl<W: Write> UhpServer<W> {
     pub fn new(config: PlayerConfig, output: W) -> Self {
-        UhpServer { board: None, pv_dirty: true, config, engine: None, output }
+        UhpServer {
+            board: None,
+            setup: String::new(),
+            redo: Vec::new(),
+            pv_dirty: true,
+            config,
+            engine: None,
+            output,
+            #[cfg(not(target_arch = "wasm32"))]
+            token: Arc::new(SearchToken::default()),
+            #[cfg(not(target_arch = "wasm32"))]
+            reads_ahead: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            ponders: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            caps: None,
+        }
     }
 
     pub fn swap_output(&mut self, mut output: W) -> W {
@@ -27,6 +87,28 @@ impl<W: Write> UhpServer<W> {
         output
     }
 
+    pub(crate) fn output(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    // For a reader that passes each command to read_ahead as soon as it comes
+    // in, while commands before it are still running.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn search_token(&mut self) -> Arc<SearchToken> {
+        self.reads_ahead = true;
+        self.token.clone()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_caps(&mut self, caps: CommandCaps) {
+        self.caps = Some(caps);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn board(&self) -> Option<&Board> {
+        self.board.as_ref()
+    }
+
     fn info(&mut self) -> Result<()> {
         // Version string
         let mut version =
@@ -40,22 +122,30 @@ impl<W: Write> UhpServer<W> {
         Ok(())
     }
 
-    fn reset_engine(&mut self) {
-        if let Some(board) = &self.board {
+    fn reset_engine(&mut self) -> Result<()> {
+        if let Some(board) = &mut self.board {
+            board.set_variant(self.config.variant);
             let mut engine = self.config.new_player();
-            engine.new_game(&board.game_type());
+            #[cfg(not(target_arch = "wasm32"))]
+            engine.set_search_token(self.token.clone());
+            engine.new_game(&self.setup)?;
             for &turn in &board.turn_history {
-                engine.play_move(turn);
+                engine.play_move(turn)?;
             }
             self.engine = Some(engine);
         }
+        Ok(())
     }
 
     fn new_game(&mut self, args: &str) -> Result<()> {
         self.pv_dirty = true;
         let args = if args.is_empty() { "Base" } else { args };
-        self.board = Some(Board::from_game_string(args)?);
-        self.reset_engine();
+        // As an extension, a position string sets up a position directly.
+        let board = Board::from_game_or_position_string_with(args, self.config.variant)?;
+        self.setup = if args.contains(';') { board.game_type() } else { args.to_owned() };
+        self.board = Some(board);
+        self.redo.clear();
+        self.reset_engine()?;
         writeln!(self.output, "{}", self.board.as_mut().unwrap().game_string())?;
         Ok(())
     }
@@ -73,43 +163,158 @@ impl<W: Write> UhpServer<W> {
         self.pv_dirty = true;
         let board = self.board.as_mut().ok_or(UhpError::GameNotStarted)?;
         let m = board.from_move_string(args)?;
-        board.apply_untrusted(m)?;
-        self.engine.as_mut().unwrap().play_move(m);
+        if self.config.strict_moves {
+            if Rules::get_winner(board).is_some() {
+                return Err(UhpError::GameOver);
+            }
+            board.apply_untrusted(m)?;
+        } else {
+            board.apply_permissive(m)?;
+        }
+        self.engine.as_mut().unwrap().play_move(m)?;
+        // Playing the next undone turn keeps the rest of the line to redo.
+        if self.redo.last() == Some(&m) {
+            self.redo.pop();
+        } else {
+            self.redo.clear();
+        }
         writeln!(self.output, "{}", board.game_string())?;
         Ok(())
     }
 
     fn best_move(&mut self, args: &str) -> Result<()> {
         self.pv_dirty = false;
-        let board = self.board.as_ref().ok_or(UhpError::GameNotStarted)?;
-        if let Some(arg) = args.strip_prefix("depth ") {
-            let depth =
-                arg.parse::<u8>().map_err(|_| UhpError::UnrecognizedCommand(args.to_string()))?;
-            self.engine.as_mut().unwrap().set_max_depth(depth);
-        } else if let Some(arg) = args.strip_prefix("time ") {
-            let dur =
-                parse_hhmmss(arg).ok_or_else(|| UhpError::UnrecognizedCommand(args.to_string()))?;
-            self.engine.as_mut().unwrap().set_timeout(dur);
-        } else {
-            return Err(UhpError::UnrecognizedCommand(args.to_string()));
+        let board = live_board(&self.board)?;
+        let err = || UhpError::UnrecognizedCommand(args.to_string());
+        // A depth, a time, or both to stop at whichever comes first. As
+        // extensions, infinite searches until a stop command, and ponder
+        // holds off on the limits until a ponderhit.
+        let (mut depth, mut time) = (None, None);
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut infinite = false;
+        #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+        let mut ponder = false;
+        let mut tokens = args.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "depth" => {
+                    depth =
+                        Some(tokens.next().and_then(|arg| arg.parse::<u8>().ok()).ok_or_else(err)?)
+                }
+                "time" => {
+                    let mut dur = tokens.next().and_then(parse_hhmmss).ok_or_else(err)?;
+                    if self.config.game_clock {
+                        // The time is what's left on our clock, not a per-move limit.
+                        dur = move_budget(dur, self.config.clock_increment, board);
+                    }
+                    time = Some(dur);
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                "infinite" if self.reads_ahead => infinite = true,
+                #[cfg(not(target_arch = "wasm32"))]
+                "ponder" if self.reads_ahead => ponder = true,
+                _ => return Err(err()),
+            }
         }
-        let m = self.engine.as_mut().unwrap().generate_move();
-        writeln!(self.output, "{}", board.to_move_string(m))?;
+        if infinite == (depth.is_some() || time.is_some()) {
+            return Err(err());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(caps) = self.caps {
+            if depth.is_some_and(|depth| depth == 0 || depth > caps.max_depth) {
+                return Err(UhpError::InvalidOption(format!("depth 1 to {}", caps.max_depth)));
+            }
+            time = Some(time.map_or(caps.max_time, |time| time.min(caps.max_time)));
+        }
+        // A forced pass needs no search.
+        let mut moves = Vec::new();
+        Rules::generate_moves(board, &mut moves);
+        if moves == [Turn::Pass] {
+            self.pv_dirty = true;
+            writeln!(self.output, "pass")?;
+            return Ok(());
+        }
+        self.engine.as_mut().unwrap().set_limits(depth, time);
+        // Only a ponder search that gets this far waits for its ponderhit.
+        #[cfg(not(target_arch = "wasm32"))]
+        if ponder {
+            self.token.start_pondering(self.ponders);
+        }
+        let engine = self.engine.as_mut().unwrap();
+        let m = if self.config.search_info {
+            let (output, eval) = (&mut self.output, &self.config.eval);
+            engine.generate_move_with_info(&mut |info| {
+                // Progress is best effort; a write error will show up below.
+                let _ = writeln!(output, "{}", info_line(board, eval, &info));
+                let _ = output.flush();
+            })
+        } else {
+            engine.generate_move()
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        self.token.end_pondering();
+        writeln!(self.output, "{}", board.to_move_string(m?))?;
         Ok(())
     }
 
-    fn pv(&mut self) -> Result<()> {
+    // Searches to one depth, or for the given time if depth is None, for hosts
+    // that can't block through a whole bestmove, and drive iterative deepening
+    // themselves to report progress and handle input between depths.
+    pub fn search_step(&mut self, depth: Option<u8>, time: Duration) -> Result<SearchProgress> {
+        self.pv_dirty = false;
+        let board = live_board(&self.board)?;
+        let engine = self.engine.as_mut().unwrap();
+        match depth {
+            Some(depth) => engine.set_max_depth(depth),
+            None => engine.set_timeout(time),
+        }
+        let m = engine.generate_move()?;
+        let pv = engine.principal_variation();
+        let score = line_score(board, &self.config.eval, &pv);
+        // Random openings are chosen without a search.
+        let complete =
+            self.config.instant || pv.first() != Some(&m) || score == "win" || score == "loss";
+        Ok(SearchProgress {
+            best_move: board.to_move_string(m),
+            depth: depth.unwrap_or(0).max(pv.len() as u8),
+            score,
+            pv: board.line_move_strings(&pv),
+            complete,
+        })
+    }
+
+    // Extension command printing the expected line from the last bestmove, one
+    // move per line: UHP move strings, or with "pv coordinates", the tiles and
+    // hexes in the engine's coordinates.
+    fn pv(&mut self, args: &str) -> Result<()> {
         let pv = self.engine.as_ref().ok_or(UhpError::GameNotStarted)?.principal_variation();
-        let board = self.board.as_mut().unwrap();
+        let board = self.board.as_ref().unwrap();
         if self.pv_dirty {
             return Err(UhpError::EngineError("Board changed since last engine move".into()));
         }
-        for &m in &pv {
-            writeln!(self.output, "{}", board.to_move_string(m))?;
-            board.apply(m);
+        let moves = match args {
+            "" => board.line_move_strings(&pv),
+            "coordinates" => board.line_coordinates(&pv),
+            _ => return Err(UhpError::UnrecognizedCommand(args.to_string())),
+        };
+        for m in moves {
+            writeln!(self.output, "{}", m)?;
         }
-        for &m in pv.iter().rev() {
-            board.undo(m);
+        Ok(())
+    }
+
+    // Extension command listing the evaluation terms of the current position.
+    fn eval_breakdown(&mut self) -> Result<()> {
+        let board = self.board.as_ref().ok_or(UhpError::GameNotStarted)?;
+        writeln!(self.output, "{}", self.config.eval.explain_eval(board))?;
+        Ok(())
+    }
+
+    // Extension command printing the search configuration the engine plays
+    // with, so game logs show what settings a binary actually used.
+    fn config(&mut self) -> Result<()> {
+        for line in self.config.describe() {
+            writeln!(self.output, "{}", line)?;
         }
         Ok(())
     }
@@ -126,8 +331,32 @@ impl<W: Write> UhpServer<W> {
             return Err(UhpError::TooManyUndos);
         }
         for _ in 0..num_undo {
-            self.engine.as_mut().unwrap().undo_move(board.last_move().unwrap());
+            let m = board.last_move().unwrap();
+            self.engine.as_mut().unwrap().undo_move(m);
             board.undo_count(1)?;
+            self.redo.push(m);
+        }
+        writeln!(self.output, "{}", board.game_string())?;
+        Ok(())
+    }
+
+    // Extension command to play undone turns again, so a GUI can step back
+    // and forth through a game.
+    fn redo(&mut self, args: &str) -> Result<()> {
+        self.pv_dirty = true;
+        let board = self.board.as_mut().ok_or(UhpError::GameNotStarted)?;
+        let num_redo = if args.is_empty() {
+            1
+        } else {
+            args.parse::<usize>().map_err(|_| UhpError::UnrecognizedCommand(args.to_string()))?
+        };
+        if num_redo > self.redo.len() {
+            return Err(UhpError::TooManyRedos);
+        }
+        for _ in 0..num_redo {
+            let m = self.redo.pop().unwrap();
+            board.apply(m);
+            self.engine.as_mut().unwrap().play_move(m)?;
         }
         writeln!(self.output, "{}", board.game_string())?;
         Ok(())
@@ -188,9 +417,21 @@ impl<W: Write> UhpServer<W> {
             "Aggression" => self.get_option_int::<AggressionOption>(),
             #[cfg(not(target_arch = "wasm32"))]
             "BackgroundPondering" => self.get_option_bool::<BackgroundPonderingOption>(),
+            "ClockIncrement" => self.get_option_int::<ClockIncrementOption>(),
+            "GameClock" => self.get_option_bool::<GameClockOption>(),
             #[cfg(not(target_arch = "wasm32"))]
             "NumThreads" => self.get_option_int::<NumThreadsOption>(),
+            "Instant" => self.get_option_bool::<InstantOption>(),
+            "MaxMemoryMiB" => self.get_option_int::<MaxMemoryOption>(),
+            "MaxStackHeight" => self.get_option_int::<MaxStackHeightOption>(),
+            #[cfg(not(target_arch = "wasm32"))]
+            "MaxNodes" => self.get_option_int::<MaxNodesOption>(),
+            "QueenMovesLast" => self.get_option_bool::<QueenMovesLastOption>(),
             "RandomOpening" => self.get_option_bool::<RandomOpeningOption>(),
+            "SearchInfo" => self.get_option_bool::<SearchInfoOption>(),
+            "StrictMoves" => self.get_option_bool::<StrictMovesOption>(),
+            "Strength" => self.get_option_int::<StrengthOption>(),
+            "TournamentOpening" => self.get_option_bool::<TournamentOpeningOption>(),
             "TableSizeMiB" => self.get_option_int::<TableSizeOption>(),
             "Verbose" => self.get_option_bool::<VerboseOption>(),
             _ => Err(UhpError::InvalidOption(option.into())),
@@ -203,10 +444,22 @@ impl<W: Write> UhpServer<W> {
             self.get_option_int::<AggressionOption>()?;
             #[cfg(not(target_arch = "wasm32"))]
             self.get_option_bool::<BackgroundPonderingOption>()?;
+            self.get_option_int::<ClockIncrementOption>()?;
+            self.get_option_bool::<GameClockOption>()?;
             #[cfg(not(target_arch = "wasm32"))]
             self.get_option_int::<NumThreadsOption>()?;
+            self.get_option_bool::<InstantOption>()?;
+            self.get_option_int::<MaxMemoryOption>()?;
+            self.get_option_int::<MaxStackHeightOption>()?;
+            #[cfg(not(target_arch = "wasm32"))]
+            self.get_option_int::<MaxNodesOption>()?;
+            self.get_option_bool::<QueenMovesLastOption>()?;
             self.get_option_bool::<RandomOpeningOption>()?;
+            self.get_option_bool::<SearchInfoOption>()?;
+            self.get_option_bool::<StrictMovesOption>()?;
+            self.get_option_int::<StrengthOption>()?;
             self.get_option_int::<TableSizeOption>()?;
+            self.get_option_bool::<TournamentOpeningOption>()?;
             self.get_option_bool::<VerboseOption>()?;
         } else if tokens.len() == 2 && tokens[0] == "get" {
             self.get_option(tokens[1])?;
@@ -217,14 +470,28 @@ impl<W: Write> UhpServer<W> {
                 "BackgroundPondering" => {
                     self.set_option_bool::<BackgroundPonderingOption>(tokens[2])?
                 }
+                "ClockIncrement" => self.set_option_int::<ClockIncrementOption>(tokens[2])?,
+                "GameClock" => self.set_option_bool::<GameClockOption>(tokens[2])?,
                 #[cfg(not(target_arch = "wasm32"))]
                 "NumThreads" => self.set_option_int::<NumThreadsOption>(tokens[2])?,
+                "Instant" => self.set_option_bool::<InstantOption>(tokens[2])?,
+                "MaxMemoryMiB" => self.set_option_int::<MaxMemoryOption>(tokens[2])?,
+                "MaxStackHeight" => self.set_option_int::<MaxStackHeightOption>(tokens[2])?,
+                #[cfg(not(target_arch = "wasm32"))]
+                "MaxNodes" => self.set_option_int::<MaxNodesOption>(tokens[2])?,
+                "QueenMovesLast" => self.set_option_bool::<QueenMovesLastOption>(tokens[2])?,
                 "RandomOpening" => self.set_option_bool::<RandomOpeningOption>(tokens[2])?,
+                "SearchInfo" => self.set_option_bool::<SearchInfoOption>(tokens[2])?,
+                "StrictMoves" => self.set_option_bool::<StrictMovesOption>(tokens[2])?,
+                "Strength" => self.set_option_int::<StrengthOption>(tokens[2])?,
+                "TournamentOpening" => {
+                    self.set_option_bool::<TournamentOpeningOption>(tokens[2])?
+                }
                 "TableSizeMiB" => self.set_option_int::<TableSizeOption>(tokens[2])?,
                 "Verbose" => self.set_option_bool::<VerboseOption>(tokens[2])?,
                 _ => return Err(UhpError::InvalidOption(args.into())),
             }
-            self.reset_engine();
+            self.reset_engine()?;
         } else {
             return Err(UhpError::UnrecognizedCommand(args.into()));
         }
@@ -234,12 +501,43 @@ impl<W: Write> UhpServer<W> {
     // Bonus undocumented command.
     fn perft(&mut self, args: &str) -> Result<()> {
         let depth = args.parse::<u8>().unwrap_or(20);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(caps) = self.caps {
+            if depth > caps.max_perft_depth {
+                return Err(UhpError::InvalidOption(format!(
+                    "perft depth up to {}",
+                    caps.max_perft_depth
+                )));
+            }
+        }
         let mut b = self.board.as_ref().ok_or(UhpError::GameNotStarted)?.clone();
         minimax::perft::<Rules>(&mut b, depth, false);
         Ok(())
     }
 
+    // Runs one command, answering with invalidmove or err per UHP if it
+    // fails. Returns whether the command was exit.
     pub fn command(&mut self, line: &str) -> bool {
+        match self.try_command(line) {
+            Ok(exit) => exit,
+            Err(err) => {
+                let response = match err {
+                    UhpError::InvalidMove(_) => "invalidmove",
+                    _ => "err",
+                };
+                writeln!(self.output, "{} {}", response, err).unwrap();
+                false
+            }
+        }
+    }
+
+    // Like command, but returns errors instead of writing them, for hosts
+    // that want to tell them apart without parsing the output.
+    pub fn try_command(&mut self, line: &str) -> Result<bool> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if is_ponder(line) {
+            self.ponders += 1;
+        }
         let line = line.trim();
         let space = line.find(' ');
         let command = if let Some(i) = space { &line[..i] } else { line };
@@ -251,47 +549,94 @@ impl<W: Write> UhpServer<W> {
             "play" => self.play(args),
             "pass" => self.play("pass"),
             "bestmove" => self.best_move(args),
-            "pv" => self.pv(),
+            "pv" => self.pv(args),
+            "evalbreakdown" => self.eval_breakdown(),
+            "config" => self.config(),
             "undo" => self.undo(args),
+            "redo" => self.redo(args),
             "options" => self.options(args),
             "perft" => self.perft(args),
-            "exit" => return true,
+            // The search has already seen these by now, if there was one.
+            #[cfg(not(target_arch = "wasm32"))]
+            "stop" => {
+                self.token.clear_stop();
+                Ok(())
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            "ponderhit" => Ok(()),
+            "exit" => return Ok(true),
             _ => Err(UhpError::UnrecognizedCommand(command.to_string())),
         };
-        if let Err(err) = result {
-            if let UhpError::InvalidMove(invalid) = err {
-                writeln!(self.output, "invalidmove {}", invalid).unwrap();
-            } else {
-                writeln!(self.output, "err {:?}", err).unwrap();
-            }
-        }
-        false
+        result.map(|_| false)
     }
 }
 
+// The board, if a game is started and not yet over.
+fn live_board(board: &Option<Board>) -> Result<&Board> {
+    let board = board.as_ref().ok_or(UhpError::GameNotStarted)?;
+    if Rules::get_winner(board).is_some() {
+        return Err(UhpError::GameOver);
+    }
+    Ok(board)
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn uhp_serve(config: PlayerConfig) {
-    let mut server = UhpServer::new(config, stdout());
-    server.info().unwrap();
-    println!("ok");
-    loop {
-        let mut line = String::new();
-        match stdin().read_line(&mut line) {
-            Ok(size) => {
-                if size == 0 {
-                    return;
-                }
-            }
-            Err(err) => {
-                eprintln!("{}", err);
-                return;
+    let lines = stdin().lines().map_while(|line| line.map_err(|err| eprintln!("{}", err)).ok());
+    serve_lines(config, stdout(), std::iter::once("info".to_owned()).chain(lines));
+}
+
+// Runs the commands on a worker thread, each answered with ok, while reading
+// ahead on this one so that stop and ponderhit reach a search still running.
+// Returns the output once the commands run out or one exits.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn serve_lines<W: Write + Send + 'static>(
+    config: PlayerConfig, output: W, lines: impl Iterator<Item = String>,
+) -> W {
+    let (sender, receiver) = mpsc::channel::<String>();
+    let (token_sender, token_receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        let mut server = UhpServer::new(config, output);
+        token_sender.send(server.search_token()).unwrap();
+        for line in receiver {
+            if server.command(&line) {
+                break;
             }
-        };
-        if server.command(&line) {
-            return;
+            // A write error will show up on the next command's output.
+            let _ = writeln!(server.output, "ok");
+            let _ = server.output.flush();
+        }
+        server.output
+    });
+    let token = token_receiver.recv().unwrap();
+    for line in lines {
+        read_ahead(&token, &line);
+        let exit = line.trim() == "exit";
+        if sender.send(line).is_err() || exit {
+            break;
         }
-        println!("ok");
     }
+    drop(sender);
+    worker.join().unwrap()
+}
+
+// Passes on stops and ponderhits as soon as they're read. Ponder searches
+// are counted here too, so that a ponderhit right behind one isn't lost.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_ahead(token: &SearchToken, line: &str) {
+    match line.split_whitespace().next() {
+        Some("stop") => token.stop(),
+        Some("ponderhit") => token.ponderhit(),
+        _ if is_ponder(line) => token.ponder_read(),
+        _ => {}
+    }
+}
+
+// Whether a line asks for a ponder search, whether or not it's valid.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_ponder(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    words.next() == Some("bestmove") && words.any(|word| word == "ponder")
 }
 
 trait UhpOptionInt {
@@ -343,6 +688,46 @@ impl UhpOptionInt for AggressionOption {
     }
 }
 
+// Weakens the engine for casual players, with 10 for full strength.
+// The highest stacks may grow, or 0 for no limit.
+struct MaxStackHeightOption {}
+impl UhpOptionInt for MaxStackHeightOption {
+    fn name() -> &'static str {
+        "MaxStackHeight"
+    }
+    fn current(config: &PlayerConfig) -> Result<usize> {
+        Ok(config.variant.max_stack_height.map_or(0, usize::from))
+    }
+    fn min() -> usize {
+        0
+    }
+    fn max() -> usize {
+        8
+    }
+    fn set(value: usize, config: &mut PlayerConfig) {
+        config.variant.max_stack_height = (value > 0).then_some(value as u8);
+    }
+}
+
+struct StrengthOption {}
+impl UhpOptionInt for StrengthOption {
+    fn name() -> &'static str {
+        "Strength"
+    }
+    fn current(config: &PlayerConfig) -> Result<usize> {
+        Ok(config.strength.level.into())
+    }
+    fn min() -> usize {
+        1
+    }
+    fn max() -> usize {
+        MAX_STRENGTH.into()
+    }
+    fn set(value: usize, config: &mut PlayerConfig) {
+        config.strength = Strength::level(value as u8).unwrap_or_default();
+    }
+}
+
 struct TableSizeOption {}
 impl UhpOptionInt for TableSizeOption {
     fn name() -> &'static str {
@@ -362,6 +747,69 @@ impl UhpOptionInt for TableSizeOption {
     }
 }
 
+// Caps the transposition table and search threads together, with 0 for no
+// limit beyond TableSizeMiB.
+struct MaxMemoryOption {}
+impl UhpOptionInt for MaxMemoryOption {
+    fn name() -> &'static str {
+        "MaxMemoryMiB"
+    }
+    fn current(config: &PlayerConfig) -> Result<usize> {
+        Ok(config.max_memory.unwrap_or(0) >> 20)
+    }
+    fn min() -> usize {
+        0
+    }
+    fn max() -> usize {
+        4095
+    }
+    fn set(value: usize, config: &mut PlayerConfig) {
+        config.max_memory = (value > 0).then_some(value << 20);
+    }
+}
+
+// Positions a search may evaluate, with 0 for no limit.
+#[cfg(not(target_arch = "wasm32"))]
+struct MaxNodesOption {}
+#[cfg(not(target_arch = "wasm32"))]
+impl UhpOptionInt for MaxNodesOption {
+    fn name() -> &'static str {
+        "MaxNodes"
+    }
+    fn current(config: &PlayerConfig) -> Result<usize> {
+        Ok(config.max_nodes.unwrap_or(0) as usize)
+    }
+    fn min() -> usize {
+        0
+    }
+    fn max() -> usize {
+        1 << 40
+    }
+    fn set(value: usize, config: &mut PlayerConfig) {
+        config.max_nodes = (value > 0).then_some(value as u64);
+    }
+}
+
+// Seconds added to the clock after each move, when GameClock is set.
+struct ClockIncrementOption {}
+impl UhpOptionInt for ClockIncrementOption {
+    fn name() -> &'static str {
+        "ClockIncrement"
+    }
+    fn current(config: &PlayerConfig) -> Result<usize> {
+        Ok(config.clock_increment.as_secs() as usize)
+    }
+    fn min() -> usize {
+        0
+    }
+    fn max() -> usize {
+        3600
+    }
+    fn set(value: usize, config: &mut PlayerConfig) {
+        config.clock_increment = Duration::from_secs(value as u64);
+    }
+}
+
 trait UhpOptionBool {
     fn name() -> &'static str;
     fn current(config: &PlayerConfig) -> Result<bool>;
@@ -394,6 +842,89 @@ impl UhpOptionBool for RandomOpeningOption {
     }
 }
 
+// Treat the time in "bestmove time" as the remaining game clock.
+struct GameClockOption {}
+impl UhpOptionBool for GameClockOption {
+    fn name() -> &'static str {
+        "GameClock"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.game_clock)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.game_clock = value;
+    }
+}
+
+// Print info lines on the search's progress before the bestmove result.
+struct SearchInfoOption {}
+impl UhpOptionBool for SearchInfoOption {
+    fn name() -> &'static str {
+        "SearchInfo"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.search_info)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.search_info = value;
+    }
+}
+
+// Reject illegal moves in play, or play anything that fits on the board.
+struct StrictMovesOption {}
+impl UhpOptionBool for StrictMovesOption {
+    fn name() -> &'static str {
+        "StrictMoves"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.strict_moves)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.strict_moves = value;
+    }
+}
+
+// Forbid placing the queen on a player's first turn.
+struct TournamentOpeningOption {}
+impl UhpOptionBool for TournamentOpeningOption {
+    fn name() -> &'static str {
+        "TournamentOpening"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.variant.tournament_opening)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.variant.tournament_opening = value;
+    }
+}
+
+// Keep queens in place until their player's hand is empty.
+struct QueenMovesLastOption {}
+impl UhpOptionBool for QueenMovesLastOption {
+    fn name() -> &'static str {
+        "QueenMovesLast"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.variant.queen_moves_last)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.variant.queen_moves_last = value;
+    }
+}
+
+struct InstantOption {}
+impl UhpOptionBool for InstantOption {
+    fn name() -> &'static str {
+        "Instant"
+    }
+    fn current(config: &PlayerConfig) -> Result<bool> {
+        Ok(config.instant)
+    }
+    fn set(value: bool, config: &mut PlayerConfig) {
+        config.instant = value;
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 struct BackgroundPonderingOption {}
 #[cfg(not(target_arch = "wasm32"))]
@@ -416,10 +947,499 @@ impl UhpOptionBool for BackgroundPonderingOption {
     }
 }
 
+// Progress line for a search from the board, e.g.
+//   info depth 3 time 120 score 35 pv wQ -wA1;bQ bG1/;wA1 bQ\
+// The score is the static evaluation at the end of the principal variation,
+// for the player to move at the root.
+fn info_line(board: &Board, eval: &BasicEvaluator, info: &SearchInfo) -> String {
+    format!(
+        "info depth {} time {} score {} pv {}",
+        info.depth,
+        info.elapsed.as_millis(),
+        line_score(board, eval, &info.pv),
+        board.line_move_strings(&info.pv).join(";")
+    )
+}
+
+pub(crate) fn line_score(board: &Board, eval: &BasicEvaluator, line: &[Turn]) -> String {
+    match line_value(board, eval, line) {
+        minimax::BEST_EVAL => "win".to_string(),
+        v if v == -minimax::BEST_EVAL => "loss".to_string(),
+        v => v.to_string(),
+    }
+}
+
+// As an extension, the seconds may have a fraction, e.g. 00:00:00.250.
 fn parse_hhmmss(time: &str) -> Option<Duration> {
     let mut toks = time.split(':');
     let hours = toks.next().unwrap_or("").parse::<u64>().ok()?;
     let minutes = toks.next().unwrap_or("").parse::<u64>().ok()?;
-    let seconds = toks.next().unwrap_or("").parse::<u64>().ok()?;
-    Some(Duration::from_secs(hours * 3600 + minutes * 60 + seconds))
+    let seconds =
+        toks.next().unwrap_or("").parse::<f64>().ok().filter(|s| s.is_finite() && *s >= 0.0)?;
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_info() {
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command("options set TableSizeMiB 1");
+        server.command("options set SearchInfo True");
+        server.command(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        server.swap_output(Vec::new());
+        server.command("bestmove depth 3");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(4, lines.len(), "{}", output);
+        for (i, line) in lines[..3].iter().enumerate() {
+            assert!(line.starts_with(&format!("info depth {} time ", i + 1)), "{}", line);
+            assert_eq!(i + 1, line.split(" pv ").nth(1).unwrap().split(';').count(), "{}", line);
+        }
+        // The move comes last, and starts the deepest line.
+        assert!(lines[2].contains(&format!(" pv {};", lines[3])), "{}", output);
+
+        server.command("options set SearchInfo False");
+        server.swap_output(Vec::new());
+        server.command("bestmove depth 2");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        assert_eq!(1, output.lines().count(), "{}", output);
+
+        // Timed searches report too.
+        assert_eq!(Some(Duration::from_millis(61_250)), parse_hhmmss("00:01:01.25"));
+        assert_eq!(None, parse_hhmmss("00:00:-1"));
+        server.command("options set SearchInfo True");
+        server.swap_output(Vec::new());
+        server.command("bestmove time 00:00:01");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        assert!(output.starts_with("info depth 1 "), "{}", output);
+        assert!(!output.lines().last().unwrap().starts_with("info"), "{}", output);
+    }
+
+    #[test]
+    fn test_config() {
+        let mut config = PlayerConfig::new();
+        config.num_threads = Some(2);
+        config.max_memory = Some(64 << 20);
+        let mut server = UhpServer::new(config, Vec::new());
+        server.command("options set Strength 7");
+        server.swap_output(Vec::new());
+        server.command("config");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!("Strategy;iterative", lines[0]);
+        assert!(lines.contains(&"NumThreads;2"), "{}", output);
+        // The table shrinks to fit the memory budget.
+        assert!(lines.contains(&"TableSizeMiB;16"), "{}", output);
+        assert!(lines.contains(&"AspirationWindow;none"), "{}", output);
+        assert!(lines.contains(&"Strength;7"), "{}", output);
+        let weights = lines.iter().find_map(|line| line.strip_prefix("EvalWeights;")).unwrap();
+        let mut eval = BasicEvaluator::new(0);
+        set_weights(&mut eval, weights).unwrap();
+        assert_eq!(BasicEvaluator::default().weights(), eval.weights());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        assert_eq!(
+            "err No game in progress, start one with newgame\n",
+            output(&mut server, "play wS1")
+        );
+        assert_eq!("err Unrecognized command or arguments: foo\n", output(&mut server, "foo"));
+        server.command("newgame Base");
+        assert!(output(&mut server, "play wQ").starts_with("invalidmove "));
+        assert!(matches!(server.try_command("play wQ"), Err(UhpError::InvalidMove(_))));
+        assert!(matches!(server.try_command("options get Foo"), Err(UhpError::InvalidOption(_))));
+        assert!(matches!(server.try_command("exit"), Ok(true)));
+
+        // Black surrounded the white queen.
+        server.command(r"newgame Base;InProgress;Black[7];wB1;bB1 wB1\;wQ \wB1;bG1 /bB1;wS1 wB1/;bG2 /bG1;wB2 -wQ;bQ -bG1;wG1 -wB2;bB2 /bG2;wG2 \wQ;bA1 /bQ;wG3 wB2\;bA1 \wS1");
+        assert_eq!("err The game is over\n", output(&mut server, "bestmove depth 1"));
+        assert!(matches!(server.try_command("play wA1 -wG3"), Err(UhpError::GameOver)));
+        assert!(matches!(server.search_step(Some(1), Duration::ZERO), Err(UhpError::GameOver)));
+    }
+
+    #[test]
+    fn test_combined_limits() {
+        let bestmove = |server: &mut UhpServer<Vec<u8>>, args: &str| {
+            server.swap_output(Vec::new());
+            server.command(&format!("bestmove {}", args));
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command("options set TableSizeMiB 1");
+        server.command("options set SearchInfo True");
+        server.command(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        // The depth comes first.
+        let output = bestmove(&mut server, "time 00:01:00 depth 2");
+        assert_eq!(3, output.lines().count(), "{}", output);
+        // The time comes first.
+        let start = std::time::Instant::now();
+        let output = bestmove(&mut server, "depth 90 time 00:00:00.2");
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+        assert!(output.starts_with("info depth 1 "), "{}", output);
+        assert!(!output.contains("info depth 90 "), "{}", output);
+        for args in ["depth", "depth 2 time", "time 1s", "depth 2 infinite", "infinite depth 2"] {
+            assert!(bestmove(&mut server, args).starts_with("err "), "{}", args);
+        }
+
+        // Only when something reads ahead for stops.
+        assert!(bestmove(&mut server, "infinite").starts_with("err "));
+        // A stop that came in before or during an infinite search ends it
+        // after the depth it's on.
+        read_ahead(&server.search_token(), "stop");
+        let output = bestmove(&mut server, "infinite");
+        assert_eq!(2, output.lines().count(), "{}", output);
+        assert!(output.starts_with("info depth 1 "), "{}", output);
+        server.command("stop");
+        assert!(!server.token.stopped());
+        // The usual limits still apply afterwards.
+        assert_eq!(3, bestmove(&mut server, "depth 2").lines().count());
+    }
+
+    // Passes output on a line at a time, as it's written.
+    struct ChannelWriter(mpsc::Sender<String>, Vec<u8>);
+
+    impl Write for ChannelWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1.extend_from_slice(buf);
+            while let Some(end) = self.1.iter().position(|&b| b == b'\n') {
+                let line = self.1.drain(..=end).collect::<Vec<_>>();
+                let _ = self.0.send(String::from_utf8(line).unwrap());
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stop_and_ponderhit() {
+        let (sender, receiver) = mpsc::channel();
+        let (output_sender, output) = mpsc::channel();
+        let serving = thread::spawn(move || {
+            serve_lines(
+                PlayerConfig::new(),
+                ChannelWriter(output_sender, Vec::new()),
+                receiver.into_iter(),
+            )
+        });
+        let send = |line: &str| sender.send(line.to_owned()).unwrap();
+        // Everything written until the line starting with prefix.
+        let read_until = |prefix: &str| {
+            let mut text = String::new();
+            while !text.lines().any(|line| line.starts_with(prefix)) {
+                text.push_str(&output.recv().unwrap());
+            }
+            text
+        };
+        send("options set NumThreads 1");
+        send("options set TableSizeMiB 1");
+        send("options set SearchInfo True");
+        send(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        // Searching keeps going past the depth until the ponderhit, which
+        // then ends it.
+        send("bestmove ponder depth 1");
+        let text = read_until("info depth 2 ");
+        send("ponderhit");
+        let text = text + &read_until("ok");
+        let lines = text.lines().skip_while(|line| !line.starts_with("info ")).collect::<Vec<_>>();
+        assert!(lines.len() >= 4, "{}", text);
+        assert!(!lines[lines.len() - 2].starts_with("info "), "{}", text);
+        assert_eq!("ok", read_until("ok").trim());
+
+        // An infinite search ends with the move when stopped.
+        send("bestmove infinite");
+        let text = read_until("info depth 2 ");
+        send("stop");
+        let text = text + &read_until("ok");
+        assert!(!text.lines().nth_back(1).unwrap().starts_with("info "), "{}", text);
+        assert_eq!("ok", read_until("ok").trim());
+
+        // A ponder command that fails leaves later searches to their limits.
+        send("bestmove ponder");
+        assert!(read_until("ok").starts_with("err "));
+        send("options set SearchInfo False");
+        read_until("ok");
+        send("bestmove depth 1");
+        let text = read_until("ok");
+        assert!(!text.starts_with("err "), "{}", text);
+        assert_eq!(2, text.lines().count(), "{}", text);
+        send("exit");
+        serving.join().unwrap();
+    }
+
+    #[test]
+    fn test_search_step() {
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        assert!(server.search_step(Some(1), Duration::ZERO).is_err());
+        server.command("options set NumThreads 1");
+        server.command("options set TableSizeMiB 1");
+        server.command(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        for depth in 1..=3 {
+            let progress = server.search_step(Some(depth), Duration::ZERO).unwrap();
+            assert_eq!(depth, progress.depth);
+            assert_eq!(depth as usize, progress.pv.len());
+            assert_eq!(progress.best_move, progress.pv[0]);
+            assert!(progress.score.parse::<i32>().is_ok(), "{}", progress.score);
+            assert!(!progress.complete);
+        }
+        let progress = server.search_step(None, Duration::from_millis(100)).unwrap();
+        assert_eq!(progress.best_move, progress.pv[0]);
+        // The last step's line is available to pv.
+        server.swap_output(Vec::new());
+        server.command("pv");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        assert_eq!(progress.pv, output.lines().collect::<Vec<_>>());
+
+        server.command("options set Instant True");
+        server.command(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        // Instant searches ignore the requested depth, and stop at depth 2.
+        let progress = server.search_step(Some(5), Duration::ZERO).unwrap();
+        assert!(progress.complete);
+        assert!(progress.pv.len() <= 2, "{:?}", progress.pv);
+    }
+
+    #[test]
+    fn test_pv() {
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command("options set TableSizeMiB 1");
+        server.command(r"newgame Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-");
+        server.swap_output(Vec::new());
+        server.command("bestmove depth 3");
+        server.command("pv");
+        server.command("pv coordinates");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(7, lines.len(), "{}", output);
+        // The line starts with the best move, in both forms.
+        assert_eq!(lines[0], lines[1]);
+        let tile = lines[0].split(' ').next().unwrap();
+        assert!(lines[4].starts_with(&format!("{}@", tile)), "{}", output);
+
+        server.command("pv hexes");
+        server.command("undo");
+        server.swap_output(Vec::new());
+        server.command("pv");
+        let output = String::from_utf8(server.swap_output(Vec::new())).unwrap();
+        assert!(output.starts_with("err "), "{}", output);
+    }
+
+    #[test]
+    fn test_strict_moves() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command("newgame Base;InProgress;White[2];wS1;bG1 wS1-");
+        // White can't place next to a black tile.
+        assert!(output(&mut server, "play wQ bG1-").starts_with("invalidmove "));
+
+        assert_eq!(
+            "StrictMoves;bool;False;True\n",
+            output(&mut server, "options set StrictMoves False")
+        );
+        assert_eq!(
+            "Base;InProgress;Black[2];wS1;bG1 wS1-;wQ bG1-\n",
+            output(&mut server, "play wQ bG1-")
+        );
+        // A grasshopper steps instead of jumping.
+        assert!(output(&mut server, "play bG1 /wS1").starts_with("Base;InProgress;White[3];"));
+        // Move strings still have to make sense.
+        assert!(output(&mut server, "play wA1 wS1").starts_with("invalidmove "));
+        assert_eq!(1, output(&mut server, "bestmove depth 1").lines().count());
+    }
+
+    #[test]
+    fn test_forced_pass() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        // The white queen is gated in by five black tiles, with nowhere to
+        // place a tile.
+        server.command("newgame Base White[6] wQ@0,0 bQ@-1,-1 bA1@0,-1 bA2@1,0 bA3@1,1 bG1@0,1");
+        assert_eq!("pass\n", output(&mut server, "validmoves"));
+        assert_eq!("pass\n", output(&mut server, "bestmove depth 3"));
+        let invalid = output(&mut server, "play wA1 -wQ");
+        assert!(
+            invalid.starts_with("invalidmove ") && invalid.contains("must pass"),
+            "{}",
+            invalid
+        );
+        assert!(output(&mut server, "play pass").contains(";InProgress;Black[6];"));
+        // Black has moves, so can't pass.
+        let invalid = output(&mut server, "pass");
+        assert!(invalid.contains("only pass without a legal move"), "{}", invalid);
+    }
+
+    #[test]
+    fn test_tournament_opening() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command("newgame Base");
+        assert!(!output(&mut server, "validmoves").contains("wQ"));
+        let invalid = output(&mut server, "play wQ");
+        assert!(invalid.contains("can't be placed on a player's first turn"), "{}", invalid);
+
+        assert_eq!(
+            "TournamentOpening;bool;False;True\n",
+            output(&mut server, "options set TournamentOpening False")
+        );
+        assert!(output(&mut server, "validmoves").contains("wQ"));
+        assert_eq!("Base;InProgress;Black[1];wQ\n", output(&mut server, "play wQ"));
+        // Games opening with the queen can be started from their GameString.
+        let game = "Base;InProgress;White[2];wQ;bQ wQ-";
+        assert_eq!(format!("{}\n", game), output(&mut server, &format!("newgame {}", game)));
+        assert_eq!(1, output(&mut server, "bestmove depth 1").lines().count());
+    }
+
+    #[test]
+    fn test_variant_options() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        assert_eq!(
+            "QueenMovesLast;bool;True;False\n",
+            output(&mut server, "options set QueenMovesLast True")
+        );
+        assert_eq!(
+            "MaxStackHeight;int;1;0;0;8\n",
+            output(&mut server, "options set MaxStackHeight 1")
+        );
+        server.command(
+            "newgame Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-",
+        );
+        let moves = output(&mut server, "validmoves");
+        assert!(!moves.trim().split(';').any(|m| m.starts_with("wQ ")), "{}", moves);
+        assert!(!moves.trim().split(';').any(|m| m == "wB1 wS1"), "{}", moves);
+        let invalid = output(&mut server, "play wQ /wS1");
+        assert!(invalid.contains("until all of a player's tiles"), "{}", invalid);
+
+        // Variants apply to games started after the options are set.
+        server.command("options set MaxStackHeight 0");
+        server.command(
+            "newgame Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-",
+        );
+        let moves = output(&mut server, "validmoves");
+        assert!(moves.trim().split(';').any(|m| m == "wB1 wS1"), "{}", moves);
+    }
+
+    #[test]
+    fn test_new_game_position() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap()
+        };
+        let game_string = r"Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-";
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        assert_eq!(
+            format!("{}\n", game_string),
+            output(&mut server, &format!("newgame {}", game_string))
+        );
+        let sorted = |moves: String| {
+            let mut moves = moves.trim().split(';').map(str::to_owned).collect::<Vec<_>>();
+            moves.sort();
+            moves
+        };
+        let moves = sorted(output(&mut server, "validmoves"));
+        let position = server.board.as_ref().unwrap().to_position_string();
+
+        // The same position without its moves.
+        assert_eq!(
+            "Base;InProgress;White[3]\n",
+            output(&mut server, &format!("newgame {}", position))
+        );
+        assert_eq!(moves, sorted(output(&mut server, "validmoves")));
+        let best = output(&mut server, "bestmove depth 2");
+        assert!(moves.contains(&best.trim().to_owned()), "{}", best);
+        output(&mut server, &format!("play {}", best.trim()));
+        assert!(output(&mut server, "undo 2").starts_with("err Can't undo"));
+        assert_eq!("Base;InProgress;White[3]\n", output(&mut server, "undo"));
+
+        assert!(output(&mut server, "newgame Base White[1] wQ@0,0").starts_with("err"));
+
+        // Custom piece sets survive the new game and its moves.
+        assert_eq!("Base+M:G2A1;NotStarted;White[1]\n", output(&mut server, "newgame Base+M:A1G2"));
+        output(&mut server, "play wA1");
+        output(&mut server, "play bM wA1-");
+        let board = server.board.as_ref().unwrap();
+        let (game_string, position) = (board.game_string(), board.to_position_string());
+        assert_eq!("Base+M:G2A1;InProgress;White[2];wA1;bM wA1-", game_string);
+        let replay =
+            format!("newgame {};InProgress;White[2];{}", board.game_type(), board.game_log());
+        assert_eq!(format!("{}\n", game_string), output(&mut server, &replay));
+        output(&mut server, &format!("newgame {}", position));
+        assert_eq!(position, server.board.as_ref().unwrap().to_position_string());
+        assert_eq!("Base+M:G2A1", server.board.as_ref().unwrap().game_type());
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let output = |server: &mut UhpServer<Vec<u8>>, command: &str| {
+            server.swap_output(Vec::new());
+            server.command(command);
+            String::from_utf8(server.swap_output(Vec::new())).unwrap().trim().to_owned()
+        };
+        let game_string =
+            r"Base;InProgress;White[5];wS1;bG1 wS1\;wQ wS1/;bQ bG1-;wS2 wQ-;bB1 bQ\;wB1 \wQ;bB1 bQ";
+        let mut server = UhpServer::new(PlayerConfig::new(), Vec::new());
+        server.command("options set NumThreads 1");
+        server.command(&format!("newgame {}", game_string));
+        let board = server.board.clone().unwrap();
+
+        assert_eq!(r"Base;InProgress;Black[2];wS1;bG1 wS1\;wQ wS1/", output(&mut server, "undo 5"));
+        assert!(output(&mut server, "undo 4").starts_with("err Can't undo"));
+        assert_eq!(
+            r"Base;InProgress;White[3];wS1;bG1 wS1\;wQ wS1/;bQ bG1-",
+            output(&mut server, "redo")
+        );
+        // Replaying the next turn keeps the rest.
+        output(&mut server, "play wS2 wQ-");
+        assert!(output(&mut server, "redo 4").starts_with("err No undone turns"));
+        assert_eq!(game_string, output(&mut server, "redo 3"));
+        let restored = server.board.as_ref().unwrap();
+        assert_eq!(
+            <Rules as minimax::Game>::zobrist_hash(&board),
+            <Rules as minimax::Game>::zobrist_hash(restored)
+        );
+        assert_eq!(board.get_remaining(), restored.get_remaining());
+        assert_eq!(board.get_opponent_remaining(), restored.get_opponent_remaining());
+
+        // A different turn starts a new line.
+        output(&mut server, "undo 2");
+        output(&mut server, "play wA1 \\wQ");
+        assert!(output(&mut server, "redo").starts_with("err No undone turns"));
+        let best = output(&mut server, "bestmove depth 1");
+        assert!(!best.starts_with("err"), "{}", best);
+    }
 }
@@ -1,27 +1,261 @@
-use crate::{PlayerConfig, UhpServer};
+use crate::board::split_offset;
+use crate::{Board, Color, PlayerConfig, UhpError, UhpServer, START_HEX};
+use std::cell::{Cell, RefCell};
 use std::io::Cursor;
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
 
-static mut UHP_SERVER: *mut UhpServer<Cursor<Vec<u8>>> = std::ptr::null_mut();
+type Server = UhpServer<Cursor<Vec<u8>>>;
+
+thread_local! {
+    static UHP_SERVER: RefCell<Server> = RefCell::new(new_server());
+    static SEARCHING: Cell<bool> = const { Cell::new(false) };
+    // Set by cancel_search, and checked by best_move_async between depths.
+    static CANCELLED: Cell<bool> = const { Cell::new(false) };
+}
+
+fn new_server() -> Server {
+    let mut config = PlayerConfig::new();
+    config.opts = config.opts.with_table_byte_size(8 << 20);
+    UhpServer::new(config, Cursor::new(Vec::new()))
+}
 
 #[wasm_bindgen]
 pub fn uhp(args: &str) -> String {
-    // Manual lazy_static.
-    let server = unsafe {
-        if UHP_SERVER.is_null() {
-            let mut config = PlayerConfig::new();
-            config.opts = config.opts.with_table_byte_size(8 << 20);
-            UHP_SERVER = Box::into_raw(Box::new(UhpServer::new(config, Cursor::new(Vec::new()))));
+    UHP_SERVER.with_borrow_mut(|server| {
+        server.swap_output(Cursor::new(Vec::new()));
+        server.command(args);
+        let buf = server.swap_output(Cursor::new(Vec::new()));
+        String::from_utf8(buf.into_inner())
+            .unwrap_or_else(|_| "err encoding".to_string())
+            .trim()
+            .to_string()
+    })
+}
+
+// The typed functions below share the game with uhp(), and throw UHP errors
+// as exceptions instead of returning them as text.
+fn command(args: &str) -> Result<String, JsError> {
+    let output = uhp(args);
+    if output.starts_with("err") || output.starts_with("invalidmove") {
+        return Err(JsError::new(&output));
+    }
+    Ok(output)
+}
+
+// Starts a game of a type like "Base+MLP", or from a GameString, and returns
+// its GameString.
+#[wasm_bindgen]
+pub fn new_game(game_type: &str) -> Result<String, JsError> {
+    command(&format!("newgame {}", game_type))
+}
+
+// An array of UHP MoveStrings, which is just ["pass"] when there are no moves.
+#[wasm_bindgen]
+pub fn legal_moves() -> Result<Vec<String>, JsError> {
+    Ok(command("validmoves")?.split(';').map(str::to_owned).collect())
+}
+
+// Plays a UHP MoveString and returns the new GameString.
+#[wasm_bindgen]
+pub fn play(move_string: &str) -> Result<String, JsError> {
+    command(&format!("play {}", move_string))
+}
+
+// Searches for up to time_ms milliseconds and returns the best MoveString,
+// without playing it.
+#[wasm_bindgen]
+pub fn best_move(time_ms: u32) -> Result<String, JsError> {
+    let secs = time_ms / 1000;
+    command(&format!(
+        "bestmove time {:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        time_ms % 1000
+    ))
+}
+
+#[wasm_bindgen]
+extern "C" {
+    // In both windows and workers.
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(callback: &js_sys::Function, millis: i32);
+}
+
+// Lets the browser handle other events, like a click on a stop button.
+async fn yield_to_browser() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| set_timeout(&resolve, 0));
+    let _ = JsFuture::from(promise).await;
+}
+
+// Like best_move, but returns a Promise, and yields to the browser after each
+// depth. progress, if given, is called after each depth as
+// progress(depth, score, pv), with the score a number for the player to move
+// or "win" or "loss", and pv an array of MoveStrings starting with the best
+// move. cancel_search() stops the search at the end of the current depth, and
+// the Promise resolves to the best move found so far.
+#[wasm_bindgen]
+pub async fn best_move_async(
+    time_ms: u32, progress: Option<js_sys::Function>,
+) -> Result<String, JsError> {
+    if SEARCHING.replace(true) {
+        return Err(JsError::new(&format!(
+            "err {}",
+            UhpError::EngineError("A search is already running".into())
+        )));
+    }
+    CANCELLED.set(false);
+    let result = search_with_progress(time_ms as f64, progress).await;
+    SEARCHING.set(false);
+    result
+}
+
+// Stops a best_move_async search at the end of the current depth.
+#[wasm_bindgen]
+pub fn cancel_search() {
+    CANCELLED.set(true);
+}
+
+async fn search_with_progress(
+    time_ms: f64, progress: Option<js_sys::Function>,
+) -> Result<String, JsError> {
+    let game = UHP_SERVER.with_borrow(|server| server.board().map(Board::game_string));
+    let start = js_sys::Date::now();
+    let mut best_move = None;
+    let mut depth = 0;
+    let mut depth_time = 0.0;
+    loop {
+        let left = time_ms - (js_sys::Date::now() - start);
+        if best_move.is_some() && (CANCELLED.get() || left <= 0.0) {
+            break;
+        }
+        // A depth can't be interrupted, so once the next depth could overrun
+        // the time, let the engine go as deep as it can in the rest of it.
+        let timed = best_move.is_some() && depth_time * 16.0 > left;
+        depth += 1;
+        let step_start = js_sys::Date::now();
+        let step = UHP_SERVER.with_borrow_mut(|server| {
+            // Commands run between depths could have changed the game.
+            if server.board().map(Board::game_string) != game {
+                return Err(JsError::new(&format!(
+                    "err {}",
+                    UhpError::EngineError("The game changed during the search".into())
+                )));
+            }
+            server
+                .search_step((!timed).then_some(depth), Duration::from_millis(left.max(1.0) as u64))
+                .map_err(|err| JsError::new(&format!("err {}", err)))
+        })?;
+        depth_time = js_sys::Date::now() - step_start;
+        if let Some(progress) = &progress {
+            let score = match step.score.parse::<f64>() {
+                Ok(score) => JsValue::from(score),
+                Err(_) => JsValue::from(&step.score),
+            };
+            let pv = step.pv.iter().map(JsValue::from).collect::<js_sys::Array>();
+            progress
+                .call3(&JsValue::NULL, &JsValue::from(step.depth), &score, &pv)
+                .map_err(|_| JsError::new("err progress callback threw an exception"))?;
         }
-        UHP_SERVER.as_mut().unwrap()
-    };
-    server.swap_output(Cursor::new(Vec::new()));
-    server.command(args);
-    let buf = server.swap_output(Cursor::new(Vec::new()));
-    String::from_utf8(buf.into_inner())
-        .unwrap_or_else(|_| "err encoding".to_string())
-        .trim()
-        .to_string()
+        best_move = Some(step.best_move);
+        if timed || step.complete {
+            break;
+        }
+        yield_to_browser().await;
+    }
+    Ok(best_move.unwrap())
+}
+
+// The position as JSON, for rendering:
+//
+//   {"gameType":"Base","state":"InProgress","turn":"Black[4]",
+//    "tiles":[{"piece":"wQ","x":0,"y":0,"height":1}, ...],
+//    "hands":{"white":"GGGSSAAB","black":"GGSSAAAB"}}
+//
+// Tiles are at their offset east and southwest of the white queen, as in
+// position strings, with stacks listed from the bottom up.
+#[wasm_bindgen]
+pub fn board_json() -> Result<String, JsError> {
+    UHP_SERVER
+        .with_borrow(|server| server.board().map(position_json))
+        .ok_or_else(|| JsError::new(&format!("err {}", UhpError::GameNotStarted)))
+}
+
+fn position_json(board: &Board) -> String {
+    let game_string = board.game_string();
+    let fields = game_string.split(';').take(3).collect::<Vec<_>>();
+    let position = board.to_position_string();
+    let mut tiles = position.split_whitespace().skip(2).collect::<Vec<_>>();
+    let hands = tiles.split_off(tiles.len() - 2);
+    let tiles = tiles
+        .iter()
+        .enumerate()
+        .map(|(i, tile)| {
+            let (piece, loc) = tile.split_once('@').unwrap();
+            let (x, y) = loc.split_once(',').unwrap();
+            let loc = format!("@{}", loc);
+            let height = tiles[..i].iter().filter(|below| below.ends_with(&loc)).count() + 1;
+            format!(r#"{{"piece":"{}","x":{},"y":{},"height":{}}}"#, piece, x, y, height)
+        })
+        .collect::<Vec<_>>();
+    let hand = |hand: &str| hand[2..].trim_start_matches('-').to_owned();
+    format!(
+        r#"{{"gameType":"{}","state":"{}","turn":"{}","tiles":[{}],"hands":{{"white":"{}","black":"{}"}}}}"#,
+        fields[0],
+        fields[1],
+        fields[2],
+        tiles.join(","),
+        hand(hands[0]),
+        hand(hands[1])
+    )
+}
+
+// The hexes around each queen and the enemy tiles that could move there next
+// turn, for danger overlays, with coordinates as in board_json.
+#[wasm_bindgen]
+pub fn threat_map() -> Result<String, JsError> {
+    UHP_SERVER
+        .with_borrow(|server| server.board().map(threat_json))
+        .ok_or_else(|| JsError::new(&format!("err {}", UhpError::GameNotStarted)))
+}
+
+fn threat_json(board: &Board) -> String {
+    let origin = board.queens[Color::White as usize].unwrap_or(START_HEX);
+    let threats = board
+        .threat_map()
+        .iter()
+        .map(|threat| {
+            let attackers = threat
+                .attackers
+                .iter()
+                .map(|&hex| {
+                    let mut piece = String::new();
+                    board.tile_name(board.node(hex), &mut piece);
+                    let (x, y) = split_offset(hex.wrapping_sub(origin));
+                    format!(r#"{{"piece":"{}","x":{},"y":{}}}"#, piece, x, y)
+                })
+                .collect::<Vec<_>>();
+            let queen = if threat.queen == Color::White { "white" } else { "black" };
+            let (x, y) = split_offset(threat.hex.wrapping_sub(origin));
+            format!(
+                r#"{{"queen":"{}","x":{},"y":{},"attackers":[{}]}}"#,
+                queen,
+                x,
+                y,
+                attackers.join(",")
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", threats.join(","))
+}
+
+// Trade strength for latency and battery: answer every bestmove with a
+// shallow search, ignoring the requested depth or time.
+#[wasm_bindgen]
+pub fn set_instant(enabled: bool) {
+    uhp(if enabled { "options set Instant True" } else { "options set Instant False" });
 }
 
 #[cfg(test)]
@@ -68,6 +302,87 @@ pub mod test {
         assert!(["wA1", "wB1", "wG1", "wS1"].contains(&best.as_str()));
     }
 
+    #[wasm_bindgen_test]
+    fn instant_test() {
+        super::set_instant(true);
+        assert_eq!(uhp("options get Instant"), "Instant;bool;True;False");
+        uhp("newgame Base");
+        let best = uhp("bestmove time 00:00:10");
+        assert!(["wA1", "wB1", "wG1", "wS1"].contains(&best.as_str()));
+        super::set_instant(false);
+    }
+
+    #[wasm_bindgen_test]
+    fn typed_test() {
+        use super::*;
+        assert_eq!(new_game("Base").unwrap(), "Base;NotStarted;White[1]");
+        let mut moves = legal_moves().unwrap();
+        moves.sort();
+        assert_eq!(moves, &["wA1", "wB1", "wG1", "wS1"]);
+        assert_eq!(play("wA1").unwrap(), "Base;InProgress;Black[1];wA1");
+        assert!(play("wQ").is_err());
+        assert!(new_game("Chess").is_err());
+        assert!(best_move(250).unwrap().starts_with('b'));
+        for m in ["bG1 wA1-", "wQ -wA1", "bQ bG1-", "wB1 -wQ", "bB1 bQ-", "wB1 wQ"] {
+            play(m).unwrap();
+        }
+        assert_eq!(
+            board_json().unwrap(),
+            concat!(
+                r#"{"gameType":"Base","state":"InProgress","turn":"Black[4]","tiles":["#,
+                r#"{"piece":"wQ","x":0,"y":0,"height":1},"#,
+                r#"{"piece":"wB1","x":0,"y":0,"height":2},"#,
+                r#"{"piece":"wA1","x":1,"y":0,"height":1},"#,
+                r#"{"piece":"bG1","x":2,"y":0,"height":1},"#,
+                r#"{"piece":"bQ","x":3,"y":0,"height":1},"#,
+                r#"{"piece":"bB1","x":4,"y":0,"height":1}],"#,
+                r#""hands":{"white":"GGGSSAAB","black":"GGSSAAAB"}}"#
+            )
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn threat_map_test() {
+        use super::*;
+        new_game("Base;InProgress;White[4];wS1;bS1 wS1-;wQ -wS1;bQ bS1-;wB1 /wS1;bA1 bQ-").unwrap();
+        let threats = threat_map().unwrap();
+        assert!(threats.starts_with(r#"[{"queen":"white","#), "{}", threats);
+        assert!(threats.contains(r#"{"piece":"bA1","#), "{}", threats);
+        assert_eq!(12, threats.matches(r#""queen""#).count());
+    }
+
+    #[wasm_bindgen_test]
+    async fn best_move_async_test() {
+        use super::*;
+        use std::rc::Rc;
+        new_game("Base;InProgress;White[2];wS1;bG1 wS1-").unwrap();
+        let depths = Rc::new(RefCell::new(Vec::new()));
+        let seen = depths.clone();
+        let progress = Closure::<dyn FnMut(u32, JsValue, js_sys::Array)>::new(
+            move |depth, score: JsValue, pv: js_sys::Array| {
+                assert!(score.as_f64().is_some());
+                assert_eq!(pv.length(), depth);
+                seen.borrow_mut().push(depth);
+            },
+        );
+        let callback = progress.as_ref().unchecked_ref::<js_sys::Function>().clone();
+        let best = best_move_async(500, Some(callback)).await.unwrap();
+        assert!(legal_moves().unwrap().contains(&best));
+        assert_eq!(1, depths.borrow()[0]);
+
+        // A stop button pressed during the second depth.
+        let stop = Closure::<dyn FnMut(u32)>::new(|depth| {
+            if depth == 2 {
+                cancel_search();
+            }
+        });
+        let callback = stop.as_ref().unchecked_ref::<js_sys::Function>().clone();
+        let start = js_sys::Date::now();
+        let best = best_move_async(10_000, Some(callback)).await.unwrap();
+        assert!(legal_moves().unwrap().contains(&best));
+        assert!(js_sys::Date::now() - start < 5_000.0);
+    }
+
     #[wasm_bindgen_test]
     fn options_test() {
         assert!(uhp("options").contains("TableSizeMiB"));
@@ -0,0 +1,439 @@
+// UHP over WebSockets, so browser frontends can talk to a native engine
+// without the wasm build. Each connection is its own session, with its own
+// board and engine. Every text message is a UHP command, and its output,
+// ending in "ok" as on stdin, comes back as one message. Search progress lines
+// from the SearchInfo option are sent as they're found.
+
+use crate::{CommandCaps, PlayerConfig, UhpServer, MAX_DEPTH, MAX_MOVE_TIME};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// From RFC 6455, to prove the server speaks WebSocket.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Commands are short; anything longer is a broken or hostile client.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+const MAX_HEADER_SIZE: usize = 8 << 10;
+// Each session has its own engine and transposition table.
+const MAX_SESSIONS: usize = 16;
+// Until the handshake is done; sessions may then sit idle between moves, but
+// not so long that abandoned ones hold every slot.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// Searches get the same limits as over HTTP. Perft has no time limit, so its
+// depth is kept to what finishes in seconds.
+const CAPS: CommandCaps =
+    CommandCaps { max_depth: MAX_DEPTH, max_time: MAX_MOVE_TIME, max_perft_depth: 3 };
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+pub fn ws_serve(config: PlayerConfig, addr: &str) {
+    eprintln!("Serving UHP over WebSockets on {}", addr);
+    serve_connections(config, addr, MAX_SESSIONS, serve_connection);
+}
+
+// A counting semaphore, to cap how many connections or searches run at once.
+pub(crate) struct Slots {
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+// Holds a slot until dropped.
+pub(crate) struct Slot<'a>(&'a Slots);
+
+impl Slots {
+    pub(crate) const fn new() -> Slots {
+        Slots { used: Mutex::new(0), freed: Condvar::new() }
+    }
+
+    // Waits until fewer than limit slots are taken, and takes one.
+    pub(crate) fn acquire(&self, limit: usize) -> Slot<'_> {
+        let mut used = self.used.lock().unwrap();
+        while *used >= limit {
+            used = self.freed.wait(used).unwrap();
+        }
+        *used += 1;
+        Slot(self)
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        *self.0.used.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+static CONNECTIONS: Slots = Slots::new();
+
+// Accepts connections on the address, each handled on its own thread with its
+// own copy of the config. Past max_connections at once, new connections wait
+// in the listen backlog until one closes.
+pub(crate) fn serve_connections(
+    config: PlayerConfig, addr: &str, max_connections: usize,
+    handler: fn(TcpStream, PlayerConfig) -> io::Result<()>,
+) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("{}: {}", addr, err);
+            return;
+        }
+    };
+    loop {
+        let slot = CONNECTIONS.acquire(max_connections);
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        let config = config.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(err) = handler(stream, config) {
+                eprintln!("{}", err);
+            }
+        });
+    }
+}
+
+fn serve_connection(stream: TcpStream, config: PlayerConfig) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    handshake(&mut reader, &mut stream)?;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut server = UhpServer::new(config, MessageWriter { stream, buffer: Vec::new() });
+    server.set_caps(CAPS);
+    server.command("info");
+    writeln!(server.output(), "ok")?;
+    server.output().flush()?;
+    while let Some(message) = read_message(&mut reader, server.output())? {
+        for line in message.lines().filter(|line| !line.trim().is_empty()) {
+            if server.command(line) {
+                return server.output().send(OP_CLOSE, &[]);
+            }
+            writeln!(server.output(), "ok")?;
+            server.output().flush()?;
+        }
+    }
+    server.output().send(OP_CLOSE, &[])
+}
+
+// Reads the HTTP upgrade request and accepts it.
+fn handshake(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let mut key = None;
+    let mut upgrade = false;
+    // A line without an end can't grow past the limit either.
+    let mut reader = reader.take(MAX_HEADER_SIZE as u64);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let len = reader.read_line(&mut line)?;
+        if len == 0 || !line.ends_with('\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad WebSocket request"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Sec-WebSocket-Key") {
+                key = Some(value.to_owned());
+            } else if name.eq_ignore_ascii_case("Upgrade") {
+                upgrade = value.eq_ignore_ascii_case("websocket");
+            }
+        }
+    }
+    match key {
+        Some(key) if upgrade => write!(
+            writer,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key)
+        ),
+        _ => {
+            write!(writer, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "not a WebSocket upgrade"))
+        }
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+// Collects UHP output, and sends it as one text message on flush.
+struct MessageWriter {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl MessageWriter {
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        // Server frames are final and unmasked.
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+impl Write for MessageWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let message = std::mem::take(&mut self.buffer);
+        self.send(OP_TEXT, &message)
+    }
+}
+
+// The next data message, answering pings along the way, or None once the
+// client closes the connection or goes quiet past the read timeout.
+fn read_message(reader: &mut impl Read, writer: &mut MessageWriter) -> io::Result<Option<String>> {
+    let mut message = Vec::new();
+    loop {
+        let (fin, opcode, payload) = match read_frame(reader) {
+            Ok(frame) => frame,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        match opcode {
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                message.extend_from_slice(&payload);
+                if message.len() > MAX_MESSAGE_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+                }
+                if fin {
+                    return String::from_utf8(message)
+                        .map(Some)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            }
+            OP_PING => writer.send(OP_PONG, &payload)?,
+            OP_PONG => {}
+            _ => return Ok(None),
+        }
+    }
+}
+
+// Reads one client frame: whether it ends a message, its opcode, and its
+// unmasked payload.
+fn read_frame(reader: &mut impl Read) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0xf;
+    if header[1] & 0x80 == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unmasked client frame"));
+    }
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((fin, opcode, payload))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp =
+                a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits =
+            chunk.iter().enumerate().fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!("2jmj7l5rSw0yVb/vlWAYkK/YBwk=", base64(&sha1(b"")));
+        // The example from RFC 6455.
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+
+        // Headers that go on without a line end are cut off.
+        let huge = format!("GET / HTTP/1.1\r\nX-Padding: {}", "a".repeat(MAX_MESSAGE_SIZE));
+        assert!(handshake(&mut huge.as_bytes(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_ws_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, PlayerConfig::new()).unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut response = String::new();
+        while !response.ends_with("\r\n\r\n") {
+            reader.read_line(&mut response).unwrap();
+        }
+        assert!(response.starts_with("HTTP/1.1 101 "), "{}", response);
+        assert!(response.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), "{}", response);
+
+        // Server frames are short and unmasked.
+        let mut receive = || {
+            let mut header = [0; 2];
+            reader.read_exact(&mut header).unwrap();
+            let mut payload = vec![0; header[1] as usize];
+            reader.read_exact(&mut payload).unwrap();
+            (header[0], String::from_utf8(payload).unwrap())
+        };
+        let send = |client: &mut TcpStream, opcode: u8, payload: &str| {
+            let mask = [1, 2, 3, 4];
+            let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.bytes().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+            client.write_all(&frame).unwrap();
+        };
+
+        let (_, info) = receive();
+        assert!(info.starts_with("id nokamute ") && info.ends_with("\nok\n"), "{}", info);
+        send(&mut client, OP_PING, "hi");
+        assert_eq!((0x80 | OP_PONG, "hi".to_owned()), receive());
+        send(&mut client, OP_TEXT, "newgame Base");
+        assert_eq!((0x80 | OP_TEXT, "Base;NotStarted;White[1]\nok\n".to_owned()), receive());
+        send(&mut client, OP_TEXT, "play wQ2");
+        assert_eq!((0x80 | OP_TEXT, "invalidmove wQ2: unknown piece\nok\n".to_owned()), receive());
+        send(&mut client, OP_TEXT, "bestmove depth 21");
+        assert_eq!(
+            (0x80 | OP_TEXT, "err Invalid option: depth 1 to 20\nok\n".to_owned()),
+            receive()
+        );
+        send(&mut client, OP_TEXT, "perft 9");
+        assert_eq!(
+            (0x80 | OP_TEXT, "err Invalid option: perft depth up to 3\nok\n".to_owned()),
+            receive()
+        );
+        send(&mut client, OP_TEXT, "exit");
+        assert_eq!(0x80 | OP_CLOSE, receive().0);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut reader = stream.try_clone().unwrap();
+        let mut writer = MessageWriter { stream, buffer: Vec::new() };
+        // A quiet client ends the session like a closed one.
+        assert_eq!(None, read_message(&mut reader, &mut writer).unwrap());
+    }
+}